@@ -0,0 +1,45 @@
+use sea_orm::entity::prelude::*;
+
+/// Scoped api key of the user for the programmatic access, amounts in msats.
+/// Only the sha256 hash of the secret is stored.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "api_keys")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+
+    pub user_id: i32,
+
+    pub name: String,
+
+    /// the leading characters of the secret to identify the key, not secret
+    pub prefix: String,
+
+    #[sea_orm(column_type = "Binary(BlobSize::Blob(None))")]
+    pub key_hash: Vec<u8>,
+
+    /// allowed scopes, separated by space
+    pub scopes: String,
+
+    /// total spend limit, 0 is unlimited
+    pub spend_limit: i64,
+    pub spent: i64,
+
+    /// allowed client ips or networks, separated by space, empty allows any ip
+    #[sea_orm(column_type = "Text")]
+    pub allowed_ips: String,
+
+    /// 0 is never expired
+    pub expires_at: i64,
+    /// 0 is active
+    pub revoked_at: i64,
+    pub last_used_at: i64,
+
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,27 @@
+use sea_orm::entity::prelude::*;
+
+/// LUD-04 challenges waiting for the signature of the wallet,
+/// the tokens are issued to the client polling by k1 after the wallet signed.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "auth_challenges")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+
+    #[sea_orm(column_type = "Binary(BlobSize::Blob(None))")]
+    pub k1: Vec<u8>,
+
+    /// link the key to the user, 0 is login
+    pub user_id: i32,
+
+    /// the user signed in, 0 is pending
+    pub authed_user_id: i32,
+
+    pub created_at: i64,
+    pub authed_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,19 @@
+use sea_orm::entity::prelude::*;
+
+/// Seen nip98 auth events, kept until they expire to reject the replays.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "auth_events")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+
+    #[sea_orm(column_type = "Binary(BlobSize::Blob(None))")]
+    pub event_id: Vec<u8>,
+
+    pub expires_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,35 @@
+use sea_orm::entity::prelude::*;
+
+/// Kind of the external auth identity
+#[derive(EnumIter, DeriveActiveEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(1))")]
+pub enum Kind {
+    /// LUD-04 linking key
+    #[default]
+    #[sea_orm(string_value = "lnurl")]
+    Lnurl,
+}
+
+/// External auth identities of the user, such as the lnurl-auth linking keys.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "auth_identities")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+
+    pub user_id: i32,
+
+    pub kind: Kind,
+
+    /// the compressed linking key of lnurl-auth
+    #[sea_orm(column_type = "Binary(BlobSize::Blob(None))")]
+    pub identifier: Vec<u8>,
+
+    pub last_used_at: i64,
+    pub created_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
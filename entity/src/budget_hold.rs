@@ -0,0 +1,41 @@
+use sea_orm::entity::prelude::*;
+
+/// The budgets limiting the payments
+#[derive(EnumIter, DeriveActiveEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(20))")]
+pub enum Budget {
+    #[sea_orm(string_value = "nwc_connection")]
+    NwcConnection,
+    #[sea_orm(string_value = "api_key")]
+    ApiKey,
+    #[sea_orm(string_value = "withdraw_link")]
+    WithdrawLink,
+}
+
+/// The budget charged by an in flight payment, given back if the payment fails.
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "budget_holds")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+
+    /// the in flight payment
+    pub invoice_id: i32,
+
+    pub budget: Budget,
+
+    /// id of the connection, api key or withdraw link
+    pub budget_id: i32,
+
+    /// msats
+    pub amount: i64,
+
+    /// data create time
+    pub created_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
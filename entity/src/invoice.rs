@@ -16,6 +16,8 @@ pub enum Status {
     Paid = 1,
     /// failed when payment or invoice is expired
     Canceled = 2,
+    /// the payment of the hold invoice is held, not credited until settled
+    Accepted = 3,
 }
 
 #[derive(EnumIter, DeriveActiveEnum, Debug, Clone, PartialEq, Eq)]
@@ -33,6 +35,15 @@ pub enum Source {
     Nwc,
     #[sea_orm(string_value = "api")]
     Api,
+    /// received keysend payment
+    #[sea_orm(string_value = "keysend")]
+    Keysend,
+    /// received by a bolt12 offer
+    #[sea_orm(string_value = "offer")]
+    Offer,
+    /// paid by a lnurl withdraw link
+    #[sea_orm(string_value = "withdraw")]
+    Withdraw,
 }
 
 impl Default for Source {
@@ -58,7 +69,7 @@ pub struct Model {
     /// 0, invoice, 1: payment
     pub r#type: Type,
 
-    /// 0, unpaid, 1: paid, 2: canceled
+    /// 0, unpaid, 1: paid, 2: canceled, 3: accepted
     pub status: Status,
 
     pub service: String,
@@ -100,6 +111,8 @@ pub struct Model {
     pub internal: bool,
     /// duplicate payment by external and internal
     pub duplicate: bool,
+    /// hold invoice, created by the payment hash and settled by the user
+    pub hold: bool,
     pub service_fee: i64,
 
     /// LUD-12 comment
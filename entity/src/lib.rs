@@ -1,5 +1,18 @@
+pub mod api_key;
+pub mod auth_challenge;
+pub mod auth_event;
+pub mod auth_identity;
+pub mod budget_hold;
 pub mod donation;
 pub mod event;
 pub mod invoice;
+pub mod lndhub_credential;
+pub mod nwc_connection;
+pub mod offer;
+pub mod posting;
 pub mod record;
+pub mod session;
+pub mod sync_state;
 pub mod user;
+pub mod user_profile;
+pub mod withdraw_link;
@@ -0,0 +1,27 @@
+use sea_orm::entity::prelude::*;
+
+/// Named lndhub password of the user, login by the user pubkey and any active credential.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "lndhub_credentials")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+
+    pub user_id: i32,
+
+    pub name: String,
+
+    /// argon2id hash of the password in the PHC string format
+    pub password_hash: String,
+
+    pub last_used_at: i64,
+    /// 0 is active
+    pub revoked_at: i64,
+
+    pub created_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
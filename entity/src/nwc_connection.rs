@@ -0,0 +1,62 @@
+use sea_orm::entity::prelude::*;
+
+/// Budget renewal period of nwc connection
+#[derive(EnumIter, DeriveActiveEnum, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(1))")]
+pub enum BudgetRenewal {
+    #[default]
+    #[sea_orm(string_value = "never")]
+    Never,
+    #[sea_orm(string_value = "daily")]
+    Daily,
+    #[sea_orm(string_value = "weekly")]
+    Weekly,
+    #[sea_orm(string_value = "monthly")]
+    Monthly,
+}
+
+/// Nostr wallet connect connections of the user, amounts in msats.
+/// The client keypair is generated for each connection, only the pubkey is stored.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "nwc_connections")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+
+    pub user_id: i32,
+
+    /// client pubkey
+    #[sea_orm(column_type = "Binary(BlobSize::Blob(None))")]
+    pub pubkey: Vec<u8>,
+
+    pub name: String,
+
+    /// allowed methods, separated by space
+    #[sea_orm(column_type = "Text")]
+    pub methods: String,
+
+    /// max amount per payment, 0 is unlimited
+    pub max_amount: i64,
+
+    /// budget per renewal period, 0 is unlimited
+    pub budget: i64,
+    pub budget_renewal: BudgetRenewal,
+    /// spent in the current period
+    pub budget_used: i64,
+    /// start time of the current period
+    pub budget_renewed_at: i64,
+
+    /// 0 is never expired
+    pub expires_at: i64,
+    /// 0 is active
+    pub revoked_at: i64,
+    pub last_used_at: i64,
+
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,33 @@
+use sea_orm::entity::prelude::*;
+
+/// Reusable bolt12 offer of the user,
+/// the invoices requested by the offer are credited to the user.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "offers")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+
+    pub user_id: i32,
+
+    /// lightning service name of the node which created the offer
+    pub service: String,
+
+    #[sea_orm(column_type = "Binary(BlobSize::Blob(None))")]
+    pub offer_id: Vec<u8>,
+
+    #[sea_orm(column_type = "Text")]
+    pub bolt12: String,
+
+    pub description: String,
+
+    /// amount in msats, 0 is any amount
+    pub amount: i64,
+
+    pub created_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,58 @@
+use sea_orm::entity::prelude::*;
+
+/// Ledger accounts
+#[derive(EnumIter, DeriveActiveEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(20))")]
+pub enum Account {
+    /// liability, spendable balance of the user
+    #[sea_orm(string_value = "user")]
+    User,
+    /// liability, balance of the user locked by in flight payments
+    #[sea_orm(string_value = "user_locked")]
+    UserLocked,
+    /// income, service fees and internal payment fees
+    #[sea_orm(string_value = "fee_income")]
+    FeeIncome,
+    /// expense, routing fees paid to the network
+    #[sea_orm(string_value = "routing_fee")]
+    RoutingFee,
+    /// asset, channel liquidity of the lightning node
+    #[sea_orm(string_value = "node")]
+    Node,
+    /// equity, opening balances of the users before the ledger was introduced
+    #[sea_orm(string_value = "equity")]
+    Equity,
+}
+
+/// Double-entry ledger postings, amount in msats.
+/// Positive is debit, negative is credit, the postings of a journal sum to zero.
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "postings")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+
+    /// journal id, groups the postings of a movement
+    pub journal: String,
+
+    /// movement type, internal_payment, external_payment ...
+    pub kind: String,
+
+    pub account: Account,
+
+    /// user of the user accounts
+    pub user_id: Option<i32>,
+
+    pub invoice_id: Option<i32>,
+
+    pub amount: i64,
+
+    /// data create time
+    pub created_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
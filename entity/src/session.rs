@@ -0,0 +1,36 @@
+use sea_orm::entity::prelude::*;
+
+/// jwt session of a signed in device, the refresh token is rotated on each refresh.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "sessions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+
+    pub user_id: i32,
+
+    /// the lndhub credential signed in by, 0 is signed in otherwise
+    pub credential_id: i32,
+
+    /// token id of the current refresh token, an older one is reused
+    pub jti: String,
+
+    /// device label, the client name or the user agent
+    pub device: String,
+
+    /// ip of the last request
+    pub ip: String,
+
+    pub last_used_at: i64,
+    pub expires_at: i64,
+    /// 0 is active
+    pub revoked_at: i64,
+
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
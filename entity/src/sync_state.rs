@@ -0,0 +1,25 @@
+use sea_orm::entity::prelude::*;
+
+/// Sync cursors of the lightning backend, keyed by service name
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "sync_states")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+
+    /// lightning service name, lnd, cln
+    pub service: String,
+
+    /// cursor name
+    pub key: String,
+
+    /// cursor value, index or timestamp
+    pub value: i64,
+
+    pub updated_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -16,12 +16,19 @@ pub struct Model {
     /// custom unique username
     pub username: Option<String>,
 
-    /// lndhub password
+    /// legacy plaintext lndhub password, moved to the hashed credentials on the next login
     pub password: Option<String>,
 
     /// donate amount
     pub donate_amount: i64,
 
+    /// LUD-09 success action of the lightning address, json
+    #[sea_orm(column_type = "Text", nullable)]
+    pub success_action: Option<String>,
+
+    /// frozen by the admin, the balance can't be spent, 0 is active
+    pub frozen_at: i64,
+
     /// data create time
     pub created_at: i64,
     pub updated_at: i64,
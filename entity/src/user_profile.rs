@@ -0,0 +1,37 @@
+use sea_orm::entity::prelude::*;
+
+/// Lightning address profile of the user, the unset fields use the global lnurl setting.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Default)]
+#[sea_orm(table_name = "user_profiles")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+
+    pub user_id: i32,
+
+    /// amount limits of the payment in msats
+    pub min_sendable: Option<i64>,
+    pub max_sendable: Option<i64>,
+
+    /// LUD-12 max comment length, 0 is disabled
+    pub comment_allowed: Option<i32>,
+
+    /// short description of the `text/plain` metadata
+    pub description: Option<String>,
+
+    /// `text/long-desc` metadata
+    #[sea_orm(column_type = "Text", nullable)]
+    pub long_description: Option<String>,
+
+    /// `image/png;base64` metadata
+    #[sea_orm(column_type = "Text", nullable)]
+    pub avatar: Option<String>,
+
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,44 @@
+use sea_orm::entity::prelude::*;
+
+/// LUD-03 withdraw links of the user, paid from the user balance, amounts in msats.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "withdraw_links")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+
+    pub user_id: i32,
+
+    /// random secret of the link, hex
+    pub k1: String,
+
+    /// default description of the withdraw invoice
+    pub title: String,
+
+    /// amount limits per use
+    pub min_withdrawable: i64,
+    pub max_withdrawable: i64,
+
+    /// max total amount, 0 is unlimited
+    pub total_cap: i64,
+    /// withdrawn total amount
+    pub withdrawn: i64,
+
+    /// max uses, 1 is single-use, 0 is unlimited
+    pub max_uses: i32,
+    pub uses: i32,
+
+    /// 0 is never expired
+    pub expires_at: i64,
+    /// 0 is active
+    pub revoked_at: i64,
+    pub last_used_at: i64,
+
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -6,6 +6,7 @@ fn main() -> std::io::Result<()> {
         "proto/lnd/peersrpc/peers.proto",
         "proto/lnd/verrpc/verrpc.proto",
         "proto/lnd/routerrpc/router.proto",
+        "proto/lnd/invoicesrpc/invoices.proto",
     ];
 
     tonic_build::configure()
@@ -14,7 +15,7 @@ fn main() -> std::io::Result<()> {
         .build_server(false)
         .compile(&lnd, &["proto/lnd"])?;
 
-    let cln = ["proto/cln/node.proto"].to_vec();
+    let cln = ["proto/cln/node.proto", "proto/cln/hold.proto"].to_vec();
     tonic_build::configure()
         .protoc_arg("--experimental_allow_proto3_optional")
         .build_client(true)
@@ -1,10 +1,11 @@
 //! cln v23.05.2 grpc api
 
-use crate::{lightning::*, Error, Result};
+use crate::{lightning::*, sha256, Error, Result};
+use futures::StreamExt;
 use rand::RngCore;
 use std::{path::Path, time::Duration};
 use tokio::fs;
-use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
 
 pub mod cln {
     #![allow(clippy::all)]
@@ -12,9 +13,19 @@ pub mod cln {
 }
 use cln::{node_client::NodeClient, *};
 
+pub mod hold {
+    #![allow(clippy::all)]
+    tonic::include_proto!("hold");
+}
+use hold::hold_client::HoldClient;
+
 #[derive(Clone, Debug)]
 pub struct Cln {
     node: NodeClient<Channel>,
+    /// client without request timeout for long polling
+    wait: NodeClient<Channel>,
+    /// hold invoice plugin
+    hold: Option<HoldClient<Channel>>,
 }
 
 impl Cln {
@@ -52,27 +63,79 @@ impl Cln {
         TF: AsRef<[u8]>,
         KF: AsRef<[u8]>,
     {
-        let ca = Certificate::from_pem(&ca);
-        let ident = Identity::from_pem(&client_pem, &client_key);
-
-        let tls = ClientTlsConfig::new()
-            .domain_name("cln")
-            .identity(ident)
-            .ca_certificate(ca);
-
-        let mut endpoint = Channel::from_shared(url)?;
+        let mut endpoint = endpoint(url, "cln", ca, client_pem, client_key)?;
+        let wait = endpoint.connect_lazy();
         if let Some(timeout) = timeout {
             endpoint = endpoint.timeout(timeout);
         }
 
-        let channel = endpoint.tls_config(tls)?.connect().await?;
+        let channel = endpoint.connect().await?;
 
         Ok(Self {
             node: NodeClient::new(channel),
+            wait: NodeClient::new(wait),
+            hold: None,
         })
     }
+
+    /// connect the grpc of the hold plugin for hold invoices
+    pub async fn with_hold<CF, TF, KF>(
+        mut self,
+        url: String,
+        ca_file: CF,
+        client_file: TF,
+        client_key_file: KF,
+        timeout: Option<Duration>,
+    ) -> Result<Self>
+    where
+        CF: AsRef<Path>,
+        TF: AsRef<Path>,
+        KF: AsRef<Path>,
+    {
+        let ca = fs::read(ca_file.as_ref()).await?;
+        let client_pem = fs::read(client_file.as_ref()).await?;
+        let client_key = fs::read(client_key_file.as_ref()).await?;
+        let mut endpoint = endpoint(url, "hold", ca, client_pem, client_key)?;
+        if let Some(timeout) = timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        self.hold = Some(HoldClient::new(endpoint.connect().await?));
+        Ok(self)
+    }
+
+    fn hold(&self) -> Result<HoldClient<Channel>> {
+        self.hold
+            .clone()
+            .ok_or_else(|| Error::Unsupported("the hold plugin is not connected".to_owned()))
+    }
 }
 
+fn endpoint<CF, TF, KF>(
+    url: String,
+    domain: &str,
+    ca: CF,
+    client_pem: TF,
+    client_key: KF,
+) -> Result<Endpoint>
+where
+    CF: AsRef<[u8]>,
+    TF: AsRef<[u8]>,
+    KF: AsRef<[u8]>,
+{
+    let ca = Certificate::from_pem(&ca);
+    let ident = Identity::from_pem(&client_pem, &client_key);
+
+    let tls = ClientTlsConfig::new()
+        .domain_name(domain)
+        .identity(ident)
+        .ca_certificate(ca);
+
+    Ok(Channel::from_shared(url)?.tls_config(tls)?)
+}
+
+/// default relative expiry of the bolt12 invoices, 2 hours
+const BOLT12_EXPIRY: u64 = 7200;
+
 fn amount_or_any(msat: u64) -> Option<AmountOrAny> {
     Some(AmountOrAny {
         value: Some(amount_or_any::Value::Amount(amount(msat))),
@@ -117,6 +180,26 @@ impl Lightning for Cln {
         })
     }
 
+    async fn channel_balance(&self) -> Result<u64> {
+        let data = self
+            .node
+            .clone()
+            .list_funds(ListfundsRequest { spent: None })
+            .await?
+            .into_inner();
+        Ok(data
+            .channels
+            .iter()
+            .filter(|c| c.state() == ChannelState::ChanneldNormal)
+            .map(|c| {
+                c.our_amount_msat
+                    .as_ref()
+                    .map(|m| m.msat)
+                    .unwrap_or_default()
+            })
+            .sum())
+    }
+
     async fn create_invoice(
         &self,
         memo: String,
@@ -176,17 +259,70 @@ impl Lightning for Cln {
         // })
     }
 
+    async fn create_hold_invoice(
+        &self,
+        memo: String,
+        msats: u64,
+        payment_hash: Vec<u8>,
+        expiry: Option<u64>,
+    ) -> Result<Invoice> {
+        let data = self
+            .hold()?
+            .invoice(hold::InvoiceRequest {
+                payment_hash,
+                amount_msat: msats,
+                description: Some(hold::invoice_request::Description::Hash(sha256(&memo))),
+                expiry,
+                min_final_cltv_expiry: None,
+            })
+            .await?
+            .into_inner();
+
+        let mut invoice = Invoice::from_bolt11(data.bolt11)?;
+        invoice.description = Some(memo);
+        invoice.status = InvoiceStatus::Open;
+        Ok(invoice)
+    }
+
+    async fn settle_hold_invoice(&self, preimage: Vec<u8>) -> Result<()> {
+        self.hold()?
+            .settle(hold::SettleRequest {
+                payment_preimage: preimage,
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn cancel_hold_invoice(&self, payment_hash: Vec<u8>) -> Result<()> {
+        self.hold()?
+            .cancel(hold::CancelRequest { payment_hash })
+            .await?;
+        Ok(())
+    }
+
     async fn lookup_invoice(&self, payment_hash: Vec<u8>) -> Result<Invoice> {
         let data = self
             .node
             .clone()
             .list_invoices(ListinvoicesRequest {
-                payment_hash: Some(payment_hash),
+                payment_hash: Some(payment_hash.clone()),
                 ..Default::default()
             })
             .await?
             .into_inner();
         if data.invoices.is_empty() {
+            // hold invoices are kept by the plugin
+            if let Some(mut hold) = self.hold.clone() {
+                let data = hold
+                    .list(hold::ListRequest {
+                        constraint: Some(hold::list_request::Constraint::PaymentHash(payment_hash)),
+                    })
+                    .await?
+                    .into_inner();
+                if let Some(inv) = data.invoices.into_iter().next() {
+                    return map_hold_invoice(inv);
+                }
+            }
             return Err(Error::InvoiceNotFound);
         }
         map_invoice(data.invoices[0].clone())
@@ -248,6 +384,87 @@ impl Lightning for Cln {
         Ok(data.payment_hash)
     }
 
+    async fn keysend(
+        &self,
+        _pubkey: Vec<u8>,
+        _preimage: Vec<u8>,
+        _msats: u64,
+        _tlv_records: Vec<(u64, Vec<u8>)>,
+        _max_fee_msat: Option<u64>,
+    ) -> Result<Vec<u8>> {
+        // the keysend rpc generates the preimage, the payment hash is unknown before sending
+        Err(Error::Unsupported(
+            "cln keysend doesn't accept the preimage".to_owned(),
+        ))
+    }
+
+    async fn create_offer(&self, description: String, msats: Option<u64>) -> Result<Offer> {
+        let data = self
+            .node
+            .clone()
+            .offer(OfferRequest {
+                amount: msats
+                    .map(|m| format!("{}msat", m))
+                    .unwrap_or_else(|| "any".to_owned()),
+                description: description.clone(),
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        Ok(Offer {
+            id: data.offer_id,
+            bolt12: data.bolt12,
+            description,
+            amount: msats.unwrap_or_default(),
+        })
+    }
+
+    async fn fetch_invoice(&self, offer: String, msats: Option<u64>) -> Result<Invoice> {
+        let bolt12 = self
+            .node
+            .clone()
+            .fetch_invoice(FetchinvoiceRequest {
+                offer,
+                amount_msat: msats.map(amount),
+                ..Default::default()
+            })
+            .await?
+            .into_inner()
+            .invoice;
+        let data = self
+            .node
+            .clone()
+            .decode(DecodeRequest {
+                string: bolt12.clone(),
+            })
+            .await?
+            .into_inner();
+        if !data.valid {
+            return Err(Error::Invalid("invalid bolt12 invoice".to_owned()));
+        }
+        Ok(Invoice {
+            bolt11: bolt12,
+            payee: data
+                .invoice_node_id
+                .ok_or_else(|| Error::Invalid("missing node_id".to_owned()))?,
+            payment_hash: data
+                .invoice_payment_hash
+                .ok_or_else(|| Error::Invalid("missing payment_hash".to_owned()))?,
+            description: data.offer_description,
+            amount: data
+                .invoice_amount_msat
+                .map(|m| m.msat)
+                .ok_or_else(|| Error::Invalid("missing amount".to_owned()))?,
+            created_at: data.invoice_created_at.unwrap_or_default(),
+            expiry: data
+                .invoice_relative_expiry
+                .map(|e| e as u64)
+                .unwrap_or(BOLT12_EXPIRY),
+            status: InvoiceStatus::Open,
+            ..Default::default()
+        })
+    }
+
     async fn lookup_payment(&self, payment_hash: Vec<u8>) -> Result<Payment> {
         let data = self
             .node
@@ -277,6 +494,42 @@ impl Lightning for Cln {
             .into_inner();
         Ok(data.payments.into_iter().map(map_payment).collect())
     }
+
+    async fn subscribe_invoices(&self, from_index: u64) -> Result<InvoiceStream> {
+        let stream = futures::stream::try_unfold(
+            (self.wait.clone(), from_index),
+            |(mut node, index)| async move {
+                let data = node
+                    .wait_any_invoice(WaitanyinvoiceRequest {
+                        lastpay_index: Some(index),
+                        timeout: None,
+                    })
+                    .await?
+                    .into_inner();
+                let invoice = if data.bolt12.is_some() {
+                    // the offer of the invoice is only in the list
+                    let list = node
+                        .list_invoices(ListinvoicesRequest {
+                            payment_hash: Some(data.payment_hash),
+                            ..Default::default()
+                        })
+                        .await?
+                        .into_inner();
+                    map_invoice(
+                        list.invoices
+                            .into_iter()
+                            .next()
+                            .ok_or(Error::InvoiceNotFound)?,
+                    )?
+                } else {
+                    map_wait_invoice(data)?
+                };
+                let index = invoice.pay_index;
+                Ok(Some((invoice, (node, index))))
+            },
+        );
+        Ok(stream.boxed())
+    }
 }
 
 fn map_invoice(inv: ListinvoicesInvoices) -> Result<Invoice> {
@@ -286,14 +539,105 @@ fn map_invoice(inv: ListinvoicesInvoices) -> Result<Invoice> {
         listinvoices_invoices::ListinvoicesInvoicesStatus::Expired => InvoiceStatus::Canceled,
     };
 
-    let mut invoice = Invoice::from_bolt11(
-        inv.bolt11
-            .ok_or_else(|| Error::Invalid("missing bolt11".to_owned()))?,
-    )?;
+    let paid_amount = inv.amount_received_msat.map(|m| m.msat).unwrap_or_default();
+    let mut invoice = if is_keysend(&inv.label) {
+        keysend_invoice(inv.payment_hash, inv.description, paid_amount, inv.paid_at)
+    } else if let Some(bolt12) = inv.bolt12 {
+        // requested by the offer, the creation time is not returned
+        let created_at = inv.expires_at.saturating_sub(BOLT12_EXPIRY);
+        Invoice {
+            bolt11: bolt12,
+            payment_hash: inv.payment_hash,
+            description: inv.description,
+            amount: inv.amount_msat.map(|m| m.msat).unwrap_or(paid_amount),
+            created_at,
+            expiry: BOLT12_EXPIRY,
+            offer_id: inv.local_offer_id,
+            ..Default::default()
+        }
+    } else {
+        Invoice::from_bolt11(
+            inv.bolt11
+                .ok_or_else(|| Error::Invalid("missing bolt11".to_owned()))?,
+        )?
+    };
+    invoice.index = inv.created_index.unwrap_or_default();
+    invoice.status = status;
+    invoice.paid_at = inv.paid_at.unwrap_or_default();
+    invoice.paid_amount = paid_amount;
+    invoice.pay_index = inv.pay_index.unwrap_or_default();
+    Ok(invoice)
+}
+
+/// the keysend plugin labels the invoices of the received keysend payments
+fn is_keysend(label: &str) -> bool {
+    label.starts_with("keysend-")
+}
+
+/// Invoice of the received keysend payment without bolt11,
+/// cln doesn't return the extra tlv records of the payment by `listinvoices` or `waitanyinvoice`,
+/// the custom records are always empty and the payment can't be routed to a user.
+fn keysend_invoice(
+    payment_hash: Vec<u8>,
+    description: Option<String>,
+    msats: u64,
+    paid_at: Option<u64>,
+) -> Invoice {
+    Invoice {
+        payment_hash,
+        description,
+        amount: msats,
+        created_at: paid_at.unwrap_or_default(),
+        keysend: true,
+        ..Default::default()
+    }
+}
+
+fn map_hold_invoice(inv: hold::Invoice) -> Result<Invoice> {
+    let status = match inv.state() {
+        hold::InvoiceState::Unpaid => InvoiceStatus::Open,
+        hold::InvoiceState::Accepted => InvoiceStatus::Accepted,
+        hold::InvoiceState::Paid => InvoiceStatus::Paid,
+        hold::InvoiceState::Cancelled => InvoiceStatus::Canceled,
+    };
+    let paid_amount = inv
+        .htlcs
+        .iter()
+        .filter(|h| h.state() != hold::InvoiceState::Cancelled)
+        .map(|h| h.msat)
+        .sum();
+    let mut invoice = Invoice::from_bolt11(inv.bolt11)?;
+    invoice.status = status;
+    invoice.paid_at = inv.settled_at.unwrap_or_default();
+    invoice.paid_amount = paid_amount;
+    Ok(invoice)
+}
+
+fn map_wait_invoice(inv: WaitanyinvoiceResponse) -> Result<Invoice> {
+    let status = match inv.status() {
+        waitanyinvoice_response::WaitanyinvoiceStatus::Paid => InvoiceStatus::Paid,
+        waitanyinvoice_response::WaitanyinvoiceStatus::Expired => InvoiceStatus::Canceled,
+    };
+
+    let paid_amount = inv.amount_received_msat.map(|m| m.msat).unwrap_or_default();
+    let mut invoice = if is_keysend(&inv.label) {
+        keysend_invoice(
+            inv.payment_hash,
+            Some(inv.description),
+            paid_amount,
+            inv.paid_at,
+        )
+    } else {
+        Invoice::from_bolt11(
+            inv.bolt11
+                .ok_or_else(|| Error::Invalid("missing bolt11".to_owned()))?,
+        )?
+    };
     invoice.index = inv.created_index.unwrap_or_default();
     invoice.status = status;
     invoice.paid_at = inv.paid_at.unwrap_or_default();
-    invoice.paid_amount = inv.amount_received_msat.map(|m| m.msat).unwrap_or_default();
+    invoice.paid_amount = paid_amount;
+    invoice.pay_index = inv.pay_index.unwrap_or_default();
     Ok(invoice)
 }
 
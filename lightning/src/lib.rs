@@ -24,6 +24,8 @@ pub enum Error {
     PaymentNotFound,
     #[error("invoice not found")]
     InvoiceNotFound,
+    #[error("unsupported: {0}")]
+    Unsupported(String),
 }
 
 impl Error {
@@ -43,6 +45,9 @@ pub use cln::Cln;
 pub mod lnd;
 pub use lnd::Lnd;
 
+pub mod mock;
+pub use mock::Mock;
+
 pub mod lightning;
 pub use lightning::Lightning;
 
@@ -1,8 +1,24 @@
 use crate::{Error, Result};
 use dyn_clone::DynClone;
+use futures::stream::BoxStream;
 use lightning_invoice::SignedRawBolt11Invoice;
 use serde::{Deserialize, Serialize};
 
+/// paid invoices stream
+pub type InvoiceStream = BoxStream<'static, Result<Invoice>>;
+
+/// keysend custom record type of the payment preimage
+pub const KEYSEND_RECORD: u64 = 5482373484;
+/// custom records type starts from, the lower types are reserved by the protocol
+pub const CUSTOM_RECORD_MIN: u64 = 65536;
+
+/// check the string is a bolt12 offer
+pub fn is_offer(s: &str) -> bool {
+    s.get(..4)
+        .map(|p| p.eq_ignore_ascii_case("lno1"))
+        .unwrap_or_default()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Info {
     #[serde(with = "hex::serde")]
@@ -24,6 +40,8 @@ pub enum InvoiceStatus {
     Open = 0, // unpaid
     Paid = 1,
     Canceled = 2,
+    /// the payment of the hold invoice is held, waiting for settle or cancel
+    Accepted = 3,
 }
 
 impl Default for InvoiceStatus {
@@ -35,6 +53,7 @@ impl Default for InvoiceStatus {
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Invoice {
     pub index: u64,
+    /// bolt11 invoice, or the bolt12 invoice of an offer
     pub bolt11: String,
     #[serde(with = "hex::serde")]
     pub payee: Vec<u8>,
@@ -55,9 +74,24 @@ pub struct Invoice {
     pub paid_at: u64,
     /// the amount actually received (could be slightly greater than amount, since clients may overpay), in msats
     pub paid_amount: u64,
+    /// the order the invoice was paid in, lnd settle_index, cln pay_index. 0 if unpaid
+    pub pay_index: u64,
+    /// spontaneous payment received by keysend, without bolt11
+    pub keysend: bool,
+    /// custom tlv records of the keysend payment, the keysend preimage record is excluded,
+    /// always empty on cln
+    pub custom_records: Vec<(u64, Vec<u8>)>,
+    /// the local offer of the invoice created by an invoice request
+    pub offer_id: Option<Vec<u8>>,
 }
 
 impl Invoice {
+    /// Created by the node without a local request,
+    /// the received keysend or the invoice requested by an offer.
+    pub fn is_spontaneous(&self) -> bool {
+        self.keysend || self.offer_id.is_some()
+    }
+
     pub fn from_bolt11(bolt11: String) -> Result<Self> {
         let inv = bolt11.parse::<SignedRawBolt11Invoice>()?;
         let payee = if let Some(key) = inv.payee_pub_key() {
@@ -103,6 +137,17 @@ impl Invoice {
     }
 }
 
+/// reusable bolt12 offer
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Offer {
+    #[serde(with = "hex::serde")]
+    pub id: Vec<u8>,
+    pub bolt12: String,
+    pub description: String,
+    /// amount in msats, 0 is any amount
+    pub amount: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Payment {
     pub id: String,
@@ -143,6 +188,9 @@ impl Default for PaymentStatus {
 pub trait Lightning: DynClone {
     /// get lightning node info
     async fn get_info(&self) -> Result<Info>;
+
+    /// spendable balance of the local side of active channels in msats
+    async fn channel_balance(&self) -> Result<u64>;
     /// create an invoice
     async fn create_invoice(
         &self,
@@ -152,6 +200,22 @@ pub trait Lightning: DynClone {
         expiry: Option<u64>,
     ) -> Result<Invoice>;
 
+    /// Create a hold invoice by the payment hash, the payment is held when accepted
+    /// until settled by the preimage or canceled.
+    async fn create_hold_invoice(
+        &self,
+        memo: String,
+        msats: u64,
+        payment_hash: Vec<u8>,
+        expiry: Option<u64>,
+    ) -> Result<Invoice>;
+
+    /// settle the accepted hold invoice by revealing the preimage
+    async fn settle_hold_invoice(&self, preimage: Vec<u8>) -> Result<()>;
+
+    /// cancel the hold invoice, the held payment is returned to the payer
+    async fn cancel_hold_invoice(&self, payment_hash: Vec<u8>) -> Result<()>;
+
     /// lookup invoice
     async fn lookup_invoice(&self, payment_hash: Vec<u8>) -> Result<Invoice>;
 
@@ -168,11 +232,36 @@ pub trait Lightning: DynClone {
     /// pay faild if lookup payment [`Error::PaymentNotFound`]
     async fn pay(&self, bolt11: String, max_fee_msat: Option<u64>) -> Result<Vec<u8>>;
 
+    /// Send a spontaneous payment to the node by keysend with the custom tlv records,
+    /// the caller generates the preimage to know the payment hash before sending,
+    /// return payment hash. Need check payment status by `lookup_payment` like `pay`.
+    async fn keysend(
+        &self,
+        pubkey: Vec<u8>,
+        preimage: Vec<u8>,
+        msats: u64,
+        tlv_records: Vec<(u64, Vec<u8>)>,
+        max_fee_msat: Option<u64>,
+    ) -> Result<Vec<u8>>;
+
+    /// Create a reusable bolt12 offer, any amount if msats is none.
+    /// Invoices requested by the offer are returned with the `offer_id`.
+    async fn create_offer(&self, description: String, msats: Option<u64>) -> Result<Offer>;
+
+    /// Request an invoice from the bolt12 offer, the amount is required if the offer has no amount.
+    /// The returned invoice can be paid by `pay`.
+    async fn fetch_invoice(&self, offer: String, msats: Option<u64>) -> Result<Invoice>;
+
     /// lookup payment, The data is unreliable until completion (successed or failed).
     async fn lookup_payment(&self, payment_hash: Vec<u8>) -> Result<Payment>;
 
     /// list payments by creation time
     async fn list_payments(&self, from: Option<u64>, to: Option<u64>) -> Result<Vec<Payment>>;
+
+    /// subscribe paid invoices with pay index greater than `from_index`,
+    /// invoices paid before the subscription are replayed first.
+    /// lnd use settle_index, cln use pay_index
+    async fn subscribe_invoices(&self, from_index: u64) -> Result<InvoiceStream>;
 }
 
 dyn_clone::clone_trait_object!(Lightning);
@@ -1,6 +1,7 @@
 //! lnd v0.16.4-beta grpc api
 
 use crate::{lightning::*, sha256, Error, Result};
+use futures::StreamExt;
 use hyper::{
     client::{HttpConnector, ResponseFuture},
     Body, Client, Request, Response, Uri,
@@ -10,7 +11,12 @@ use openssl::{
     ssl::{SslConnector, SslMethod},
     x509::X509,
 };
-use std::{path::Path, task::Poll, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    task::Poll,
+    time::Duration,
+};
 use tokio::fs;
 use tonic::{body::BoxBody, codegen::InterceptedService, Code};
 use tower::{timeout::TimeoutLayer, Service, ServiceBuilder};
@@ -45,6 +51,11 @@ pub mod routerrpc {
     tonic::include_proto!("routerrpc");
 }
 
+pub mod invoicesrpc {
+    #![allow(clippy::all)]
+    tonic::include_proto!("invoicesrpc");
+}
+
 type ChannelType = tower::util::Either<tower::timeout::Timeout<LndChannel>, LndChannel>;
 
 pub type MacaroonChannel = InterceptedService<ChannelType, MacaroonInterceptor>;
@@ -55,15 +66,19 @@ pub type SignerClient = signrpc::signer_client::SignerClient<MacaroonChannel>;
 pub type VersionerClient = verrpc::versioner_client::VersionerClient<MacaroonChannel>;
 pub type WalletKitClient = walletrpc::wallet_kit_client::WalletKitClient<MacaroonChannel>;
 pub type RouterClient = routerrpc::router_client::RouterClient<MacaroonChannel>;
+pub type InvoicesClient = invoicesrpc::invoices_client::InvoicesClient<MacaroonChannel>;
 
 #[derive(Clone, Debug)]
 pub struct Lnd {
     lightning: LightningClient,
+    /// client without request timeout for streaming
+    subscriber: LightningClient,
     wallet: WalletKitClient,
     signer: SignerClient,
     peers: PeersClient,
     version: VersionerClient,
     router: RouterClient,
+    invoices: InvoicesClient,
 }
 
 impl Lnd {
@@ -97,6 +112,11 @@ impl Lnd {
         &mut self.router
     }
 
+    /// Returns the invoices client.
+    pub fn invoices(&mut self) -> &mut InvoicesClient {
+        &mut self.invoices
+    }
+
     pub async fn connect<CF, MF>(
         url: String,
         cert_file: CF,
@@ -128,11 +148,18 @@ impl Lnd {
         };
 
         let channel = LndChannel::new(cert.as_ref(), uri).await?;
+        let subscriber = ServiceBuilder::new()
+            .option_layer(None::<TimeoutLayer>)
+            .service(channel.clone());
         let channel = ServiceBuilder::new()
             .option_layer(timeout.map(TimeoutLayer::new))
             .service(channel);
 
         Ok(Self {
+            subscriber: lnrpc::lightning_client::LightningClient::with_interceptor(
+                subscriber,
+                interceptor.clone(),
+            ),
             lightning: lnrpc::lightning_client::LightningClient::with_interceptor(
                 channel.clone(),
                 interceptor.clone(),
@@ -153,7 +180,14 @@ impl Lnd {
                 channel.clone(),
                 interceptor.clone(),
             ),
-            router: routerrpc::router_client::RouterClient::with_interceptor(channel, interceptor),
+            router: routerrpc::router_client::RouterClient::with_interceptor(
+                channel.clone(),
+                interceptor.clone(),
+            ),
+            invoices: invoicesrpc::invoices_client::InvoicesClient::with_interceptor(
+                channel,
+                interceptor,
+            ),
         })
     }
 }
@@ -255,6 +289,16 @@ impl Lightning for Lnd {
         })
     }
 
+    async fn channel_balance(&self) -> Result<u64> {
+        let data = self
+            .lightning
+            .clone()
+            .channel_balance(lnrpc::ChannelBalanceRequest {})
+            .await?
+            .into_inner();
+        Ok(data.local_balance.map(|b| b.msat).unwrap_or_default())
+    }
+
     async fn create_invoice(
         &self,
         memo: String,
@@ -308,6 +352,49 @@ impl Lightning for Lnd {
         // })
     }
 
+    async fn create_hold_invoice(
+        &self,
+        memo: String,
+        msats: u64,
+        payment_hash: Vec<u8>,
+        expiry: Option<u64>,
+    ) -> Result<Invoice> {
+        let data = self
+            .invoices
+            .clone()
+            .add_hold_invoice(invoicesrpc::AddHoldInvoiceRequest {
+                description_hash: sha256(&memo),
+                hash: payment_hash,
+                value_msat: msats as i64,
+                expiry: expiry.unwrap_or_default() as i64,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+
+        let mut invoice = Invoice::from_bolt11(data.payment_request)?;
+        invoice.index = data.add_index;
+        invoice.description = Some(memo);
+        invoice.status = InvoiceStatus::Open;
+        Ok(invoice)
+    }
+
+    async fn settle_hold_invoice(&self, preimage: Vec<u8>) -> Result<()> {
+        self.invoices
+            .clone()
+            .settle_invoice(invoicesrpc::SettleInvoiceMsg { preimage })
+            .await?;
+        Ok(())
+    }
+
+    async fn cancel_hold_invoice(&self, payment_hash: Vec<u8>) -> Result<()> {
+        self.invoices
+            .clone()
+            .cancel_invoice(invoicesrpc::CancelInvoiceMsg { payment_hash })
+            .await?;
+        Ok(())
+    }
+
     async fn lookup_invoice(&self, payment_hash: Vec<u8>) -> Result<Invoice> {
         let data = self
             .lightning
@@ -376,6 +463,62 @@ impl Lightning for Lnd {
         Ok(data.payment_hash)
     }
 
+    async fn keysend(
+        &self,
+        pubkey: Vec<u8>,
+        preimage: Vec<u8>,
+        msats: u64,
+        tlv_records: Vec<(u64, Vec<u8>)>,
+        max_fee_msat: Option<u64>,
+    ) -> Result<Vec<u8>> {
+        let payment_hash = sha256(&preimage);
+        let mut dest_custom_records = tlv_records.into_iter().collect::<HashMap<_, _>>();
+        dest_custom_records.insert(KEYSEND_RECORD, preimage);
+
+        let mut stream = self
+            .router
+            .clone()
+            .send_payment_v2(routerrpc::SendPaymentRequest {
+                dest: pubkey,
+                amt_msat: msats as i64,
+                payment_hash: payment_hash.clone(),
+                dest_custom_records,
+                dest_features: vec![lnrpc::FeatureBit::TlvOnionOpt as i32],
+                fee_limit_msat: max_fee_msat.map(|f| f as i64).unwrap_or(i64::MAX),
+                timeout_seconds: 60,
+                no_inflight_updates: true,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+
+        // the final update of the payment
+        while let Some(payment) = stream.message().await? {
+            match payment.status() {
+                lnrpc::payment::PaymentStatus::Succeeded => return Ok(payment_hash),
+                lnrpc::payment::PaymentStatus::Failed => {
+                    return Err(Error::Message(
+                        payment.failure_reason().as_str_name().to_lowercase(),
+                    ))
+                }
+                _ => {}
+            }
+        }
+        Ok(payment_hash)
+    }
+
+    async fn create_offer(&self, _description: String, _msats: Option<u64>) -> Result<Offer> {
+        Err(Error::Unsupported(
+            "lnd doesn't support bolt12 offers".to_owned(),
+        ))
+    }
+
+    async fn fetch_invoice(&self, _offer: String, _msats: Option<u64>) -> Result<Invoice> {
+        Err(Error::Unsupported(
+            "lnd doesn't support bolt12 offers".to_owned(),
+        ))
+    }
+
     async fn lookup_payment(&self, payment_hash: Vec<u8>) -> Result<Payment> {
         let mut stream = self
             .router
@@ -430,20 +573,68 @@ impl Lightning for Lnd {
         }
         Ok(list)
     }
+
+    async fn subscribe_invoices(&self, from_index: u64) -> Result<InvoiceStream> {
+        let stream = self
+            .subscriber
+            .clone()
+            .subscribe_invoices(lnrpc::InvoiceSubscription {
+                settle_index: from_index,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        // ignore the added invoices
+        Ok(stream
+            .filter_map(|inv| async move {
+                match inv {
+                    Ok(inv) if inv.state() == lnrpc::invoice::InvoiceState::Settled => {
+                        Some(map_invoice(inv))
+                    }
+                    Ok(_) => None,
+                    Err(e) => Some(Err(e.into())),
+                }
+            })
+            .boxed())
+    }
 }
 
 fn map_invoice(data: lnrpc::Invoice) -> Result<Invoice> {
     let status = match data.state() {
         lnrpc::invoice::InvoiceState::Canceled => InvoiceStatus::Canceled,
         lnrpc::invoice::InvoiceState::Settled => InvoiceStatus::Paid,
-        _ => InvoiceStatus::Open,
+        lnrpc::invoice::InvoiceState::Accepted => InvoiceStatus::Accepted,
+        lnrpc::invoice::InvoiceState::Open => InvoiceStatus::Open,
     };
 
-    let mut invoice = Invoice::from_bolt11(data.payment_request)?;
+    let mut invoice = if data.is_keysend {
+        // merge the records of the htlcs
+        let records = data
+            .htlcs
+            .iter()
+            .flat_map(|h| h.custom_records.iter())
+            .filter(|(t, _)| **t != KEYSEND_RECORD)
+            .map(|(t, v)| (*t, v.clone()))
+            .collect::<BTreeMap<_, _>>();
+        Invoice {
+            payment_hash: data.r_hash,
+            description: Some(data.memo),
+            expiry: data.expiry as u64,
+            amount: data.amt_paid_msat as u64,
+            created_at: data.creation_date as u64,
+            cltv_expiry: data.cltv_expiry,
+            keysend: true,
+            custom_records: records.into_iter().collect(),
+            ..Default::default()
+        }
+    } else {
+        Invoice::from_bolt11(data.payment_request)?
+    };
     invoice.index = data.add_index;
     invoice.status = status;
     invoice.paid_at = data.settle_date as u64;
     invoice.paid_amount = data.amt_paid_msat as u64;
+    invoice.pay_index = data.settle_index;
     Ok(invoice)
 }
 
@@ -0,0 +1,740 @@
+//! in-process mock lightning node, for tests without regtest nodes.
+//!
+//! Invoices are real signed bolt11 invoices created with a local node key,
+//! invoices and payments are kept in memory, payment results can be scripted.
+
+use crate::{lightning::*, sha256, Error, Result};
+use bitcoin::{
+    hashes::{sha256::Hash as Sha256, Hash},
+    secp256k1::{PublicKey, Secp256k1, SecretKey},
+};
+use futures::{channel::mpsc, StreamExt};
+use ldk::ln::PaymentSecret;
+use lightning_invoice::{Currency, InvoiceBuilder};
+use rand::RngCore;
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// default spendable balance of the mock node, 1 btc in msats
+const DEFAULT_BALANCE: u64 = 100_000_000_000;
+
+/// Scripted result for outgoing payments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayBehavior {
+    /// payment succeeded with the routing fee in msats
+    Succeed { fee: u64 },
+    /// payment failed with the error message
+    Fail(String),
+    /// payment stays in flight until [`Mock::complete_payment`] or [`Mock::fail_payment`]
+    InFlight,
+}
+
+impl Default for PayBehavior {
+    fn default() -> Self {
+        Self::Succeed { fee: 0 }
+    }
+}
+
+/// outgoing keysend, the payee is not in the payment
+#[derive(Debug, Clone)]
+struct Keysend {
+    payee: Vec<u8>,
+    preimage: Vec<u8>,
+    msats: u64,
+    records: Vec<(u64, Vec<u8>)>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// spendable balance in msats
+    balance: u64,
+    invoices: Vec<(Invoice, Vec<u8>)>,
+    payments: Vec<Payment>,
+    keysends: Vec<(Vec<u8>, Keysend)>,
+    behaviors: Vec<(Vec<u8>, PayBehavior)>,
+    default_behavior: PayBehavior,
+    peers: Vec<Mock>,
+    offers: Vec<Offer>,
+    /// last pay index
+    pay_index: u64,
+    subscribers: Vec<mpsc::UnboundedSender<Result<Invoice>>>,
+}
+
+impl State {
+    fn invoice_mut(&mut self, payment_hash: &[u8]) -> Result<&mut (Invoice, Vec<u8>)> {
+        self.invoices
+            .iter_mut()
+            .find(|(inv, _)| inv.payment_hash == payment_hash)
+            .ok_or(Error::InvoiceNotFound)
+    }
+
+    fn payment_mut(&mut self, payment_hash: &[u8]) -> Result<&mut Payment> {
+        self.payments
+            .iter_mut()
+            .find(|p| p.payment_hash == payment_hash)
+            .ok_or(Error::PaymentNotFound)
+    }
+
+    /// Receive a payment for the invoice, return the preimage,
+    /// none if the payment is held by the hold invoice.
+    fn settle(&mut self, payment_hash: &[u8], msats: Option<u64>) -> Result<Option<Vec<u8>>> {
+        let (invoice, preimage) = self.invoice_mut(payment_hash)?;
+        if invoice.status != InvoiceStatus::Open {
+            return Err(Error::Message("invoice is not open".to_owned()));
+        }
+        let msats = msats.unwrap_or(invoice.amount);
+        if msats < invoice.amount {
+            return Err(Error::Message(
+                "amount is less than invoice amount".to_owned(),
+            ));
+        }
+        invoice.paid_amount = msats;
+        // the preimage of the hold invoice is unknown until settled
+        if preimage.is_empty() {
+            invoice.status = InvoiceStatus::Accepted;
+            return Ok(None);
+        }
+        let preimage = preimage.clone();
+        self.paid(payment_hash)?;
+        Ok(Some(preimage))
+    }
+
+    /// mark the invoice paid and notify the subscribers
+    fn paid(&mut self, payment_hash: &[u8]) -> Result<()> {
+        let pay_index = self.pay_index + 1;
+        let (invoice, _) = self.invoice_mut(payment_hash)?;
+        invoice.status = InvoiceStatus::Paid;
+        invoice.paid_at = now();
+        invoice.pay_index = pay_index;
+        let invoice = invoice.clone();
+        self.pay_index = pay_index;
+        self.balance += invoice.paid_amount;
+        // notify subscribers, drop the closed
+        self.subscribers
+            .retain(|tx| tx.unbounded_send(Ok(invoice.clone())).is_ok());
+        Ok(())
+    }
+
+    /// settle the accepted hold invoice by the preimage
+    fn settle_hold(&mut self, preimage: &[u8]) -> Result<()> {
+        let payment_hash = sha256(preimage);
+        let (invoice, stored) = self.invoice_mut(&payment_hash)?;
+        if invoice.status != InvoiceStatus::Accepted {
+            return Err(Error::Message("invoice is not accepted".to_owned()));
+        }
+        *stored = preimage.to_vec();
+        self.paid(&payment_hash)
+    }
+
+    /// cancel the hold invoice, return true if the payment was held
+    fn cancel_hold(&mut self, payment_hash: &[u8]) -> Result<bool> {
+        let (invoice, preimage) = self.invoice_mut(payment_hash)?;
+        if !preimage.is_empty() {
+            return Err(Error::Message("invoice is not a hold invoice".to_owned()));
+        }
+        let accepted = match invoice.status {
+            InvoiceStatus::Open => false,
+            InvoiceStatus::Accepted => true,
+            _ => return Err(Error::Message("invoice is already closed".to_owned())),
+        };
+        invoice.status = InvoiceStatus::Canceled;
+        Ok(accepted)
+    }
+
+    fn behavior(&self, payment_hash: &[u8]) -> PayBehavior {
+        self.behaviors
+            .iter()
+            .find(|(h, _)| h == payment_hash)
+            .map(|(_, b)| b.clone())
+            .unwrap_or_else(|| self.default_behavior.clone())
+    }
+
+    /// complete the in flight payment with the preimage and the routing fee
+    fn complete_payment(&mut self, payment_hash: &[u8], preimage: Vec<u8>, fee: u64) -> Result<()> {
+        let payment = self.payment_mut(payment_hash)?;
+        payment.status = PaymentStatus::Succeeded;
+        payment.payment_preimage = preimage;
+        payment.fee = fee;
+        payment.total = payment.amount + fee;
+        self.balance = self.balance.saturating_sub(fee);
+        Ok(())
+    }
+
+    /// fail the in flight payment, return the locked amount
+    fn fail_payment(&mut self, payment_hash: &[u8]) -> Result<()> {
+        let payment = self.payment_mut(payment_hash)?;
+        if payment.status != PaymentStatus::InFlight {
+            return Err(Error::Message("payment is not in flight".to_owned()));
+        }
+        payment.status = PaymentStatus::Failed;
+        let amount = payment.amount;
+        self.balance += amount;
+        Ok(())
+    }
+
+    /// Resolve the payment held by the payee hold invoice,
+    /// succeeded if settled with the preimage, failed if canceled.
+    fn release_held(&mut self, payment_hash: &[u8], preimage: Option<&[u8]>) -> Result<()> {
+        match self.payment_mut(payment_hash) {
+            Ok(p) if p.status == PaymentStatus::InFlight => {}
+            _ => return Ok(()),
+        }
+        match preimage {
+            Some(preimage) => {
+                let fee = match self.behavior(payment_hash) {
+                    PayBehavior::Succeed { fee } => fee,
+                    _ => 0,
+                };
+                self.complete_payment(payment_hash, preimage.to_vec(), fee)
+            }
+            None => self.fail_payment(payment_hash),
+        }
+    }
+
+    /// receive a keysend payment, create a paid invoice without bolt11
+    fn receive_keysend(
+        &mut self,
+        preimage: &[u8],
+        msats: u64,
+        records: Vec<(u64, Vec<u8>)>,
+    ) -> Result<Vec<u8>> {
+        let payment_hash = sha256(preimage);
+        if self.invoice_mut(&payment_hash).is_ok() {
+            return Err(Error::Message("invoice already exists".to_owned()));
+        }
+        let time = now();
+        self.pay_index += 1;
+        let invoice = Invoice {
+            index: self.invoices.len() as u64 + 1,
+            payment_hash,
+            amount: msats,
+            created_at: time,
+            status: InvoiceStatus::Paid,
+            paid_at: time,
+            paid_amount: msats,
+            pay_index: self.pay_index,
+            keysend: true,
+            custom_records: records,
+            ..Default::default()
+        };
+        self.invoices.push((invoice.clone(), preimage.to_vec()));
+        self.balance += msats;
+        self.subscribers
+            .retain(|tx| tx.unbounded_send(Ok(invoice.clone())).is_ok());
+        Ok(preimage.to_vec())
+    }
+}
+
+/// Mock lightning node
+#[derive(Clone, Debug)]
+pub struct Mock {
+    key: SecretKey,
+    id: Vec<u8>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for Mock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mock {
+    /// create a mock node with a random node key
+    pub fn new() -> Self {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let key = SecretKey::from_slice(&bytes).expect("32 bytes, within curve order");
+        let id = PublicKey::from_secret_key(&Secp256k1::new(), &key)
+            .serialize()
+            .to_vec();
+        Self {
+            key,
+            id,
+            state: Arc::new(Mutex::new(State {
+                balance: DEFAULT_BALANCE,
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// node id
+    pub fn id(&self) -> &Vec<u8> {
+        &self.id
+    }
+
+    /// Open a channel with another mock node,
+    /// payments to the invoices of the peer will settle them.
+    pub fn connect_peer(&self, peer: &Mock) {
+        self.lock().peers.push(peer.clone());
+        peer.lock().peers.push(self.clone());
+    }
+
+    /// spendable balance in msats
+    pub fn balance(&self) -> u64 {
+        self.lock().balance
+    }
+
+    pub fn set_balance(&self, msats: u64) {
+        self.lock().balance = msats;
+    }
+
+    /// set the result of the payments without a specific behavior
+    pub fn set_default_pay_behavior(&self, behavior: PayBehavior) {
+        self.lock().default_behavior = behavior;
+    }
+
+    /// set the result of paying the invoice with the payment hash
+    pub fn set_pay_behavior(&self, payment_hash: Vec<u8>, behavior: PayBehavior) {
+        let mut state = self.lock();
+        state.behaviors.retain(|(h, _)| h != &payment_hash);
+        state.behaviors.push((payment_hash, behavior));
+    }
+
+    /// Simulate an external payer paying the invoice.
+    /// Pay the invoice amount if msats is none, overpay if msats greater than the amount.
+    pub fn settle_invoice(&self, payment_hash: &[u8], msats: Option<u64>) -> Result<()> {
+        self.lock().settle(payment_hash, msats)?;
+        Ok(())
+    }
+
+    /// Simulate an external node sending a keysend payment with the custom records,
+    /// return the payment hash.
+    pub fn receive_keysend(&self, msats: u64, records: Vec<(u64, Vec<u8>)>) -> Result<Vec<u8>> {
+        let preimage = rand_bytes();
+        self.lock().receive_keysend(&preimage, msats, records)?;
+        Ok(sha256(preimage))
+    }
+
+    /// expire the invoice
+    pub fn cancel_invoice(&self, payment_hash: &[u8]) -> Result<()> {
+        let mut state = self.lock();
+        let (invoice, _) = state.invoice_mut(payment_hash)?;
+        if invoice.status != InvoiceStatus::Open {
+            return Err(Error::Message("invoice is not open".to_owned()));
+        }
+        invoice.status = InvoiceStatus::Canceled;
+        Ok(())
+    }
+
+    /// complete the in flight payment with the routing fee
+    pub fn complete_payment(&self, payment_hash: &[u8], fee: u64) -> Result<()> {
+        let (peer, keysend) = {
+            let mut state = self.lock();
+            let payment = state.payment_mut(payment_hash)?;
+            if payment.status != PaymentStatus::InFlight {
+                return Err(Error::Message("payment is not in flight".to_owned()));
+            }
+            let bolt11 = payment.bolt11.clone();
+            match state.keysends.iter().find(|(h, _)| h == payment_hash) {
+                Some((_, keysend)) => (find_peer(&state, &keysend.payee), Some(keysend.clone())),
+                None => {
+                    let inv = Invoice::from_bolt11(bolt11)?;
+                    (find_peer(&state, &inv.payee), None)
+                }
+            }
+        };
+        let preimage = settle(peer, payment_hash, keysend.as_ref())?
+            .ok_or_else(|| Error::Message("payment is held by the payee".to_owned()))?;
+        self.lock().complete_payment(payment_hash, preimage, fee)
+    }
+
+    /// fail the in flight payment
+    pub fn fail_payment(&self, payment_hash: &[u8]) -> Result<()> {
+        self.lock().fail_payment(payment_hash)
+    }
+
+    /// Create a signed invoice, requested by the offer if offer_id is some.
+    /// The preimage is empty for the hold invoice.
+    fn new_invoice(
+        &self,
+        memo: String,
+        msats: u64,
+        payment_hash: Vec<u8>,
+        preimage: Vec<u8>,
+        expiry: Option<u64>,
+        offer_id: Option<Vec<u8>>,
+    ) -> Result<Invoice> {
+        let payment_secret: [u8; 32] = rand_bytes()
+            .try_into()
+            .map_err(|_| Error::Invalid("payment secret".to_owned()))?;
+
+        let signed = InvoiceBuilder::new(Currency::Regtest)
+            .description_hash(Sha256::from_slice(&sha256(&memo)).map_err(Error::from)?)
+            .payment_hash(Sha256::from_slice(&payment_hash).map_err(Error::from)?)
+            .payment_secret(PaymentSecret(payment_secret))
+            .amount_milli_satoshis(msats)
+            .expiry_time(Duration::from_secs(
+                expiry.unwrap_or(lightning_invoice::DEFAULT_EXPIRY_TIME),
+            ))
+            .current_timestamp()
+            .min_final_cltv_expiry_delta(lightning_invoice::DEFAULT_MIN_FINAL_CLTV_EXPIRY_DELTA)
+            .build_signed(|hash| Secp256k1::new().sign_ecdsa_recoverable(hash, &self.key))
+            .map_err(Error::from)?;
+
+        let mut invoice = Invoice::from_bolt11(signed.to_string())?;
+        invoice.description = Some(memo);
+        invoice.status = InvoiceStatus::Open;
+        invoice.offer_id = offer_id;
+
+        let mut state = self.lock();
+        if state.invoice_mut(&invoice.payment_hash).is_ok() {
+            return Err(Error::Message("invoice already exists".to_owned()));
+        }
+        invoice.index = state.invoices.len() as u64 + 1;
+        state.invoices.push((invoice.clone(), preimage));
+        Ok(invoice)
+    }
+
+    /// Send the payment of the invoice or keysend by the behavior,
+    /// settle the payment on the peer node when succeeded.
+    fn send(
+        &self,
+        payee: &[u8],
+        payment_hash: Vec<u8>,
+        amount: u64,
+        bolt11: String,
+        keysend: Option<Keysend>,
+        max_fee_msat: Option<u64>,
+    ) -> Result<Vec<u8>> {
+        if payee == self.id {
+            return Err(Error::Message("self-payments not allowed".to_owned()));
+        }
+
+        let (behavior, peer) = {
+            let mut state = self.lock();
+            match state.payment_mut(&payment_hash) {
+                Ok(p) if p.status != PaymentStatus::Failed => {
+                    return Err(Error::Message("invoice is already paid".to_owned()));
+                }
+                _ => {}
+            }
+            let behavior = state.behavior(&payment_hash);
+
+            let fee = match &behavior {
+                PayBehavior::Succeed { fee } => *fee,
+                _ => 0,
+            };
+            if max_fee_msat.map(|max| fee > max).unwrap_or_default() {
+                return Err(Error::Message("fee exceeds the limit".to_owned()));
+            }
+            if amount + fee > state.balance {
+                return Err(Error::Message("insufficient_balance".to_owned()));
+            }
+
+            // remove the failed payment for retry
+            state.payments.retain(|p| p.payment_hash != payment_hash);
+            let mut payment = Payment {
+                id: (state.payments.len() + 1).to_string(),
+                bolt11,
+                payment_hash: payment_hash.clone(),
+                amount,
+                total: amount,
+                created_at: now(),
+                status: PaymentStatus::InFlight,
+                ..Default::default()
+            };
+            if let PayBehavior::Fail(_) = behavior {
+                payment.status = PaymentStatus::Failed;
+            } else {
+                // lock the amount
+                state.balance -= amount;
+            }
+            state.payments.push(payment);
+            state.keysends.retain(|(h, _)| h != &payment_hash);
+            if let Some(keysend) = keysend.clone() {
+                state.keysends.push((payment_hash.clone(), keysend));
+            }
+            (behavior, find_peer(&state, payee))
+        };
+
+        match behavior {
+            PayBehavior::Succeed { fee } => match settle(peer, &payment_hash, keysend.as_ref()) {
+                Ok(Some(preimage)) => {
+                    self.lock().complete_payment(&payment_hash, preimage, fee)?;
+                    Ok(payment_hash)
+                }
+                // held by the hold invoice of the payee
+                Ok(None) => Err(Error::Message("payment is in flight".to_owned())),
+                Err(e) => {
+                    self.fail_payment(&payment_hash)?;
+                    Err(e)
+                }
+            },
+            PayBehavior::Fail(message) => Err(Error::Message(message)),
+            PayBehavior::InFlight => Err(Error::Message("payment is in flight".to_owned())),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn rand_bytes() -> Vec<u8> {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.to_vec()
+}
+
+fn find_peer(state: &State, id: &[u8]) -> Option<Arc<Mutex<State>>> {
+    state
+        .peers
+        .iter()
+        .find(|peer| peer.id == id)
+        .map(|peer| peer.state.clone())
+}
+
+/// settle the invoice on the peer node, unknown payee returns a random preimage.
+fn settle_peer(peer: Option<Arc<Mutex<State>>>, payment_hash: &[u8]) -> Result<Option<Vec<u8>>> {
+    match peer {
+        Some(peer) => peer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .settle(payment_hash, None),
+        None => Ok(Some(rand_bytes())),
+    }
+}
+
+/// deliver the keysend to the peer node, unknown payee just returns the preimage.
+fn deliver_keysend(peer: Option<Arc<Mutex<State>>>, keysend: &Keysend) -> Result<Vec<u8>> {
+    match peer {
+        Some(peer) => peer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .receive_keysend(&keysend.preimage, keysend.msats, keysend.records.clone()),
+        None => Ok(keysend.preimage.clone()),
+    }
+}
+
+fn settle(
+    peer: Option<Arc<Mutex<State>>>,
+    payment_hash: &[u8],
+    keysend: Option<&Keysend>,
+) -> Result<Option<Vec<u8>>> {
+    match keysend {
+        Some(keysend) => deliver_keysend(peer, keysend).map(Some),
+        None => settle_peer(peer, payment_hash),
+    }
+}
+
+#[tonic::async_trait]
+impl Lightning for Mock {
+    async fn get_info(&self) -> Result<Info> {
+        let state = self.lock();
+        Ok(Info {
+            id: self.id.clone(),
+            alias: "mock".to_owned(),
+            color: "#000000".to_owned(),
+            num_peers: state.peers.len() as u32,
+            num_pending_channels: 0,
+            num_active_channels: state.peers.len() as u32,
+            num_inactive_channels: 0,
+            version: "mock".to_owned(),
+            block_height: 0,
+        })
+    }
+
+    async fn channel_balance(&self) -> Result<u64> {
+        Ok(self.lock().balance)
+    }
+
+    async fn create_invoice(
+        &self,
+        memo: String,
+        msats: u64,
+        preimage: Option<Vec<u8>>,
+        expiry: Option<u64>,
+    ) -> Result<Invoice> {
+        let preimage = preimage.unwrap_or_else(rand_bytes);
+        self.new_invoice(memo, msats, sha256(&preimage), preimage, expiry, None)
+    }
+
+    async fn create_hold_invoice(
+        &self,
+        memo: String,
+        msats: u64,
+        payment_hash: Vec<u8>,
+        expiry: Option<u64>,
+    ) -> Result<Invoice> {
+        self.new_invoice(memo, msats, payment_hash, vec![], expiry, None)
+    }
+
+    async fn settle_hold_invoice(&self, preimage: Vec<u8>) -> Result<()> {
+        let peers = {
+            let mut state = self.lock();
+            state.settle_hold(&preimage)?;
+            state.peers.clone()
+        };
+        let payment_hash = sha256(&preimage);
+        for peer in peers {
+            peer.lock().release_held(&payment_hash, Some(&preimage))?;
+        }
+        Ok(())
+    }
+
+    async fn cancel_hold_invoice(&self, payment_hash: Vec<u8>) -> Result<()> {
+        let (accepted, peers) = {
+            let mut state = self.lock();
+            (state.cancel_hold(&payment_hash)?, state.peers.clone())
+        };
+        if accepted {
+            for peer in peers {
+                peer.lock().release_held(&payment_hash, None)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn lookup_invoice(&self, payment_hash: Vec<u8>) -> Result<Invoice> {
+        Ok(self.lock().invoice_mut(&payment_hash)?.0.clone())
+    }
+
+    // filter by index like cln
+    async fn list_invoices(
+        &self,
+        from: Option<(u64, u64)>,
+        to: Option<u64>,
+    ) -> Result<Vec<Invoice>> {
+        let start = from.map(|f| f.1).unwrap_or_default();
+        Ok(self
+            .lock()
+            .invoices
+            .iter()
+            .map(|(inv, _)| inv)
+            .filter(|inv| inv.index >= start && to.map(|t| inv.created_at <= t).unwrap_or(true))
+            .cloned()
+            .collect())
+    }
+
+    async fn pay(&self, bolt11: String, max_fee_msat: Option<u64>) -> Result<Vec<u8>> {
+        let inv = Invoice::from_bolt11(bolt11.clone())?;
+        self.send(
+            &inv.payee,
+            inv.payment_hash,
+            inv.amount,
+            bolt11,
+            None,
+            max_fee_msat,
+        )
+    }
+
+    async fn keysend(
+        &self,
+        pubkey: Vec<u8>,
+        preimage: Vec<u8>,
+        msats: u64,
+        tlv_records: Vec<(u64, Vec<u8>)>,
+        max_fee_msat: Option<u64>,
+    ) -> Result<Vec<u8>> {
+        let keysend = Keysend {
+            payee: pubkey.clone(),
+            preimage: preimage.clone(),
+            msats,
+            records: tlv_records,
+        };
+        self.send(
+            &pubkey,
+            sha256(&preimage),
+            msats,
+            String::new(),
+            Some(keysend),
+            max_fee_msat,
+        )
+    }
+
+    async fn create_offer(&self, description: String, msats: Option<u64>) -> Result<Offer> {
+        let id = rand_bytes();
+        // not a real bech32 offer, only resolvable by the connected mock nodes
+        let offer = Offer {
+            bolt12: format!("lno1{}", hex::encode(&id)),
+            id,
+            description,
+            amount: msats.unwrap_or_default(),
+        };
+        self.lock().offers.push(offer.clone());
+        Ok(offer)
+    }
+
+    async fn fetch_invoice(&self, offer: String, msats: Option<u64>) -> Result<Invoice> {
+        let peers = self.lock().peers.clone();
+        let (peer, offer) = peers
+            .into_iter()
+            .find_map(|peer| {
+                let offer = peer
+                    .lock()
+                    .offers
+                    .iter()
+                    .find(|o| o.bolt12 == offer)
+                    .cloned();
+                offer.map(|o| (peer, o))
+            })
+            .ok_or_else(|| Error::Message("offer not found".to_owned()))?;
+        let msats = match (offer.amount, msats) {
+            (0, Some(msats)) => msats,
+            (0, None) => return Err(Error::Message("missing amount".to_owned())),
+            (amount, Some(msats)) if msats < amount => {
+                return Err(Error::Message(
+                    "amount is less than offer amount".to_owned(),
+                ))
+            }
+            (amount, msats) => msats.unwrap_or(amount),
+        };
+        let preimage = rand_bytes();
+        let mut invoice = peer.new_invoice(
+            offer.description,
+            msats,
+            sha256(&preimage),
+            preimage,
+            Some(7200),
+            Some(offer.id),
+        )?;
+        // the offer belongs to the payee
+        invoice.offer_id = None;
+        Ok(invoice)
+    }
+
+    async fn lookup_payment(&self, payment_hash: Vec<u8>) -> Result<Payment> {
+        Ok(self.lock().payment_mut(&payment_hash)?.clone())
+    }
+
+    async fn list_payments(&self, from: Option<u64>, to: Option<u64>) -> Result<Vec<Payment>> {
+        Ok(self
+            .lock()
+            .payments
+            .iter()
+            .filter(|p| {
+                from.map(|f| p.created_at >= f).unwrap_or(true)
+                    && to.map(|t| p.created_at <= t).unwrap_or(true)
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn subscribe_invoices(&self, from_index: u64) -> Result<InvoiceStream> {
+        let (tx, rx) = mpsc::unbounded();
+        let mut state = self.lock();
+        let mut paid = state
+            .invoices
+            .iter()
+            .map(|(inv, _)| inv)
+            .filter(|inv| inv.pay_index > from_index)
+            .cloned()
+            .collect::<Vec<_>>();
+        paid.sort_by_key(|inv| inv.pay_index);
+        for inv in paid {
+            let _r = tx.unbounded_send(Ok(inv));
+        }
+        state.subscribers.push(tx);
+        Ok(rx.boxed())
+    }
+}
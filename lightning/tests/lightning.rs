@@ -1,9 +1,11 @@
 #![allow(unused)]
+use futures::StreamExt;
 use lightning_client::{
     lightning::{InvoiceStatus, PaymentStatus},
     sha256, Error, Lightning, Result,
 };
 use rand::RngCore;
+use std::time::Duration;
 
 pub fn rand_preimage() -> Vec<u8> {
     let mut store_key_bytes = [0u8; 32];
@@ -153,3 +155,72 @@ pub async fn payment_error<L1: Lightning, L2: Lightning>(c1: &L1, c2: &L2) -> Re
     // println!("invoice {:?}", inv);
     Ok(())
 }
+
+pub async fn subscribe_invoices<L1: Lightning, L2: Lightning>(c1: &L1, c2: &L2) -> Result<()> {
+    let expiry = 60 * 10; // 10 minutes
+    let msats = 100_000; // 100 sats
+    let memo = "c1 pay to c2".to_owned();
+    let invoice = c2
+        .create_invoice(memo.clone(), msats, None, Some(expiry))
+        .await?;
+    c1.pay(invoice.bolt11.clone(), None).await?;
+    let inv = c2.lookup_invoice(invoice.payment_hash.clone()).await?;
+    assert!(inv.pay_index > 0);
+
+    // replay paid invoices
+    let mut stream = c2.subscribe_invoices(inv.pay_index - 1).await?;
+    let item = stream.next().await.unwrap()?;
+    assert_eq!(item.payment_hash, invoice.payment_hash);
+    assert_eq!(item.status, InvoiceStatus::Paid);
+    assert_eq!(item.pay_index, inv.pay_index);
+    assert_eq!(item.paid_amount, msats);
+
+    // new paid invoice
+    let invoice = c2
+        .create_invoice(memo.clone(), msats, None, Some(expiry))
+        .await?;
+    c1.pay(invoice.bolt11.clone(), None).await?;
+    let item = tokio::time::timeout(Duration::from_secs(5), stream.next())
+        .await
+        .map_err(|e| Error::Message(e.to_string()))?
+        .unwrap()?;
+    assert_eq!(item.payment_hash, invoice.payment_hash);
+    assert!(item.pay_index > inv.pay_index);
+    Ok(())
+}
+
+pub async fn keysend<L1: Lightning, L2: Lightning>(c1: &L1, c2: &L2) -> Result<()> {
+    let msats = 100_000; // 100 sats
+    let payee = c2.get_info().await?.id;
+    let preimage = rand_preimage();
+    let hash = c1
+        .keysend(
+            payee,
+            preimage.clone(),
+            msats,
+            vec![(696969, b"satsbox".to_vec())],
+            None,
+        )
+        .await?;
+    assert_eq!(hash, sha256(&preimage));
+
+    let payment = c1.lookup_payment(hash.clone()).await?;
+    assert_eq!(payment.status, PaymentStatus::Succeeded);
+    assert_eq!(payment.amount, msats);
+    assert_eq!(sha256(&payment.payment_preimage), hash);
+
+    let inv = c2.lookup_invoice(hash.clone()).await?;
+    assert!(inv.keysend);
+    assert_eq!(inv.status, InvoiceStatus::Paid);
+    assert_eq!(inv.paid_amount, msats);
+    Ok(())
+}
+
+pub async fn keysend_unsupported<L1: Lightning, L2: Lightning>(c1: &L1, c2: &L2) -> Result<()> {
+    let payee = c2.get_info().await?.id;
+    let res = c1
+        .keysend(payee, rand_preimage(), 100_000, vec![], None)
+        .await;
+    assert!(matches!(res, Err(Error::Unsupported(_))));
+    Ok(())
+}
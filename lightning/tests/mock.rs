@@ -0,0 +1,229 @@
+mod lightning;
+use anyhow::Result;
+use lightning_client::{
+    lightning::{InvoiceStatus, PaymentStatus},
+    mock::PayBehavior,
+    Error, Lightning, Mock,
+};
+
+macro_rules! test_method {
+    ($t:ident) => {
+        #[tokio::test]
+        async fn $t() -> Result<()> {
+            let client = Mock::new();
+            lightning::$t(&client).await?;
+            Ok(())
+        }
+    };
+    ($t:ident, $c1:ident, $c2: ident) => {
+        #[tokio::test]
+        async fn $t() -> Result<()> {
+            let $c1 = Mock::new();
+            let $c2 = Mock::new();
+            $c1.connect_peer(&$c2);
+            lightning::$t(&$c1, &$c2).await?;
+            Ok(())
+        }
+    };
+}
+
+test_method!(get_info);
+test_method!(create_invoice);
+test_method!(payment, c1, c2);
+test_method!(payment_error, c1, c2);
+test_method!(subscribe_invoices, c1, c2);
+test_method!(keysend, c1, c2);
+
+#[tokio::test]
+async fn pay_behavior() -> Result<()> {
+    let c1 = Mock::new();
+    let c2 = Mock::new();
+    c1.connect_peer(&c2);
+    let msats = 100_000;
+
+    // failed
+    let invoice = c2
+        .create_invoice("fail".to_owned(), msats, None, Some(600))
+        .await?;
+    c1.set_pay_behavior(
+        invoice.payment_hash.clone(),
+        PayBehavior::Fail("no route".to_owned()),
+    );
+    assert!(c1.pay(invoice.bolt11.clone(), None).await.is_err());
+    let payment = c1.lookup_payment(invoice.payment_hash.clone()).await?;
+    assert_eq!(payment.status, PaymentStatus::Failed);
+    let inv = c2.lookup_invoice(invoice.payment_hash.clone()).await?;
+    assert_eq!(inv.status, InvoiceStatus::Open);
+
+    // fee limit
+    c1.set_pay_behavior(
+        invoice.payment_hash.clone(),
+        PayBehavior::Succeed { fee: 100 },
+    );
+    assert!(c1.pay(invoice.bolt11.clone(), Some(99)).await.is_err());
+
+    // retry after failed
+    let balance = c1.balance();
+    c1.pay(invoice.bolt11.clone(), Some(100)).await?;
+    let payment = c1.lookup_payment(invoice.payment_hash.clone()).await?;
+    assert_eq!(payment.status, PaymentStatus::Succeeded);
+    assert_eq!(payment.fee, 100);
+    assert_eq!(payment.total, msats + 100);
+    assert_eq!(c1.balance(), balance - msats - 100);
+    // repeat
+    assert!(c1.pay(invoice.bolt11.clone(), None).await.is_err());
+
+    // in flight
+    let invoice = c2
+        .create_invoice("in flight".to_owned(), msats, None, Some(600))
+        .await?;
+    c1.set_default_pay_behavior(PayBehavior::InFlight);
+    assert!(c1.pay(invoice.bolt11.clone(), None).await.is_err());
+    let payment = c1.lookup_payment(invoice.payment_hash.clone()).await?;
+    assert_eq!(payment.status, PaymentStatus::InFlight);
+    c1.complete_payment(&invoice.payment_hash, 10)?;
+    let payment = c1.lookup_payment(invoice.payment_hash.clone()).await?;
+    assert_eq!(payment.status, PaymentStatus::Succeeded);
+    assert_eq!(payment.fee, 10);
+    let inv = c2.lookup_invoice(invoice.payment_hash.clone()).await?;
+    assert_eq!(inv.status, InvoiceStatus::Paid);
+
+    let invoice = c2
+        .create_invoice("in flight".to_owned(), msats, None, Some(600))
+        .await?;
+    let balance = c1.balance();
+    assert!(c1.pay(invoice.bolt11.clone(), None).await.is_err());
+    assert_eq!(c1.balance(), balance - msats);
+    c1.fail_payment(&invoice.payment_hash)?;
+    assert_eq!(c1.balance(), balance);
+    let payment = c1.lookup_payment(invoice.payment_hash.clone()).await?;
+    assert_eq!(payment.status, PaymentStatus::Failed);
+    Ok(())
+}
+
+#[tokio::test]
+async fn receive() -> Result<()> {
+    let client = Mock::new();
+    let msats = 100_000;
+    let invoice = client
+        .create_invoice("overpay".to_owned(), msats, None, Some(600))
+        .await?;
+    // underpay
+    assert!(client
+        .settle_invoice(&invoice.payment_hash, Some(msats - 1))
+        .is_err());
+    client.settle_invoice(&invoice.payment_hash, Some(msats + 1000))?;
+    let inv = client.lookup_invoice(invoice.payment_hash.clone()).await?;
+    assert_eq!(inv.status, InvoiceStatus::Paid);
+    assert_eq!(inv.paid_amount, msats + 1000);
+
+    let invoice = client
+        .create_invoice("cancel".to_owned(), msats, None, Some(600))
+        .await?;
+    client.cancel_invoice(&invoice.payment_hash)?;
+    let inv = client.lookup_invoice(invoice.payment_hash.clone()).await?;
+    assert_eq!(inv.status, InvoiceStatus::Canceled);
+    assert!(client.settle_invoice(&invoice.payment_hash, None).is_err());
+
+    let res = client.lookup_invoice(vec![0; 32]).await;
+    assert!(matches!(res, Err(Error::InvoiceNotFound)));
+
+    let hash = client.receive_keysend(msats, vec![(696969, b"alice".to_vec())])?;
+    let inv = client.lookup_invoice(hash).await?;
+    assert!(inv.keysend);
+    assert!(inv.bolt11.is_empty());
+    assert_eq!(inv.paid_amount, msats);
+    assert_eq!(inv.custom_records, vec![(696969, b"alice".to_vec())]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn offer() -> Result<()> {
+    let c1 = Mock::new();
+    let c2 = Mock::new();
+    c1.connect_peer(&c2);
+    let msats = 100_000;
+
+    let offer = c1.create_offer("any amount".to_owned(), None).await?;
+    assert!(lightning_client::lightning::is_offer(&offer.bolt12));
+    assert_eq!(offer.amount, 0);
+    assert!(c2.fetch_invoice(offer.bolt12.clone(), None).await.is_err());
+    assert!(c2
+        .fetch_invoice("lno1unknown".to_owned(), Some(msats))
+        .await
+        .is_err());
+
+    let invoice = c2.fetch_invoice(offer.bolt12.clone(), Some(msats)).await?;
+    assert_eq!(invoice.amount, msats);
+    assert_eq!(&invoice.payee, c1.id());
+    assert_eq!(invoice.offer_id, None);
+    c2.pay(invoice.bolt11, None).await?;
+
+    let inv = c1.lookup_invoice(invoice.payment_hash).await?;
+    assert_eq!(inv.status, InvoiceStatus::Paid);
+    assert_eq!(inv.offer_id, Some(offer.id));
+    assert!(inv.is_spontaneous());
+
+    // fixed amount
+    let offer = c1.create_offer("fixed".to_owned(), Some(msats)).await?;
+    assert!(c2
+        .fetch_invoice(offer.bolt12.clone(), Some(msats - 1))
+        .await
+        .is_err());
+    let invoice = c2.fetch_invoice(offer.bolt12, None).await?;
+    assert_eq!(invoice.amount, msats);
+    Ok(())
+}
+
+#[tokio::test]
+async fn hold_invoice() -> Result<()> {
+    let c1 = Mock::new();
+    let c2 = Mock::new();
+    c1.connect_peer(&c2);
+    let msats = 100_000;
+    let balance = c2.balance();
+
+    // settle
+    let preimage = vec![1; 32];
+    let hash = lightning_client::sha256(&preimage);
+    let invoice = c1
+        .create_hold_invoice("hold".to_owned(), msats, hash.clone(), Some(600))
+        .await?;
+    assert_eq!(invoice.payment_hash, hash);
+    assert!(c1.settle_hold_invoice(preimage.clone()).await.is_err());
+    assert!(c2.pay(invoice.bolt11, None).await.is_err());
+    let inv = c1.lookup_invoice(hash.clone()).await?;
+    assert_eq!(inv.status, InvoiceStatus::Accepted);
+    let payment = c2.lookup_payment(hash.clone()).await?;
+    assert_eq!(payment.status, PaymentStatus::InFlight);
+
+    c1.settle_hold_invoice(preimage.clone()).await?;
+    let inv = c1.lookup_invoice(hash.clone()).await?;
+    assert_eq!(inv.status, InvoiceStatus::Paid);
+    assert_eq!(inv.paid_amount, msats);
+    let payment = c2.lookup_payment(hash).await?;
+    assert_eq!(payment.status, PaymentStatus::Succeeded);
+    assert_eq!(payment.payment_preimage, preimage);
+    assert_eq!(c2.balance(), balance - msats);
+
+    // cancel the held payment
+    let hash = lightning_client::sha256([2; 32]);
+    let invoice = c1
+        .create_hold_invoice("hold".to_owned(), msats, hash.clone(), Some(600))
+        .await?;
+    assert!(c2.pay(invoice.bolt11, None).await.is_err());
+    c1.cancel_hold_invoice(hash.clone()).await?;
+    let inv = c1.lookup_invoice(hash.clone()).await?;
+    assert_eq!(inv.status, InvoiceStatus::Canceled);
+    let payment = c2.lookup_payment(hash.clone()).await?;
+    assert_eq!(payment.status, PaymentStatus::Failed);
+    assert_eq!(c2.balance(), balance - msats);
+    assert!(c1.cancel_hold_invoice(hash).await.is_err());
+
+    // normal invoices can't be canceled as hold
+    let invoice = c1
+        .create_invoice("normal".to_owned(), msats, None, Some(600))
+        .await?;
+    assert!(c1.cancel_hold_invoice(invoice.payment_hash).await.is_err());
+    Ok(())
+}
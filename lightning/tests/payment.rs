@@ -19,10 +19,14 @@ mod cln_to_lnd {
     use super::*;
     test_method!(payment, connect_cln, connect_lnd);
     test_method!(payment_error, connect_cln, connect_lnd);
+    test_method!(subscribe_invoices, connect_cln, connect_lnd);
+    test_method!(keysend_unsupported, connect_cln, connect_lnd);
 }
 
 mod lnd_to_cln {
     use super::*;
     test_method!(payment, connect_lnd, connect_cln);
     test_method!(payment_error, connect_lnd, connect_cln);
+    test_method!(subscribe_invoices, connect_lnd, connect_cln);
+    test_method!(keysend, connect_lnd, connect_cln);
 }
@@ -5,6 +5,22 @@ mod m20230714_040219_create_invoice_table;
 mod m20230804_082552_create_record_table;
 mod m20230822_184929_create_event_table;
 mod m20230828_220838_create_donation_table;
+mod m20231020_093012_create_sync_state_table;
+mod m20231021_101530_create_posting_table;
+mod m20231023_083417_create_nwc_connection_table;
+mod m20231105_061842_create_offer_table;
+mod m20231107_032516_add_hold_to_invoice_table;
+mod m20231109_024127_create_withdraw_link_table;
+mod m20231111_083042_create_auth_identity_table;
+mod m20231113_061205_add_success_action_to_user_table;
+mod m20231114_075220_create_user_profile_table;
+mod m20231116_021433_create_lndhub_credential_table;
+mod m20231117_093108_create_session_table;
+mod m20231119_044517_create_auth_event_table;
+mod m20231121_030642_create_api_key_table;
+mod m20231122_081327_add_frozen_at_to_user_table;
+mod m20231123_024815_post_opening_balances;
+mod m20231123_091204_create_budget_hold_table;
 
 pub struct Migrator;
 
@@ -17,6 +33,22 @@ impl MigratorTrait for Migrator {
             Box::new(m20230804_082552_create_record_table::Migration),
             Box::new(m20230822_184929_create_event_table::Migration),
             Box::new(m20230828_220838_create_donation_table::Migration),
+            Box::new(m20231020_093012_create_sync_state_table::Migration),
+            Box::new(m20231021_101530_create_posting_table::Migration),
+            Box::new(m20231023_083417_create_nwc_connection_table::Migration),
+            Box::new(m20231105_061842_create_offer_table::Migration),
+            Box::new(m20231107_032516_add_hold_to_invoice_table::Migration),
+            Box::new(m20231109_024127_create_withdraw_link_table::Migration),
+            Box::new(m20231111_083042_create_auth_identity_table::Migration),
+            Box::new(m20231113_061205_add_success_action_to_user_table::Migration),
+            Box::new(m20231114_075220_create_user_profile_table::Migration),
+            Box::new(m20231116_021433_create_lndhub_credential_table::Migration),
+            Box::new(m20231117_093108_create_session_table::Migration),
+            Box::new(m20231119_044517_create_auth_event_table::Migration),
+            Box::new(m20231121_030642_create_api_key_table::Migration),
+            Box::new(m20231122_081327_add_frozen_at_to_user_table::Migration),
+            Box::new(m20231123_024815_post_opening_balances::Migration),
+            Box::new(m20231123_091204_create_budget_hold_table::Migration),
         ]
     }
 }
@@ -0,0 +1,70 @@
+use entity::sync_state;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(sync_state::Entity)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(sync_state::Column::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(sync_state::Column::Service)
+                            .string_len(50)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(sync_state::Column::Key)
+                            .string_len(50)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(sync_state::Column::Value)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(sync_state::Column::UpdatedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("uq_sync_state_service_key")
+                    .col(sync_state::Column::Service)
+                    .col(sync_state::Column::Key)
+                    .table(sync_state::Entity)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("uq_sync_state_service_key").to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(sync_state::Entity).to_owned())
+            .await
+    }
+}
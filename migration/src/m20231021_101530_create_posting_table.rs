@@ -0,0 +1,90 @@
+use entity::posting;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(posting::Entity)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(posting::Column::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(posting::Column::Journal)
+                            .string_len(32)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(posting::Column::Kind)
+                            .string()
+                            .not_null()
+                            .default("".to_owned()),
+                    )
+                    .col(
+                        ColumnDef::new(posting::Column::Account)
+                            .string_len(20)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(posting::Column::UserId).integer().null())
+                    .col(ColumnDef::new(posting::Column::InvoiceId).integer().null())
+                    .col(
+                        ColumnDef::new(posting::Column::Amount)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(posting::Column::CreatedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_posting_account_user_id")
+                    .col(posting::Column::Account)
+                    .col(posting::Column::UserId)
+                    .table(posting::Entity)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_posting_journal")
+                    .col(posting::Column::Journal)
+                    .table(posting::Entity)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("idx_posting_account_user_id").to_owned())
+            .await?;
+        manager
+            .drop_index(Index::drop().name("idx_posting_journal").to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(posting::Entity).to_owned())
+            .await
+    }
+}
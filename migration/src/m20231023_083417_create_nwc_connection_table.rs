@@ -0,0 +1,139 @@
+use entity::nwc_connection;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(nwc_connection::Entity)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::UserId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::Pubkey)
+                            .binary_len(32)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::Name)
+                            .string_len(100)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::Methods)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::MaxAmount)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::Budget)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::BudgetRenewal)
+                            .string_len(20)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::BudgetUsed)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::BudgetRenewedAt)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::ExpiresAt)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::RevokedAt)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::LastUsedAt)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::CreatedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(nwc_connection::Column::UpdatedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("uq_nwc_connection_pubkey")
+                    .col(nwc_connection::Column::Pubkey)
+                    .table(nwc_connection::Entity)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_nwc_connection_user_id")
+                    .col(nwc_connection::Column::UserId)
+                    .table(nwc_connection::Entity)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("idx_nwc_connection_user_id").to_owned())
+            .await?;
+        manager
+            .drop_index(Index::drop().name("uq_nwc_connection_pubkey").to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(nwc_connection::Entity).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,92 @@
+use entity::offer;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(offer::Entity)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(offer::Column::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(offer::Column::UserId).integer().not_null())
+                    .col(
+                        ColumnDef::new(offer::Column::Service)
+                            .string_len(50)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(offer::Column::OfferId)
+                            .binary_len(32)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(offer::Column::Bolt12).text().not_null())
+                    .col(
+                        ColumnDef::new(offer::Column::Description)
+                            .string_len(255)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(offer::Column::Amount)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(offer::Column::CreatedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("uq_offer_offer_id")
+                    .col(offer::Column::OfferId)
+                    .table(offer::Entity)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("uq_offer_service_user_id")
+                    .col(offer::Column::Service)
+                    .col(offer::Column::UserId)
+                    .table(offer::Entity)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("uq_offer_service_user_id").to_owned())
+            .await?;
+        manager
+            .drop_index(Index::drop().name("uq_offer_offer_id").to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(offer::Entity).to_owned())
+            .await
+    }
+}
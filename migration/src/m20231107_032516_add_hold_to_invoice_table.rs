@@ -0,0 +1,35 @@
+use entity::invoice;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(invoice::Entity)
+                    .add_column(
+                        ColumnDef::new(invoice::Column::Hold)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(invoice::Entity)
+                    .drop_column(invoice::Column::Hold)
+                    .to_owned(),
+            )
+            .await
+    }
+}
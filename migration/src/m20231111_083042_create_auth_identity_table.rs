@@ -0,0 +1,155 @@
+use entity::{auth_challenge, auth_identity};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(auth_identity::Entity)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(auth_identity::Column::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(auth_identity::Column::UserId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(auth_identity::Column::Kind)
+                            .string_len(20)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(auth_identity::Column::Identifier)
+                            .binary_len(33)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(auth_identity::Column::LastUsedAt)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(auth_identity::Column::CreatedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("uq_auth_identity_kind_identifier")
+                    .col(auth_identity::Column::Kind)
+                    .col(auth_identity::Column::Identifier)
+                    .table(auth_identity::Entity)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_auth_identity_user_id")
+                    .col(auth_identity::Column::UserId)
+                    .table(auth_identity::Entity)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(auth_challenge::Entity)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(auth_challenge::Column::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(auth_challenge::Column::K1)
+                            .binary_len(32)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(auth_challenge::Column::UserId)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(auth_challenge::Column::AuthedUserId)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(auth_challenge::Column::CreatedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(auth_challenge::Column::AuthedAt)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("uq_auth_challenge_k1")
+                    .col(auth_challenge::Column::K1)
+                    .table(auth_challenge::Entity)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("uq_auth_challenge_k1").to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(auth_challenge::Entity).to_owned())
+            .await?;
+        manager
+            .drop_index(Index::drop().name("idx_auth_identity_user_id").to_owned())
+            .await?;
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("uq_auth_identity_kind_identifier")
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(auth_identity::Entity).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,30 @@
+use entity::user;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(user::Entity)
+                    .add_column(ColumnDef::new(user::Column::SuccessAction).text().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(user::Entity)
+                    .drop_column(user::Column::SuccessAction)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,89 @@
+use entity::user_profile;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(user_profile::Entity)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(user_profile::Column::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(user_profile::Column::UserId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(user_profile::Column::MinSendable)
+                            .big_integer()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(user_profile::Column::MaxSendable)
+                            .big_integer()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(user_profile::Column::CommentAllowed)
+                            .integer()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(user_profile::Column::Description)
+                            .string_len(255)
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(user_profile::Column::LongDescription)
+                            .text()
+                            .null(),
+                    )
+                    .col(ColumnDef::new(user_profile::Column::Avatar).text().null())
+                    .col(
+                        ColumnDef::new(user_profile::Column::CreatedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(user_profile::Column::UpdatedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("uq_user_profile_user_id")
+                    .col(user_profile::Column::UserId)
+                    .table(user_profile::Entity)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("uq_user_profile_user_id").to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(user_profile::Entity).to_owned())
+            .await
+    }
+}
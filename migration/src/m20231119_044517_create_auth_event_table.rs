@@ -0,0 +1,72 @@
+use entity::auth_event;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(auth_event::Entity)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(auth_event::Column::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(auth_event::Column::EventId)
+                            .binary_len(32)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(auth_event::Column::ExpiresAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("uq_auth_event_event_id")
+                    .col(auth_event::Column::EventId)
+                    .table(auth_event::Entity)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_auth_event_expires_at")
+                    .col(auth_event::Column::ExpiresAt)
+                    .table(auth_event::Entity)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("idx_auth_event_expires_at").to_owned())
+            .await?;
+        manager
+            .drop_index(Index::drop().name("uq_auth_event_event_id").to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(auth_event::Entity).to_owned())
+            .await
+    }
+}
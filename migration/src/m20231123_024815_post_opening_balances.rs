@@ -0,0 +1,99 @@
+use entity::{posting, user};
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{
+        ActiveModelTrait, ColumnTrait, DbBackend, EntityTrait, NotSet, QueryFilter, QuerySelect,
+        Set,
+    },
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Post the user balances not covered by the ledger against the equity account,
+/// the users created before the ledger was introduced have no postings.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+
+        // select the columns only, the user table may have more columns than at this migration
+        let users: Vec<(i32, i64, i64)> = user::Entity::find()
+            .select_only()
+            .column(user::Column::Id)
+            .column(user::Column::Balance)
+            .column(user::Column::LockAmount)
+            .into_tuple()
+            .all(db)
+            .await?;
+
+        for (user_id, balance, lock_amount) in users {
+            let posted = |account: posting::Account| {
+                posting::Entity::find()
+                    .select_only()
+                    .column_as(sum_bigint(db.get_database_backend()), "amount")
+                    .filter(posting::Column::Account.eq(account))
+                    .filter(posting::Column::UserId.eq(user_id))
+                    .into_tuple::<Option<i64>>()
+                    .one(db)
+            };
+            // the user accounts are liabilities, credit is negative
+            let user = -balance - posted(posting::Account::User).await?.flatten().unwrap_or(0);
+            let locked = -lock_amount
+                - posted(posting::Account::UserLocked)
+                    .await?
+                    .flatten()
+                    .unwrap_or(0);
+            if user == 0 && locked == 0 {
+                continue;
+            }
+            let journal = format!("opening_{}", user_id);
+            let entries = [
+                (posting::Account::User, Some(user_id), user),
+                (posting::Account::UserLocked, Some(user_id), locked),
+                (posting::Account::Equity, None, -(user + locked)),
+            ];
+            for (account, user_id, amount) in entries.into_iter().filter(|e| e.2 != 0) {
+                posting::ActiveModel {
+                    id: NotSet,
+                    journal: Set(journal.clone()),
+                    kind: Set("opening_balance".to_owned()),
+                    account: Set(account),
+                    user_id: Set(user_id),
+                    invoice_id: Set(None),
+                    amount: Set(amount),
+                    created_at: Set(now),
+                }
+                .insert(db)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        posting::Entity::delete_many()
+            .filter(posting::Column::Kind.eq("opening_balance"))
+            .exec(manager.get_connection())
+            .await?;
+        Ok(())
+    }
+}
+
+/// sum as bigint, postgres and mysql return decimal for the sum of bigint
+fn sum_bigint(backend: DbBackend) -> SimpleExpr {
+    let ty = match backend {
+        DbBackend::MySql => "SIGNED",
+        _ => "BIGINT",
+    };
+    Func::cast_as(
+        Func::sum(Expr::col(posting::Column::Amount)),
+        Alias::new(ty),
+    )
+    .into()
+}
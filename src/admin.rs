@@ -0,0 +1,373 @@
+//! admin http api, only for the admin pubkeys of the auth setting
+
+use crate::{api::status_str, auth::AdminAuth, AppState, Error, Result};
+use actix_web::{get, post, web, Responder, Scope};
+use entity::{event, invoice, user};
+use nostr_sdk::{
+    prelude::{FromBech32, ToBech32},
+    secp256k1::XOnlyPublicKey,
+};
+use sea_orm::{ActiveEnum, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+const LIST_LIMIT: u64 = 20;
+const LIST_MAX_LIMIT: u64 = 100;
+const NOTE_MAX_CHARS: usize = 255;
+
+pub fn scope() -> Scope {
+    web::scope("/admin")
+        .service(info)
+        .service(list_users)
+        .service(get_user)
+        .service(adjust_balance)
+        .service(freeze_user)
+        .service(unfreeze_user)
+        .service(list_payments)
+        .service(settle_payment)
+        .service(fail_payment)
+        .service(list_events)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ListReq {
+    /// list the items with the id less than the cursor
+    cursor: Option<i32>,
+    limit: Option<u64>,
+    /// users: hex pubkey, npub or a part of the username
+    q: Option<String>,
+    /// payments: unpaid, paid or canceled, events: created, succeeded or failed
+    status: Option<String>,
+    user_id: Option<i32>,
+}
+
+impl ListReq {
+    fn limit(&self) -> u64 {
+        self.limit.unwrap_or(LIST_LIMIT).clamp(1, LIST_MAX_LIMIT)
+    }
+
+    /// the cursor of the next page
+    fn next_cursor(&self, ids: &[i32]) -> Option<i32> {
+        if ids.len() as u64 == self.limit() {
+            ids.last().copied()
+        } else {
+            None
+        }
+    }
+}
+
+async fn find_user(state: &AppState, id: i32) -> Result<user::Model> {
+    user::Entity::find_by_id(id)
+        .one(state.service.db())
+        .await?
+        .ok_or_else(|| Error::NotFound("User not found".to_owned()))
+}
+
+fn user_json(user: &user::Model) -> Value {
+    json!({
+        "id": user.id,
+        "pubkey": hex::encode(&user.pubkey),
+        "npub": XOnlyPublicKey::from_slice(&user.pubkey)
+            .ok()
+            .and_then(|k| k.to_bech32().ok()),
+        "username": user.username,
+        "balance": user.balance,
+        "lock_amount": user.lock_amount,
+        "donate_amount": user.donate_amount,
+        "frozen_at": user.frozen_at,
+        "created_at": user.created_at,
+        "updated_at": user.updated_at,
+    })
+}
+
+fn payment_json(inv: &invoice::Model) -> Value {
+    json!({
+        "id": inv.id,
+        "user_id": inv.user_id,
+        "payment_hash": hex::encode(&inv.payment_hash),
+        "bolt11": inv.bolt11,
+        "status": status_str(&inv.status),
+        "source": inv.source.to_value(),
+        "amount": inv.amount,
+        "fee": inv.fee,
+        "service_fee": inv.service_fee,
+        "total": inv.total,
+        "lock_amount": inv.lock_amount,
+        "internal": inv.internal,
+        "created_at": inv.created_at,
+        "paid_at": inv.paid_at,
+    })
+}
+
+fn event_json(event: &event::Model) -> Value {
+    let status = match event.status {
+        event::Status::Created => "created",
+        event::Status::Succeeded => "succeeded",
+        event::Status::Failed => "failed",
+    };
+    json!({
+        "id": event.id,
+        "event_id": hex::encode(&event.event_id),
+        "status": status,
+        "event": serde_json::from_str::<Value>(&event.json).unwrap_or_default(),
+        "message": event.message,
+        "created_at": event.created_at,
+        "updated_at": event.updated_at,
+    })
+}
+
+/// node info and the spendable channel balance
+#[get("/info")]
+pub async fn info(state: web::Data<AppState>, _admin: AdminAuth) -> Result<impl Responder, Error> {
+    let info = state.service.info().await?;
+    let channel_balance = state.service.lightning().channel_balance().await?;
+    Ok(web::Json(json!({
+        "node": {
+            "id": hex::encode(&info.id),
+            "alias": info.alias,
+            "color": info.color,
+            "num_peers": info.num_peers,
+            "num_pending_channels": info.num_pending_channels,
+            "num_active_channels": info.num_active_channels,
+            "num_inactive_channels": info.num_inactive_channels,
+            "version": info.version,
+            "block_height": info.block_height,
+        },
+        "channel_balance": channel_balance,
+    })))
+}
+
+/// list and search users, newest first
+#[get("/users")]
+pub async fn list_users(
+    state: web::Data<AppState>,
+    _admin: AdminAuth,
+    query: web::Query<ListReq>,
+) -> Result<impl Responder, Error> {
+    let mut find = user::Entity::find();
+    if let Some(cursor) = query.cursor {
+        find = find.filter(user::Column::Id.lt(cursor));
+    }
+    if let Some(q) = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        let pubkey = match XOnlyPublicKey::from_bech32(q) {
+            Ok(key) => Some(key.serialize().to_vec()),
+            Err(_) => hex::decode(q).ok().filter(|k| k.len() == 32),
+        };
+        find = match pubkey {
+            Some(pubkey) => find.filter(user::Column::Pubkey.eq(pubkey)),
+            None => find.filter(user::Column::Username.contains(q)),
+        };
+    }
+    let list = find
+        .order_by_desc(user::Column::Id)
+        .limit(query.limit())
+        .all(state.service.db())
+        .await?;
+    let ids = list.iter().map(|u| u.id).collect::<Vec<_>>();
+    Ok(web::Json(json!({
+        "users": list.iter().map(user_json).collect::<Vec<_>>(),
+        "next_cursor": query.next_cursor(&ids),
+    })))
+}
+
+#[get("/users/{id}")]
+pub async fn get_user(
+    state: web::Data<AppState>,
+    _admin: AdminAuth,
+    path: web::Path<i32>,
+) -> Result<impl Responder, Error> {
+    let user = find_user(&state, path.into_inner()).await?;
+    Ok(web::Json(json!({
+        "user": user_json(&user),
+    })))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct AdjustBalanceReq {
+    /// msats, negative to debit
+    amount: i64,
+    /// reason of the adjustment, saved in the balance record
+    note: String,
+}
+
+/// credit or debit the user balance with a note
+#[post("/users/{id}/adjust_balance")]
+pub async fn adjust_balance(
+    state: web::Data<AppState>,
+    admin: AdminAuth,
+    path: web::Path<i32>,
+) -> Result<impl Responder, Error> {
+    let data: AdjustBalanceReq = serde_json::from_slice(&admin.auth.payload)?;
+    if data.amount == 0 {
+        return Err(Error::InvalidParam("Invalid amount".to_owned()));
+    }
+    let note = data.note.trim();
+    if note.is_empty() || note.chars().count() > NOTE_MAX_CHARS {
+        return Err(Error::InvalidParam("Invalid note".to_owned()));
+    }
+    let user = find_user(&state, path.into_inner()).await?;
+    if user.balance + data.amount < 0 {
+        return Err(Error::InvalidParam(
+            "The balance can't be negative".to_owned(),
+        ));
+    }
+    tracing::info!(
+        "admin {} adjust user {} balance {} {}",
+        hex::encode(&admin.auth.pubkey),
+        user.id,
+        data.amount,
+        note
+    );
+    let user = state
+        .service
+        .admin_adjust_user_balance(&user, data.amount, Some(note.to_owned()))
+        .await?;
+    Ok(web::Json(json!({
+        "user": user_json(&user),
+    })))
+}
+
+/// the balance of the frozen user can't be spent
+#[post("/users/{id}/freeze")]
+pub async fn freeze_user(
+    state: web::Data<AppState>,
+    admin: AdminAuth,
+    path: web::Path<i32>,
+) -> Result<impl Responder, Error> {
+    let id = path.into_inner();
+    find_user(&state, id).await?;
+    tracing::info!(
+        "admin {} freeze user {}",
+        hex::encode(&admin.auth.pubkey),
+        id
+    );
+    let user = state.service.admin_freeze_user(id, true).await?;
+    Ok(web::Json(json!({
+        "user": user_json(&user),
+    })))
+}
+
+#[post("/users/{id}/unfreeze")]
+pub async fn unfreeze_user(
+    state: web::Data<AppState>,
+    admin: AdminAuth,
+    path: web::Path<i32>,
+) -> Result<impl Responder, Error> {
+    let id = path.into_inner();
+    find_user(&state, id).await?;
+    tracing::info!(
+        "admin {} unfreeze user {}",
+        hex::encode(&admin.auth.pubkey),
+        id
+    );
+    let user = state.service.admin_freeze_user(id, false).await?;
+    Ok(web::Json(json!({
+        "user": user_json(&user),
+    })))
+}
+
+/// list payments, the in-flight payments by default, newest first
+#[get("/payments")]
+pub async fn list_payments(
+    state: web::Data<AppState>,
+    _admin: AdminAuth,
+    query: web::Query<ListReq>,
+) -> Result<impl Responder, Error> {
+    let status = match query.status.as_deref().unwrap_or("unpaid") {
+        "unpaid" => invoice::Status::Unpaid,
+        "paid" => invoice::Status::Paid,
+        "canceled" => invoice::Status::Canceled,
+        _ => return Err(Error::InvalidParam("Invalid status".to_owned())),
+    };
+    let mut find = invoice::Entity::find()
+        .filter(invoice::Column::Type.eq(invoice::Type::Payment))
+        .filter(invoice::Column::Status.eq(status));
+    if let Some(cursor) = query.cursor {
+        find = find.filter(invoice::Column::Id.lt(cursor));
+    }
+    if let Some(user_id) = query.user_id {
+        find = find.filter(invoice::Column::UserId.eq(user_id));
+    }
+    let list = find
+        .order_by_desc(invoice::Column::Id)
+        .limit(query.limit())
+        .all(state.service.db())
+        .await?;
+    let ids = list.iter().map(|p| p.id).collect::<Vec<_>>();
+    Ok(web::Json(json!({
+        "payments": list.iter().map(payment_json).collect::<Vec<_>>(),
+        "next_cursor": query.next_cursor(&ids),
+    })))
+}
+
+/// settle the in-flight payment succeeded on the node
+#[post("/payments/{id}/settle")]
+pub async fn settle_payment(
+    state: web::Data<AppState>,
+    admin: AdminAuth,
+    path: web::Path<i32>,
+) -> Result<impl Responder, Error> {
+    let id = path.into_inner();
+    tracing::info!(
+        "admin {} settle payment {}",
+        hex::encode(&admin.auth.pubkey),
+        id
+    );
+    let payment = state.service.admin_resolve_payment(id, true).await?;
+    Ok(web::Json(json!({
+        "payment": payment_json(&payment),
+    })))
+}
+
+/// fail the in-flight payment failed or unknown on the node, unlock the user balance
+#[post("/payments/{id}/fail")]
+pub async fn fail_payment(
+    state: web::Data<AppState>,
+    admin: AdminAuth,
+    path: web::Path<i32>,
+) -> Result<impl Responder, Error> {
+    let id = path.into_inner();
+    tracing::info!(
+        "admin {} fail payment {}",
+        hex::encode(&admin.auth.pubkey),
+        id
+    );
+    let payment = state.service.admin_resolve_payment(id, false).await?;
+    Ok(web::Json(json!({
+        "payment": payment_json(&payment),
+    })))
+}
+
+/// list the nwc request events, newest first
+#[get("/events")]
+pub async fn list_events(
+    state: web::Data<AppState>,
+    _admin: AdminAuth,
+    query: web::Query<ListReq>,
+) -> Result<impl Responder, Error> {
+    let mut find = event::Entity::find();
+    if let Some(status) = &query.status {
+        let status = match status.as_str() {
+            "created" => event::Status::Created,
+            "succeeded" => event::Status::Succeeded,
+            "failed" => event::Status::Failed,
+            _ => return Err(Error::InvalidParam("Invalid status".to_owned())),
+        };
+        find = find.filter(event::Column::Status.eq(status));
+    }
+    if let Some(cursor) = query.cursor {
+        find = find.filter(event::Column::Id.lt(cursor));
+    }
+    let list = find
+        .order_by_desc(event::Column::Id)
+        .limit(query.limit())
+        .all(state.service.db())
+        .await?;
+    let ids = list.iter().map(|e| e.id).collect::<Vec<_>>();
+    Ok(web::Json(json!({
+        "events": list.iter().map(event_json).collect::<Vec<_>>(),
+        "next_cursor": query.next_cursor(&ids),
+    })))
+}
@@ -1,12 +1,33 @@
 //! http api
 
-use crate::{auth, full_uri_from_req, key::Privkey, setting::Setting, AppState, Error, Result};
-use actix_web::{get, http::Uri, post, web, HttpRequest, HttpResponse, Responder, Scope};
-use entity::user;
-use nostr_sdk::{prelude::ToBech32, secp256k1::XOnlyPublicKey, Keys};
+use crate::{
+    auth, bus::WalletEvent, full_uri_from_req, key::Privkey, lnurl::SuccessAction, lnurl_client,
+    now, nwc, setting::Setting, sha256, AddressProfile, ApiKeyPolicy, AppState, Error,
+    InvoiceExtra, NwcPolicy, Result, WithdrawPolicy,
+};
+use actix_web::{get, http::Uri, post, web, Either, HttpRequest, HttpResponse, Responder, Scope};
+use base64::engine::{general_purpose, Engine};
+use entity::{
+    api_key, invoice, lndhub_credential,
+    nwc_connection::{self, BudgetRenewal},
+    record, session, user, user_profile, withdraw_link,
+};
+use futures::Stream;
+use ipnet::IpNet;
+use nostr_sdk::{
+    prelude::ToBech32,
+    secp256k1::{SecretKey, XOnlyPublicKey},
+    Keys, Url,
+};
 use rand::RngCore;
+use sea_orm::{ActiveEnum, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+use tokio::{
+    sync::broadcast::{error::RecvError, Receiver},
+    time::Instant,
+};
 pub const CARGO_PKG_VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 
 fn version() -> String {
@@ -18,10 +39,48 @@ pub fn scope() -> Scope {
         .service(info)
         .service(post_auth)
         .service(get_auth)
+        .service(create_lnurl_auth)
+        .service(link_lnurl_auth)
+        .service(check_lnurl_auth)
         .service(my)
         .service(reset_lndhub)
+        .service(list_lndhub_credentials)
+        .service(revoke_lndhub_credential)
+        .service(list_sessions)
+        .service(revoke_session)
         .service(update_username)
+        .service(update_success_action)
+        .service(get_profile)
+        .service(update_profile)
         .service(pay_invoice)
+        .service(pay_lnurl)
+        .service(keysend)
+        .service(get_offer)
+        .service(create_invoice)
+        .service(create_hold_invoice)
+        .service(settle_hold_invoice)
+        .service(cancel_hold_invoice)
+        .service(get_invoice)
+        .service(transactions)
+        .service(stream)
+        .service(create_nwc_connection)
+        .service(list_nwc_connections)
+        .service(revoke_nwc_connection)
+        .service(create_withdraw_link)
+        .service(list_withdraw_links)
+        .service(revoke_withdraw_link)
+        .service(create_api_key)
+        .service(list_api_keys)
+        .service(revoke_api_key)
+}
+
+/// scheme and host of the request
+fn base_url(uri: &Uri) -> String {
+    format!(
+        "{}://{}",
+        uri.scheme_str().unwrap_or("https"),
+        uri.authority().map(|a| a.as_str()).unwrap_or_default()
+    )
 }
 
 fn privkey_to_pubkey(k: Privkey) -> String {
@@ -85,6 +144,81 @@ pub async fn get_auth(
     Ok(web::Json(json!({"success": true})))
 }
 
+/// the lnurl of the LUD-04 challenge on the host of the request
+fn lnurl_auth_json(k1: &[u8], action: &str, uri: &Uri) -> Value {
+    let k1 = hex::encode(k1);
+    let url = format!(
+        "{}/.well-known/lnurla?tag=login&k1={}&action={}",
+        base_url(uri),
+        k1,
+        action
+    );
+    json!({
+        "k1": k1,
+        "lnurl": lnurl_client::encode(&url),
+    })
+}
+
+/// create the lnurl-auth login challenge, poll the tokens by k1 after the wallet signed
+#[get("/lnurl_auth")]
+pub async fn create_lnurl_auth(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let challenge = state.service.create_auth_challenge(None).await?;
+    Ok(web::Json(lnurl_auth_json(
+        &challenge.k1,
+        "login",
+        &full_uri_from_req(&req),
+    )))
+}
+
+/// create the lnurl-auth challenge to link the wallet key to the current user
+#[post("/lnurl_auth/link")]
+pub async fn link_lnurl_auth(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
+    let user = state
+        .service
+        .get_or_create_user(nostr_user.pubkey.clone())
+        .await?;
+    let challenge = state.service.create_auth_challenge(Some(user.id)).await?;
+    Ok(web::Json(lnurl_auth_json(
+        &challenge.k1,
+        "link",
+        &full_uri_from_req(&req),
+    )))
+}
+
+/// check the lnurl-auth challenge, the tokens are returned once after the wallet signed
+#[get("/lnurl_auth/{k1}")]
+pub async fn check_lnurl_auth(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<impl Responder, Error> {
+    let k1 =
+        hex::decode(path.into_inner()).map_err(|_| Error::InvalidParam("Invalid k1".to_owned()))?;
+    match state.service.take_auth_challenge(&k1).await? {
+        Some(user) => {
+            state.setting.auth.check_permission(&user.pubkey)?;
+            let authed = auth::AuthedUser::login(user, None, &state, &req, None).await?;
+            let (refresh_token, access_token) = authed.tokens(&state.setting.auth)?;
+            let user = authed.user;
+            Ok(web::Json(json!({
+                "status": "OK",
+                "pubkey": hex::encode(&user.pubkey),
+                "refresh_token": refresh_token,
+                "access_token": access_token,
+            })))
+        }
+        None => Ok(web::Json(json!({ "status": "pending" }))),
+    }
+}
+
 const USERNAME_MAX_CHARS: usize = 20;
 
 fn get_username_setting(setting: &Setting, donate_amount: u64) -> (bool, usize) {
@@ -113,6 +247,7 @@ pub async fn my(
         .get_user(nostr_user.pubkey.clone())
         .await?
         .unwrap_or_default();
+    let credentials = state.service.list_lndhub_credentials(user.id).await?;
 
     let pubkey = hex::encode(nostr_user.pubkey.clone());
     let host = nostr_user
@@ -139,22 +274,46 @@ pub async fn my(
         "lock_amount": user.lock_amount,
         "username": user.username,
         "donate_amount": user.donate_amount,
-        "lndhub": lndhub_info(&nostr_user.url, &user),
+        "success_action": user
+            .success_action
+            .as_deref()
+            .and_then(|s| serde_json::from_str::<SuccessAction>(s).ok()),
+        "lndhub": lndhub_info(&nostr_user.url, &user, &credentials, None),
         "allow_update_username": allowed,
         "allow_update_username_min_chars": min,
         "allow_update_username_max_chars": USERNAME_MAX_CHARS,
     }})))
 }
 
+const LNDHUB_NAME_MAX_CHARS: usize = 100;
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct ResetLndhubReq {
     disable: bool,
+    /// the active credential of the same name is replaced
+    name: Option<String>,
+}
+
+fn lndhub_credential_json(credential: &lndhub_credential::Model) -> Value {
+    json!({
+        "id": credential.id,
+        "name": credential.name,
+        "last_used_at": credential.last_used_at,
+        "revoked_at": credential.revoked_at,
+        "created_at": credential.created_at,
+    })
 }
 
-fn lndhub_info(uri: &Uri, user: &user::Model) -> Value {
+/// the password and the url are only available when the credential is issued
+fn lndhub_info(
+    uri: &Uri,
+    user: &user::Model,
+    credentials: &[lndhub_credential::Model],
+    password: Option<&str>,
+) -> Value {
     let pubkey = hex::encode(&user.pubkey);
-    let url = user.password.clone().map(|p| {
+    let url = password.map(|p| {
         format!(
             "lndhub://{}:{}@{}://{}",
             pubkey.clone(),
@@ -165,12 +324,19 @@ fn lndhub_info(uri: &Uri, user: &user::Model) -> Value {
     });
     json!({
         "login": pubkey,
-        "password": user.password,
+        "password": password,
         "url": url,
+        // not migrated to the hashed credentials yet
+        "legacy_password": user.password.is_some(),
+        "credentials": credentials
+            .iter()
+            .filter(|c| c.revoked_at == 0)
+            .map(lndhub_credential_json)
+            .collect::<Vec<_>>(),
     })
 }
 
-/// reset lndhub password
+/// issue a new lndhub credential or disable lndhub
 #[post("/reset_lndhub")]
 pub async fn reset_lndhub(
     state: web::Data<AppState>,
@@ -179,31 +345,118 @@ pub async fn reset_lndhub(
     // data: web::Json<ResetLndhubReq>,
     // req: HttpRequest,
 ) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
     let data: ResetLndhubReq = serde_json::from_slice(&nostr_user.payload)?;
+    let name = data.name.unwrap_or_else(|| "default".to_owned());
+    if name.is_empty() || name.chars().count() > LNDHUB_NAME_MAX_CHARS {
+        return Err(Error::InvalidParam("Invalid name".to_owned()));
+    }
     let user = state
         .service
         .get_or_create_user(nostr_user.pubkey.clone())
         .await?;
-    let password = if data.disable {
-        None
+    let (user, password) = if data.disable {
+        let user = state.service.update_user_password(user.id, None).await?;
+        (user, None)
     } else {
-        Some(rand_password())
+        let (_, password) = state
+            .service
+            .create_lndhub_credential(user.id, &name)
+            .await?;
+        (user, Some(password))
+    };
+    let credentials = state.service.list_lndhub_credentials(user.id).await?;
+
+    Ok(web::Json(json!({
+        "lndhub": lndhub_info(&nostr_user.url, &user, &credentials, password.as_deref())
+    })))
+}
+
+#[get("/lndhub_credentials")]
+pub async fn list_lndhub_credentials(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
+    let user = state.service.get_user(nostr_user.pubkey.clone()).await?;
+    let list = match user {
+        Some(user) => state.service.list_lndhub_credentials(user.id).await?,
+        None => vec![],
     };
+    Ok(web::Json(json!({
+        "credentials": list.iter().map(lndhub_credential_json).collect::<Vec<_>>(),
+    })))
+}
 
+#[post("/lndhub_credentials/{id}/revoke")]
+pub async fn revoke_lndhub_credential(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+    path: web::Path<i32>,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
     let user = state
         .service
-        .update_user_password(user.id, password)
+        .get_user(nostr_user.pubkey.clone())
+        .await?
+        .ok_or_else(|| Error::NotFound("Credential not found".to_owned()))?;
+    let credential = state
+        .service
+        .revoke_lndhub_credential(user.id, path.into_inner())
         .await?;
+    Ok(web::Json(json!({
+        "credential": lndhub_credential_json(&credential),
+    })))
+}
+
+fn session_json(session: &session::Model) -> Value {
+    json!({
+        "id": session.id,
+        "device": session.device,
+        "ip": session.ip,
+        "last_used_at": session.last_used_at,
+        "expires_at": session.expires_at,
+        "created_at": session.created_at,
+    })
+}
 
+/// list the active jwt sessions of the signed in devices
+#[get("/sessions")]
+pub async fn list_sessions(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
+    let user = state.service.get_user(nostr_user.pubkey.clone()).await?;
+    let list = match user {
+        Some(user) => state.service.list_sessions(user.id).await?,
+        None => vec![],
+    };
     Ok(web::Json(json!({
-        "lndhub": lndhub_info(&nostr_user.url, &user)
+        "sessions": list.iter().map(session_json).collect::<Vec<_>>(),
     })))
 }
 
-fn rand_password() -> String {
-    let mut store_key_bytes = [0u8; 16];
-    rand::thread_rng().fill_bytes(&mut store_key_bytes);
-    hex::encode(store_key_bytes)
+/// revoke the session, the tokens of the session are invalid immediately
+#[post("/sessions/{id}/revoke")]
+pub async fn revoke_session(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+    path: web::Path<i32>,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
+    let user = state
+        .service
+        .get_user(nostr_user.pubkey.clone())
+        .await?
+        .ok_or_else(|| Error::NotFound("Session not found".to_owned()))?;
+    let session = state
+        .service
+        .revoke_session(user.id, path.into_inner())
+        .await?;
+    Ok(web::Json(json!({
+        "session": session_json(&session),
+    })))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -265,10 +518,167 @@ pub async fn update_username(
     Ok(web::Json(json!({"success": true})))
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct UpdateSuccessActionReq {
+    success_action: Option<SuccessAction>,
+}
+
+/// update the success action of the lightning address, null to reset the default message
+#[post("/update_success_action")]
+pub async fn update_success_action(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    let data: UpdateSuccessActionReq = serde_json::from_slice(&nostr_user.payload)?;
+    if let Some(action) = &data.success_action {
+        action.validate()?;
+    }
+
+    let user = state
+        .service
+        .get_or_create_user(nostr_user.pubkey.clone())
+        .await?;
+    state
+        .service
+        .update_success_action(user.id, data.success_action.as_ref())
+        .await?;
+
+    Ok(web::Json(json!({"success": true})))
+}
+
+const PROFILE_DESCRIPTION_MAX_CHARS: usize = 255;
+const PROFILE_LONG_DESCRIPTION_MAX_CHARS: usize = 2000;
+const PROFILE_AVATAR_MAX_BYTES: usize = 32 * 1024;
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+/// lightning address profile, null uses the default setting
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct UpdateProfileReq {
+    /// msats
+    min_sendable: Option<u64>,
+    max_sendable: Option<u64>,
+    comment_allowed: Option<usize>,
+    description: Option<String>,
+    long_description: Option<String>,
+    /// base64 png
+    avatar: Option<String>,
+}
+
+fn profile_json(profile: &user_profile::Model) -> Value {
+    json!({
+        "min_sendable": profile.min_sendable,
+        "max_sendable": profile.max_sendable,
+        "comment_allowed": profile.comment_allowed,
+        "description": profile.description,
+        "long_description": profile.long_description,
+        "avatar": profile.avatar,
+    })
+}
+
+/// get the lightning address profile
+#[get("/profile")]
+pub async fn get_profile(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    // don't create account
+    let profile = match state.service.get_user(nostr_user.pubkey.clone()).await? {
+        Some(user) => state.service.get_user_profile(user.id).await?,
+        None => None,
+    };
+    Ok(web::Json(json!({
+        "profile": profile_json(&profile.unwrap_or_default()),
+    })))
+}
+
+/// update the lightning address profile
+#[post("/update_profile")]
+pub async fn update_profile(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    let data: UpdateProfileReq = serde_json::from_slice(&nostr_user.payload)?;
+    let setting = &state.setting.lnurl;
+    let min = data.min_sendable.unwrap_or(setting.min_sendable);
+    let max = data.max_sendable.unwrap_or(setting.max_sendable);
+    if min < setting.min_sendable || max > setting.max_sendable || min > max {
+        return Err(Error::InvalidParam(format!(
+            "The sendable amount must be between {} and {} msats",
+            setting.min_sendable, setting.max_sendable
+        )));
+    }
+    if data.comment_allowed.unwrap_or_default() > setting.comment_allowed {
+        return Err(Error::InvalidParam(format!(
+            "The comment allowed cannot be greater than {}",
+            setting.comment_allowed
+        )));
+    }
+    let too_long = |s: &Option<String>, max: usize| {
+        s.as_ref()
+            .map(|s| s.chars().count() > max)
+            .unwrap_or_default()
+    };
+    if too_long(&data.description, PROFILE_DESCRIPTION_MAX_CHARS) {
+        return Err(Error::InvalidParam(format!(
+            "The length of the description cannot be greater than {}",
+            PROFILE_DESCRIPTION_MAX_CHARS
+        )));
+    }
+    if too_long(&data.long_description, PROFILE_LONG_DESCRIPTION_MAX_CHARS) {
+        return Err(Error::InvalidParam(format!(
+            "The length of the long description cannot be greater than {}",
+            PROFILE_LONG_DESCRIPTION_MAX_CHARS
+        )));
+    }
+    if let Some(avatar) = &data.avatar {
+        let image = general_purpose::STANDARD
+            .decode(avatar)
+            .map_err(|_| Error::InvalidParam("The avatar must be a base64 png".to_owned()))?;
+        if !image.starts_with(&PNG_SIGNATURE) {
+            return Err(Error::InvalidParam(
+                "The avatar must be a base64 png".to_owned(),
+            ));
+        }
+        if image.len() > PROFILE_AVATAR_MAX_BYTES {
+            return Err(Error::InvalidParam(format!(
+                "The avatar cannot be larger than {} bytes",
+                PROFILE_AVATAR_MAX_BYTES
+            )));
+        }
+    }
+
+    let user = state
+        .service
+        .get_or_create_user(nostr_user.pubkey.clone())
+        .await?;
+    let profile = state
+        .service
+        .update_user_profile(
+            user.id,
+            AddressProfile {
+                min_sendable: data.min_sendable.map(|v| v as i64),
+                max_sendable: data.max_sendable.map(|v| v as i64),
+                comment_allowed: data.comment_allowed.map(|v| v as i32),
+                description: data.description,
+                long_description: data.long_description,
+                avatar: data.avatar,
+            },
+        )
+        .await?;
+
+    Ok(web::Json(json!({
+        "profile": profile_json(&profile),
+    })))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct PayInvoiceReq {
     invoice: String,
+    /// amount in msats of the offer without amount
+    amount: Option<u64>,
 }
 
 /// pay invoice api
@@ -281,20 +691,1068 @@ pub async fn pay_invoice(
     let user = state.service.get_user(nostr_user.pubkey.clone()).await?;
 
     if let Some(user) = user {
-        let payment = state
+        // the amount of the offer is known from the requested invoice
+        let payable = state
             .service
-            .pay(
-                &user,
-                data.invoice,
-                &state.setting.fee,
-                entity::invoice::Source::Api,
-                false,
-            )
+            .resolve_payable(data.invoice, data.amount)
             .await?;
+        let amount = payable.amount();
+        let pay = state.service.pay_payable(
+            &user,
+            payable,
+            &state.setting.fee,
+            entity::invoice::Source::Api,
+            false,
+        );
+        let payment =
+            auth::limit_spend(&state, nostr_user.api_key.as_ref(), amount as i64, pay).await?;
+        Ok(web::Json(json!({
+            "preimage": hex::encode(payment.payment_preimage)
+        })))
+    } else {
+        Err(Error::InsufficientBalance)
+    }
+}
+
+/// pay lightning address or lnurl api, amount in msats as nwc pay_lnurl
+#[post("/pay_lnurl")]
+pub async fn pay_lnurl(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    let data: nwc::PayLnurlParam = serde_json::from_slice(&nostr_user.payload)?;
+    let user = state.service.get_user(nostr_user.pubkey.clone()).await?;
+
+    if let Some(user) = user {
+        let pay = state.service.pay_lnurl(
+            &user,
+            data.lnurl,
+            data.amount,
+            data.comment,
+            &state.setting.fee,
+            invoice::Source::Api,
+        );
+        let payment =
+            auth::limit_spend(&state, nostr_user.api_key.as_ref(), data.amount as i64, pay).await?;
         Ok(web::Json(json!({
+            "payment_hash": hex::encode(&payment.payment_hash),
             "preimage": hex::encode(payment.payment_preimage)
         })))
     } else {
         Err(Error::InsufficientBalance)
     }
 }
+
+/// keysend api, amount in msats, tlv records as nwc pay_keysend
+#[post("/keysend")]
+pub async fn keysend(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    let data: nwc::PayKeysendParam = serde_json::from_slice(&nostr_user.payload)?;
+    if data.preimage.is_some() {
+        return Err(Error::InvalidParam(
+            "Custom preimage is not supported".to_owned(),
+        ));
+    }
+    let pubkey =
+        hex::decode(&data.pubkey).map_err(|_| Error::InvalidParam("Invalid pubkey".to_owned()))?;
+    let mut records = vec![];
+    for r in data.tlv_records {
+        let value = hex::decode(&r.value)
+            .map_err(|_| Error::InvalidParam("Invalid tlv record value".to_owned()))?;
+        records.push((r.r#type, value));
+    }
+    let user = state.service.get_user(nostr_user.pubkey.clone()).await?;
+
+    if let Some(user) = user {
+        let pay = state.service.keysend(
+            &user,
+            pubkey,
+            data.amount,
+            records,
+            &state.setting.fee,
+            invoice::Source::Api,
+        );
+        let payment =
+            auth::limit_spend(&state, nostr_user.api_key.as_ref(), data.amount as i64, pay).await?;
+        Ok(web::Json(json!({
+            "payment_hash": hex::encode(&payment.payment_hash),
+            "preimage": hex::encode(payment.payment_preimage)
+        })))
+    } else {
+        Err(Error::InsufficientBalance)
+    }
+}
+
+/// get the reusable bolt12 offer of the user, create it if not exists
+#[get("/offer")]
+pub async fn get_offer(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    let user = state
+        .service
+        .get_or_create_user(nostr_user.pubkey.clone())
+        .await?;
+    let offer = state.service.get_or_create_offer(&user).await?;
+    Ok(web::Json(json!({
+        "offer_id": hex::encode(&offer.offer_id),
+        "bolt12": offer.bolt12,
+        "description": offer.description,
+        "created_at": offer.created_at,
+    })))
+}
+
+const INVOICE_EXPIRY: u64 = 3600 * 24;
+const INVOICE_MAX_EXPIRY: u64 = 3600 * 24 * 30;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct CreateInvoiceReq {
+    /// msats
+    amount: u64,
+    memo: String,
+    /// seconds, default one day
+    expiry: Option<u64>,
+    /// hex, the invoice always commits to the hash of the memo, so it must match
+    description_hash: Option<String>,
+}
+
+fn invoice_json(inv: &invoice::Model) -> Value {
+    json!({
+        "id": inv.id,
+        "payment_hash": hex::encode(&inv.payment_hash),
+        "bolt11": inv.bolt11,
+        "status": status_str(&inv.status),
+        "amount": inv.amount,
+        "paid_amount": inv.paid_amount,
+        "created_at": inv.created_at,
+        "paid_at": inv.paid_at,
+        "expired_at": inv.expired_at,
+    })
+}
+
+/// create invoice api
+#[post("/invoices")]
+pub async fn create_invoice(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    let data: CreateInvoiceReq = serde_json::from_slice(&nostr_user.payload)?;
+    if data.amount == 0 {
+        return Err(Error::InvalidParam("Invalid amount".to_owned()));
+    }
+    let expiry = data.expiry.unwrap_or(INVOICE_EXPIRY);
+    if expiry == 0 || expiry > INVOICE_MAX_EXPIRY {
+        return Err(Error::InvalidParam("Invalid expiry".to_owned()));
+    }
+    if let Some(hash) = &data.description_hash {
+        let hash = hex::decode(hash)
+            .map_err(|_| Error::InvalidParam("Invalid description hash".to_owned()))?;
+        if hash != sha256(&data.memo) {
+            return Err(Error::InvalidParam(
+                "The description hash does not match the memo".to_owned(),
+            ));
+        }
+    }
+
+    let user = state
+        .service
+        .get_or_create_user(nostr_user.pubkey.clone())
+        .await?;
+    let invoice = state
+        .service
+        .create_invoice(
+            &user,
+            data.memo,
+            data.amount,
+            expiry,
+            InvoiceExtra::new(invoice::Source::Api),
+        )
+        .await?;
+    Ok(web::Json(json!({ "invoice": invoice_json(&invoice) })))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct CreateHoldInvoiceReq {
+    /// msats
+    amount: u64,
+    memo: String,
+    /// seconds, default one day
+    expiry: Option<u64>,
+    /// hex, the preimage is kept by the caller until settled
+    payment_hash: String,
+}
+
+/// create hold invoice api, the payment is held until settled or canceled
+#[post("/hold_invoices")]
+pub async fn create_hold_invoice(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    let data: CreateHoldInvoiceReq = serde_json::from_slice(&nostr_user.payload)?;
+    if data.amount == 0 {
+        return Err(Error::InvalidParam("Invalid amount".to_owned()));
+    }
+    let expiry = data.expiry.unwrap_or(INVOICE_EXPIRY);
+    if expiry == 0 || expiry > INVOICE_MAX_EXPIRY {
+        return Err(Error::InvalidParam("Invalid expiry".to_owned()));
+    }
+    let hash = hex::decode(&data.payment_hash)
+        .map_err(|_| Error::InvalidParam("Invalid payment hash".to_owned()))?;
+
+    let user = state
+        .service
+        .get_or_create_user(nostr_user.pubkey.clone())
+        .await?;
+    let invoice = state
+        .service
+        .create_hold_invoice(
+            &user,
+            data.memo,
+            data.amount,
+            hash,
+            expiry,
+            InvoiceExtra::new(invoice::Source::Api),
+        )
+        .await?;
+    Ok(web::Json(json!({ "invoice": invoice_json(&invoice) })))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct SettleHoldInvoiceReq {
+    /// hex
+    preimage: String,
+}
+
+/// settle the accepted hold invoice by the preimage
+#[post("/hold_invoices/{payment_hash}/settle")]
+pub async fn settle_hold_invoice(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+    path: web::Path<String>,
+) -> Result<impl Responder, Error> {
+    let data: SettleHoldInvoiceReq = serde_json::from_slice(&nostr_user.payload)?;
+    let hash = hex::decode(path.into_inner())
+        .map_err(|_| Error::InvalidParam("Invalid payment hash".to_owned()))?;
+    let preimage = hex::decode(&data.preimage)
+        .map_err(|_| Error::InvalidParam("Invalid preimage".to_owned()))?;
+    if sha256(&preimage) != hash {
+        return Err(Error::InvalidParam(
+            "The preimage does not match the payment hash".to_owned(),
+        ));
+    }
+    let user = state
+        .service
+        .get_user(nostr_user.pubkey.clone())
+        .await?
+        .ok_or_else(|| Error::NotFound("Invoice not found".to_owned()))?;
+    let invoice = state.service.settle_hold_invoice(&user, preimage).await?;
+    Ok(web::Json(json!({ "invoice": invoice_json(&invoice) })))
+}
+
+/// cancel the hold invoice, the held payment is returned to the payer
+#[post("/hold_invoices/{payment_hash}/cancel")]
+pub async fn cancel_hold_invoice(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+    path: web::Path<String>,
+) -> Result<impl Responder, Error> {
+    let hash = hex::decode(path.into_inner())
+        .map_err(|_| Error::InvalidParam("Invalid payment hash".to_owned()))?;
+    let user = state
+        .service
+        .get_user(nostr_user.pubkey.clone())
+        .await?
+        .ok_or_else(|| Error::NotFound("Invoice not found".to_owned()))?;
+    let invoice = state.service.cancel_hold_invoice(&user, hash).await?;
+    Ok(web::Json(json!({ "invoice": invoice_json(&invoice) })))
+}
+
+/// get invoice status by payment hash
+#[get("/invoices/{payment_hash}")]
+pub async fn get_invoice(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+    path: web::Path<String>,
+) -> Result<impl Responder, Error> {
+    let hash = hex::decode(path.into_inner())
+        .map_err(|_| Error::InvalidParam("Invalid payment hash".to_owned()))?;
+    let user = state.service.get_user(nostr_user.pubkey.clone()).await?;
+    let invoice = match user {
+        Some(user) => {
+            invoice::Entity::find()
+                .filter(invoice::Column::UserId.eq(user.id))
+                .filter(invoice::Column::Type.eq(invoice::Type::Invoice))
+                .filter(invoice::Column::PaymentHash.eq(hash))
+                .one(state.service.db())
+                .await?
+        }
+        None => None,
+    };
+    let invoice = invoice.ok_or(Error::NotFound("Invoice not found".to_owned()))?;
+    Ok(web::Json(json!({ "invoice": invoice_json(&invoice) })))
+}
+
+const TRANSACTIONS_LIMIT: u64 = 20;
+const TRANSACTIONS_MAX_LIMIT: u64 = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct TransactionsReq {
+    /// the next_cursor of the previous page
+    cursor: Option<String>,
+    limit: Option<u64>,
+    /// invoice, payment or adjustment
+    r#type: Option<String>,
+    /// unpaid, paid, canceled or accepted
+    status: Option<String>,
+    /// invoice source, lndhub, lnurlp, zaps, nwc, api, keysend, offer, withdraw
+    source: Option<String>,
+    zap: Option<bool>,
+    /// creation time range
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+fn type_str(t: &invoice::Type) -> &'static str {
+    match t {
+        invoice::Type::Invoice => "invoice",
+        invoice::Type::Payment => "payment",
+    }
+}
+
+pub(crate) fn status_str(s: &invoice::Status) -> &'static str {
+    match s {
+        invoice::Status::Unpaid => "unpaid",
+        invoice::Status::Paid => "paid",
+        invoice::Status::Canceled => "canceled",
+        invoice::Status::Accepted => "accepted",
+    }
+}
+
+fn transaction_json(inv: invoice::Model, records: Vec<record::Model>) -> Value {
+    let preimage = if inv.status == invoice::Status::Paid {
+        Some(hex::encode(&inv.payment_preimage))
+    } else {
+        None
+    };
+    let zap = if inv.zap {
+        json!({
+            "from": inv.zap_from.map(hex::encode),
+            "pubkey": inv.zap_pubkey.map(hex::encode),
+            "event": inv.zap_event.map(hex::encode),
+            "status": inv.zap_status,
+            "receipt": inv.zap_receipt,
+        })
+    } else {
+        Value::Null
+    };
+    let records = records
+        .into_iter()
+        .map(|r| {
+            json!({
+                "change": r.change,
+                "source": r.source,
+                "note": r.note,
+                "created_at": r.created_at,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "id": inv.id,
+        "type": type_str(&inv.r#type),
+        "status": status_str(&inv.status),
+        "source": inv.source.to_value(),
+        "payment_hash": hex::encode(&inv.payment_hash),
+        "preimage": preimage,
+        "bolt11": inv.bolt11,
+        "description": inv.description,
+        "amount": inv.amount,
+        "paid_amount": inv.paid_amount,
+        "fee": inv.fee,
+        "service_fee": inv.service_fee,
+        "total": inv.total,
+        "internal": inv.internal,
+        "duplicate": inv.duplicate,
+        "comment": inv.comment,
+        "payer": {
+            "data": inv.payer,
+            "name": inv.payer_name,
+            "email": inv.payer_email,
+            "pubkey": inv.payer_pubkey.map(hex::encode),
+        },
+        "zap": zap,
+        "created_at": inv.created_at,
+        "paid_at": inv.paid_at,
+        "expired_at": inv.expired_at,
+        "records": records,
+    })
+}
+
+/// list invoices and payments of the current user, newest first
+#[get("/transactions")]
+pub async fn transactions(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+    query: web::Query<TransactionsReq>,
+) -> Result<impl Responder, Error> {
+    let user = state.service.get_user(nostr_user.pubkey.clone()).await?;
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(web::Json(json!({"transactions": [], "next_cursor": null}))),
+    };
+
+    let limit = query
+        .limit
+        .unwrap_or(TRANSACTIONS_LIMIT)
+        .clamp(1, TRANSACTIONS_MAX_LIMIT);
+    let (invoice_cursor, record_cursor) = parse_transactions_cursor(query.cursor.as_deref())?;
+    let adjustment = query.r#type.as_deref() == Some("adjustment");
+    let mut find = invoice::Entity::find().filter(invoice::Column::UserId.eq(user.id));
+    if let Some(cursor) = invoice_cursor {
+        find = find.filter(invoice::Column::Id.lt(cursor));
+    }
+    if let Some(t) = query.r#type.as_deref().filter(|_| !adjustment) {
+        let t = match t {
+            "invoice" => invoice::Type::Invoice,
+            "payment" => invoice::Type::Payment,
+            _ => return Err(Error::InvalidParam("Invalid type".to_owned())),
+        };
+        find = find.filter(invoice::Column::Type.eq(t));
+    }
+    if let Some(status) = &query.status {
+        let status = match status.as_str() {
+            "unpaid" => invoice::Status::Unpaid,
+            "paid" => invoice::Status::Paid,
+            "canceled" => invoice::Status::Canceled,
+            "accepted" => invoice::Status::Accepted,
+            _ => return Err(Error::InvalidParam("Invalid status".to_owned())),
+        };
+        find = find.filter(invoice::Column::Status.eq(status));
+    }
+    if let Some(source) = &query.source {
+        let source = invoice::Source::try_from_value(source)
+            .map_err(|_| Error::InvalidParam("Invalid source".to_owned()))?;
+        find = find.filter(invoice::Column::Source.eq(source));
+    }
+    if let Some(zap) = query.zap {
+        find = find.filter(invoice::Column::Zap.eq(zap));
+    }
+    if let Some(from) = query.from {
+        find = find.filter(invoice::Column::CreatedAt.gte(from));
+    }
+    if let Some(to) = query.to {
+        find = find.filter(invoice::Column::CreatedAt.lte(to));
+    }
+    let invoices = if adjustment {
+        vec![]
+    } else {
+        find.order_by_desc(invoice::Column::Id)
+            .limit(limit)
+            .all(state.service.db())
+            .await?
+    };
+
+    // the balance changes without invoice, only listed without the invoice filters
+    let with_adjustments = (query.r#type.is_none() || adjustment)
+        && query.status.is_none()
+        && query.source.is_none()
+        && query.zap.is_none();
+    let adjustments = if with_adjustments {
+        let mut find = record::Entity::find()
+            .filter(record::Column::UserId.eq(user.id))
+            .filter(record::Column::InvoiceId.is_null());
+        if let Some(cursor) = record_cursor {
+            find = find.filter(record::Column::Id.lt(cursor));
+        }
+        if let Some(from) = query.from {
+            find = find.filter(record::Column::CreatedAt.gte(from));
+        }
+        if let Some(to) = query.to {
+            find = find.filter(record::Column::CreatedAt.lte(to));
+        }
+        find.order_by_desc(record::Column::Id)
+            .limit(limit)
+            .all(state.service.db())
+            .await?
+    } else {
+        vec![]
+    };
+
+    let ids = invoices.iter().map(|i| i.id).collect::<Vec<_>>();
+    let mut records: HashMap<i32, Vec<record::Model>> = HashMap::new();
+    if !ids.is_empty() {
+        for r in record::Entity::find()
+            .filter(record::Column::UserId.eq(user.id))
+            .filter(record::Column::InvoiceId.is_in(ids))
+            .order_by_asc(record::Column::Id)
+            .all(state.service.db())
+            .await?
+        {
+            if let Some(id) = r.invoice_id {
+                records.entry(id).or_default().push(r);
+            }
+        }
+    }
+
+    // merge newest first, the skipped items of both lists are older than the page
+    let mut invoices = invoices.into_iter().peekable();
+    let mut adjustments = adjustments.into_iter().peekable();
+    let (mut invoice_cursor, mut record_cursor) = (invoice_cursor, record_cursor);
+    let mut list = vec![];
+    while (list.len() as u64) < limit {
+        let take_invoice = match (invoices.peek(), adjustments.peek()) {
+            (Some(inv), Some(r)) => inv.created_at >= r.created_at,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+        if take_invoice {
+            if let Some(inv) = invoices.next() {
+                invoice_cursor = Some(inv.id);
+                let r = records.remove(&inv.id).unwrap_or_default();
+                list.push(transaction_json(inv, r));
+            }
+        } else if let Some(r) = adjustments.next() {
+            record_cursor = Some(r.id);
+            list.push(adjustment_json(r));
+        }
+    }
+    let next_cursor = if list.len() as u64 == limit {
+        Some(format!(
+            "{}_{}",
+            invoice_cursor.map(|i| i.to_string()).unwrap_or_default(),
+            record_cursor.map(|i| i.to_string()).unwrap_or_default()
+        ))
+    } else {
+        None
+    };
+    Ok(web::Json(json!({
+        "transactions": list,
+        "next_cursor": next_cursor,
+    })))
+}
+
+/// `{invoice_id}_{record_id}`, either id may be empty, a single id is an invoice id
+fn parse_transactions_cursor(cursor: Option<&str>) -> Result<(Option<i32>, Option<i32>)> {
+    let parse = |id: &str| {
+        if id.is_empty() {
+            Ok(None)
+        } else {
+            id.parse::<i32>()
+                .map(Some)
+                .map_err(|_| Error::InvalidParam("Invalid cursor".to_owned()))
+        }
+    };
+    match cursor {
+        None => Ok((None, None)),
+        Some(cursor) => match cursor.split_once('_') {
+            Some((invoice, record)) => Ok((parse(invoice)?, parse(record)?)),
+            None => Ok((parse(cursor)?, None)),
+        },
+    }
+}
+
+/// the balance change without invoice, by the admin
+fn adjustment_json(r: record::Model) -> Value {
+    json!({
+        "id": r.id,
+        "type": "adjustment",
+        "source": r.source,
+        "change": r.change,
+        "note": r.note,
+        "created_at": r.created_at,
+    })
+}
+
+/// send a comment line when idle, keep the connection through proxies
+const STREAM_KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// push the wallet events of the current user as server-sent events
+#[get("/stream")]
+pub async fn stream(
+    state: web::Data<AppState>,
+    auth: Either<auth::NostrAuth, auth::AuthedUser>,
+) -> Result<HttpResponse, Error> {
+    let user = match auth {
+        Either::Left(nostr_user) => {
+            state
+                .service
+                .get_or_create_user(nostr_user.pubkey.clone())
+                .await?
+        }
+        Either::Right(authed) => authed.user,
+    };
+    let rx = state.service.bus().subscribe();
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(user_events(rx, user.id, STREAM_KEEP_ALIVE)))
+}
+
+/// The server-sent events of the user, the keep-alive is sent when nothing is sent
+/// in time however many events of the other users arrive.
+fn user_events(
+    rx: Receiver<WalletEvent>,
+    user_id: i32,
+    keep_alive: Duration,
+) -> impl Stream<Item = Result<web::Bytes>> {
+    let deadline = Instant::now() + keep_alive;
+    futures::stream::unfold((rx, deadline), move |(mut rx, deadline)| async move {
+        loop {
+            let data = match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Err(_) => ":\n\n".to_owned(),
+                Ok(Ok(event)) if event.user_id == user_id => format!(
+                    "data: {}\n\n",
+                    serde_json::to_string(&event).unwrap_or_default()
+                ),
+                Ok(Ok(_)) => continue,
+                // the client should reload the state
+                Ok(Err(RecvError::Lagged(_))) => "data: {\"type\":\"lagged\"}\n\n".to_owned(),
+                Ok(Err(RecvError::Closed)) => return None,
+            };
+            let deadline = Instant::now() + keep_alive;
+            return Some((Ok(web::Bytes::from(data)), (rx, deadline)));
+        }
+    })
+}
+
+const NWC_NAME_MAX_CHARS: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct CreateNwcConnectionReq {
+    name: String,
+    /// allowed methods, default all methods
+    methods: Option<Vec<String>>,
+    /// msats, 0 is unlimited
+    max_amount: i64,
+    /// msats, 0 is unlimited
+    budget: i64,
+    /// never, daily, weekly or monthly
+    budget_renewal: Option<String>,
+    /// timestamp, 0 is never expired
+    expires_at: i64,
+}
+
+fn nwc_connection_json(conn: &nwc_connection::Model) -> Value {
+    json!({
+        "id": conn.id,
+        "name": conn.name,
+        "pubkey": hex::encode(&conn.pubkey),
+        "methods": conn.methods.split(' ').collect::<Vec<_>>(),
+        "max_amount": conn.max_amount,
+        "budget": conn.budget,
+        "budget_renewal": conn.budget_renewal.to_value(),
+        "budget_used": conn.budget_used,
+        "budget_renewed_at": conn.budget_renewed_at,
+        "expires_at": conn.expires_at,
+        "revoked_at": conn.revoked_at,
+        "last_used_at": conn.last_used_at,
+        "created_at": conn.created_at,
+    })
+}
+
+/// create nwc connection, the secret is only returned here
+#[post("/nwc_connections")]
+pub async fn create_nwc_connection(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
+    let data: CreateNwcConnectionReq = serde_json::from_slice(&nostr_user.payload)?;
+    let setting = &state.setting.nwc;
+    if !setting.support() {
+        return Err(Error::Str("Nostr wallet connect is not supported"));
+    }
+    if data.name.is_empty() || data.name.len() > NWC_NAME_MAX_CHARS {
+        return Err(Error::InvalidParam("Invalid name".to_owned()));
+    }
+    if data.max_amount < 0 || data.budget < 0 {
+        return Err(Error::InvalidParam("Invalid amount".to_owned()));
+    }
+    let all = nwc::METHODS.split(' ').collect::<Vec<_>>();
+    let methods = match data.methods {
+        Some(methods) => {
+            if methods.is_empty() || !methods.iter().all(|m| all.contains(&m.as_str())) {
+                return Err(Error::InvalidParam("Invalid methods".to_owned()));
+            }
+            methods
+        }
+        None => all.iter().map(|m| m.to_string()).collect(),
+    };
+    let budget_renewal = match &data.budget_renewal {
+        Some(r) => BudgetRenewal::try_from_value(r)
+            .map_err(|_| Error::InvalidParam("Invalid budget renewal".to_owned()))?,
+        None => BudgetRenewal::default(),
+    };
+
+    let user = state
+        .service
+        .get_or_create_user(nostr_user.pubkey.clone())
+        .await?;
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let keys = Keys::new(SecretKey::from_slice(&secret)?);
+    let conn = state
+        .service
+        .create_nwc_connection(
+            &user,
+            keys.public_key().serialize().to_vec(),
+            data.name,
+            NwcPolicy {
+                methods,
+                max_amount: data.max_amount,
+                budget: data.budget,
+                budget_renewal,
+                expires_at: data.expires_at,
+            },
+        )
+        .await?;
+
+    let wallet = Keys::new(setting.privkey.unwrap().into()).public_key();
+    let mut uri = Url::parse(&format!("nostr+walletconnect://{}", wallet))
+        .map_err(|e| Error::Message(e.to_string()))?;
+    for relay in &setting.relays {
+        uri.query_pairs_mut().append_pair("relay", relay);
+    }
+    uri.query_pairs_mut()
+        .append_pair("secret", &hex::encode(secret));
+
+    Ok(web::Json(json!({
+        "connection": nwc_connection_json(&conn),
+        "uri": uri.to_string(),
+    })))
+}
+
+/// list nwc connections
+#[get("/nwc_connections")]
+pub async fn list_nwc_connections(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
+    let user = state.service.get_user(nostr_user.pubkey.clone()).await?;
+    let list = match user {
+        Some(user) => state.service.list_nwc_connections(user.id).await?,
+        None => vec![],
+    };
+    Ok(web::Json(json!({
+        "connections": list.iter().map(nwc_connection_json).collect::<Vec<_>>(),
+    })))
+}
+
+/// revoke nwc connection
+#[post("/nwc_connections/{id}/revoke")]
+pub async fn revoke_nwc_connection(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+    path: web::Path<i32>,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
+    let user = state
+        .service
+        .get_user(nostr_user.pubkey.clone())
+        .await?
+        .ok_or_else(|| Error::NotFound("Connection not found".to_owned()))?;
+    let conn = state
+        .service
+        .revoke_nwc_connection(user.id, path.into_inner())
+        .await?;
+    Ok(web::Json(json!({
+        "connection": nwc_connection_json(&conn),
+    })))
+}
+
+const WITHDRAW_TITLE_MAX_CHARS: usize = 255;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct CreateWithdrawLinkReq {
+    /// default description of the withdraw invoice
+    title: String,
+    /// msats per use
+    min_withdrawable: i64,
+    max_withdrawable: i64,
+    /// msats, 0 is unlimited
+    total_cap: i64,
+    /// 1 is single-use, 0 is unlimited
+    max_uses: i32,
+    /// timestamp, 0 is never expired
+    expires_at: i64,
+}
+
+/// the link json with the lnurl encoding of the link url on the host of the request
+fn withdraw_link_json(link: &withdraw_link::Model, uri: &Uri) -> Value {
+    let url = format!("{}/.well-known/lnurlw/{}", base_url(uri), link.k1);
+    json!({
+        "id": link.id,
+        "title": link.title,
+        "lnurl": lnurl_client::encode(&url),
+        "min_withdrawable": link.min_withdrawable,
+        "max_withdrawable": link.max_withdrawable,
+        "total_cap": link.total_cap,
+        "withdrawn": link.withdrawn,
+        "max_uses": link.max_uses,
+        "uses": link.uses,
+        "expires_at": link.expires_at,
+        "revoked_at": link.revoked_at,
+        "last_used_at": link.last_used_at,
+        "created_at": link.created_at,
+    })
+}
+
+/// create lnurl withdraw link paid from the user balance
+#[post("/withdraw_links")]
+pub async fn create_withdraw_link(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
+    let data: CreateWithdrawLinkReq = serde_json::from_slice(&nostr_user.payload)?;
+    if data.title.len() > WITHDRAW_TITLE_MAX_CHARS {
+        return Err(Error::InvalidParam("Invalid title".to_owned()));
+    }
+    if data.min_withdrawable <= 0
+        || data.max_withdrawable < data.min_withdrawable
+        || data.total_cap < 0
+        || (data.total_cap > 0 && data.total_cap < data.min_withdrawable)
+    {
+        return Err(Error::InvalidParam("Invalid amount".to_owned()));
+    }
+    if data.max_uses < 0 {
+        return Err(Error::InvalidParam("Invalid max uses".to_owned()));
+    }
+    let user = state
+        .service
+        .get_or_create_user(nostr_user.pubkey.clone())
+        .await?;
+    let link = state
+        .service
+        .create_withdraw_link(
+            &user,
+            WithdrawPolicy {
+                title: data.title,
+                min_withdrawable: data.min_withdrawable,
+                max_withdrawable: data.max_withdrawable,
+                total_cap: data.total_cap,
+                max_uses: data.max_uses,
+                expires_at: data.expires_at,
+            },
+        )
+        .await?;
+    Ok(web::Json(json!({
+        "link": withdraw_link_json(&link, &full_uri_from_req(&req)),
+    })))
+}
+
+/// list lnurl withdraw links
+#[get("/withdraw_links")]
+pub async fn list_withdraw_links(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
+    let user = state.service.get_user(nostr_user.pubkey.clone()).await?;
+    let list = match user {
+        Some(user) => state.service.list_withdraw_links(user.id).await?,
+        None => vec![],
+    };
+    let uri = full_uri_from_req(&req);
+    Ok(web::Json(json!({
+        "links": list.iter().map(|l| withdraw_link_json(l, &uri)).collect::<Vec<_>>(),
+    })))
+}
+
+/// revoke lnurl withdraw link
+#[post("/withdraw_links/{id}/revoke")]
+pub async fn revoke_withdraw_link(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+    path: web::Path<i32>,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
+    let user = state
+        .service
+        .get_user(nostr_user.pubkey.clone())
+        .await?
+        .ok_or_else(|| Error::NotFound("Withdraw link not found".to_owned()))?;
+    let link = state
+        .service
+        .revoke_withdraw_link(user.id, path.into_inner())
+        .await?;
+    Ok(web::Json(json!({
+        "link": withdraw_link_json(&link, &full_uri_from_req(&req)),
+    })))
+}
+
+const API_KEY_NAME_MAX_CHARS: usize = 100;
+const API_KEY_MAX_ALLOWED_IPS: usize = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct CreateApiKeyReq {
+    name: String,
+    /// read, receive or send
+    scopes: Vec<String>,
+    /// msats, 0 is unlimited
+    spend_limit: i64,
+    /// client ips or networks, empty allows any ip
+    allowed_ips: Vec<String>,
+    /// timestamp, 0 is never expired
+    expires_at: i64,
+}
+
+fn api_key_json(key: &api_key::Model) -> Value {
+    json!({
+        "id": key.id,
+        "name": key.name,
+        "prefix": key.prefix,
+        "scopes": key.scopes.split(' ').collect::<Vec<_>>(),
+        "spend_limit": key.spend_limit,
+        "spent": key.spent,
+        "allowed_ips": key.allowed_ips.split_whitespace().collect::<Vec<_>>(),
+        "expires_at": key.expires_at,
+        "revoked_at": key.revoked_at,
+        "last_used_at": key.last_used_at,
+        "created_at": key.created_at,
+    })
+}
+
+/// create api key, the secret is only returned here
+#[post("/api_keys")]
+pub async fn create_api_key(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
+    let data: CreateApiKeyReq = serde_json::from_slice(&nostr_user.payload)?;
+    if data.name.is_empty() || data.name.len() > API_KEY_NAME_MAX_CHARS {
+        return Err(Error::InvalidParam("Invalid name".to_owned()));
+    }
+    if data.scopes.is_empty() {
+        return Err(Error::InvalidParam("Invalid scopes".to_owned()));
+    }
+    let mut scopes = vec![];
+    for scope in &data.scopes {
+        let scope = scope.parse::<auth::ApiKeyScope>()?;
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    if data.spend_limit < 0 {
+        return Err(Error::InvalidParam("Invalid spend limit".to_owned()));
+    }
+    if data.allowed_ips.len() > API_KEY_MAX_ALLOWED_IPS
+        || !data
+            .allowed_ips
+            .iter()
+            .all(|ip| ip.parse::<IpNet>().is_ok() || ip.parse::<IpAddr>().is_ok())
+    {
+        return Err(Error::InvalidParam("Invalid allowed ips".to_owned()));
+    }
+    if data.expires_at < 0 || (data.expires_at > 0 && data.expires_at <= now() as i64) {
+        return Err(Error::InvalidParam("Invalid expiry".to_owned()));
+    }
+
+    let user = state
+        .service
+        .get_or_create_user(nostr_user.pubkey.clone())
+        .await?;
+    let (key, secret) = state
+        .service
+        .create_api_key(
+            user.id,
+            data.name,
+            ApiKeyPolicy {
+                scopes,
+                spend_limit: data.spend_limit,
+                allowed_ips: data.allowed_ips,
+                expires_at: data.expires_at,
+            },
+        )
+        .await?;
+    Ok(web::Json(json!({
+        "key": api_key_json(&key),
+        "secret": secret,
+    })))
+}
+
+/// list api keys
+#[get("/api_keys")]
+pub async fn list_api_keys(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
+    let user = state.service.get_user(nostr_user.pubkey.clone()).await?;
+    let list = match user {
+        Some(user) => state.service.list_api_keys(user.id).await?,
+        None => vec![],
+    };
+    Ok(web::Json(json!({
+        "keys": list.iter().map(api_key_json).collect::<Vec<_>>(),
+    })))
+}
+
+/// revoke api key
+#[post("/api_keys/{id}/revoke")]
+pub async fn revoke_api_key(
+    state: web::Data<AppState>,
+    nostr_user: auth::NostrAuth,
+    path: web::Path<i32>,
+) -> Result<impl Responder, Error> {
+    nostr_user.require_event()?;
+    let user = state
+        .service
+        .get_user(nostr_user.pubkey.clone())
+        .await?
+        .ok_or_else(|| Error::NotFound("Api key not found".to_owned()))?;
+    let key = state
+        .service
+        .revoke_api_key(user.id, path.into_inner())
+        .await?;
+    Ok(web::Json(json!({
+        "key": api_key_json(&key),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, WalletEventKind};
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn keep_alive() -> Result<()> {
+        let bus = Bus::default();
+        let events = user_events(bus.subscribe(), 1, Duration::from_millis(200));
+        futures::pin_mut!(events);
+        // the events of the other users arrive faster than the keep-alive
+        let other = bus.clone();
+        let publish = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                other.publish(
+                    2,
+                    WalletEventKind::Balance {
+                        balance: 0,
+                        lock_amount: 0,
+                    },
+                );
+            }
+        });
+        let data = tokio::time::timeout(Duration::from_secs(1), events.next()).await;
+        publish.abort();
+        assert_eq!(data.unwrap().unwrap()?, web::Bytes::from(":\n\n"));
+        Ok(())
+    }
+}
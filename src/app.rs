@@ -1,5 +1,5 @@
 use crate::{
-    api, lndhub,
+    admin, api, lndhub,
     lnurl::{self, loop_handle_receipts},
     nip05,
     nwc::Nwc,
@@ -12,7 +12,7 @@ use actix_web::{
     dev::{ServiceFactory, ServiceRequest},
     middleware, web, App as WebApp, HttpServer,
 };
-use lightning_client::{Cln, Lightning, Lnd};
+use lightning_client::{Cln, Lightning, Lnd, Mock};
 use nostr_sdk::Keys;
 use sea_orm::{ConnectOptions, Database};
 use std::{path::Path, sync::Arc, time::Duration};
@@ -97,15 +97,33 @@ impl AppState {
                     .cln
                     .clone()
                     .ok_or_else(|| Error::Message("Need config cln".to_string()))?;
-                let lightning = Cln::connect(s.url, s.ca, s.client, s.client_key, timeout).await?;
+                let mut lightning =
+                    Cln::connect(s.url, s.ca, s.client, s.client_key, timeout).await?;
+                if let Some(h) = s.hold {
+                    lightning = lightning
+                        .with_hold(h.url, h.ca, h.client, h.client_key, timeout)
+                        .await?;
+                }
                 ("cln".to_owned(), Box::new(lightning))
             }
+            crate::setting::Lightning::Mock => ("mock".to_owned(), Box::new(Mock::new())),
         };
+        Self::with_lightning(setting, conf.0, conf.1).await
+    }
 
+    /// create app state with a connected lightning client
+    pub async fn with_lightning(
+        setting: Setting,
+        name: String,
+        lightning: Box<dyn Lightning + Sync + Send>,
+    ) -> Result<Self> {
         let mut options = ConnectOptions::from(&setting.db_url);
         options.sqlx_logging_level(tracing::log::LevelFilter::Trace);
         let conn = Database::connect(options).await?;
-        let mut service = Service::new(conf.0, conf.1, conn);
+        let mut service = Service::new(name, lightning, conn);
+        service.keysend_record = setting.keysend_record;
+        service.lnurl_domains = setting.lnurl.domains.clone();
+        service.auth_events_db = setting.auth.replay_db;
         // set donation receiver
         if let Some(prikey) = &setting.donation.privkey {
             let keys = Keys::new((*prikey).into());
@@ -144,6 +162,8 @@ pub fn create_web_app(
                         .max_age(86_400),
                 )
                 .service(lnurl::scope())
+                .service(lnurl::withdraw_scope())
+                .service(lnurl::auth)
                 .service(nip05::info),
         )
         .service(
@@ -151,6 +171,7 @@ pub fn create_web_app(
                 .use_etag(true)
                 .use_last_modified(true),
         )
+        .service(admin::scope())
         .service(ui::index)
         .service(ui::wallet)
         .service(ui::wallet_sub);
@@ -171,9 +192,11 @@ pub fn create_web_app(
     }
 }
 
-/// start the service sync task for sync invoices and payments from lightning node.
+/// start the service sync task for sync invoices and payments from lightning node,
+/// reconcile the missed updates of the subscription.
 pub fn start_service_sync(state: Arc<AppState>) {
     let _r = tokio::spawn(async move {
+        // look back 25 hours when there is no sync cursor
         state
             .service
             .sync(Duration::from_secs(5), Duration::from_secs(60 * 60 * 25))
@@ -181,10 +204,17 @@ pub fn start_service_sync(state: Arc<AppState>) {
     });
 }
 
+/// start the service task for handle paid invoices pushed by lightning node.
+pub fn start_service_subscribe(state: Arc<AppState>) {
+    let _r = tokio::spawn(async move { state.service.subscribe(Duration::from_secs(5)).await });
+}
+
 /// start nwc task
 pub async fn start_nwc(state: Arc<AppState>) -> Result<()> {
     let nwc = Nwc::new(state);
     nwc.connect().await?;
+    let notifier = nwc.clone();
+    tokio::spawn(async move { notifier.handle_wallet_events().await });
     tokio::spawn(async move { nwc.handle_notifications().await });
     Ok(())
 }
@@ -193,6 +223,7 @@ pub async fn start_nwc(state: Arc<AppState>) -> Result<()> {
 pub async fn start(state: AppState) -> Result<()> {
     let state = web::Data::new(state);
 
+    start_service_subscribe(state.clone().into_inner());
     start_service_sync(state.clone().into_inner());
     if state.setting.nwc.support() {
         info!("Start nwc");
@@ -0,0 +1,28 @@
+use super::NostrAuth;
+use crate::{AppState, Error, Result};
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use std::{future::Future, pin::Pin};
+
+/// Nostr authed admin, the pubkey must be in the admins of the auth setting,
+/// the bearer token and the api key are not accepted
+#[derive(Debug)]
+pub struct AdminAuth {
+    pub auth: NostrAuth,
+}
+
+impl FromRequest for AdminAuth {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<AdminAuth>>>>;
+
+    fn from_request(req: &HttpRequest, pl: &mut Payload) -> Self::Future {
+        let fut = NostrAuth::from_request(req, pl);
+        let state = req.app_data::<web::Data<AppState>>().cloned();
+        Box::pin(async move {
+            let auth = fut.await?;
+            auth.require_event()?;
+            let state = state.ok_or(Error::Str("AppState required"))?;
+            state.setting.auth.check_admin(&auth.pubkey)?;
+            Ok(AdminAuth { auth })
+        })
+    }
+}
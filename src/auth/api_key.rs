@@ -0,0 +1,219 @@
+use super::{client_info, AuthError};
+use crate::{AppState, Error, Result};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use entity::{api_key, budget_hold::Budget, invoice, user};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::{future::Future, net::IpAddr, pin::Pin, str::FromStr};
+
+/// prefix of the api key secret
+pub const API_KEY_PREFIX: &str = "sk_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiKeyScope {
+    /// balance, invoices and transactions
+    Read,
+    /// create and settle invoices
+    Receive,
+    /// pay within the spend limit
+    Send,
+}
+
+impl ApiKeyScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::Read => "read",
+            ApiKeyScope::Receive => "receive",
+            ApiKeyScope::Send => "send",
+        }
+    }
+}
+
+impl FromStr for ApiKeyScope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "read" => Ok(ApiKeyScope::Read),
+            "receive" => Ok(ApiKeyScope::Receive),
+            "send" => Ok(ApiKeyScope::Send),
+            _ => Err(Error::InvalidParam("Invalid scope".to_owned())),
+        }
+    }
+}
+
+/// Routes accepting the api keys and the required scope, the other routes reject the api keys.
+const ROUTES: &[(&str, &str, ApiKeyScope)] = &[
+    ("GET", "/v1/my", ApiKeyScope::Read),
+    ("GET", "/v1/invoices/{payment_hash}", ApiKeyScope::Read),
+    ("GET", "/v1/transactions", ApiKeyScope::Read),
+    ("GET", "/v1/stream", ApiKeyScope::Read),
+    ("GET", "/v1/offer", ApiKeyScope::Receive),
+    ("POST", "/v1/invoices", ApiKeyScope::Receive),
+    ("POST", "/v1/hold_invoices", ApiKeyScope::Receive),
+    (
+        "POST",
+        "/v1/hold_invoices/{payment_hash}/settle",
+        ApiKeyScope::Receive,
+    ),
+    (
+        "POST",
+        "/v1/hold_invoices/{payment_hash}/cancel",
+        ApiKeyScope::Receive,
+    ),
+    ("POST", "/v1/pay_invoice", ApiKeyScope::Send),
+    ("POST", "/v1/pay_lnurl", ApiKeyScope::Send),
+    ("POST", "/v1/keysend", ApiKeyScope::Send),
+    // lndhub
+    ("GET", "/getinfo", ApiKeyScope::Read),
+    ("GET", "/balance", ApiKeyScope::Read),
+    ("GET", "/getuserinvoices", ApiKeyScope::Read),
+    ("GET", "/gettxs", ApiKeyScope::Read),
+    ("GET", "/checkpayment/{payment_hash}", ApiKeyScope::Read),
+    ("GET", "/getbtc", ApiKeyScope::Read),
+    ("GET", "/getpending", ApiKeyScope::Read),
+    ("POST", "/addinvoice", ApiKeyScope::Receive),
+    ("POST", "/payinvoice", ApiKeyScope::Send),
+    ("POST", "/keysend", ApiKeyScope::Send),
+];
+
+/// the scope required by the route pattern, None if the route rejects the api keys
+pub fn route_scope(method: &str, pattern: &str) -> Option<ApiKeyScope> {
+    ROUTES
+        .iter()
+        .find(|(m, p, _)| *m == method && *p == pattern)
+        .map(|r| r.2)
+}
+
+/// match the client ip by the allowed ips or networks separated by space, empty allows any ip
+pub fn ip_allowed(allowed_ips: &str, ip: &str) -> bool {
+    if allowed_ips.trim().is_empty() {
+        return true;
+    }
+    let ip = match ip.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => return false,
+    };
+    allowed_ips
+        .split_whitespace()
+        .any(|allowed| match allowed.parse::<IpNet>() {
+            Ok(net) => net.contains(&ip),
+            Err(_) => allowed.parse::<IpAddr>().is_ok_and(|a| a == ip),
+        })
+}
+
+/// the bearer token is an api key instead of a jwt token
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(API_KEY_PREFIX)
+}
+
+/// Authorized by the api key, `Authorization: Bearer sk_...`
+#[derive(Debug)]
+pub struct ApiKeyAuth {
+    pub user: user::Model,
+    pub key: api_key::Model,
+}
+
+impl ApiKeyAuth {
+    /// authorize by an active api key allowed for the client ip and the scope of the route
+    pub async fn from_token(token: &str, state: &AppState, req: &HttpRequest) -> Result<Self> {
+        let key = state.service.get_active_api_key(token).await?;
+        let (ip, _) = client_info(req, None);
+        if !ip_allowed(&key.allowed_ips, &ip) {
+            return Err(AuthError::Invalid("The client ip is not allowed").into());
+        }
+        let scope = req
+            .match_pattern()
+            .and_then(|pattern| route_scope(req.method().as_str(), &pattern))
+            .ok_or_else(|| Error::Restricted("The api key is not allowed".to_owned()))?;
+        if !key.scopes.split(' ').any(|s| s == scope.as_str()) {
+            return Err(Error::Restricted(format!(
+                "The api key requires the {} scope",
+                scope.as_str()
+            )));
+        }
+        state.service.touch_api_key(&key).await?;
+        let user = state.service.get_user_by_id(key.user_id).await?;
+        state.setting.auth.check_permission(&user.pubkey)?;
+        Ok(Self { user, key })
+    }
+}
+
+/// Pay within the spend limit of the api key, the amount with the max fees is charged
+/// and settled to the payment total, give back the limit when the payment fails.
+pub async fn limit_spend(
+    state: &AppState,
+    key: Option<&api_key::Model>,
+    amount: i64,
+    pay: impl Future<Output = Result<invoice::Model>>,
+) -> Result<invoice::Model> {
+    let key = match key {
+        Some(key) => key,
+        None => return pay.await,
+    };
+    let charge = state.setting.fee.max_total(amount);
+    state.service.spend_api_key(key, charge).await?;
+    let res = pay.await;
+    state
+        .service
+        .release_budget(Budget::ApiKey, key.id, charge, &res)
+        .await?;
+    res
+}
+
+impl FromRequest for ApiKeyAuth {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<ApiKeyAuth>>>>;
+
+    fn from_request(req: &HttpRequest, _pl: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            if let Some(state) = req.app_data::<web::Data<AppState>>() {
+                if let Some(auth) = req.headers().get(AUTHORIZATION) {
+                    if let Ok(auth) = auth.to_str() {
+                        if auth.starts_with("bearer") || auth.starts_with("Bearer") {
+                            let token = auth[6..auth.len()].trim();
+                            if is_api_key(token) {
+                                return ApiKeyAuth::from_token(token, state, &req).await;
+                            }
+                        }
+                    }
+                }
+            }
+            Err(AuthError::Invalid("missing api key").into())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_ips() {
+        assert!(ip_allowed("", "1.2.3.4"));
+        assert!(ip_allowed("", ""));
+        assert!(ip_allowed("1.2.3.4", "1.2.3.4"));
+        assert!(!ip_allowed("1.2.3.4", "1.2.3.5"));
+        assert!(!ip_allowed("1.2.3.4", ""));
+        assert!(ip_allowed("10.0.0.1 192.168.0.0/16", "192.168.3.4"));
+        assert!(!ip_allowed("10.0.0.1 192.168.0.0/16", "192.169.3.4"));
+        assert!(ip_allowed("::1", "::1"));
+        assert!(!ip_allowed("invalid", "1.2.3.4"));
+    }
+
+    #[test]
+    fn scope() {
+        assert_eq!(route_scope("GET", "/balance"), Some(ApiKeyScope::Read));
+        assert_eq!(route_scope("POST", "/v1/keysend"), Some(ApiKeyScope::Send));
+        assert_eq!(route_scope("GET", "/v1/keysend"), None);
+        assert_eq!(route_scope("POST", "/v1/api_keys"), None);
+        assert_eq!(
+            "receive".parse::<ApiKeyScope>().ok(),
+            Some(ApiKeyScope::Receive)
+        );
+        assert!("admin".parse::<ApiKeyScope>().is_err());
+    }
+}
@@ -1,10 +1,32 @@
-use crate::{auth::AuthError, now, AppState, Error, Result};
-use actix_web::http::header::AUTHORIZATION;
+use crate::{auth::AuthError, now, service::rand_preimage, setting::Auth, AppState, Error, Result};
+use actix_web::http::header::{AUTHORIZATION, USER_AGENT};
 use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
-use entity::user;
+use entity::{lndhub_credential, session, user};
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
-use std::{future::Future, pin::Pin};
+use sha2::Sha256;
+use std::{future::Future, net::IpAddr, pin::Pin};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    Access,
+    Refresh,
+}
+
+impl TokenType {
+    /// each token type is signed by its own key derived from the secret
+    fn key(&self, secret: &[u8]) -> Vec<u8> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret).expect("any key length");
+        mac.update(match self {
+            TokenType::Access => b"access",
+            TokenType::Refresh => b"refresh",
+        });
+        mac.finalize().into_bytes().to_vec()
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct JwtToken {
@@ -14,48 +36,206 @@ pub struct JwtToken {
     pub exp: i64,
     // data
     pub user_id: i32,
+    pub typ: TokenType,
+    // session id
+    pub sid: i32,
+    // token id, the refresh token id is rotated on each refresh
+    pub jti: String,
 }
 
 impl JwtToken {
-    pub fn from_str(token: &str, secret: &[u8]) -> Result<Self, AuthError> {
+    pub fn from_str(token: &str, typ: TokenType, secret: &[u8]) -> Result<Self, AuthError> {
         let mut validation = Validation::default();
         validation.leeway = 0;
-        Ok(
-            jsonwebtoken::decode::<JwtToken>(
-                token,
-                &DecodingKey::from_secret(secret),
-                &validation,
-            )?
-            .claims,
-        )
+        let token = jsonwebtoken::decode::<JwtToken>(
+            token,
+            &DecodingKey::from_secret(&typ.key(secret)),
+            &validation,
+        )?
+        .claims;
+        if token.typ != typ {
+            return Err(AuthError::Invalid("Invalid token type"));
+        }
+        Ok(token)
     }
 
-    pub fn generate(user_id: i32, expiry: usize, secret: &[u8]) -> Result<String, AuthError> {
+    pub fn generate(
+        user_id: i32,
+        typ: TokenType,
+        sid: i32,
+        jti: String,
+        expiry: usize,
+        secret: &[u8],
+    ) -> Result<String, AuthError> {
         let now = now() as i64;
         let payload = JwtToken {
             iat: now,
             exp: now + expiry as i64,
             user_id,
+            typ,
+            sid,
+            jti,
         };
 
         Ok(jsonwebtoken::encode(
             &Header::default(),
             &payload,
-            &EncodingKey::from_secret(secret),
+            &EncodingKey::from_secret(&typ.key(secret)),
         )?)
     }
+
+    /// generate the refresh token and the access token of the session
+    pub fn generate_pair(
+        session: &session::Model,
+        setting: &Auth,
+    ) -> Result<(String, String), AuthError> {
+        let secret = setting.secret.as_bytes();
+        Ok((
+            Self::generate(
+                session.user_id,
+                TokenType::Refresh,
+                session.id,
+                session.jti.clone(),
+                setting.refresh_token_expiry,
+                secret,
+            )?,
+            Self::generate(
+                session.user_id,
+                TokenType::Access,
+                session.id,
+                hex::encode(&rand_preimage()[..16]),
+                setting.access_token_expiry,
+                secret,
+            )?,
+        ))
+    }
+}
+
+/// the client ip and the device label of the request, the label defaults to the user agent
+pub fn client_info(req: &HttpRequest, device: Option<&str>) -> (String, String) {
+    let ip = client_ip(req).map(|ip| ip.to_string()).unwrap_or_default();
+    let device = device
+        .filter(|d| !d.is_empty())
+        .map(ToOwned::to_owned)
+        .or_else(|| {
+            req.headers()
+                .get(USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(ToOwned::to_owned)
+        })
+        .unwrap_or_default();
+    (ip, device)
+}
+
+/// the peer address of the connection, or the client ip header set by the trusted proxies,
+/// the header is read from right to left and skips the trusted proxies.
+fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    let network = match req.app_data::<web::Data<AppState>>() {
+        Some(state) => &state.setting.network,
+        None => return Some(peer),
+    };
+    let trusted = |ip: &IpAddr| {
+        network
+            .trusted_proxies
+            .iter()
+            .any(|p| match p.parse::<IpNet>() {
+                Ok(net) => net.contains(ip),
+                Err(_) => p.parse::<IpAddr>().is_ok_and(|p| &p == ip),
+            })
+    };
+    if !trusted(&peer) {
+        return Some(peer);
+    }
+    let header = network
+        .real_ip_header
+        .as_deref()
+        .unwrap_or("x-forwarded-for");
+    let forwarded = req
+        .headers()
+        .get_all(header)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|ip| ip.trim().parse::<IpAddr>().ok())
+        .collect::<Vec<_>>();
+    let mut ip = peer;
+    for forwarded in forwarded.into_iter().rev() {
+        match forwarded {
+            Some(forwarded) if trusted(&ip) => ip = forwarded,
+            _ => break,
+        }
+    }
+    Some(ip)
 }
 
 #[derive(Debug)]
 pub struct AuthedUser {
     pub user: user::Model,
+    pub session: session::Model,
 }
 
 impl AuthedUser {
-    pub async fn from_token(token: &str, state: &AppState) -> Result<Self, Error> {
-        let token = JwtToken::from_str(token, state.setting.auth.secret.as_bytes())?;
+    /// create a new session of the signed in user, by the lndhub credential if set
+    pub async fn login(
+        user: user::Model,
+        credential: Option<&lndhub_credential::Model>,
+        state: &AppState,
+        req: &HttpRequest,
+        device: Option<&str>,
+    ) -> Result<Self, Error> {
+        let (ip, device) = client_info(req, device);
+        let session = state
+            .service
+            .create_session(
+                user.id,
+                credential.map(|c| c.id).unwrap_or_default(),
+                &device,
+                &ip,
+                state.setting.auth.refresh_token_expiry as i64,
+            )
+            .await?;
+        Ok(Self { user, session })
+    }
+
+    /// authorize by the access token of an active session
+    pub async fn from_token(token: &str, state: &AppState, ip: &str) -> Result<Self, Error> {
+        let token = JwtToken::from_str(
+            token,
+            TokenType::Access,
+            state.setting.auth.secret.as_bytes(),
+        )?;
+        let session = state.service.get_active_session(token.sid).await?;
+        if session.user_id != token.user_id {
+            return Err(AuthError::Invalid("Invalid session").into());
+        }
+        state.service.touch_session(&session, ip).await?;
         let user = state.service.get_user_by_id(token.user_id).await?;
-        Ok(Self { user })
+        Ok(Self { user, session })
+    }
+
+    /// rotate the session by the refresh token
+    pub async fn refresh(token: &str, state: &AppState, ip: &str) -> Result<Self, Error> {
+        let token = JwtToken::from_str(
+            token,
+            TokenType::Refresh,
+            state.setting.auth.secret.as_bytes(),
+        )?;
+        let session = state
+            .service
+            .refresh_session(
+                token.sid,
+                &token.jti,
+                ip,
+                state.setting.auth.refresh_token_expiry as i64,
+            )
+            .await?;
+        let user = state.service.get_user_by_id(session.user_id).await?;
+        Ok(Self { user, session })
+    }
+
+    /// the new token pair of the session, (refresh token, access token)
+    pub fn tokens(&self, setting: &Auth) -> Result<(String, String), AuthError> {
+        JwtToken::generate_pair(&self.session, setting)
     }
 }
 
@@ -72,7 +252,8 @@ impl FromRequest for AuthedUser {
                     if let Ok(auth) = auth.to_str() {
                         if auth.starts_with("bearer") || auth.starts_with("Bearer") {
                             let token = auth[6..auth.len()].trim();
-                            return AuthedUser::from_token(token, state).await;
+                            let (ip, _) = client_info(&req, None);
+                            return AuthedUser::from_token(token, state, &ip).await;
                         }
                     }
                 }
@@ -89,13 +270,17 @@ mod tests {
 
     #[tokio::test]
     async fn token() -> anyhow::Result<()> {
-        let token = JwtToken::generate(1, 3600, b"secret")?;
-        let auth = JwtToken::from_str(&token, b"secret")?;
+        let token = JwtToken::generate(1, TokenType::Access, 2, "id".to_owned(), 3600, b"secret")?;
+        let auth = JwtToken::from_str(&token, TokenType::Access, b"secret")?;
         assert_eq!(auth.user_id, 1);
+        assert_eq!(auth.sid, 2);
+        // an access token is not a refresh token
+        let res = JwtToken::from_str(&token, TokenType::Refresh, b"secret");
+        assert!(res.is_err());
         // expired
-        let token = JwtToken::generate(1, 1, b"secret")?;
+        let token = JwtToken::generate(1, TokenType::Access, 2, "id".to_owned(), 1, b"secret")?;
         tokio::time::sleep(Duration::from_secs(2)).await;
-        let res = JwtToken::from_str(&token, b"secret");
+        let res = JwtToken::from_str(&token, TokenType::Access, b"secret");
         assert!(res.is_err());
         Ok(())
     }
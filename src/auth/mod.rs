@@ -1,8 +1,14 @@
+mod admin;
+mod api_key;
 mod jwt;
 mod nostr;
+mod replay;
 
+pub use admin::*;
+pub use api_key::*;
 pub use jwt::*;
 pub use nostr::*;
+pub use replay::*;
 
 #[derive(thiserror::Error, Debug)]
 pub enum AuthError {
@@ -24,4 +30,6 @@ pub enum AuthError {
     // Unauthorized,
     #[error("Pubkey not in whitelist")]
     Whitelist,
+    #[error("Invalid nostr event, the event is already used")]
+    Replay,
 }
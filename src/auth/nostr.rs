@@ -1,14 +1,17 @@
-use super::AuthError;
+use super::{client_info, is_api_key, ApiKeyAuth, AuthError, AuthedUser};
 use crate::{full_uri_from_req, now, sha256, AppState, Error, Result};
 use actix_web::http::header::AUTHORIZATION;
 use actix_web::{dev::Payload, http::Uri, web, FromRequest, HttpRequest};
 use base64::engine::{general_purpose, Engine};
+use entity::api_key;
 use nostr_sdk::nostr::Event;
 use serde::de::DeserializeOwned;
 use std::{future::Future, pin::Pin};
 
 #[derive(Debug)]
 pub struct NostrAuth {
+    /// event id, empty for the bearer token
+    pub id: Vec<u8>,
     pub pubkey: Vec<u8>,
     pub url: Uri,
     pub method: String,
@@ -16,6 +19,8 @@ pub struct NostrAuth {
     pub created_at: i64,
     /// real payload
     pub payload: Vec<u8>,
+    /// authorized by the api key
+    pub api_key: Option<api_key::Model>,
 }
 
 impl NostrAuth {
@@ -29,6 +34,7 @@ impl NostrAuth {
         }
     }
     pub fn verify_http(&self, url: &Uri, method: &str) -> Result<(), AuthError> {
+        // the uri comparison includes the query string
         if url != &self.url {
             return Err(AuthError::InvalidEvent("Invalid nostr event, invalid url"));
         }
@@ -53,6 +59,21 @@ impl NostrAuth {
                     ));
                 }
             }
+        } else if method == "GET" && !self.payload.is_empty() {
+            return Err(AuthError::InvalidEvent(
+                "Invalid nostr event, unexpected payload",
+            ));
+        }
+        Ok(())
+    }
+
+    /// the account management requires the signed nostr event,
+    /// reject the bearer token of the lnurl-auth session and the api key
+    pub fn require_event(&self) -> Result<(), Error> {
+        if self.id.is_empty() {
+            return Err(Error::Restricted(
+                "The nostr event auth is required".to_owned(),
+            ));
         }
         Ok(())
     }
@@ -100,12 +121,14 @@ impl NostrAuth {
         }
 
         Ok(Self {
+            id: event.id.as_bytes().to_vec(),
             pubkey: event.pubkey.serialize().to_vec(),
             url: url.unwrap(),
             method: method.unwrap(),
             payload_sha,
             created_at: event.created_at.as_i64(),
             payload,
+            api_key: None,
         })
     }
 }
@@ -137,7 +160,46 @@ impl FromRequest for NostrAuth {
 
                             user.verify_time(60)?;
                             user.verify_http(&full_uri_from_req(&req), req.method().as_str())?;
+                            if !state
+                                .service
+                                .record_auth_event(&user.id, user.created_at + 60)
+                                .await?
+                            {
+                                return Err(AuthError::Replay.into());
+                            }
                             return Ok(user);
+                        } else if auth.starts_with("Bearer") || auth.starts_with("bearer") {
+                            // jwt token of the user signed in without nostr, such as lnurl-auth,
+                            // or the api key
+                            let bytes = web::Bytes::from_request(&req, &mut payload)
+                                .await
+                                .map_err(|e| Error::Message(e.to_string()))?;
+                            let token = auth[6..auth.len()].trim();
+                            let (user, api_key) = if is_api_key(token) {
+                                let authed = ApiKeyAuth::from_token(token, state, &req).await?;
+                                (authed.user, Some(authed.key))
+                            } else {
+                                let (ip, _) = client_info(&req, None);
+                                let authed = AuthedUser::from_token(token, state, &ip).await?;
+                                // the lndhub sessions are limited to the lndhub api
+                                if authed.session.credential_id > 0 {
+                                    return Err(Error::Restricted(
+                                        "The lndhub session is not allowed".to_owned(),
+                                    ));
+                                }
+                                (authed.user, None)
+                            };
+                            state.setting.auth.check_permission(&user.pubkey)?;
+                            return Ok(Self {
+                                id: vec![],
+                                pubkey: user.pubkey,
+                                url: full_uri_from_req(&req),
+                                method: req.method().to_string(),
+                                payload_sha: None,
+                                created_at: now() as i64,
+                                payload: bytes.to_vec(),
+                                api_key,
+                            });
                         }
                     } else {
                         return Err(Error::Str("AppState required"));
@@ -203,6 +265,30 @@ mod tests {
         assert!(user.verify_http(&"url1".parse()?, "GET").is_err());
         assert!(user.verify_http(&"url".parse()?, "POST").is_err());
 
+        let event = EventBuilder::new(
+            Kind::from(27235),
+            "",
+            &[
+                Tag::try_from(vec!["u", "http://localhost/v1/my?a=1"])?,
+                Tag::try_from(vec!["method", "GET"])?,
+            ],
+        )
+        .to_event(&alice_keys)?;
+        let encoded = general_purpose::STANDARD.encode(event.as_json());
+        let user: NostrAuth = NostrAuth::from_token(&encoded, vec![])?;
+        assert_eq!(user.id, event.id.as_bytes().to_vec());
+        user.verify_http(&"http://localhost/v1/my?a=1".parse()?, "GET")?;
+        assert!(user
+            .verify_http(&"http://localhost/v1/my".parse()?, "GET")
+            .is_err());
+        assert!(user
+            .verify_http(&"http://localhost/v1/my?a=2".parse()?, "GET")
+            .is_err());
+        let user: NostrAuth = NostrAuth::from_token(&encoded, b"{}".to_vec())?;
+        assert!(user
+            .verify_http(&"http://localhost/v1/my?a=1".parse()?, "GET")
+            .is_err());
+
         let body = b"{}".to_vec();
         let event = EventBuilder::new(
             Kind::from(27235),
@@ -0,0 +1,89 @@
+use crate::{Error, Result};
+use parking_lot::Mutex;
+use std::collections::{BTreeSet, HashMap};
+
+/// Bounded in-memory store of the seen auth event ids until they expire.
+/// The new ids are rejected when the store is full, an unexpired id is never dropped.
+#[derive(Debug)]
+pub struct SeenEvents {
+    max: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    ids: HashMap<Vec<u8>, i64>,
+    expiry: BTreeSet<(i64, Vec<u8>)>,
+}
+
+impl Inner {
+    fn pop_first(&mut self) {
+        if let Some((_, id)) = self.expiry.pop_first() {
+            self.ids.remove(&id);
+        }
+    }
+}
+
+impl Default for SeenEvents {
+    fn default() -> Self {
+        Self::new(100_000)
+    }
+}
+
+impl SeenEvents {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Record the id until the expiry time, false if it is already seen,
+    /// error if the store is full of the unexpired ids.
+    pub fn insert(&self, id: &[u8], expires_at: i64, now: i64) -> Result<bool> {
+        let mut inner = self.inner.lock();
+        while inner.expiry.first().is_some_and(|first| first.0 <= now) {
+            inner.pop_first();
+        }
+        if inner.ids.contains_key(id) {
+            return Ok(false);
+        }
+        if inner.ids.len() >= self.max {
+            return Err(Error::QuotaExceeded(
+                "Too many auth events, try again later".to_owned(),
+            ));
+        }
+        inner.ids.insert(id.to_vec(), expires_at);
+        inner.expiry.insert((expires_at, id.to_vec()));
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seen() -> Result<()> {
+        let seen = SeenEvents::new(2);
+        assert!(seen.insert(b"a", 10, 0)?);
+        assert!(!seen.insert(b"a", 10, 5)?);
+        assert!(seen.insert(b"b", 20, 5)?);
+        // full of the unexpired ids, reject the new id and keep the seen ones
+        assert!(matches!(
+            seen.insert(b"c", 30, 5),
+            Err(Error::QuotaExceeded(_))
+        ));
+        assert_eq!(seen.inner.lock().ids.len(), 2);
+        assert!(!seen.insert(b"a", 10, 5)?);
+        assert!(!seen.insert(b"b", 20, 5)?);
+        // expired
+        assert!(seen.insert(b"c", 30, 10)?);
+        assert_eq!(seen.inner.lock().ids.len(), 2);
+        assert!(seen.insert(b"a", 40, 25)?);
+        assert!(!seen.insert(b"c", 30, 25)?);
+        assert!(seen.insert(b"b", 40, 35)?);
+        assert!(!seen.insert(b"a", 40, 35)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+//! In-process wallet event bus, used to push the changes to the clients.
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+const CAPACITY: usize = 1024;
+
+/// Wallet event of a user, amounts in msats
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct WalletEvent {
+    #[serde(skip)]
+    pub user_id: i32,
+    #[serde(flatten)]
+    pub kind: WalletEventKind,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WalletEventKind {
+    InvoicePaid {
+        id: i32,
+        payment_hash: String,
+        amount: i64,
+    },
+    PaymentSucceeded {
+        id: i32,
+        payment_hash: String,
+        total: i64,
+    },
+    PaymentFailed {
+        id: i32,
+        payment_hash: String,
+    },
+    ZapReceipt {
+        id: i32,
+        receipt: String,
+    },
+    Balance {
+        balance: i64,
+        lock_amount: i64,
+    },
+}
+
+/// Broadcast the wallet events to all subscribers,
+/// slow subscribers may miss the events when lagged.
+#[derive(Debug, Clone)]
+pub struct Bus {
+    sender: broadcast::Sender<WalletEvent>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self { sender }
+    }
+}
+
+impl Bus {
+    pub fn publish(&self, user_id: i32, kind: WalletEventKind) {
+        // no subscribers
+        let _r = self.sender.send(WalletEvent { user_id, kind });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<WalletEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+}
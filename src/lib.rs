@@ -4,13 +4,17 @@ use actix_web::{
 use serde_json::json;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod admin;
 pub mod api;
 mod app;
 mod auth;
+pub mod bus;
 mod hash;
 pub mod lndhub;
 pub mod lnurl;
+pub mod lnurl_client;
 pub mod nip05;
+pub mod nip44;
 pub mod nwc;
 mod service;
 pub mod setting;
@@ -19,7 +23,11 @@ pub mod key;
 
 pub use {
     app::*,
-    service::{InvoiceExtra, Service},
+    auth::ApiKeyScope,
+    service::{
+        AddressProfile, ApiKeyPolicy, InvoiceExtra, NwcPolicy, Reconciliation, Service,
+        WithdrawPolicy,
+    },
 };
 
 #[derive(thiserror::Error, Debug)]
@@ -56,27 +64,47 @@ pub enum Error {
     AddrParseError(#[from] std::net::AddrParseError),
     #[error(transparent)]
     Auth(#[from] auth::AuthError),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
     #[error("{0}")]
     Message(String),
     #[error("{0}")]
     Str(&'static str),
     #[error("{0}")]
     InvalidPayment(String),
+    /// the payment id
     #[error("Payment is being processed, please check the result later")]
-    PaymentInProgress,
+    PaymentInProgress(i32),
     #[error("The wallet does not have enough funds")]
     InsufficientBalance,
     #[error("Rate limiter exceeded")]
     RateLimited,
     #[error("{0}")]
     InvalidParam(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Restricted(String),
+    #[error("{0}")]
+    QuotaExceeded(String),
+    #[error("Not implemented")]
+    NotImplemented,
+    #[error("nip44: {0}")]
+    Nip44(&'static str),
+    #[error("Unsupported encryption")]
+    UnsupportedEncryption,
+    /// error of the remote lnurl service
+    #[error("lnurl: {0}")]
+    Lnurl(String),
 }
 
 impl ResponseError for Error {
     fn status_code(&self) -> StatusCode {
         match self {
             Error::Auth(_) => StatusCode::UNAUTHORIZED,
-            Error::InvalidParam(_) => StatusCode::BAD_REQUEST,
+            Error::InvalidParam(_) | Error::Lnurl(_) => StatusCode::BAD_REQUEST,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Restricted(_) => StatusCode::FORBIDDEN,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -1,20 +1,20 @@
 //! lnd hub api
 
 use crate::{
-    auth::{AuthError, AuthedUser, JwtToken},
-    AppState, Error, InvoiceExtra, Result,
+    auth::{client_info, is_api_key, limit_spend, ApiKeyAuth, AuthError, AuthedUser},
+    lnurl_client, AppState, Error, InvoiceExtra, Result,
 };
 use actix_web::{
-    dev::Payload, get, http::StatusCode, post, web, FromRequest, HttpRequest, HttpResponse,
-    Responder, ResponseError,
+    dev::Payload, get, http::header::AUTHORIZATION, http::StatusCode, post, web, FromRequest,
+    HttpRequest, HttpResponse, Responder, ResponseError,
 };
-use entity::{invoice, user};
+use entity::{api_key, invoice, user};
 use lightning_client::lightning;
 use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
 use serde::{Deserialize, Serialize};
 use serde_aux::prelude::deserialize_number_from_string;
 use serde_json::json;
-use std::{future::Future, pin::Pin};
+use std::{collections::HashMap, future::Future, pin::Pin};
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(get_info)
@@ -25,22 +25,27 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
         .service(get_btc)
         .service(get_pending)
         .service(pay_invoice)
+        .service(keysend)
         .service(get_txs)
         .service(check_payment);
 }
 
 /// Lndhub authed user.
-/// Requires password already set.
+/// Requires an active lndhub credential, signed in by lnurl-auth, or an api key.
 #[derive(Debug)]
 pub struct LndhubAuthedUser {
     pub user: user::Model,
+    /// authorized by the api key
+    pub api_key: Option<api_key::Model>,
 }
 
-impl TryFrom<AuthedUser> for LndhubAuthedUser {
-    type Error = LndhubError;
-    fn try_from(user: AuthedUser) -> Result<Self, LndhubError> {
-        if user.user.password.is_some() {
-            Ok(LndhubAuthedUser { user: user.user })
+impl LndhubAuthedUser {
+    pub async fn from_user(user: user::Model, state: &AppState) -> Result<Self, LndhubError> {
+        if state.service.lndhub_enabled(&user).await? {
+            Ok(LndhubAuthedUser {
+                user,
+                api_key: None,
+            })
         } else {
             Err(Error::from(AuthError::Invalid("Unauthorized")).into())
         }
@@ -51,8 +56,29 @@ impl FromRequest for LndhubAuthedUser {
     type Error = LndhubError;
     type Future = Pin<Box<dyn Future<Output = Result<LndhubAuthedUser, LndhubError>>>>;
     fn from_request(req: &HttpRequest, pl: &mut Payload) -> Self::Future {
+        let api_key = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.len() > 6 && is_api_key(value[6..].trim()))
+            .is_some();
+        if api_key {
+            let fut = ApiKeyAuth::from_request(req, pl);
+            return Box::pin(async move {
+                let authed = fut.await?;
+                Ok(LndhubAuthedUser {
+                    user: authed.user,
+                    api_key: Some(authed.key),
+                })
+            });
+        }
         let fut = AuthedUser::from_request(req, pl);
-        Box::pin(async move { LndhubAuthedUser::try_from(fut.await?) })
+        let state = req.app_data::<web::Data<AppState>>().cloned();
+        Box::pin(async move {
+            let user = fut.await?;
+            let state = state.ok_or(Error::Str("AppState required"))?;
+            LndhubAuthedUser::from_user(user.user, &state).await
+        })
     }
 }
 
@@ -77,6 +103,13 @@ impl LndhubError {
     }
 }
 
+/// The msats of the amount in sats, the msats must fit in the balance.
+fn to_msats(sats: u64) -> Result<u64, LndhubError> {
+    sats.checked_mul(1000)
+        .filter(|msats| *msats <= i64::MAX as u64)
+        .ok_or(LndhubError::BadArguments)
+}
+
 impl ResponseError for LndhubError {
     fn status_code(&self) -> StatusCode {
         StatusCode::OK
@@ -146,6 +179,8 @@ pub struct AuthReq {
     login: String,
     password: String,
     refresh_token: String,
+    /// label of the session, defaults to the user agent
+    device: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -157,46 +192,43 @@ pub struct AuthRes {
 
 #[post("/auth")]
 pub async fn auth(
+    req: HttpRequest,
     state: web::Data<AppState>,
     data: web::Json<AuthReq>,
 ) -> Result<HttpResponse, LndhubError> {
-    let user = if !data.refresh_token.is_empty() {
-        // auth from refresh token
-        let user = AuthedUser::from_token(&data.refresh_token, &state).await?;
-        LndhubAuthedUser::try_from(user)?.user
+    let authed = if !data.refresh_token.is_empty() {
+        // rotate the tokens of the session
+        let (ip, _) = client_info(&req, None);
+        let authed = AuthedUser::refresh(&data.refresh_token, &state, &ip).await?;
+        LndhubAuthedUser::from_user(authed.user.clone(), &state).await?;
+        authed
     } else if !data.login.is_empty() && !data.password.is_empty() {
         let user = state
             .service
             .get_user(hex::decode(&data.login).map_err(Error::from)?)
             .await?;
-        if let Some(user) = user {
-            if user.password.as_ref() == Some(&data.password) {
-                user
-            } else {
-                return Err(LndhubError::BadAuth);
-            }
-        } else {
-            return Err(LndhubError::BadAuth);
-        }
+        let user = user.ok_or(LndhubError::BadAuth)?;
+        let credential = state
+            .service
+            .verify_lndhub_password(&user, &data.password)
+            .await?
+            .ok_or(LndhubError::BadAuth)?;
+        state.setting.auth.check_permission(&user.pubkey)?;
+        AuthedUser::login(
+            user,
+            Some(&credential),
+            &state,
+            &req,
+            Some(data.device.as_deref().unwrap_or("lndhub")),
+        )
+        .await?
     } else {
         return Err(LndhubError::BadArguments);
     };
 
-    state.setting.auth.check_permission(&user.pubkey)?;
-
-    let refresh_token = JwtToken::generate(
-        user.id,
-        state.setting.auth.refresh_token_expiry,
-        state.setting.auth.secret.as_bytes(),
-    )
-    .map_err(Error::from)?;
+    state.setting.auth.check_permission(&authed.user.pubkey)?;
 
-    let access_token = JwtToken::generate(
-        user.id,
-        state.setting.auth.access_token_expiry,
-        state.setting.auth.secret.as_bytes(),
-    )
-    .map_err(Error::from)?;
+    let (refresh_token, access_token) = authed.tokens(&state.setting.auth).map_err(Error::from)?;
 
     Ok(HttpResponse::Ok().json(AuthRes {
         refresh_token,
@@ -258,6 +290,7 @@ pub async fn add_invoice(
     if data.amt == 0 {
         return Err(LndhubError::BadArguments);
     }
+    let msats = to_msats(data.amt)?;
     let expiry = 3600 * 24; // one day
     let source = invoice::Source::Lndhub;
     let invoice = state
@@ -265,7 +298,7 @@ pub async fn add_invoice(
         .create_invoice(
             &user.user,
             data.memo.clone(),
-            data.amt * 1000,
+            msats,
             expiry,
             InvoiceExtra::new(source),
         )
@@ -316,16 +349,83 @@ pub async fn pay_invoice(
     data: web::Json<PayInvoiceReq>,
     user: LndhubAuthedUser,
 ) -> Result<impl Responder, LndhubError> {
-    let payment = state
-        .service
-        .pay(
+    // lightning address or lnurl-pay code with the amount in sats
+    let payment = if lnurl_client::is_lnurl(&data.invoice) {
+        if data.amount == 0 {
+            return Err(LndhubError::BadArguments);
+        }
+        let msats = to_msats(data.amount)?;
+        let pay = state.service.pay_lnurl(
             &user.user,
             data.invoice.clone(),
+            msats,
+            None,
+            &state.setting.fee,
+            invoice::Source::Lndhub,
+        );
+        limit_spend(&state, user.api_key.as_ref(), msats as i64, pay).await?
+    } else {
+        // the amount in sats of the offer without amount
+        let msats = if lightning::is_offer(&data.invoice) && data.amount > 0 {
+            Some(to_msats(data.amount)?)
+        } else {
+            None
+        };
+        let payable = state
+            .service
+            .resolve_payable(data.invoice.clone(), msats)
+            .await?;
+        let amount = payable.amount();
+        let pay = state.service.pay_payable(
+            &user.user,
+            payable,
             &state.setting.fee,
             invoice::Source::Lndhub,
             false,
-        )
-        .await?;
+        );
+        limit_spend(&state, user.api_key.as_ref(), amount as i64, pay).await?
+    };
+    Ok(web::Json(PayRes::from(payment)))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct KeysendReq {
+    /// hex node pubkey
+    destination: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    amount: u64,
+    /// custom record type to the utf8 value
+    #[serde(rename = "customRecords")]
+    custom_records: HashMap<String, String>,
+}
+
+#[post("/keysend")]
+pub async fn keysend(
+    state: web::Data<AppState>,
+    data: web::Json<KeysendReq>,
+    user: LndhubAuthedUser,
+) -> Result<impl Responder, LndhubError> {
+    let data = data.into_inner();
+    if data.amount == 0 {
+        return Err(LndhubError::BadArguments);
+    }
+    let msats = to_msats(data.amount)?;
+    let pubkey = hex::decode(&data.destination).map_err(|_| LndhubError::BadArguments)?;
+    let mut records = vec![];
+    for (t, v) in data.custom_records {
+        let t = t.parse::<u64>().map_err(|_| LndhubError::BadArguments)?;
+        records.push((t, v.into_bytes()));
+    }
+    let pay = state.service.keysend(
+        &user.user,
+        pubkey,
+        msats,
+        records,
+        &state.setting.fee,
+        invoice::Source::Lndhub,
+    );
+    let payment = limit_spend(&state, user.api_key.as_ref(), msats as i64, pay).await?;
     Ok(web::Json(PayRes::from(payment)))
 }
 
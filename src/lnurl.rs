@@ -1,27 +1,32 @@
 //! lnurl api
 
 use crate::{
+    bus::WalletEventKind,
     full_uri_from_req,
     service::{InvoicePayer, InvoiceZap},
-    AppState, Error, InvoiceExtra, Result,
+    setting, AppState, Error, InvoiceExtra, Result,
 };
 use actix_web::{
     get, http::StatusCode, http::Uri, web, HttpRequest, HttpResponse, Responder, ResponseError,
     Scope,
 };
-use entity::invoice;
+use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+use base64::engine::{general_purpose, Engine};
+use entity::{invoice, user_profile};
 use nostr_sdk::{
     prelude::{verify_delegation_signature, FromBech32},
     secp256k1::XOnlyPublicKey,
     Client, Event, EventId, Keys, Kind, Options, Tag, Timestamp, UnsignedEvent,
 };
+use rand::RngCore;
+use reqwest::Url;
 use sea_orm::{
     ActiveModelTrait, ColumnTrait, DbConn, EntityTrait, FromQueryResult, QueryFilter, QuerySelect,
     Set,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_aux::prelude::deserialize_number_from_string;
-use serde_json::json;
+use serde_json::{json, Value};
 use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
 use tokio::time::sleep;
 
@@ -47,21 +52,162 @@ impl ResponseError for LnurlError {
 }
 
 pub fn scope() -> Scope {
-    web::scope("/lnurlp").service(info).service(create_invoice)
+    web::scope("/lnurlp")
+        .service(info)
+        .service(create_invoice)
+        .service(verify)
 }
 
-fn metadata(host: &str, username: &String) -> Result<String> {
+pub fn withdraw_scope() -> Scope {
+    web::scope("/lnurlw")
+        .service(withdraw_info)
+        .service(withdraw)
+}
+
+const SUCCESS_ACTION_MAX_CHARS: usize = 144;
+const SUCCESS_ACTION_MAX_PLAINTEXT: usize = 2048;
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+
+/// LUD-09 success action of the lightning address configured by the user.
+/// The aes plaintext is encrypted by the preimage of each invoice (LUD-10).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "tag", rename_all = "lowercase")]
+pub enum SuccessAction {
+    Message {
+        message: String,
+    },
+    Url {
+        description: String,
+        url: String,
+    },
+    Aes {
+        description: String,
+        plaintext: String,
+    },
+}
+
+impl Default for SuccessAction {
+    fn default() -> Self {
+        Self::Message {
+            message: "Thank you for your sats!".to_owned(),
+        }
+    }
+}
+
+impl SuccessAction {
+    pub fn validate(&self) -> Result<()> {
+        let too_long = |name: &str, max: usize| {
+            Err(Error::InvalidParam(format!(
+                "The length of the {} cannot be greater than {}",
+                name, max
+            )))
+        };
+        match self {
+            Self::Message { message } => {
+                if message.chars().count() > SUCCESS_ACTION_MAX_CHARS {
+                    return too_long("message", SUCCESS_ACTION_MAX_CHARS);
+                }
+            }
+            Self::Url { description, url } => {
+                if description.chars().count() > SUCCESS_ACTION_MAX_CHARS {
+                    return too_long("description", SUCCESS_ACTION_MAX_CHARS);
+                }
+                let valid = Url::parse(url)
+                    .map(|u| matches!(u.scheme(), "https" | "http") && u.host_str().is_some())
+                    .unwrap_or_default();
+                if !valid {
+                    return Err(Error::InvalidParam("Invalid url".to_owned()));
+                }
+            }
+            Self::Aes {
+                description,
+                plaintext,
+            } => {
+                if description.chars().count() > SUCCESS_ACTION_MAX_CHARS {
+                    return too_long("description", SUCCESS_ACTION_MAX_CHARS);
+                }
+                if plaintext.len() > SUCCESS_ACTION_MAX_PLAINTEXT {
+                    return too_long("plaintext", SUCCESS_ACTION_MAX_PLAINTEXT);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// the success action of the callback response for the invoice preimage
+    pub fn response(&self, preimage: &[u8]) -> Result<Value> {
+        Ok(match self {
+            Self::Aes {
+                description,
+                plaintext,
+            } => {
+                let mut iv = [0u8; 16];
+                rand::thread_rng().fill_bytes(&mut iv);
+                let ciphertext = Aes256CbcEnc::new_from_slices(preimage, &iv)
+                    .map_err(|_| Error::Str("invalid preimage"))?
+                    .encrypt_padded_vec_mut::<Pkcs7>(plaintext.as_bytes());
+                json!({
+                    "tag": "aes",
+                    "description": description,
+                    "ciphertext": general_purpose::STANDARD.encode(ciphertext),
+                    "iv": general_purpose::STANDARD.encode(iv),
+                })
+            }
+            action => serde_json::to_value(action)?,
+        })
+    }
+}
+
+pub(crate) fn metadata(
+    host: &str,
+    username: &str,
+    profile: Option<&user_profile::Model>,
+) -> Result<String> {
     let id = format!("{}@{}", username, host);
-    let metadata = json!([
-        [
-            "text/plain", // mandatory,
-            "Sats for "
-        ],
-        ["text/identifier", id], // lud16 mandatory
-    ]);
+    let description = profile
+        .and_then(|p| p.description.as_deref())
+        .unwrap_or("Sats for ");
+    let mut metadata = vec![
+        json!(["text/plain", description]), // mandatory
+        json!(["text/identifier", id]),     // lud16 mandatory
+    ];
+    if let Some(desc) = profile.and_then(|p| p.long_description.as_deref()) {
+        metadata.push(json!(["text/long-desc", desc]));
+    }
+    if let Some(avatar) = profile.and_then(|p| p.avatar.as_deref()) {
+        metadata.push(json!(["image/png;base64", avatar]));
+    }
     Ok(serde_json::to_string(&metadata)?)
 }
 
+/// min sendable, max sendable and comment allowed of the lightning address
+fn limits(setting: &setting::Lnurl, profile: Option<&user_profile::Model>) -> (u64, u64, usize) {
+    (
+        profile
+            .and_then(|p| p.min_sendable)
+            .map_or(setting.min_sendable, |v| v as u64),
+        profile
+            .and_then(|p| p.max_sendable)
+            .map_or(setting.max_sendable, |v| v as u64),
+        profile
+            .and_then(|p| p.comment_allowed)
+            .map_or(setting.comment_allowed, |v| v as usize),
+    )
+}
+
+/// the profile of the lightning address, don't create account
+async fn address_profile(state: &AppState, username: &str) -> Result<Option<user_profile::Model>> {
+    let user = match XOnlyPublicKey::from_bech32(username) {
+        Ok(key) => state.service.get_user(key.serialize().to_vec()).await?,
+        Err(_) => state.service.get_user_by_name(username.to_owned()).await?,
+    };
+    match user {
+        Some(user) => state.service.get_user_profile(user.id).await,
+        None => Ok(None),
+    }
+}
+
 fn host_from_uri(uri: &Uri) -> &str {
     uri.authority().map(|a| a.as_str()).unwrap_or("")
 }
@@ -101,14 +247,17 @@ pub async fn info(
     };
     let uri = full_uri_from_req(&req);
 
-    let metadata = metadata(host_from_uri(&uri), &username)?;
+    let profile = address_profile(&state, &username).await?;
+    let (min_sendable, max_sendable, comment_allowed) =
+        limits(&state.setting.lnurl, profile.as_ref());
+    let metadata = metadata(host_from_uri(&uri), &username, profile.as_ref())?;
     Ok(web::Json(json!({
         "tag": "payRequest",
         "status": "OK",
         "metadata": metadata,
-        "commentAllowed": state.setting.lnurl.comment_allowed,
-        "maxSendable": state.setting.lnurl.max_sendable,
-        "minSendable": state.setting.lnurl.min_sendable,
+        "commentAllowed": comment_allowed,
+        "maxSendable": max_sendable,
+        "minSendable": min_sendable,
         "callback": format!("{}/callback", uri),
         "allowsNostr": allow,
         "nostrPubkey": pubkey,
@@ -146,13 +295,15 @@ pub async fn create_invoice(
     let uri = full_uri_from_req(&req);
 
     let username = username.into_inner();
-    let setting = &state.setting.lnurl;
+    let profile = address_profile(&state, &username).await?;
+    let (min_sendable, max_sendable, comment_allowed) =
+        limits(&state.setting.lnurl, profile.as_ref());
     let amount = query.amount;
-    if amount < setting.min_sendable || amount > setting.max_sendable {
+    if amount < min_sendable || amount > max_sendable {
         return Err(LnurlError::Invalid(format!(
             "Amount out of bounds (min: {} sat, max: {} sat).",
-            state.setting.lnurl.min_sendable / 1000,
-            state.setting.lnurl.max_sendable / 1000,
+            min_sendable / 1000,
+            max_sendable / 1000,
         )));
     }
 
@@ -164,10 +315,10 @@ pub async fn create_invoice(
 
     let comment = query.comment.clone();
     if let Some(comment) = &comment {
-        if comment.len() > setting.comment_allowed {
+        if comment.len() > comment_allowed {
             return Err(LnurlError::Invalid(format!(
                 "Comment too long (max: {} characters).",
-                setting.comment_allowed
+                comment_allowed
             )));
         }
     }
@@ -276,7 +427,7 @@ pub async fn create_invoice(
         // lud06, lud18 description hash
         let memo = format!(
             "{}{}",
-            metadata(host_from_uri(&uri), &username,)?,
+            metadata(host_from_uri(&uri), &username, profile.as_ref())?,
             query.payerdata.clone().unwrap_or_default(),
         );
         (memo, extra)
@@ -305,17 +456,147 @@ pub async fn create_invoice(
     let routes: Vec<String> = vec![];
 
     // lud-09 successAction
+    let action = user
+        .success_action
+        .as_deref()
+        .and_then(|s| serde_json::from_str::<SuccessAction>(s).ok())
+        .unwrap_or_default();
+
+    // lud-21 verify
+    let verify_url = format!(
+        "{}://{}{}/verify/{}",
+        uri.scheme_str().unwrap_or("https"),
+        host_from_uri(&uri),
+        uri.path().trim_end_matches("/callback"),
+        hex::encode(&invoice.payment_hash)
+    );
+
     Ok(web::Json(json!({
         "status": "OK",
         "routes": routes,
         "pr": invoice.bolt11,
-        "successAction": {
-            "tag": "message",
-            "message": "Thank you for your sats!"
-        }
+        "successAction": action.response(&invoice.payment_preimage)?,
+        "verify": verify_url,
+    })))
+}
+
+// LUD-21 lnurlp/{usename}/verify/{payment_hash}
+
+#[get("/{usename}/verify/{payment_hash}")]
+pub async fn verify(
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> Result<impl Responder, LnurlError> {
+    let (username, payment_hash) = path.into_inner();
+    let not_found = || LnurlError::Invalid("Not found".to_owned());
+    let payment_hash = hex::decode(payment_hash).map_err(|_| not_found())?;
+    // don't create account
+    let user = match XOnlyPublicKey::from_bech32(&username) {
+        Ok(key) => state.service.get_user(key.serialize().to_vec()).await?,
+        Err(_) => state.service.get_user_by_name(username).await?,
+    }
+    .ok_or_else(not_found)?;
+    let invoice = state
+        .service
+        .get_lnurl_invoice(user.id, payment_hash)
+        .await?
+        .ok_or_else(not_found)?;
+    let settled = invoice.status == invoice::Status::Paid;
+    Ok(web::Json(json!({
+        "status": "OK",
+        "settled": settled,
+        "preimage": settled.then(|| hex::encode(&invoice.payment_preimage)),
+        "pr": invoice.bolt11,
     })))
 }
 
+// LUD-03 lnurlw/{k1}
+// k1: the secret of the withdraw link
+
+#[get("/{k1}")]
+pub async fn withdraw_info(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    k1: web::Path<String>,
+) -> Result<impl Responder, LnurlError> {
+    let link = state.service.get_withdraw_link(&k1).await?;
+    let mut max = link.max_withdrawable;
+    if link.total_cap > 0 {
+        max = max.min(link.total_cap - link.withdrawn);
+    }
+    let uri = full_uri_from_req(&req);
+    Ok(web::Json(json!({
+        "tag": "withdrawRequest",
+        "callback": format!("{}/callback", uri),
+        "k1": link.k1,
+        "defaultDescription": link.title,
+        "minWithdrawable": link.min_withdrawable,
+        "maxWithdrawable": max,
+    })))
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct WithdrawReq {
+    pub k1: String,
+    pub pr: String,
+}
+
+#[get("/{k1}/callback")]
+pub async fn withdraw(
+    state: web::Data<AppState>,
+    k1: web::Path<String>,
+    query: web::Query<WithdrawReq>,
+) -> Result<impl Responder, LnurlError> {
+    let k1 = k1.into_inner();
+    if query.k1 != k1 {
+        return Err(LnurlError::Invalid("Invalid k1".to_owned()));
+    }
+    let (link, amount) = state.service.reserve_withdraw(&k1, &query.pr).await?;
+    // the wallet waits for the payment by itself, respond without waiting for the pay
+    let service = state.service.clone();
+    let fee = state.setting.fee.clone();
+    let pr = query.pr.clone();
+    tokio::spawn(async move {
+        match service.pay_withdraw(&link, pr, amount, &fee).await {
+            Ok(_) | Err(Error::PaymentInProgress(_)) => {}
+            Err(e) => tracing::warn!("withdraw link {} pay failed {:?}", link.id, e),
+        }
+    });
+    Ok(web::Json(json!({ "status": "OK" })))
+}
+
+// LUD-04 lnurla?tag=login&k1={k1}&action={action}&sig={sig}&key={key}
+// sig: DER signature of k1 by the linking key
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct AuthReq {
+    pub tag: String,
+    pub k1: String,
+    pub sig: String,
+    pub key: String,
+}
+
+#[get("/lnurla")]
+pub async fn auth(
+    state: web::Data<AppState>,
+    query: web::Query<AuthReq>,
+) -> Result<impl Responder, LnurlError> {
+    if query.tag != "login" {
+        return Err(LnurlError::Invalid("Invalid tag".to_owned()));
+    }
+    let decode = |s: &String, name: &str| {
+        hex::decode(s).map_err(|_| LnurlError::Invalid(format!("Invalid {}", name)))
+    };
+    let k1 = decode(&query.k1, "k1")?;
+    let sig = decode(&query.sig, "sig")?;
+    let key = decode(&query.key, "key")?;
+    let user = state.service.lnurl_auth(&k1, &key, &sig).await?;
+    state.setting.auth.check_permission(&user.pubkey)?;
+    Ok(web::Json(json!({ "status": "OK" })))
+}
+
 pub async fn loop_handle_receipts(state: Arc<AppState>, duration: Duration) -> Result<()> {
     loop {
         // TODO: log error
@@ -328,6 +609,7 @@ pub async fn loop_handle_receipts(state: Arc<AppState>, duration: Duration) -> R
 #[derive(FromQueryResult, Debug)]
 struct PartInvoice {
     id: i32,
+    user_id: i32,
     bolt11: String,
     description: String,
     paid_at: i64,
@@ -343,6 +625,7 @@ pub async fn handle_receipts(state: &AppState) -> Result<usize> {
         .select_only()
         .columns([
             invoice::Column::Id,
+            invoice::Column::UserId,
             invoice::Column::Bolt11,
             invoice::Column::Zap,
             invoice::Column::Status,
@@ -362,7 +645,14 @@ pub async fn handle_receipts(state: &AppState) -> Result<usize> {
     for invoice in &list {
         // TODO: log error
         let r = send_receipt(state.service.db(), invoice, &keys, relays, proxy).await;
-        if r.is_ok() {
+        if let Ok(receipt) = r {
+            state.service.bus().publish(
+                invoice.user_id,
+                WalletEventKind::ZapReceipt {
+                    id: invoice.id,
+                    receipt,
+                },
+            );
             success += 1;
         }
     }
@@ -375,7 +665,7 @@ async fn send_receipt(
     keys: &Keys,
     relays: &[String],
     proxy: Option<&String>,
-) -> Result<()> {
+) -> Result<String> {
     let pubkey = keys.public_key();
 
     let event = Event::from_json(&invoice.description)?;
@@ -432,14 +722,14 @@ async fn send_receipt(
     // mark success
     invoice::ActiveModel {
         id: Set(invoice.id),
-        zap_receipt: Set(Some(event_json)),
+        zap_receipt: Set(Some(event_json.clone())),
         zap_status: Set(1),
         ..Default::default()
     }
     .update(db)
     .await?;
 
-    Ok(())
+    Ok(event_json)
 }
 
 async fn send_event(
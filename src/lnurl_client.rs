@@ -0,0 +1,395 @@
+//! lnurl client, resolve and pay the remote lnurl-pay services
+
+use crate::{sha256, Error, Result};
+use lightning_client::lightning;
+use nostr_sdk::bech32::{self, FromBase32, ToBase32, Variant};
+use reqwest::{redirect::Policy, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Check if the string is a LUD-16 lightning address or a LUD-06 / LUD-17 lnurl
+pub fn is_lnurl(s: &str) -> bool {
+    decode(s).is_ok()
+}
+
+/// Decode the lnurl to the url of the lnurl-pay service.
+///
+/// - LUD-06 bech32 `lnurl1...`
+/// - LUD-16 lightning address `name@domain`
+/// - LUD-17 `lnurlp://domain/path`
+pub fn decode(s: &str) -> Result<Url> {
+    let s = s.trim();
+    let s = s
+        .strip_prefix("lightning:")
+        .or_else(|| s.strip_prefix("LIGHTNING:"))
+        .unwrap_or(s);
+    let lower = s.to_lowercase();
+    let url = if lower.starts_with("lnurl1") {
+        let (hrp, data, _) = bech32::decode(&lower).map_err(|_| invalid())?;
+        if hrp != "lnurl" {
+            return Err(invalid());
+        }
+        let data = Vec::<u8>::from_base32(&data).map_err(|_| invalid())?;
+        String::from_utf8(data).map_err(|_| invalid())?
+    } else if let Some(rest) = lower.strip_prefix("lnurlp://") {
+        format!(
+            "{}://{}",
+            scheme(rest.split('/').next().unwrap_or_default()),
+            rest
+        )
+    } else if let Some((name, domain)) = lower.split_once('@') {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '-' | '_' | '.' | '+'))
+        {
+            return Err(invalid());
+        }
+        format!(
+            "{}://{}/.well-known/lnurlp/{}",
+            scheme(domain),
+            domain,
+            name
+        )
+    } else {
+        return Err(invalid());
+    };
+    let url = Url::parse(&url).map_err(|_| invalid())?;
+    if !matches!(url.scheme(), "https" | "http") || url.host_str().is_none() {
+        return Err(invalid());
+    }
+    Ok(url)
+}
+
+/// LUD-01 bech32 encode the url to `lnurl1...`
+pub fn encode(url: &str) -> String {
+    bech32::encode("lnurl", url.as_bytes().to_base32(), Variant::Bech32).unwrap_or_default()
+}
+
+fn invalid() -> Error {
+    Error::InvalidParam("Invalid lightning address or lnurl".to_owned())
+}
+
+fn not_allowed() -> Error {
+    Error::Lnurl("The lnurl host is not allowed".to_owned())
+}
+
+/// not the loopback, private, link-local or other special-purpose address
+fn is_global(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                // shared address space 100.64.0.0/10
+                || a == 100 && b & 0xc0 == 64
+                || a == 0
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_global(v4.into()),
+            None => {
+                let segment = ip.segments()[0];
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    // unique local fc00::/7 and link-local fe80::/10
+                    || segment & 0xfe00 == 0xfc00
+                    || segment & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// clearnet domains use https, onion domains use http
+fn scheme(domain: &str) -> &'static str {
+    if domain
+        .split(':')
+        .next()
+        .unwrap_or_default()
+        .ends_with(".onion")
+    {
+        "http"
+    } else {
+        "https"
+    }
+}
+
+/// Split the username of the lnurl-pay url served by satsbox,
+/// `/lnurlp/{username}` or `/.well-known/lnurlp/{username}`
+pub fn local_username(url: &Url) -> Option<String> {
+    let segments = url.path_segments()?.collect::<Vec<_>>();
+    match segments.as_slice() {
+        ["lnurlp", name] | [".well-known", "lnurlp", name] if !name.is_empty() => {
+            Some(name.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// the host with the port of the url
+pub fn authority(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned(),
+    }
+}
+
+/// LUD-18 payer data field
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PayerDataField {
+    pub mandatory: bool,
+}
+
+/// LUD-06 pay request of the lnurl-pay service, amounts in msats
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PayRequest {
+    pub callback: String,
+    pub min_sendable: u64,
+    pub max_sendable: u64,
+    pub metadata: String,
+    pub tag: String,
+    /// LUD-12 max comment length
+    #[serde(default)]
+    pub comment_allowed: usize,
+    /// LUD-18 requested payer data
+    #[serde(default)]
+    pub payer_data: Option<HashMap<String, PayerDataField>>,
+}
+
+/// LUD-06 callback response
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PayResponse {
+    pub pr: String,
+    /// LUD-09 success action
+    #[serde(default)]
+    pub success_action: Option<Value>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Client {
+    /// allow http and the private addresses, only for the local test services
+    pub insecure: bool,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build the http client to request the url without redirects,
+    /// http is allowed only for the onion hosts, the resolved addresses are pinned
+    /// and the loopback, private and link-local addresses are rejected.
+    async fn http(&self, url: &Url) -> Result<reqwest::Client> {
+        let builder = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .redirect(Policy::none());
+        if self.insecure {
+            return Ok(builder.build()?);
+        }
+        let host = url.host_str().ok_or_else(not_allowed)?;
+        let onion = host.ends_with(".onion");
+        if !(url.scheme() == "https" || url.scheme() == "http" && onion) {
+            return Err(not_allowed());
+        }
+        if onion {
+            // resolved by the tor proxy
+            return Ok(builder.build()?);
+        }
+        if let Ok(ip) = host
+            .trim_matches(|c| c == '[' || c == ']')
+            .parse::<IpAddr>()
+        {
+            if !is_global(ip) {
+                return Err(not_allowed());
+            }
+            return Ok(builder.build()?);
+        }
+        let port = url.port_or_known_default().unwrap_or(443);
+        let addrs = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|e| Error::Lnurl(e.to_string()))?
+            .collect::<Vec<SocketAddr>>();
+        if addrs.is_empty() || !addrs.iter().all(|a| is_global(a.ip())) {
+            return Err(not_allowed());
+        }
+        Ok(builder.resolve_to_addrs(host, &addrs).build()?)
+    }
+
+    /// get the json of the lnurl service, the `{"status": "ERROR"}` response is an error
+    async fn get<T: DeserializeOwned>(&self, url: Url) -> Result<T> {
+        let value: Value = self.http(&url).await?.get(url).send().await?.json().await?;
+        if value["status"].as_str() == Some("ERROR") {
+            return Err(Error::Lnurl(
+                value["reason"]
+                    .as_str()
+                    .unwrap_or("unknown error")
+                    .to_owned(),
+            ));
+        }
+        serde_json::from_value(value).map_err(|_| Error::Lnurl("Invalid response".to_owned()))
+    }
+
+    /// fetch the pay request of the lnurl-pay service
+    pub async fn pay_request(&self, url: Url) -> Result<PayRequest> {
+        let req: PayRequest = self.get(url).await?;
+        if req.tag != "payRequest" {
+            return Err(Error::Lnurl("Not a pay request".to_owned()));
+        }
+        Ok(req)
+    }
+
+    /// Request the invoice of the amount from the callback,
+    /// verify the amount and the description hash of the metadata and the payer data.
+    pub async fn invoice(
+        &self,
+        req: &PayRequest,
+        msats: u64,
+        comment: Option<&str>,
+        payer_data: Option<&str>,
+    ) -> Result<(lightning::Invoice, PayResponse)> {
+        if msats < req.min_sendable || msats > req.max_sendable {
+            return Err(Error::InvalidParam(format!(
+                "Amount out of bounds (min: {} sat, max: {} sat).",
+                req.min_sendable / 1000,
+                req.max_sendable / 1000,
+            )));
+        }
+        let comment = comment.filter(|c| !c.is_empty());
+        if let Some(comment) = comment {
+            if comment.len() > req.comment_allowed {
+                return Err(Error::InvalidParam(format!(
+                    "Comment too long (max: {} characters).",
+                    req.comment_allowed
+                )));
+            }
+        }
+        let mut url = Url::parse(&req.callback)
+            .map_err(|_| Error::Lnurl("Invalid callback url".to_owned()))?;
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("amount", &msats.to_string());
+            if let Some(comment) = comment {
+                query.append_pair("comment", comment);
+            }
+            if let Some(data) = payer_data {
+                query.append_pair("payerdata", data);
+            }
+        }
+        let res: PayResponse = self.get(url).await?;
+        let inv = lightning::Invoice::from_bolt11(res.pr.clone())
+            .map_err(|_| Error::Lnurl("Invalid invoice".to_owned()))?;
+        if inv.amount != msats {
+            return Err(Error::Lnurl("The invoice amount does not match".to_owned()));
+        }
+        let hash = sha256(format!(
+            "{}{}",
+            req.metadata,
+            payer_data.unwrap_or_default()
+        ));
+        if inv.description_hash.as_ref() != Some(&hash) {
+            return Err(Error::Lnurl(
+                "The invoice description hash does not match the metadata".to_owned(),
+            ));
+        }
+        Ok((inv, res))
+    }
+}
+
+/// Build the LUD-18 payer data requested by the service from the known fields,
+/// error if a mandatory field is unknown.
+pub fn payer_data(req: &PayRequest, known: &[(&str, Option<String>)]) -> Result<Option<String>> {
+    let fields = match &req.payer_data {
+        Some(fields) if !fields.is_empty() => fields,
+        _ => return Ok(None),
+    };
+    let mut data = Map::new();
+    for (name, field) in fields {
+        match known
+            .iter()
+            .find(|(k, _)| k == name)
+            .and_then(|(_, v)| v.clone())
+        {
+            Some(value) => {
+                data.insert(name.clone(), Value::String(value));
+            }
+            None if field.mandatory => {
+                return Err(Error::InvalidParam(format!(
+                    "The payer data {} is required",
+                    name
+                )));
+            }
+            None => {}
+        }
+    }
+    if data.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(serde_json::to_string(&data)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_lnurl() -> Result<()> {
+        let url = decode("Alice@Example.com")?;
+        assert_eq!(url.as_str(), "https://example.com/.well-known/lnurlp/alice");
+        assert_eq!(local_username(&url), Some("alice".to_owned()));
+
+        let url = decode("bob@abc.onion")?;
+        assert_eq!(url.as_str(), "http://abc.onion/.well-known/lnurlp/bob");
+
+        let url = decode("lnurlp://example.com/lnurlp/bob")?;
+        assert_eq!(url.as_str(), "https://example.com/lnurlp/bob");
+        assert_eq!(local_username(&url), Some("bob".to_owned()));
+
+        let encoded = encode("http://127.0.0.1:8080/lnurlp/bob");
+        let url = decode(&format!("lightning:{}", encoded.to_uppercase()))?;
+        assert_eq!(url.as_str(), "http://127.0.0.1:8080/lnurlp/bob");
+        assert_eq!(authority(&url), "127.0.0.1:8080");
+
+        assert!(!is_lnurl("lnbc1"));
+        assert!(!is_lnurl("@example.com"));
+        assert!(!is_lnurl("a b@example.com"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reject_insecure_url() -> Result<()> {
+        let client = Client::new();
+        for url in [
+            "http://example.com/lnurlp/bob",
+            "https://127.0.0.1/lnurlp/bob",
+            "https://localhost/lnurlp/bob",
+            "https://10.0.0.1/lnurlp/bob",
+            "https://169.254.169.254/latest",
+            "https://[::1]/lnurlp/bob",
+            "https://[fd00::1]/lnurlp/bob",
+            "https://[::ffff:192.168.1.1]/lnurlp/bob",
+        ] {
+            let res = client.pay_request(Url::parse(url).unwrap()).await;
+            assert!(matches!(res, Err(Error::Lnurl(_))), "{}", url);
+        }
+        assert!(is_global("8.8.8.8".parse()?));
+        assert!(is_global("2001:4860:4860::8888".parse()?));
+        Ok(())
+    }
+}
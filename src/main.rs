@@ -13,6 +13,18 @@ pub struct Cli {
     /// config file path
     #[arg(short = 'c', value_name = "PATH")]
     pub config: Option<PathBuf>,
+
+    /// full resync invoices and payments created after the unix timestamp, then exit
+    #[arg(long, value_name = "TIMESTAMP")]
+    pub resync: Option<u64>,
+
+    /// end of the resync time range
+    #[arg(long, value_name = "TIMESTAMP", requires = "resync")]
+    pub resync_to: Option<u64>,
+
+    /// print the reconciliation report of the ledger and the node balance, then exit
+    #[arg(long)]
+    pub reconcile: bool,
 }
 
 #[actix_web::main]
@@ -28,6 +40,19 @@ async fn main() -> Result<()> {
     let args = Cli::parse();
     let state: AppState = AppState::create(args.config, Some("SATSBOX".to_string())).await?;
     Migrator::up(state.service.db(), None).await?;
+    if let Some(from) = args.resync {
+        let (invoices, payments) = state.service.resync(from, args.resync_to).await?;
+        info!(
+            "Resync updated {} invoices, {} payments",
+            invoices, payments
+        );
+        return Ok(());
+    }
+    if args.reconcile {
+        let report = state.service.reconcile().await?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
     info!("Start satsbox server");
     start(state).await?;
     info!("Server shutdown");
@@ -0,0 +1,185 @@
+//! NIP-44 v2 encryption
+//!
+//! <https://github.com/nostr-protocol/nips/blob/master/44.md>
+
+use crate::{Error, Result};
+use base64::engine::{general_purpose, Engine};
+use chacha20::{
+    cipher::{KeyIvInit, StreamCipher},
+    ChaCha20,
+};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use nostr_sdk::secp256k1::{ecdh, PublicKey, SecretKey, XOnlyPublicKey};
+use rand::RngCore;
+use sha2::Sha256;
+
+const VERSION: u8 = 2;
+const SALT: &[u8] = b"nip44-v2";
+const MIN_PLAINTEXT: usize = 1;
+const MAX_PLAINTEXT: usize = 65535;
+
+fn err(msg: &'static str) -> Error {
+    Error::Nip44(msg)
+}
+
+/// shared key of the two parties, the same for both directions
+pub fn conversation_key(sk: &SecretKey, pk: &XOnlyPublicKey) -> Result<[u8; 32]> {
+    let mut full = [2u8; 33];
+    full[1..].copy_from_slice(&pk.serialize());
+    let pk = PublicKey::from_slice(&full)?;
+    let point = ecdh::shared_secret_point(&pk, sk);
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(SALT), &point[..32]);
+    Ok(prk.into())
+}
+
+fn message_keys(key: &[u8; 32], nonce: &[u8]) -> Result<([u8; 32], [u8; 12], [u8; 32])> {
+    let hk = Hkdf::<Sha256>::from_prk(key).map_err(|_| err("invalid conversation key"))?;
+    let mut okm = [0u8; 76];
+    hk.expand(nonce, &mut okm)
+        .map_err(|_| err("invalid nonce"))?;
+    let mut chacha_key = [0u8; 32];
+    let mut chacha_nonce = [0u8; 12];
+    let mut hmac_key = [0u8; 32];
+    chacha_key.copy_from_slice(&okm[0..32]);
+    chacha_nonce.copy_from_slice(&okm[32..44]);
+    hmac_key.copy_from_slice(&okm[44..76]);
+    Ok((chacha_key, chacha_nonce, hmac_key))
+}
+
+fn padded_len(len: usize) -> usize {
+    if len <= 32 {
+        return 32;
+    }
+    let next_power = 1 << (usize::BITS - (len - 1).leading_zeros());
+    let chunk = if next_power <= 256 {
+        32
+    } else {
+        next_power / 8
+    };
+    chunk * ((len - 1) / chunk + 1)
+}
+
+fn hmac_aad(key: &[u8; 32], nonce: &[u8], ciphertext: &[u8]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("any key length");
+    mac.update(nonce);
+    mac.update(ciphertext);
+    mac
+}
+
+/// encrypt with the conversation key and nonce
+pub fn encrypt_with(key: &[u8; 32], nonce: &[u8; 32], plaintext: &str) -> Result<String> {
+    let len = plaintext.len();
+    if !(MIN_PLAINTEXT..=MAX_PLAINTEXT).contains(&len) {
+        return Err(err("invalid plaintext length"));
+    }
+    let (chacha_key, chacha_nonce, hmac_key) = message_keys(key, nonce)?;
+
+    let mut buf = Vec::with_capacity(2 + padded_len(len));
+    buf.extend_from_slice(&(len as u16).to_be_bytes());
+    buf.extend_from_slice(plaintext.as_bytes());
+    buf.resize(2 + padded_len(len), 0);
+    ChaCha20::new(&chacha_key.into(), &chacha_nonce.into()).apply_keystream(&mut buf);
+
+    let mac = hmac_aad(&hmac_key, nonce, &buf).finalize().into_bytes();
+
+    let mut payload = Vec::with_capacity(1 + 32 + buf.len() + 32);
+    payload.push(VERSION);
+    payload.extend_from_slice(nonce);
+    payload.extend_from_slice(&buf);
+    payload.extend_from_slice(&mac);
+    Ok(general_purpose::STANDARD.encode(payload))
+}
+
+pub fn encrypt(sk: &SecretKey, pk: &XOnlyPublicKey, plaintext: &str) -> Result<String> {
+    let mut nonce = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    encrypt_with(&conversation_key(sk, pk)?, &nonce, plaintext)
+}
+
+pub fn decrypt_with(key: &[u8; 32], payload: &str) -> Result<String> {
+    if payload.starts_with('#') {
+        return Err(err("unknown version"));
+    }
+    if payload.len() < 132 || payload.len() > 87472 {
+        return Err(err("invalid payload length"));
+    }
+    let data = general_purpose::STANDARD
+        .decode(payload)
+        .map_err(|_| err("invalid base64"))?;
+    if data.len() < 99 || data.len() > 65603 {
+        return Err(err("invalid data length"));
+    }
+    if data[0] != VERSION {
+        return Err(err("unknown version"));
+    }
+    let nonce = &data[1..33];
+    let mac = &data[data.len() - 32..];
+    let mut buf = data[33..data.len() - 32].to_vec();
+
+    let (chacha_key, chacha_nonce, hmac_key) = message_keys(key, nonce)?;
+    hmac_aad(&hmac_key, nonce, &buf)
+        .verify_slice(mac)
+        .map_err(|_| err("invalid mac"))?;
+    ChaCha20::new(&chacha_key.into(), &chacha_nonce.into()).apply_keystream(&mut buf);
+
+    let len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    if len < MIN_PLAINTEXT || buf.len() != 2 + padded_len(len) {
+        return Err(err("invalid padding"));
+    }
+    String::from_utf8(buf[2..2 + len].to_vec()).map_err(|_| err("invalid utf8"))
+}
+
+pub fn decrypt(sk: &SecretKey, pk: &XOnlyPublicKey, payload: &str) -> Result<String> {
+    decrypt_with(&conversation_key(sk, pk)?, payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn sk(n: u8) -> SecretKey {
+        let mut b = [0u8; 32];
+        b[31] = n;
+        SecretKey::from_slice(&b).unwrap()
+    }
+
+    #[test]
+    fn vector() -> anyhow::Result<()> {
+        let pk2 = nostr_sdk::Keys::new(sk(2)).public_key();
+        let key = conversation_key(&sk(1), &pk2)?;
+        assert_eq!(
+            hex::encode(key),
+            "c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d"
+        );
+        let mut nonce = [0u8; 32];
+        nonce[31] = 1;
+        let payload = encrypt_with(&key, &nonce, "a")?;
+        assert_eq!(payload, "AgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAABee0G5VSK0/9YypIObAtDKfYEAjD35uVkHyB0F4DwrcNaCXlCWZKaArsGrY6M9wnuTMxWfp1RTN9Xga8no+kF5Vsb");
+        assert_eq!(decrypt_with(&key, &payload)?, "a");
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip() -> anyhow::Result<()> {
+        let pk1 = XOnlyPublicKey::from_str(
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )?;
+        let pk2 = nostr_sdk::Keys::new(sk(2)).public_key();
+        for len in [1, 31, 32, 33, 257, 1000, 65535] {
+            let text = "x".repeat(len);
+            let payload = encrypt(&sk(1), &pk2, &text)?;
+            assert_eq!(decrypt(&sk(2), &pk1, &payload)?, text);
+        }
+        assert_eq!(padded_len(33), 64);
+        assert_eq!(padded_len(257), 320);
+        // tampered
+        let payload = encrypt(&sk(1), &pk2, "hello")?;
+        let mut data = general_purpose::STANDARD.decode(&payload)?;
+        data[40] ^= 1;
+        let payload = general_purpose::STANDARD.encode(data);
+        assert!(decrypt(&sk(2), &pk1, &payload).is_err());
+        Ok(())
+    }
+}
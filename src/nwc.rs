@@ -1,35 +1,197 @@
 //! nostr wallet connect api
 
-use crate::{now, AppState, Error, Result};
+use crate::{
+    bus::{WalletEvent, WalletEventKind},
+    nip44, now, sha256, AppState, Error, InvoiceExtra, Result,
+};
+use entity::{budget_hold::Budget, invoice, nwc_connection, user};
 use futures::FutureExt;
 use governor::{
     clock::DefaultClock,
     state::{InMemoryState, NotKeyed},
     Quota, RateLimiter,
 };
+use lightning_client::lightning;
 use nostr_sdk::{
     prelude::{
         nips, Client, Event, EventBuilder, Filter, Keys, Kind, Options, RelayPoolNotification, Tag,
-        XOnlyPublicKey,
+        TagKind, XOnlyPublicKey,
     },
     EventId,
 };
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::{net::SocketAddr, str::FromStr, sync::Arc};
+use std::{future::Future, net::SocketAddr, str::FromStr, sync::Arc};
+use tokio::sync::broadcast::error::RecvError;
+
+pub const METHODS: &str = "pay_invoice get_balance make_invoice lookup_invoice list_transactions get_info multi_pay_invoice pay_keysend pay_lnurl";
+
+/// notification types
+pub const NOTIFICATIONS: &str = "payment_received payment_sent";
+/// nip04 encrypted notification event kind
+const NOTIFICATION_KIND: u64 = 23196;
+/// nip44 encrypted notification event kind
+const NOTIFICATION_NIP44_KIND: u64 = 23197;
 
-pub const METHODS: &str = "pay_invoice get_balance";
+/// encryption tag of the events
+const ENCRYPTION_TAG: &str = "encryption";
+
+const INVOICE_EXPIRY: u64 = 3600 * 24;
+const INVOICE_MAX_EXPIRY: u64 = 3600 * 24 * 30;
+const LIST_LIMIT: u64 = 20;
+const LIST_MAX_LIMIT: u64 = 100;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum RequestMethod {
     PayInvoice,
     GetBalance,
+    MakeInvoice,
+    LookupInvoice,
+    ListTransactions,
+    GetInfo,
+    MultiPayInvoice,
+    PayKeysend,
+    PayLnurl,
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct PayInvoiceParam {
     pub invoice: String,
+    /// amount in msats of the offer without amount
+    pub amount: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MultiPayInvoiceItem {
+    pub id: Option<String>,
+    pub invoice: String,
+    pub amount: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MultiPayInvoiceParam {
+    pub invoices: Vec<MultiPayInvoiceItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TlvRecord {
+    pub r#type: u64,
+    /// hex
+    pub value: String,
+}
+
+/// amount in msats
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PayKeysendParam {
+    pub amount: u64,
+    pub pubkey: String,
+    pub preimage: Option<String>,
+    #[serde(default)]
+    pub tlv_records: Vec<TlvRecord>,
+}
+
+/// pay the lightning address or the lnurl-pay code, amount in msats
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PayLnurlParam {
+    pub lnurl: String,
+    pub amount: u64,
+    pub comment: Option<String>,
+}
+
+/// outgoing payment of the pay requests
+enum Payment {
+    /// the bolt11 invoice or the bolt12 offer with the amount
+    Invoice(String, Option<u64>),
+    Lnurl(PayLnurlParam),
+    Keysend {
+        pubkey: Vec<u8>,
+        amount: u64,
+        tlv_records: Vec<(u64, Vec<u8>)>,
+    },
+}
+
+impl TryFrom<PayKeysendParam> for Payment {
+    type Error = Error;
+
+    fn try_from(params: PayKeysendParam) -> Result<Self> {
+        // the preimage is generated by the node
+        if params.preimage.is_some() {
+            return Err(Error::InvalidParam(
+                "Custom preimage is not supported".to_owned(),
+            ));
+        }
+        let mut tlv_records = vec![];
+        for r in params.tlv_records {
+            tlv_records.push((r.r#type, hex::decode(r.value)?));
+        }
+        Ok(Self::Keysend {
+            pubkey: hex::decode(params.pubkey)?,
+            amount: params.amount,
+            tlv_records,
+        })
+    }
+}
+
+/// amounts in msats
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MakeInvoiceParam {
+    pub amount: u64,
+    #[serde(default)]
+    pub description: String,
+    pub description_hash: Option<String>,
+    pub expiry: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct LookupInvoiceParam {
+    pub payment_hash: Option<String>,
+    pub invoice: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct ListTransactionsParam {
+    pub from: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+    /// include unpaid invoices
+    pub unpaid: bool,
+    /// incoming or outgoing
+    pub r#type: Option<String>,
+}
+
+/// nip47 transaction of invoice or payment
+fn transaction(inv: &invoice::Model) -> Value {
+    let paid = inv.status == invoice::Status::Paid;
+    let (tx_type, amount, description_hash) = match inv.r#type {
+        invoice::Type::Invoice => (
+            "incoming",
+            if paid { inv.paid_amount } else { inv.amount },
+            // the invoices always commit to the hash of the description
+            Some(hex::encode(sha256(&inv.description))),
+        ),
+        invoice::Type::Payment => ("outgoing", inv.amount, None),
+    };
+    json!({
+        "type": tx_type,
+        "invoice": inv.bolt11,
+        "description": inv.description,
+        "description_hash": description_hash,
+        "preimage": if paid { Some(hex::encode(&inv.payment_preimage)) } else { None },
+        "payment_hash": hex::encode(&inv.payment_hash),
+        "amount": amount,
+        "fees_paid": inv.fee + inv.service_fee,
+        "created_at": inv.created_at,
+        "expires_at": inv.expired_at,
+        "settled_at": if paid { Some(inv.paid_at) } else { None },
+        "metadata": {},
+    })
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
@@ -47,7 +209,55 @@ fn verify_time(created_at: i64, diff_seconds: u64) -> Result<()> {
     }
 }
 
-fn parse_request(event: &Event, keys: &Keys) -> Result<Request> {
+/// Encryption scheme of the request, reply in kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encryption {
+    Nip04,
+    Nip44V2,
+}
+
+impl Encryption {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encryption::Nip04 => "nip04",
+            Encryption::Nip44V2 => "nip44_v2",
+        }
+    }
+
+    /// detect by the encryption tag, the clients without the tag use nip04
+    pub fn detect(event: &Event) -> Result<Self> {
+        let tag = event.tags.iter().find_map(|t| {
+            let t = t.as_vec();
+            (t.len() > 1 && t[0] == ENCRYPTION_TAG).then(|| t[1].clone())
+        });
+        match tag.as_deref() {
+            Some("nip44_v2") => Ok(Encryption::Nip44V2),
+            Some("nip04") => Ok(Encryption::Nip04),
+            Some(_) => Err(Error::UnsupportedEncryption),
+            // nip04 content has the iv query
+            None if event.content.contains("?iv=") => Ok(Encryption::Nip04),
+            None => Ok(Encryption::Nip44V2),
+        }
+    }
+
+    pub fn encrypt(&self, keys: &Keys, pubkey: &XOnlyPublicKey, content: String) -> Result<String> {
+        let sk = keys.secret_key().unwrap();
+        Ok(match self {
+            Encryption::Nip04 => nips::nip04::encrypt(&sk, pubkey, content)?,
+            Encryption::Nip44V2 => nip44::encrypt(&sk, pubkey, &content)?,
+        })
+    }
+
+    pub fn decrypt(&self, keys: &Keys, pubkey: &XOnlyPublicKey, content: String) -> Result<String> {
+        let sk = keys.secret_key().unwrap();
+        Ok(match self {
+            Encryption::Nip04 => nips::nip04::decrypt(&sk, pubkey, content)?,
+            Encryption::Nip44V2 => nip44::decrypt(&sk, pubkey, &content)?,
+        })
+    }
+}
+
+fn parse_request(event: &Event, keys: &Keys, encryption: Encryption) -> Result<Request> {
     if event.kind != Kind::WalletConnectRequest
         || !event.tags.iter().any(|t| match t {
             Tag::PubKey(k, _) => k == &keys.public_key(),
@@ -57,64 +267,44 @@ fn parse_request(event: &Event, keys: &Keys) -> Result<Request> {
         return Err(Error::Str("Invalid event kind or tags"));
     }
 
-    let content = nips::nip04::decrypt(
-        &keys.secret_key().unwrap(),
-        &event.pubkey,
-        event.content.clone(),
-    )?;
+    let content = encryption.decrypt(keys, &event.pubkey, event.content.clone())?;
 
     Ok(serde_json::from_str(&content)?)
 }
 
-async fn handle_request(
-    created_at: i64,
-    pubkey: Vec<u8>,
-    req: Request,
-    nwc: &Nwc,
-) -> Result<Value> {
+fn check_request(created_at: i64, encryption: Encryption, nwc: &Nwc) -> Result<()> {
     verify_time(created_at, 60 * 5)?;
+    if nwc.state.setting.nwc.require_nip44 && encryption != Encryption::Nip44V2 {
+        return Err(Error::UnsupportedEncryption);
+    }
     nwc.limiter_per_second
         .check()
         .map_err(|_| Error::RateLimited)?;
+    Ok(())
+}
 
-    let state = &nwc.state;
-
-    match req.method {
-        RequestMethod::PayInvoice => {
-            let params: PayInvoiceParam = serde_json::from_value(req.params)?;
-            let user = state.service.get_user(pubkey).await?;
-            match user {
-                Some(user) => {
-                    let payment = state
-                        .service
-                        .pay(
-                            &user,
-                            params.invoice,
-                            &state.setting.fee,
-                            entity::invoice::Source::Nwc,
-                            false,
-                        )
-                        .await?;
-                    let image = hex::encode(payment.payment_preimage);
-                    Ok(json!({
-                        "preimage": image,
-                    }))
-                }
-                None => Err(Error::InsufficientBalance),
-            }
-        }
-        RequestMethod::GetBalance => {
-            let user = state.service.get_user(pubkey).await?;
-
-            let sats = user
-                .map(|u: ::entity::user::Model| u.balance - u.lock_amount)
-                .unwrap_or_default()
-                / 1000;
-            Ok(json!({
-                "balance": sats,
-            }))
-        }
+fn method_name(method: &RequestMethod) -> String {
+    serde_json::to_value(method)
+        .ok()
+        .and_then(|v| v.as_str().map(ToOwned::to_owned))
+        .unwrap_or_default()
+}
+
+fn check_connection(conn: &nwc_connection::Model, method: &RequestMethod) -> Result<()> {
+    if conn.revoked_at > 0 {
+        return Err(Error::Restricted("The connection is revoked".to_owned()));
     }
+    if conn.expires_at > 0 && conn.expires_at <= now() as i64 {
+        return Err(Error::Restricted("The connection is expired".to_owned()));
+    }
+    let name = method_name(method);
+    if !conn.methods.split(' ').any(|m| m == name) {
+        return Err(Error::Restricted(format!(
+            "The method {} is not allowed",
+            name
+        )));
+    }
+    Ok(())
 }
 
 // nip47 respose event kind: 23195
@@ -123,32 +313,63 @@ fn create_response_event(
     request_event_id: EventId,
     keys: &Keys,
     content: Value,
+    d: Option<String>,
+    encryption: Encryption,
 ) -> Result<Event> {
-    let content = nips::nip04::encrypt(
-        &keys.secret_key().unwrap(),
-        &user_pubkey,
-        serde_json::to_string(&content)?,
-    )?;
-    Ok(EventBuilder::new(
-        Kind::WalletConnectResponse,
-        content,
-        &vec![
-            Tag::PubKey(user_pubkey, None),
-            Tag::Event(request_event_id, None, None),
-        ],
+    let content = encryption.encrypt(keys, &user_pubkey, serde_json::to_string(&content)?)?;
+    let mut tags = vec![
+        Tag::PubKey(user_pubkey, None),
+        Tag::Event(request_event_id, None, None),
+    ];
+    if encryption == Encryption::Nip44V2 {
+        tags.push(encryption_tag(encryption.as_str()));
+    }
+    // multi methods
+    if let Some(d) = d {
+        tags.push(Tag::Identifier(d));
+    }
+    Ok(EventBuilder::new(Kind::WalletConnectResponse, content, &tags).to_event(keys)?)
+}
+
+fn encryption_tag(value: &str) -> Tag {
+    Tag::Generic(
+        TagKind::Custom(ENCRYPTION_TAG.to_owned()),
+        vec![value.to_owned()],
     )
-    .to_event(keys)?)
 }
 
 // nip47 info event kind: 13194
-fn create_info_event(keys: &Keys) -> Result<Event> {
-    Ok(EventBuilder::new(Kind::WalletConnectInfo, METHODS, &[]).to_event(keys)?)
+fn create_info_event(keys: &Keys, require_nip44: bool) -> Result<Event> {
+    let encryption = if require_nip44 {
+        "nip44_v2"
+    } else {
+        "nip44_v2 nip04"
+    };
+    Ok(EventBuilder::new(
+        Kind::WalletConnectInfo,
+        format!("{} notifications", METHODS),
+        &[
+            encryption_tag(encryption),
+            Tag::Generic(
+                TagKind::Custom("notifications".to_owned()),
+                vec![NOTIFICATIONS.to_owned()],
+            ),
+        ],
+    )
+    .to_event(keys)?)
 }
 
 fn error_response(err: &Error, method: RequestMethod) -> Value {
     let code = match err {
         Error::InsufficientBalance => "INSUFFICIENT_BALANCE",
         Error::RateLimited => "RATE_LIMITED",
+        Error::NotFound(_) => "NOT_FOUND",
+        Error::Restricted(_) => "RESTRICTED",
+        Error::QuotaExceeded(_) => "QUOTA_EXCEEDED",
+        Error::NotImplemented => "NOT_IMPLEMENTED",
+        Error::UnsupportedEncryption => "UNSUPPORTED_ENCRYPTION",
+        Error::InvalidPayment(_) => "PAYMENT_FAILED",
+        Error::InvalidParam(_) | Error::Json(_) => "INVALID_PARAMS",
         _ => "INTERNAL",
     };
     json!({
@@ -161,11 +382,23 @@ fn error_response(err: &Error, method: RequestMethod) -> Value {
 }
 
 async fn handle_event(event: Event, nwc: Nwc) -> Result<()> {
+    // the connection keys spend from the owner, or the user use the own keys if allowed
+    let connection = nwc
+        .state
+        .service
+        .get_nwc_connection(event.pubkey.serialize().to_vec())
+        .await?;
+    let user_pubkey = match &connection {
+        Some(c) => nwc.state.service.get_user_by_id(c.user_id).await?.pubkey,
+        None if nwc.state.setting.nwc.legacy_user_keys => event.pubkey.serialize().to_vec(),
+        // unknown connection
+        None => return Ok(()),
+    };
     if nwc
         .state
         .setting
         .auth
-        .check_permission(event.pubkey.serialize().as_slice())
+        .check_permission(&user_pubkey)
         .is_err()
     {
         // has not permission
@@ -179,7 +412,25 @@ async fn handle_event(event: Event, nwc: Nwc) -> Result<()> {
     }
     let model = model.unwrap();
 
-    let res = parse_request(&event, &nwc.keys);
+    let encryption = match Encryption::detect(&event) {
+        Ok(encryption) => encryption,
+        Err(e) => {
+            // the request can't be decrypted, reply in nip04
+            nwc.state.service.update_event_error(model.id, &e).await?;
+            nwc.client
+                .send_event(create_response_event(
+                    event.pubkey,
+                    event.id,
+                    &nwc.keys,
+                    error_response(&e, RequestMethod::Unknown),
+                    None,
+                    Encryption::Nip04,
+                )?)
+                .await?;
+            return Ok(());
+        }
+    };
+    let res = parse_request(&event, &nwc.keys, encryption);
     if let Err(e) = res {
         nwc.state.service.update_event_error(model.id, &e).await?;
         return Ok(());
@@ -187,40 +438,43 @@ async fn handle_event(event: Event, nwc: Nwc) -> Result<()> {
     let req = res.unwrap();
 
     let method = req.method.clone();
-    let user_pubkey = event.pubkey;
+    let client_pubkey = event.pubkey;
 
-    let res = handle_request(
-        event.created_at.as_i64(),
-        user_pubkey.serialize().to_vec(),
-        req,
-        &nwc,
-    )
-    .await;
-    let res = match res {
-        Ok(res) => {
+    let responses = match check_request(event.created_at.as_i64(), encryption, &nwc) {
+        Ok(_) => nwc.handle(user_pubkey, connection.as_ref(), req).await,
+        Err(e) => vec![(None, Err(e))],
+    };
+
+    // the event failed if any of the responses failed
+    match responses.iter().find_map(|(_, r)| r.as_ref().err()) {
+        Some(err) => nwc.state.service.update_event_error(model.id, err).await?,
+        None => {
             nwc.state
                 .service
                 .update_event_success(model.id, "".to_owned())
-                .await?;
-            json!({
-                "result_type": method,
-                "result": res,
-            })
-        }
-        Err(err) => {
-            nwc.state.service.update_event_error(model.id, &err).await?;
-            error_response(&err, method)
+                .await?
         }
-    };
+    }
 
-    nwc.client
-        .send_event(create_response_event(
-            user_pubkey,
-            event.id,
-            &nwc.keys,
-            res,
-        )?)
-        .await?;
+    for (d, res) in responses {
+        let res = match res {
+            Ok(res) => json!({
+                "result_type": method,
+                "result": res,
+            }),
+            Err(err) => error_response(&err, method.clone()),
+        };
+        nwc.client
+            .send_event(create_response_event(
+                client_pubkey,
+                event.id,
+                &nwc.keys,
+                res,
+                d,
+                encryption,
+            )?)
+            .await?;
+    }
 
     Ok(())
 }
@@ -249,6 +503,357 @@ impl Nwc {
         }
     }
 
+    /// Handle the request of the user, return the results with the optional `d` tag,
+    /// multi methods have one result for each item.
+    pub async fn handle(
+        &self,
+        pubkey: Vec<u8>,
+        connection: Option<&nwc_connection::Model>,
+        req: Request,
+    ) -> Vec<(Option<String>, Result<Value>)> {
+        if let Some(conn) = connection {
+            if let Err(e) = check_connection(conn, &req.method) {
+                return vec![(None, Err(e))];
+            }
+        }
+        if req.method == RequestMethod::MultiPayInvoice {
+            let params: MultiPayInvoiceParam = match serde_json::from_value(req.params) {
+                Ok(params) => params,
+                Err(e) => return vec![(None, Err(e.into()))],
+            };
+            let mut responses = vec![];
+            for item in params.invoices {
+                let id = item.id.clone().unwrap_or_else(|| {
+                    lightning::Invoice::from_bolt11(item.invoice.clone())
+                        .map(|i| hex::encode(i.payment_hash))
+                        .unwrap_or_default()
+                });
+                let res = self
+                    .pay(
+                        &pubkey,
+                        connection,
+                        Payment::Invoice(item.invoice, item.amount),
+                    )
+                    .await;
+                responses.push((Some(id), res));
+            }
+            responses
+        } else {
+            vec![(None, self.handle_one(&pubkey, connection, req).await)]
+        }
+    }
+
+    async fn handle_one(
+        &self,
+        pubkey: &[u8],
+        connection: Option<&nwc_connection::Model>,
+        req: Request,
+    ) -> Result<Value> {
+        let state = &self.state;
+        match req.method {
+            RequestMethod::PayInvoice => {
+                let params: PayInvoiceParam = serde_json::from_value(req.params)?;
+                self.pay(
+                    pubkey,
+                    connection,
+                    Payment::Invoice(params.invoice, params.amount),
+                )
+                .await
+            }
+            RequestMethod::PayKeysend => {
+                let params: PayKeysendParam = serde_json::from_value(req.params)?;
+                self.pay(pubkey, connection, params.try_into()?).await
+            }
+            RequestMethod::PayLnurl => {
+                let params: PayLnurlParam = serde_json::from_value(req.params)?;
+                self.pay(pubkey, connection, Payment::Lnurl(params)).await
+            }
+            RequestMethod::GetBalance => {
+                let user = state.service.get_user(pubkey.to_vec()).await?;
+
+                let sats = user
+                    .map(|u: user::Model| u.balance - u.lock_amount)
+                    .unwrap_or_default()
+                    / 1000;
+                Ok(json!({
+                    "balance": sats,
+                }))
+            }
+            RequestMethod::MakeInvoice => {
+                let params: MakeInvoiceParam = serde_json::from_value(req.params)?;
+                if params.amount == 0 {
+                    return Err(Error::InvalidParam("Invalid amount".to_owned()));
+                }
+                let expiry = params.expiry.unwrap_or(INVOICE_EXPIRY);
+                if expiry == 0 || expiry > INVOICE_MAX_EXPIRY {
+                    return Err(Error::InvalidParam("Invalid expiry".to_owned()));
+                }
+                if let Some(hash) = &params.description_hash {
+                    if hex::decode(hash)? != sha256(&params.description) {
+                        return Err(Error::InvalidParam(
+                            "The description hash does not match the description".to_owned(),
+                        ));
+                    }
+                }
+                let user = state.service.get_or_create_user(pubkey.to_vec()).await?;
+                let invoice = state
+                    .service
+                    .create_invoice(
+                        &user,
+                        params.description,
+                        params.amount,
+                        expiry,
+                        InvoiceExtra::new(invoice::Source::Nwc),
+                    )
+                    .await?;
+                Ok(transaction(&invoice))
+            }
+            RequestMethod::LookupInvoice => {
+                let params: LookupInvoiceParam = serde_json::from_value(req.params)?;
+                let hash = match (params.payment_hash, params.invoice) {
+                    (Some(hash), _) => hex::decode(hash)?,
+                    (None, Some(bolt11)) => lightning::Invoice::from_bolt11(bolt11)?.payment_hash,
+                    (None, None) => {
+                        return Err(Error::InvalidParam(
+                            "Missing payment_hash or invoice".to_owned(),
+                        ))
+                    }
+                };
+                let user = state.service.get_user(pubkey.to_vec()).await?;
+                let invoice = match user {
+                    Some(user) => {
+                        invoice::Entity::find()
+                            .filter(invoice::Column::UserId.eq(user.id))
+                            .filter(invoice::Column::PaymentHash.eq(hash))
+                            .one(state.service.db())
+                            .await?
+                    }
+                    None => None,
+                };
+                let invoice =
+                    invoice.ok_or_else(|| Error::NotFound("Invoice not found".to_owned()))?;
+                Ok(transaction(&invoice))
+            }
+            RequestMethod::ListTransactions => {
+                let params: ListTransactionsParam = serde_json::from_value(req.params)?;
+                let user = state.service.get_user(pubkey.to_vec()).await?;
+                let user = match user {
+                    Some(user) => user,
+                    None => return Ok(json!({ "transactions": [] })),
+                };
+                let mut find = invoice::Entity::find().filter(invoice::Column::UserId.eq(user.id));
+                if !params.unpaid {
+                    find = find.filter(invoice::Column::Status.eq(invoice::Status::Paid));
+                }
+                if let Some(t) = &params.r#type {
+                    let t = match t.as_str() {
+                        "incoming" => invoice::Type::Invoice,
+                        "outgoing" => invoice::Type::Payment,
+                        _ => return Err(Error::InvalidParam("Invalid type".to_owned())),
+                    };
+                    find = find.filter(invoice::Column::Type.eq(t));
+                }
+                if let Some(from) = params.from {
+                    find = find.filter(invoice::Column::CreatedAt.gte(from));
+                }
+                if let Some(until) = params.until {
+                    find = find.filter(invoice::Column::CreatedAt.lte(until));
+                }
+                let list = find
+                    .order_by_desc(invoice::Column::CreatedAt)
+                    .order_by_desc(invoice::Column::Id)
+                    .offset(params.offset.unwrap_or_default())
+                    .limit(params.limit.unwrap_or(LIST_LIMIT).min(LIST_MAX_LIMIT))
+                    .all(state.service.db())
+                    .await?;
+                Ok(json!({
+                    "transactions": list.iter().map(transaction).collect::<Vec<_>>(),
+                }))
+            }
+            RequestMethod::GetInfo => {
+                let info = state.service.info().await?;
+                Ok(json!({
+                    "alias": info.alias,
+                    "color": info.color,
+                    "pubkey": hex::encode(info.id),
+                    "block_height": info.block_height,
+                    "methods": connection
+                        .map(|c| c.methods.as_str())
+                        .unwrap_or(METHODS)
+                        .split(' ')
+                        .collect::<Vec<_>>(),
+                }))
+            }
+            RequestMethod::MultiPayInvoice | RequestMethod::Unknown => Err(Error::NotImplemented),
+        }
+    }
+
+    async fn pay(
+        &self,
+        pubkey: &[u8],
+        connection: Option<&nwc_connection::Model>,
+        payment: Payment,
+    ) -> Result<Value> {
+        let state = &self.state;
+        let user = state.service.get_user(pubkey.to_vec()).await?;
+        match user {
+            Some(user) => {
+                let fee = &state.setting.fee;
+                let payment = match payment {
+                    Payment::Invoice(bolt11, msats) => {
+                        // the amount of the offer is known from the requested invoice
+                        let payable = state.service.resolve_payable(bolt11, msats).await?;
+                        let amount = payable.amount();
+                        let pay = state.service.pay_payable(
+                            &user,
+                            payable,
+                            fee,
+                            invoice::Source::Nwc,
+                            false,
+                        );
+                        self.limit_spend(connection, amount, pay).await?
+                    }
+                    Payment::Lnurl(params) => {
+                        let amount = params.amount;
+                        let pay = state.service.pay_lnurl(
+                            &user,
+                            params.lnurl,
+                            params.amount,
+                            params.comment,
+                            fee,
+                            invoice::Source::Nwc,
+                        );
+                        self.limit_spend(connection, amount, pay).await?
+                    }
+                    Payment::Keysend {
+                        pubkey,
+                        amount,
+                        tlv_records,
+                    } => {
+                        let pay = state.service.keysend(
+                            &user,
+                            pubkey,
+                            amount,
+                            tlv_records,
+                            fee,
+                            invoice::Source::Nwc,
+                        );
+                        self.limit_spend(connection, amount, pay).await?
+                    }
+                };
+                Ok(json!({
+                    "preimage": hex::encode(payment.payment_preimage),
+                }))
+            }
+            None => Err(Error::InsufficientBalance),
+        }
+    }
+
+    /// Pay within the max amount and the budget of the connection,
+    /// the amount with the max fees is charged and settled to the payment total,
+    /// give back the budget when the payment fails.
+    async fn limit_spend(
+        &self,
+        connection: Option<&nwc_connection::Model>,
+        amount: u64,
+        pay: impl Future<Output = Result<invoice::Model>>,
+    ) -> Result<invoice::Model> {
+        let conn = match connection {
+            Some(conn) => conn,
+            None => return pay.await,
+        };
+        let service = &self.state.service;
+        let charge = self.state.setting.fee.max_total(amount as i64);
+        if conn.max_amount > 0 && charge > conn.max_amount {
+            return Err(Error::QuotaExceeded(
+                "The amount exceeds the max amount of the connection".to_owned(),
+            ));
+        }
+        service.spend_nwc_budget(conn, charge).await?;
+        let res = pay.await;
+        service
+            .release_budget(Budget::NwcConnection, conn.id, charge, &res)
+            .await?;
+        res
+    }
+
+    /// Create the notification events of the wallet event for the active connections of the user,
+    /// publish nip44 kind 23197 and nip04 kind 23196 unless nip44 is required.
+    pub async fn notification_events(&self, event: &WalletEvent) -> Result<Vec<Event>> {
+        let (notification_type, id) = match &event.kind {
+            WalletEventKind::InvoicePaid { id, .. } => ("payment_received", *id),
+            WalletEventKind::PaymentSucceeded { id, .. } => ("payment_sent", *id),
+            _ => return Ok(vec![]),
+        };
+        let now = now() as i64;
+        let connections = self
+            .state
+            .service
+            .list_nwc_connections(event.user_id)
+            .await?
+            .into_iter()
+            .filter(|c| c.revoked_at == 0 && (c.expires_at == 0 || c.expires_at > now))
+            .collect::<Vec<_>>();
+        if connections.is_empty() {
+            return Ok(vec![]);
+        }
+        let invoice = self
+            .state
+            .service
+            .get_invoice(id)
+            .await?
+            .ok_or(Error::Str("where is the invoice?"))?;
+        let content = serde_json::to_string(&json!({
+            "notification_type": notification_type,
+            "notification": transaction(&invoice),
+        }))?;
+
+        let mut kinds = vec![(NOTIFICATION_NIP44_KIND, Encryption::Nip44V2)];
+        if !self.state.setting.nwc.require_nip44 {
+            kinds.push((NOTIFICATION_KIND, Encryption::Nip04));
+        }
+        let mut events = vec![];
+        for conn in connections {
+            let pubkey = XOnlyPublicKey::from_slice(&conn.pubkey)?;
+            for (kind, encryption) in &kinds {
+                let content = encryption.encrypt(&self.keys, &pubkey, content.clone())?;
+                events.push(
+                    EventBuilder::new(Kind::from(*kind), content, &[Tag::PubKey(pubkey, None)])
+                        .to_event(&self.keys)?,
+                );
+            }
+        }
+        Ok(events)
+    }
+
+    /// publish the notifications of the wallet events
+    pub async fn handle_wallet_events(&self) -> Result<()> {
+        let mut rx = self.state.service.bus().subscribe();
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    let res = self.notification_events(&event).await;
+                    match res {
+                        Ok(events) => {
+                            for e in events {
+                                if let Err(err) = self.client.send_event(e).await {
+                                    tracing::error!("send notification error: {:?}", err);
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            tracing::error!("create notification error: {:?} {:?}", err, event);
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    tracing::warn!("nwc notifications lagged, missed {} events", n);
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+
     pub async fn connect(&self) -> Result<()> {
         let proxy = if let Some(proxy) = &self.state.setting.nwc.proxy {
             Some(SocketAddr::from_str(proxy)?)
@@ -262,7 +867,10 @@ impl Nwc {
         self.client.connect().await;
 
         self.client
-            .send_event(create_info_event(&self.keys)?)
+            .send_event(create_info_event(
+                &self.keys,
+                self.state.setting.nwc.require_nip44,
+            )?)
             .await?;
 
         let subscription = Filter::new()
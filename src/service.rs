@@ -1,14 +1,46 @@
-use crate::{key::Pubkey, now, setting::Fee, sha256, Error, Result};
-use entity::{donation, event, invoice, record, user};
+use crate::{
+    auth::{ApiKeyScope, AuthError, SeenEvents, API_KEY_PREFIX},
+    bus::{Bus, WalletEventKind},
+    key::Pubkey,
+    lnurl::SuccessAction,
+    lnurl_client, now,
+    setting::Fee,
+    sha256, Error, Result,
+};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use entity::{
+    api_key, auth_challenge, auth_event, auth_identity,
+    budget_hold::{self, Budget},
+    donation, event, invoice, lndhub_credential,
+    nwc_connection::{self, BudgetRenewal},
+    offer,
+    posting::{self, Account},
+    record, session, sync_state, user, user_profile, withdraw_link,
+};
+use futures::StreamExt;
 use lightning_client::{lightning, Lightning};
-use nostr_sdk::{secp256k1::XOnlyPublicKey, Event, EventId};
+use nostr_sdk::{
+    prelude::FromBech32,
+    secp256k1::{ecdsa::Signature, Message, PublicKey, XOnlyPublicKey},
+    Event, EventId, SECP256K1,
+};
 use rand::RngCore;
 use sea_orm::{
-    sea_query::Expr, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseTransaction, DbConn,
-    EntityTrait, NotSet, QueryFilter, QueryOrder, Set, TransactionTrait,
+    sea_query::{Alias, Expr, Func, OnConflict, SimpleExpr},
+    ActiveEnum, ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseTransaction,
+    DbBackend, DbConn, EntityTrait, NotSet, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect,
+    Set, TransactionTrait,
 };
 use serde::Deserialize;
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+use subtle::ConstantTimeEq;
 use tokio::time::sleep;
 
 pub fn rand_preimage() -> Vec<u8> {
@@ -51,6 +83,110 @@ impl InvoiceExtra {
     }
 }
 
+/// Limits of a nwc connection, amounts in msats, 0 is unlimited
+#[derive(Debug, Default, Clone)]
+pub struct NwcPolicy {
+    pub methods: Vec<String>,
+    pub max_amount: i64,
+    pub budget: i64,
+    pub budget_renewal: BudgetRenewal,
+    pub expires_at: i64,
+}
+
+/// Limits of a lnurl withdraw link, amounts in msats, 0 is unlimited
+#[derive(Debug, Default, Clone)]
+pub struct WithdrawPolicy {
+    pub title: String,
+    pub min_withdrawable: i64,
+    pub max_withdrawable: i64,
+    pub total_cap: i64,
+    pub max_uses: i32,
+    pub expires_at: i64,
+}
+
+/// Lightning address profile of the user, None uses the global lnurl setting
+#[derive(Debug, Default, Clone)]
+pub struct AddressProfile {
+    pub min_sendable: Option<i64>,
+    pub max_sendable: Option<i64>,
+    pub comment_allowed: Option<i32>,
+    pub description: Option<String>,
+    pub long_description: Option<String>,
+    pub avatar: Option<String>,
+}
+
+/// Scopes and limits of an api key, amounts in msats, 0 is unlimited
+#[derive(Debug, Default, Clone)]
+pub struct ApiKeyPolicy {
+    pub scopes: Vec<ApiKeyScope>,
+    pub spend_limit: i64,
+    /// allowed client ips or networks, empty allows any ip
+    pub allowed_ips: Vec<String>,
+    pub expires_at: i64,
+}
+
+/// The bolt11 invoice, the invoice requested from the remote offer,
+/// or the local offer with the amount in msats to pay.
+#[derive(Debug, Clone)]
+pub enum Payable {
+    Invoice(lightning::Invoice),
+    Offer(offer::Model, u64),
+}
+
+impl Payable {
+    /// the amount to pay in msats
+    pub fn amount(&self) -> u64 {
+        match self {
+            Payable::Invoice(inv) => inv.amount,
+            Payable::Offer(_, amount) => *amount,
+        }
+    }
+}
+
+const LNDHUB_MAX_CREDENTIALS: u64 = 20;
+/// name of the credential set by the password or migrated from the legacy password
+const LNDHUB_DEFAULT_NAME: &str = "default";
+const SESSION_DEVICE_MAX_CHARS: usize = 100;
+/// seconds between the updates of the session last used time
+const SESSION_TOUCH_INTERVAL: i64 = 60;
+/// the leading characters of the api key shown to the user, including the sk_ prefix
+const API_KEY_DISPLAY_LEN: usize = 11;
+const API_KEY_MAX_KEYS: u64 = 20;
+/// sync state key of the last processed pay index of the invoices subscription
+const PAY_INDEX: &str = "pay_index";
+/// sync state keys of the invoices cursor, lnd list by creation time, cln list by index
+const INVOICE_TIME: &str = "invoice_time";
+const INVOICE_INDEX: &str = "invoice_index";
+/// sync state key of the payments cursor
+const PAYMENT_TIME: &str = "payment_time";
+/// keep syncing the expired invoices for a while, the node may mark them late
+const EXPIRY_GRACE: i64 = 60 * 10;
+/// lnurl-auth challenges are valid for 10 minutes
+const AUTH_CHALLENGE_EXPIRY: i64 = 60 * 10;
+/// rescan the recent seconds when nothing is pending, cover the records in uncommitted transactions
+const CURSOR_OVERLAP: i64 = 60;
+
+/// Reconciliation report of the ledger, the users and the node, amounts in msats
+#[derive(serde::Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Reconciliation {
+    /// total balance and lock amount of the users
+    pub liabilities: i64,
+    /// user liabilities by the ledger postings
+    pub ledger_liabilities: i64,
+    /// node liquidity by the ledger postings
+    pub ledger_node: i64,
+    pub fee_income: i64,
+    pub routing_fee: i64,
+    /// sum of all postings, should be 0
+    pub unbalanced: i64,
+    /// spendable channel balance of the node
+    pub node_balance: i64,
+    /// node balance minus liabilities, negative means the node can't cover the users
+    pub surplus: i64,
+    /// the ledger and the users mismatch, or the node balance is insufficient
+    pub drift: bool,
+}
+
 type Lt = Box<dyn Lightning + Sync + Send>;
 /// Lightning service
 #[derive(Clone)]
@@ -60,6 +196,16 @@ pub struct Service {
     name: String,
     pub self_payment: bool,
     pub donation_receiver: Option<Vec<u8>>,
+    /// custom record of the keysend payee user, 696969 as the podcasting wallets
+    pub keysend_record: u64,
+    /// domains of the local lightning addresses
+    pub lnurl_domains: Vec<String>,
+    /// client of the remote lnurl services
+    pub lnurl: lnurl_client::Client,
+    bus: Bus,
+    auth_events: Arc<SeenEvents>,
+    /// also store the seen auth events in the database
+    pub auth_events_db: bool,
 }
 
 impl Service {
@@ -70,6 +216,12 @@ impl Service {
             conn,
             self_payment: false,
             donation_receiver: None,
+            keysend_record: 696969,
+            lnurl_domains: vec![],
+            lnurl: lnurl_client::Client::new(),
+            bus: Bus::default(),
+            auth_events: Arc::new(SeenEvents::default()),
+            auth_events_db: false,
         }
     }
 
@@ -85,6 +237,27 @@ impl Service {
         &self.conn
     }
 
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    /// publish the current balance of the user
+    async fn notify_balance(&self, user_id: i32) {
+        if !self.bus.has_subscribers() {
+            return;
+        }
+        match get_user_by_id(self.db(), user_id).await {
+            Ok(user) => self.bus.publish(
+                user_id,
+                WalletEventKind::Balance {
+                    balance: user.balance,
+                    lock_amount: user.lock_amount,
+                },
+            ),
+            Err(e) => tracing::warn!("notify balance error: {:?}", e),
+        }
+    }
+
     pub async fn info(&self) -> Result<lightning::Info> {
         Ok(self.lightning.get_info().await?)
     }
@@ -112,51 +285,349 @@ impl Service {
     ) -> Result<user::Model> {
         let txn = self.db().begin().await?;
 
-        // update user balance
-        let res = user::Entity::update_many()
+        // update user balance, the debit can't make the balance negative
+        let mut update = user::Entity::update_many()
             .col_expr(
                 user::Column::Balance,
                 Expr::col(user::Column::Balance).add(change),
             )
-            .filter(user::Column::Id.eq(user.id))
-            .exec(&txn)
-            .await?;
+            .filter(user::Column::Id.eq(user.id));
+        if change < 0 {
+            update = update.filter(user::Column::Balance.gte(-change));
+        }
+        let res = update.exec(&txn).await?;
 
         if res.rows_affected != 1 {
-            return Err(Error::Str("update user balance error"));
+            return Err(Error::InvalidParam(
+                "The balance can't be negative".to_owned(),
+            ));
         }
 
         new_record(user, None, change, "admin".to_owned(), note)
             .insert(&txn)
             .await?;
+        // the operator funds the adjustment
+        insert_postings(
+            &txn,
+            "admin",
+            None,
+            &[
+                (Account::User, Some(user.id), -change),
+                (Account::FeeIncome, None, change),
+            ],
+        )
+        .await?;
         txn.commit().await?;
+        self.notify_balance(user.id).await;
         get_user_by_id(self.db(), user.id).await
     }
 
+    /// Freeze or unfreeze the user, the balance of the frozen user can't be spent
+    pub async fn admin_freeze_user(&self, user_id: i32, frozen: bool) -> Result<user::Model> {
+        let now = now() as i64;
+        let mut update = user::Entity::update_many()
+            .col_expr(
+                user::Column::FrozenAt,
+                Expr::value(if frozen { now } else { 0 }),
+            )
+            .col_expr(user::Column::UpdatedAt, Expr::value(now))
+            .filter(user::Column::Id.eq(user_id));
+        if frozen {
+            // keep the first frozen time
+            update = update.filter(user::Column::FrozenAt.eq(0));
+        }
+        update.exec(self.db()).await?;
+        get_user_by_id(self.db(), user_id).await
+    }
+
+    /// Resolve the in-flight payment after checking the node.
+    /// Settle it only if the node reports it succeeded,
+    /// fail it unless the node reports it succeeded or still in flight.
+    pub async fn admin_resolve_payment(&self, id: i32, settle: bool) -> Result<invoice::Model> {
+        let payment = invoice::Entity::find_by_id(id)
+            .filter(invoice::Column::Type.eq(invoice::Type::Payment))
+            .one(self.db())
+            .await?
+            .ok_or_else(|| Error::NotFound("Payment not found".to_owned()))?;
+        if payment.status != invoice::Status::Unpaid {
+            return Err(Error::InvalidParam(
+                "The payment is not in flight".to_owned(),
+            ));
+        }
+        let remote = match self
+            .lightning
+            .lookup_payment(payment.payment_hash.clone())
+            .await
+        {
+            Ok(remote) => Some(remote),
+            Err(lightning_client::Error::PaymentNotFound) => None,
+            Err(e) => return Err(e.into()),
+        };
+        let status = remote.as_ref().map(|r| r.status);
+        match (settle, status) {
+            (true, Some(lightning::PaymentStatus::Succeeded)) => {
+                pay_success(self, remote.as_ref().unwrap(), &payment).await
+            }
+            (true, _) => Err(Error::InvalidParam(
+                "The payment is not succeeded on the node".to_owned(),
+            )),
+            (false, Some(lightning::PaymentStatus::Succeeded)) => Err(Error::InvalidParam(
+                "The payment is succeeded on the node".to_owned(),
+            )),
+            (false, Some(lightning::PaymentStatus::InFlight)) => Err(Error::InvalidParam(
+                "The payment is still in flight on the node".to_owned(),
+            )),
+            (false, _) => {
+                pay_failed(self, &payment).await?;
+                self.get_invoice(payment.id)
+                    .await?
+                    .ok_or(Error::Str("invoice not found"))
+            }
+        }
+    }
+
+    /// Replace the lndhub credentials of the user by the password, None disables lndhub.
     pub async fn update_user_password(
         &self,
         user_id: i32,
         password: Option<String>,
     ) -> Result<user::Model> {
+        let hash = password.as_deref().map(hash_password).transpose()?;
+        let now = now() as i64;
+        let txn = self.conn.begin().await?;
+        lndhub_credential::Entity::update_many()
+            .col_expr(lndhub_credential::Column::RevokedAt, Expr::value(now))
+            .filter(lndhub_credential::Column::UserId.eq(user_id))
+            .filter(lndhub_credential::Column::RevokedAt.eq(0))
+            .exec(&txn)
+            .await?;
+        revoke_credential_sessions(&txn, user_id, None).await?;
+        user::ActiveModel {
+            id: Set(user_id),
+            password: Set(None),
+            ..Default::default()
+        }
+        .update(&txn)
+        .await?;
+        if let Some(hash) = hash {
+            insert_lndhub_credential(&txn, user_id, LNDHUB_DEFAULT_NAME, hash, 0).await?;
+        }
+        txn.commit().await?;
+        get_user_by_id(self.db(), user_id).await
+    }
+
+    /// Issue a new lndhub credential, the password is only returned here.
+    /// The active credential of the same name is replaced.
+    pub async fn create_lndhub_credential(
+        &self,
+        user_id: i32,
+        name: &str,
+    ) -> Result<(lndhub_credential::Model, String)> {
+        let password = hex::encode(&rand_preimage()[..16]);
+        let hash = hash_password(&password)?;
+        let txn = self.conn.begin().await?;
+        let replaced = lndhub_credential::Entity::find()
+            .filter(lndhub_credential::Column::UserId.eq(user_id))
+            .filter(lndhub_credential::Column::Name.eq(name))
+            .filter(lndhub_credential::Column::RevokedAt.eq(0))
+            .all(&txn)
+            .await?;
+        for credential in replaced {
+            revoke_lndhub_credential(&txn, user_id, credential.id).await?;
+        }
+        let count = lndhub_credential::Entity::find()
+            .filter(lndhub_credential::Column::UserId.eq(user_id))
+            .filter(lndhub_credential::Column::RevokedAt.eq(0))
+            .count(&txn)
+            .await?;
+        if count >= LNDHUB_MAX_CREDENTIALS {
+            return Err(Error::QuotaExceeded(format!(
+                "The number of lndhub credentials cannot be greater than {}",
+                LNDHUB_MAX_CREDENTIALS
+            )));
+        }
+        let credential = insert_lndhub_credential(&txn, user_id, name, hash, 0).await?;
+        txn.commit().await?;
+        Ok((credential, password))
+    }
+
+    pub async fn list_lndhub_credentials(
+        &self,
+        user_id: i32,
+    ) -> Result<Vec<lndhub_credential::Model>> {
+        Ok(lndhub_credential::Entity::find()
+            .filter(lndhub_credential::Column::UserId.eq(user_id))
+            .order_by_desc(lndhub_credential::Column::Id)
+            .all(self.db())
+            .await?)
+    }
+
+    /// revoke the credential and the sessions signed in by it
+    pub async fn revoke_lndhub_credential(
+        &self,
+        user_id: i32,
+        id: i32,
+    ) -> Result<lndhub_credential::Model> {
+        let txn = self.conn.begin().await?;
+        revoke_lndhub_credential(&txn, user_id, id).await?;
+        txn.commit().await?;
+        lndhub_credential::Entity::find_by_id(id)
+            .filter(lndhub_credential::Column::UserId.eq(user_id))
+            .one(self.db())
+            .await?
+            .ok_or_else(|| Error::NotFound("Credential not found".to_owned()))
+    }
+
+    /// Verify the lndhub password of the user by the active credentials, return the matched one,
+    /// the legacy plaintext password is moved to a hashed credential on success.
+    pub async fn verify_lndhub_password(
+        &self,
+        user: &user::Model,
+        password: &str,
+    ) -> Result<Option<lndhub_credential::Model>> {
+        let now = now() as i64;
+        // at most LNDHUB_MAX_CREDENTIALS, the recently used first
+        let list = lndhub_credential::Entity::find()
+            .filter(lndhub_credential::Column::UserId.eq(user.id))
+            .filter(lndhub_credential::Column::RevokedAt.eq(0))
+            .order_by_desc(lndhub_credential::Column::LastUsedAt)
+            .all(self.db())
+            .await?;
+        for credential in list {
+            if verify_password(password, &credential.password_hash) {
+                return Ok(Some(
+                    lndhub_credential::ActiveModel {
+                        id: Set(credential.id),
+                        last_used_at: Set(now),
+                        ..Default::default()
+                    }
+                    .update(self.db())
+                    .await?,
+                ));
+            }
+        }
+
+        match &user.password {
+            Some(legacy) if bool::from(legacy.as_bytes().ct_eq(password.as_bytes())) => {
+                let hash = hash_password(password)?;
+                let txn = self.conn.begin().await?;
+                // migrated by the concurrent login if not changed
+                let res = user::Entity::update_many()
+                    .col_expr(user::Column::Password, Expr::value(Option::<String>::None))
+                    .filter(user::Column::Id.eq(user.id))
+                    .filter(user::Column::Password.eq(legacy.clone()))
+                    .exec(&txn)
+                    .await?;
+                let credential = if res.rows_affected == 1 {
+                    Some(
+                        insert_lndhub_credential(&txn, user.id, LNDHUB_DEFAULT_NAME, hash, now)
+                            .await?,
+                    )
+                } else {
+                    None
+                };
+                txn.commit().await?;
+                match credential {
+                    Some(credential) => Ok(Some(credential)),
+                    None => Ok(lndhub_credential::Entity::find()
+                        .filter(lndhub_credential::Column::UserId.eq(user.id))
+                        .filter(lndhub_credential::Column::Name.eq(LNDHUB_DEFAULT_NAME))
+                        .filter(lndhub_credential::Column::RevokedAt.eq(0))
+                        .order_by_desc(lndhub_credential::Column::Id)
+                        .one(self.db())
+                        .await?
+                        .filter(|c| verify_password(password, &c.password_hash))),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// lndhub is enabled by an active credential, the legacy password or a lnurl-auth identity
+    pub async fn lndhub_enabled(&self, user: &user::Model) -> Result<bool> {
+        if user.password.is_some() {
+            return Ok(true);
+        }
+        let count = lndhub_credential::Entity::find()
+            .filter(lndhub_credential::Column::UserId.eq(user.id))
+            .filter(lndhub_credential::Column::RevokedAt.eq(0))
+            .count(self.db())
+            .await?;
+        Ok(count > 0 || !self.list_auth_identities(user.id).await?.is_empty())
+    }
+
+    pub async fn update_username(&self, user_id: i32, name: Option<String>) -> Result<user::Model> {
         Ok(user::ActiveModel {
             id: Set(user_id),
-            password: Set(password),
+            username: Set(name),
             ..Default::default()
         }
         .update(self.db())
         .await?)
     }
 
-    pub async fn update_username(&self, user_id: i32, name: Option<String>) -> Result<user::Model> {
+    pub async fn update_success_action(
+        &self,
+        user_id: i32,
+        action: Option<&SuccessAction>,
+    ) -> Result<user::Model> {
+        let action = action.map(serde_json::to_string).transpose()?;
         Ok(user::ActiveModel {
             id: Set(user_id),
-            username: Set(name),
+            success_action: Set(action),
             ..Default::default()
         }
         .update(self.db())
         .await?)
     }
 
+    pub async fn get_user_profile(&self, user_id: i32) -> Result<Option<user_profile::Model>> {
+        Ok(user_profile::Entity::find()
+            .filter(user_profile::Column::UserId.eq(user_id))
+            .one(self.db())
+            .await?)
+    }
+
+    /// create or replace the lightning address profile of the user
+    pub async fn update_user_profile(
+        &self,
+        user_id: i32,
+        profile: AddressProfile,
+    ) -> Result<user_profile::Model> {
+        let now = now() as i64;
+        let model = user_profile::ActiveModel {
+            id: NotSet,
+            user_id: Set(user_id),
+            min_sendable: Set(profile.min_sendable),
+            max_sendable: Set(profile.max_sendable),
+            comment_allowed: Set(profile.comment_allowed),
+            description: Set(profile.description),
+            long_description: Set(profile.long_description),
+            avatar: Set(profile.avatar),
+            created_at: Set(now),
+            updated_at: Set(now),
+        };
+        user_profile::Entity::insert(model)
+            .on_conflict(
+                OnConflict::column(user_profile::Column::UserId)
+                    .update_columns([
+                        user_profile::Column::MinSendable,
+                        user_profile::Column::MaxSendable,
+                        user_profile::Column::CommentAllowed,
+                        user_profile::Column::Description,
+                        user_profile::Column::LongDescription,
+                        user_profile::Column::Avatar,
+                        user_profile::Column::UpdatedAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec(self.db())
+            .await?;
+        self.get_user_profile(user_id)
+            .await?
+            .ok_or(Error::Str("profile not found"))
+    }
+
     pub async fn get_or_create_user(&self, pubkey: Vec<u8>) -> Result<user::Model> {
         get_or_create_user(self.db(), pubkey).await
     }
@@ -169,6 +640,21 @@ impl Service {
         Ok(invoice::Entity::find_by_id(id).one(self.db()).await?)
     }
 
+    /// the invoice received by the lightning address of the user
+    pub async fn get_lnurl_invoice(
+        &self,
+        user_id: i32,
+        payment_hash: Vec<u8>,
+    ) -> Result<Option<invoice::Model>> {
+        Ok(invoice::Entity::find()
+            .filter(invoice::Column::UserId.eq(user_id))
+            .filter(invoice::Column::Type.eq(invoice::Type::Invoice))
+            .filter(invoice::Column::Source.is_in([invoice::Source::Lnurlp, invoice::Source::Zaps]))
+            .filter(invoice::Column::PaymentHash.eq(payment_hash))
+            .one(self.db())
+            .await?)
+    }
+
     pub async fn create_invoice(
         &self,
         user: &user::Model,
@@ -193,15 +679,193 @@ impl Service {
         Ok(model.insert(self.db()).await?)
     }
 
+    /// Create a hold invoice by the payment hash of the user,
+    /// the payment is credited after the user settles it by the preimage.
+    pub async fn create_hold_invoice(
+        &self,
+        user: &user::Model,
+        memo: String,
+        msats: u64,
+        payment_hash: Vec<u8>,
+        expiry: u64,
+        extra: InvoiceExtra,
+    ) -> Result<invoice::Model> {
+        if payment_hash.len() != 32 {
+            return Err(Error::InvalidParam("Invalid payment hash".to_owned()));
+        }
+        let invoice = self
+            .lightning
+            .create_hold_invoice(memo, msats, payment_hash.clone(), Some(expiry))
+            .await?;
+
+        if invoice.payment_hash != payment_hash {
+            return Err(Error::Str("invalid payment hash"));
+        }
+
+        let mut model =
+            create_invoice_active_model(user, vec![], invoice, self.name.clone(), extra);
+        model.hold = Set(true);
+
+        Ok(model.insert(self.db()).await?)
+    }
+
+    /// find the open hold invoice of the user
+    async fn get_hold_invoice(
+        &self,
+        user: &user::Model,
+        payment_hash: Vec<u8>,
+    ) -> Result<invoice::Model> {
+        let invoice = invoice::Entity::find()
+            .filter(invoice::Column::UserId.eq(user.id))
+            .filter(invoice::Column::Type.eq(invoice::Type::Invoice))
+            .filter(invoice::Column::Hold.eq(true))
+            .filter(invoice::Column::PaymentHash.eq(payment_hash))
+            .one(self.db())
+            .await?
+            .ok_or_else(|| Error::NotFound("Invoice not found".to_owned()))?;
+        if !matches!(
+            invoice.status,
+            invoice::Status::Unpaid | invoice::Status::Accepted
+        ) {
+            return Err(Error::InvalidParam("The invoice is closed.".to_owned()));
+        }
+        Ok(invoice)
+    }
+
+    /// Settle the accepted hold invoice of the user by revealing the preimage,
+    /// credit the held payment to the user.
+    pub async fn settle_hold_invoice(
+        &self,
+        user: &user::Model,
+        preimage: Vec<u8>,
+    ) -> Result<invoice::Model> {
+        let invoice = self.get_hold_invoice(user, sha256(&preimage)).await?;
+        self.lightning.settle_hold_invoice(preimage.clone()).await?;
+        invoice::Entity::update_many()
+            .set(invoice::ActiveModel {
+                payment_preimage: Set(preimage),
+                ..Default::default()
+            })
+            .filter(invoice::Column::Id.eq(invoice.id))
+            .exec(self.db())
+            .await?;
+
+        let remote = self
+            .lightning
+            .lookup_invoice(invoice.payment_hash.clone())
+            .await?;
+        // or credited by the subscription at the same time
+        if remote.status == lightning::InvoiceStatus::Paid {
+            match invoice_paid(self, &invoice, &remote).await {
+                Ok(()) | Err(Error::InvalidPayment(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.get_invoice(invoice.id)
+            .await?
+            .ok_or(Error::Str("invoice not found"))
+    }
+
+    /// cancel the hold invoice of the user, the held payment is returned to the payer
+    pub async fn cancel_hold_invoice(
+        &self,
+        user: &user::Model,
+        payment_hash: Vec<u8>,
+    ) -> Result<invoice::Model> {
+        let invoice = self.get_hold_invoice(user, payment_hash).await?;
+        self.lightning
+            .cancel_hold_invoice(invoice.payment_hash.clone())
+            .await?;
+        let res = invoice::Entity::update_many()
+            .set(invoice::ActiveModel {
+                status: Set(invoice::Status::Canceled),
+                ..Default::default()
+            })
+            .filter(invoice::Column::Id.eq(invoice.id))
+            .filter(
+                invoice::Column::Status.is_in([invoice::Status::Unpaid, invoice::Status::Accepted]),
+            )
+            .exec(self.db())
+            .await?;
+        if res.rows_affected != 1 {
+            return Err(Error::InvalidParam("The invoice is closed.".to_owned()));
+        }
+        self.get_invoice(invoice.id)
+            .await?
+            .ok_or(Error::Str("invoice not found"))
+    }
+
+    /// Pay the bolt11 invoice or the bolt12 offer,
+    /// the amount in msats is required by the offer without amount.
     pub async fn pay(
         &self,
         user: &user::Model,
         bolt11: String,
+        msats: Option<u64>,
+        fee: &Fee,
+        source: invoice::Source,
+        ignore_result: bool,
+    ) -> Result<invoice::Model> {
+        let payable = self.resolve_payable(bolt11, msats).await?;
+        self.pay_payable(user, payable, fee, source, ignore_result)
+            .await
+    }
+
+    /// Resolve the bolt11 invoice or the bolt12 offer to know the amount before paying,
+    /// the invoice is requested from the remote offer.
+    /// The amount in msats is required by the offer without amount.
+    pub async fn resolve_payable(&self, bolt11: String, msats: Option<u64>) -> Result<Payable> {
+        if lightning::is_offer(&bolt11) {
+            if let Some(offer) = self.get_local_offer(&bolt11).await? {
+                let amount = match (offer.amount as u64, msats) {
+                    (0, None) => {
+                        return Err(Error::InvalidParam(
+                            "The amount of the offer is required".to_owned(),
+                        ))
+                    }
+                    (amount, Some(msats)) if msats < amount => {
+                        return Err(Error::InvalidParam(
+                            "The amount is less than the offer amount".to_owned(),
+                        ))
+                    }
+                    (amount, msats) => msats.unwrap_or(amount),
+                };
+                if amount == 0 {
+                    return Err(Error::InvalidParam("Invalid amount".to_owned()));
+                }
+                return Ok(Payable::Offer(offer, amount));
+            }
+            return Ok(Payable::Invoice(
+                self.lightning.fetch_invoice(bolt11, msats).await?,
+            ));
+        }
+        let inv = lightning::Invoice::from_bolt11(bolt11)?;
+        if msats.is_some_and(|m| m != inv.amount) {
+            return Err(Error::InvalidParam(
+                "The amount doesn't match the invoice".to_owned(),
+            ));
+        }
+        Ok(Payable::Invoice(inv))
+    }
+
+    /// Pay the resolved invoice or the local offer.
+    pub async fn pay_payable(
+        &self,
+        user: &user::Model,
+        payable: Payable,
         fee: &Fee,
         source: invoice::Source,
         ignore_result: bool,
     ) -> Result<invoice::Model> {
-        let inv = lightning::Invoice::from_bolt11(bolt11.clone())?;
+        let inv = match payable {
+            Payable::Invoice(inv) => inv,
+            Payable::Offer(offer, amount) => {
+                return self
+                    .internal_offer_pay(user, offer, amount, fee, source)
+                    .await
+            }
+        };
+        let bolt11 = inv.bolt11.clone();
         let info = self.lightning.get_info().await?;
         // expired
         if inv.created_at + inv.expiry <= now() {
@@ -212,8 +876,6 @@ impl Service {
             self.internal_pay(user, inv, fee, source).await
         } else {
             // external payment
-            let payment_hash = inv.payment_hash.clone();
-
             let amount = inv.amount as i64;
             let (max_fee, service_fee) = fee.cal(amount, false);
             let total = amount + max_fee + service_fee;
@@ -234,39 +896,7 @@ impl Service {
             invoice.lock_amount = Set(total);
             invoice.service_fee = Set(service_fee);
 
-            let txn = self.conn.begin().await?;
-            // lock balance
-            let res = user::Entity::update_many()
-                .col_expr(
-                    user::Column::Balance,
-                    Expr::col(user::Column::Balance).sub(total),
-                )
-                .col_expr(
-                    user::Column::LockAmount,
-                    Expr::col(user::Column::LockAmount).add(total),
-                )
-                .filter(user::Column::Id.eq(user.id))
-                .filter(user::Column::Balance.gte(total))
-                .exec(&txn)
-                .await?;
-            if res.rows_affected != 1 {
-                return Err(Error::InvalidPayment(
-                    "The balance is insufficient or locked.".to_owned(),
-                ));
-            }
-
-            // create payment
-            let model = invoice.insert(&txn).await.map_err(|e| {
-                if matches!(
-                    e.sql_err(),
-                    Some(sea_orm::SqlErr::UniqueConstraintViolation(_))
-                ) {
-                    Error::InvalidPayment("The payment already exists.".to_owned())
-                } else {
-                    Error::InvalidPayment(e.to_string())
-                }
-            })?;
-            txn.commit().await?;
+            let model = self.lock_payment(user, invoice, total).await?;
 
             // try pay
             let pay = self.lightning.pay(bolt11, Some(max_fee as u64)).await;
@@ -276,71 +906,450 @@ impl Service {
                 return Ok(model);
             }
 
-            let payment = self.lightning.lookup_payment(payment_hash).await;
-
-            match payment {
-                Ok(p) => {
-                    match p.status {
-                        lightning::PaymentStatus::Succeeded => {
-                            pay_success(self.db(), &p, &model).await
-                        }
-                        lightning::PaymentStatus::Failed => {
-                            // failed
-                            pay_failed(self.db(), &model).await?;
-                            Err(pay
-                                .err()
-                                .map(Error::from)
-                                .unwrap_or(Error::Str("pay failed")))
-                        }
-                        _ => {
-                            Err(Error::PaymentInProgress)
-                            // will handle by the task.
-                        }
-                    }
-                }
-
-                Err(lightning_client::Error::PaymentNotFound) => {
-                    pay_failed(self.db(), &model).await?;
-                    Err(pay
-                        .err()
-                        .map(Error::from)
-                        .unwrap_or(Error::Str("pay failed")))
-                    // failed
-                }
-                // will handle by the task.
-                Err(e) => Err(e.into()),
-            }
+            self.check_payment(&model, pay).await
         }
     }
 
-    async fn internal_pay(
+    /// Pay the amount to the lightning address or the lnurl-pay code,
+    /// the recipients of the local domains are paid internally without requests.
+    pub async fn pay_lnurl(
         &self,
         user: &user::Model,
-        inv: lightning::Invoice,
+        lnurl: String,
+        msats: u64,
+        comment: Option<String>,
         fee: &Fee,
         source: invoice::Source,
     ) -> Result<invoice::Model> {
-        let payment_hash = inv.payment_hash.clone();
-        let amount = inv.amount as i64;
-        let (fee, service_fee) = fee.cal(amount, true);
-        let total = amount + fee + service_fee;
-        if user.balance < total {
-            return Err(Error::Str("The balance is insufficient."));
+        if msats == 0 {
+            return Err(Error::InvalidParam("Invalid amount".to_owned()));
+        }
+        let url = lnurl_client::decode(&lnurl)?;
+        let host = lnurl_client::authority(&url);
+        if self
+            .lnurl_domains
+            .iter()
+            .any(|d| d.eq_ignore_ascii_case(&host))
+        {
+            if let Some(username) = lnurl_client::local_username(&url) {
+                let payee = self.lnurl_user(&username).await?;
+                let info = self.lightning.get_info().await?;
+                let inv = lightning::Invoice {
+                    bolt11: format!("{}@{}", username, host),
+                    payee: info.id,
+                    amount: msats,
+                    created_at: now(),
+                    description: Some(crate::lnurl::metadata(
+                        &host,
+                        &username,
+                        self.get_user_profile(payee.id).await?.as_ref(),
+                    )?),
+                    ..Default::default()
+                };
+                let extra = InvoiceExtra {
+                    source: invoice::Source::Lnurlp,
+                    comment: comment.filter(|c| !c.is_empty()),
+                    ..Default::default()
+                };
+                return self
+                    .internal_transfer(user, &payee, inv, fee, source, extra)
+                    .await;
+            }
         }
 
-        let payee_inv = invoice::Entity::find()
-            .filter(invoice::Column::PaymentHash.eq(payment_hash.clone()))
-            .filter(invoice::Column::Type.eq(invoice::Type::Invoice))
-            .one(self.db())
-            .await?
-            .ok_or(Error::InvalidPayment("Can't find payee invoice".to_owned()))?;
+        let req = self.lnurl.pay_request(url).await?;
+        let identifier = match (&user.username, self.lnurl_domains.first()) {
+            (Some(name), Some(domain)) => Some(format!("{}@{}", name, domain)),
+            _ => None,
+        };
+        let payer_data = lnurl_client::payer_data(
+            &req,
+            &[
+                ("name", user.username.clone()),
+                ("identifier", identifier),
+                ("pubkey", Some(hex::encode(&user.pubkey))),
+            ],
+        )?;
+        let (inv, _) = self
+            .lnurl
+            .invoice(&req, msats, comment.as_deref(), payer_data.as_deref())
+            .await?;
+        self.pay(user, inv.bolt11, None, fee, source, false).await
+    }
 
-        if !self.self_payment && payee_inv.user_id == user.id {
+    /// find the user of the local lightning address, the username or the npub
+    async fn lnurl_user(&self, username: &str) -> Result<user::Model> {
+        let user = match XOnlyPublicKey::from_bech32(username) {
+            Ok(key) => Some(self.get_or_create_user(key.serialize().to_vec()).await?),
+            Err(_) => self.get_user_by_name(username.to_owned()).await?,
+        };
+        user.ok_or_else(|| Error::NotFound("The recipient is not found".to_owned()))
+    }
+
+    /// lock the total amount of the user balance and create the pending payment
+    async fn lock_payment(
+        &self,
+        user: &user::Model,
+        invoice: invoice::ActiveModel,
+        total: i64,
+    ) -> Result<invoice::Model> {
+        check_frozen(user)?;
+        let txn = self.conn.begin().await?;
+        // lock balance
+        let res = user::Entity::update_many()
+            .col_expr(
+                user::Column::Balance,
+                Expr::col(user::Column::Balance).sub(total),
+            )
+            .col_expr(
+                user::Column::LockAmount,
+                Expr::col(user::Column::LockAmount).add(total),
+            )
+            .filter(user::Column::Id.eq(user.id))
+            .filter(user::Column::Balance.gte(total))
+            .filter(user::Column::FrozenAt.eq(0))
+            .exec(&txn)
+            .await?;
+        if res.rows_affected != 1 {
+            return Err(Error::InvalidPayment(
+                "The balance is insufficient or locked.".to_owned(),
+            ));
+        }
+
+        // create payment
+        let model = invoice.insert(&txn).await.map_err(|e| {
+            if matches!(
+                e.sql_err(),
+                Some(sea_orm::SqlErr::UniqueConstraintViolation(_))
+            ) {
+                Error::InvalidPayment("The payment already exists.".to_owned())
+            } else {
+                Error::InvalidPayment(e.to_string())
+            }
+        })?;
+        insert_postings(
+            &txn,
+            "payment_lock",
+            Some(model.id),
+            &[
+                (Account::User, Some(user.id), total),
+                (Account::UserLocked, Some(user.id), -total),
+            ],
+        )
+        .await?;
+        txn.commit().await?;
+        Ok(model)
+    }
+
+    /// check the result of the sent payment, refund the locked amount if failed
+    async fn check_payment(
+        &self,
+        model: &invoice::Model,
+        pay: lightning_client::Result<Vec<u8>>,
+    ) -> Result<invoice::Model> {
+        let payment = self
+            .lightning
+            .lookup_payment(model.payment_hash.clone())
+            .await;
+
+        match payment {
+            Ok(p) => {
+                match p.status {
+                    lightning::PaymentStatus::Succeeded => pay_success(self, &p, model).await,
+                    lightning::PaymentStatus::Failed => {
+                        // failed
+                        pay_failed(self, model).await?;
+                        Err(pay
+                            .err()
+                            .map(Error::from)
+                            .unwrap_or(Error::Str("pay failed")))
+                    }
+                    _ => {
+                        Err(Error::PaymentInProgress(model.id))
+                        // will handle by the task.
+                    }
+                }
+            }
+
+            Err(lightning_client::Error::PaymentNotFound) => {
+                pay_failed(self, model).await?;
+                Err(pay
+                    .err()
+                    .map(Error::from)
+                    .unwrap_or(Error::Str("pay failed")))
+                // failed
+            }
+            // will handle by the task.
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Send a keysend payment to the node with the custom tlv records,
+    /// keysend to our own node is settled internally to the user of the routing record.
+    pub async fn keysend(
+        &self,
+        user: &user::Model,
+        pubkey: Vec<u8>,
+        msats: u64,
+        tlv_records: Vec<(u64, Vec<u8>)>,
+        fee: &Fee,
+        source: invoice::Source,
+    ) -> Result<invoice::Model> {
+        if msats == 0 {
+            return Err(Error::InvalidParam("Invalid amount".to_owned()));
+        }
+        if pubkey.len() != 33 {
+            return Err(Error::InvalidParam("Invalid pubkey".to_owned()));
+        }
+        if let Some((t, _)) = tlv_records
+            .iter()
+            .find(|(t, _)| *t < lightning::CUSTOM_RECORD_MIN || *t == lightning::KEYSEND_RECORD)
+        {
+            return Err(Error::InvalidParam(format!(
+                "Invalid tlv record type {}",
+                t
+            )));
+        }
+        let info = self.lightning.get_info().await?;
+        // generate the preimage to store the payment hash before sending
+        let preimage = rand_preimage();
+        let inv = lightning::Invoice {
+            payee: pubkey.clone(),
+            payment_hash: sha256(&preimage),
+            amount: msats,
+            created_at: now(),
+            description: Some("keysend".to_owned()),
+            ..Default::default()
+        };
+        if info.id.eq(&pubkey) {
+            return self
+                .internal_keysend(user, inv, &tlv_records, fee, source)
+                .await;
+        }
+
+        let amount = msats as i64;
+        let (max_fee, service_fee) = fee.cal(amount, false);
+        let total = amount + max_fee + service_fee;
+        if user.balance < total {
+            return Err(Error::Str("The balance is insufficient."));
+        }
+
+        let mut invoice = create_invoice_active_model(
+            user,
+            vec![],
+            inv,
+            self.name.clone(),
+            InvoiceExtra::new(source),
+        );
+        invoice.r#type = Set(invoice::Type::Payment);
+        invoice.total = Set(total);
+        invoice.lock_amount = Set(total);
+        invoice.service_fee = Set(service_fee);
+
+        let model = self.lock_payment(user, invoice, total).await?;
+
+        let pay = self
+            .lightning
+            .keysend(pubkey, preimage, msats, tlv_records, Some(max_fee as u64))
+            .await;
+        self.check_payment(&model, pay).await
+    }
+
+    /// Find the user by the keysend routing record,
+    /// the value is the hex pubkey or the username of the user.
+    pub async fn keysend_user(
+        &self,
+        tlv_records: &[(u64, Vec<u8>)],
+    ) -> Result<Option<user::Model>> {
+        let value = match tlv_records.iter().find(|(t, _)| *t == self.keysend_record) {
+            Some((_, v)) => String::from_utf8_lossy(v).trim().to_owned(),
+            None => return Ok(None),
+        };
+        match hex::decode(&value) {
+            Ok(pubkey) if pubkey.len() == 32 => self.get_user(pubkey).await,
+            _ => self.get_user_by_name(value).await,
+        }
+    }
+
+    /// Credit the received keysend payment to the user of the routing record,
+    /// or the invoice requested by an offer to the user of the offer,
+    /// return false if it's credited already or no user found.
+    async fn spontaneous_paid(&self, remote: &lightning::Invoice) -> Result<bool> {
+        let exists = invoice::Entity::find()
+            .filter(invoice::Column::PaymentHash.eq(remote.payment_hash.clone()))
+            .filter(invoice::Column::Type.eq(invoice::Type::Invoice))
+            .one(self.db())
+            .await?;
+        let invoice = match exists {
+            Some(inv) if inv.status != invoice::Status::Unpaid => return Ok(false),
+            Some(inv) => inv,
+            None => {
+                let (user, source) = match &remote.offer_id {
+                    Some(offer_id) => (self.offer_user(offer_id).await?, invoice::Source::Offer),
+                    None => (
+                        self.keysend_user(&remote.custom_records).await?,
+                        invoice::Source::Keysend,
+                    ),
+                };
+                let user = match user {
+                    Some(user) => user,
+                    None => {
+                        tracing::warn!(
+                            "paid invoice {} without payee user",
+                            hex::encode(&remote.payment_hash)
+                        );
+                        return Ok(false);
+                    }
+                };
+                let mut inv = remote.clone();
+                inv.amount = remote.paid_amount;
+                inv.description = Some(
+                    inv.description
+                        .filter(|d| !d.is_empty())
+                        .unwrap_or_else(|| source.to_value()),
+                );
+                let res = create_invoice_active_model(
+                    &user,
+                    vec![],
+                    inv,
+                    self.name.clone(),
+                    InvoiceExtra::new(source),
+                )
+                .insert(self.db())
+                .await;
+                match res {
+                    Ok(inv) => inv,
+                    // handled by the subscription or the sync at the same time
+                    Err(e)
+                        if matches!(
+                            e.sql_err(),
+                            Some(sea_orm::SqlErr::UniqueConstraintViolation(_))
+                        ) =>
+                    {
+                        return Ok(false)
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        };
+        invoice_paid(self, &invoice, remote).await?;
+        Ok(true)
+    }
+
+    /// keysend to our own node, create the paid invoice of the payee by an internal payment
+    async fn internal_keysend(
+        &self,
+        user: &user::Model,
+        inv: lightning::Invoice,
+        tlv_records: &[(u64, Vec<u8>)],
+        fee: &Fee,
+        source: invoice::Source,
+    ) -> Result<invoice::Model> {
+        let payee = self
+            .keysend_user(tlv_records)
+            .await?
+            .ok_or(Error::InvalidPayment("Can't find keysend payee".to_owned()))?;
+        let extra = InvoiceExtra::new(source.clone());
+        self.internal_transfer(user, &payee, inv, fee, source, extra)
+            .await
+    }
+
+    /// pay the local offer of the user internally
+    async fn internal_offer_pay(
+        &self,
+        user: &user::Model,
+        offer: offer::Model,
+        amount: u64,
+        fee: &Fee,
+        source: invoice::Source,
+    ) -> Result<invoice::Model> {
+        let payee = get_user_by_id(self.db(), offer.user_id).await?;
+        let info = self.lightning.get_info().await?;
+        let inv = lightning::Invoice {
+            bolt11: offer.bolt12,
+            payee: info.id,
+            amount,
+            created_at: now(),
+            description: Some(offer.description),
+            ..Default::default()
+        };
+        let extra = InvoiceExtra::new(source.clone());
+        self.internal_transfer(user, &payee, inv, fee, source, extra)
+            .await
+    }
+
+    /// create the invoice of the payee and pay it by an internal payment
+    async fn internal_transfer(
+        &self,
+        user: &user::Model,
+        payee: &user::Model,
+        mut inv: lightning::Invoice,
+        fee: &Fee,
+        source: invoice::Source,
+        extra: InvoiceExtra,
+    ) -> Result<invoice::Model> {
+        if !self.self_payment && payee.id == user.id {
+            return Err(Error::InvalidPayment(
+                "Not allowed to pay yourself.".to_owned(),
+            ));
+        }
+        let preimage = rand_preimage();
+        inv.payment_hash = sha256(&preimage);
+        let payee_inv =
+            create_invoice_active_model(payee, preimage, inv.clone(), self.name.clone(), extra)
+                .insert(self.db())
+                .await?;
+
+        let res = self.internal_pay(user, inv, fee, source).await;
+        if res.is_err() {
+            invoice::Entity::update_many()
+                .set(invoice::ActiveModel {
+                    status: Set(invoice::Status::Canceled),
+                    ..Default::default()
+                })
+                .filter(invoice::Column::Id.eq(payee_inv.id))
+                .filter(invoice::Column::Status.eq(invoice::Status::Unpaid))
+                .exec(self.db())
+                .await?;
+        }
+        res
+    }
+
+    async fn internal_pay(
+        &self,
+        user: &user::Model,
+        inv: lightning::Invoice,
+        fee: &Fee,
+        source: invoice::Source,
+    ) -> Result<invoice::Model> {
+        check_frozen(user)?;
+        let payment_hash = inv.payment_hash.clone();
+        let amount = inv.amount as i64;
+        let (fee, service_fee) = fee.cal(amount, true);
+        let total = amount + fee + service_fee;
+        if user.balance < total {
+            return Err(Error::Str("The balance is insufficient."));
+        }
+
+        let payee_inv = invoice::Entity::find()
+            .filter(invoice::Column::PaymentHash.eq(payment_hash.clone()))
+            .filter(invoice::Column::Type.eq(invoice::Type::Invoice))
+            .one(self.db())
+            .await?
+            .ok_or(Error::InvalidPayment("Can't find payee invoice".to_owned()))?;
+
+        if !self.self_payment && payee_inv.user_id == user.id {
             return Err(Error::InvalidPayment(
                 "Not allowed to pay yourself.".to_owned(),
             ));
         }
 
+        if payee_inv.hold {
+            return Err(Error::InvalidPayment(
+                "The hold invoice can't be paid internally.".to_owned(),
+            ));
+        }
+
         let payee_user = get_user_by_id(self.db(), payee_inv.user_id).await?;
 
         if payee_inv.status != invoice::Status::Unpaid {
@@ -401,6 +1410,7 @@ impl Service {
             )
             .filter(user::Column::Id.eq(user.id))
             .filter(user::Column::Balance.gte(total))
+            .filter(user::Column::FrozenAt.eq(0))
             .exec(&txn)
             .await?;
         if res.rows_affected != 1 {
@@ -441,19 +1451,54 @@ impl Service {
         .insert(&txn)
         .await?;
 
+        insert_postings(
+            &txn,
+            "internal_payment",
+            Some(payment.id),
+            &[
+                (Account::User, Some(user.id), total),
+                (Account::User, Some(payee_inv.user_id), -amount),
+                (Account::FeeIncome, None, -(fee + service_fee)),
+            ],
+        )
+        .await?;
+
         sync_donation(self, Some(user.pubkey.clone()), &txn, &payee_inv).await?;
 
         txn.commit().await?;
 
+        self.bus.publish(
+            user.id,
+            WalletEventKind::PaymentSucceeded {
+                id: payment.id,
+                payment_hash: hex::encode(&payment.payment_hash),
+                total,
+            },
+        );
+        self.bus.publish(
+            payee_inv.user_id,
+            WalletEventKind::InvoicePaid {
+                id: payee_inv.id,
+                payment_hash: hex::encode(&payee_inv.payment_hash),
+                amount,
+            },
+        );
+        self.notify_balance(user.id).await;
+        self.notify_balance(payee_inv.user_id).await;
+
         Ok(payment)
     }
 
-    pub async fn sync(&self, duration: Duration, invoice_expiry: Duration) -> Result<()> {
-        let seconds = invoice_expiry.as_secs();
+    /// Sync invoices and payments from the persisted cursors,
+    /// the invoices cursor start from `now - lookback` when it's first run.
+    pub async fn sync(&self, duration: Duration, lookback: Duration) -> Result<()> {
+        if self.get_sync_state(INVOICE_TIME).await?.is_none() {
+            let from_time = now().saturating_sub(lookback.as_secs());
+            self.set_sync_state(INVOICE_TIME, from_time as i64).await?;
+        }
         tracing::info!("start task for sync invoices and payments");
         loop {
-            let from_time = now() - seconds;
-            let r = self.sync_invoices(from_time).await;
+            let r = self.sync_invoices(None).await;
             tracing::trace!("sync invoices {:?}", r);
             let r = self.sync_payments(None).await;
             tracing::trace!("sync payments {:?}", r);
@@ -461,38 +1506,295 @@ impl Service {
         }
     }
 
-    pub async fn sync_invoices(&self, from_time: u64) -> Result<usize> {
+    /// Full resync invoices and payments created in the time range, the cursors are not changed.
+    pub async fn resync(&self, from_time: u64, to_time: Option<u64>) -> Result<(usize, usize)> {
+        tracing::info!(
+            "resync invoices and payments from {} to {:?}",
+            from_time,
+            to_time
+        );
+        let invoices = self.sync_invoices_range(from_time, None, to_time).await?;
+        let payments = self.sync_payments_range(from_time, to_time).await?;
+        Ok((invoices, payments))
+    }
+
+    /// Keep the invoices subscription running, reconnect after the retry duration if closed.
+    pub async fn subscribe(&self, retry: Duration) -> Result<()> {
+        tracing::info!("start task for subscribe invoices");
+        loop {
+            let r = self.subscribe_invoices().await;
+            tracing::warn!("invoices subscription closed {:?}", r);
+            sleep(retry).await;
+        }
+    }
+
+    /// Handle paid invoices pushed by the lightning node until the stream closed,
+    /// resume from the persisted pay index.
+    pub async fn subscribe_invoices(&self) -> Result<()> {
+        let from_index = self.get_sync_state(PAY_INDEX).await?.unwrap_or_default();
+        let mut stream = self.lightning.subscribe_invoices(from_index as u64).await?;
+        while let Some(remote) = stream.next().await {
+            let remote = remote?;
+            if remote.status == lightning::InvoiceStatus::Paid {
+                let invoice = invoice::Entity::find()
+                    .filter(invoice::Column::PaymentHash.eq(remote.payment_hash.clone()))
+                    .filter(invoice::Column::Type.eq(invoice::Type::Invoice))
+                    .one(self.db())
+                    .await?;
+                if remote.is_spontaneous() && invoice.is_none() {
+                    if let Err(e) = self.spontaneous_paid(&remote).await {
+                        tracing::error!(
+                            "handle spontaneous invoice {} {:?}",
+                            hex::encode(&remote.payment_hash),
+                            e
+                        );
+                    }
+                } else if let Some(invoice) = invoice {
+                    let r = match invoice.status {
+                        invoice::Status::Unpaid | invoice::Status::Accepted => {
+                            invoice_paid(self, &invoice, &remote).await
+                        }
+                        // check duplicate pay by external and internal
+                        invoice::Status::Paid if invoice.internal && !invoice.duplicate => {
+                            invoice_dup_paid(self, &invoice, &remote).await
+                        }
+                        _ => Ok(()),
+                    };
+                    if let Err(e) = r {
+                        // the polling sync will retry
+                        tracing::error!("handle paid invoice {} {:?}", invoice.id, e);
+                    }
+                }
+            }
+            self.set_sync_state(PAY_INDEX, remote.pay_index as i64)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_sync_state(&self, key: &str) -> Result<Option<i64>> {
+        Ok(sync_state::Entity::find()
+            .filter(sync_state::Column::Service.eq(self.name.clone()))
+            .filter(sync_state::Column::Key.eq(key))
+            .one(self.db())
+            .await?
+            .map(|s| s.value))
+    }
+
+    pub async fn set_sync_state(&self, key: &str, value: i64) -> Result<()> {
+        sync_state::Entity::insert(sync_state::ActiveModel {
+            id: NotSet,
+            service: Set(self.name.clone()),
+            key: Set(key.to_owned()),
+            value: Set(value),
+            updated_at: Set(now() as i64),
+        })
+        .on_conflict(
+            OnConflict::columns([sync_state::Column::Service, sync_state::Column::Key])
+                .update_columns([sync_state::Column::Value, sync_state::Column::UpdatedAt])
+                .to_owned(),
+        )
+        .exec(self.db())
+        .await?;
+        Ok(())
+    }
+
+    /// Compare the ledger with the users balance and the node spendable balance.
+    pub async fn reconcile(&self) -> Result<Reconciliation> {
+        let db = self.db();
+        let accounts: Vec<(Account, Option<i64>)> = posting::Entity::find()
+            .select_only()
+            .column(posting::Column::Account)
+            .column_as(sum_bigint(db, posting::Column::Amount), "amount")
+            .group_by(posting::Column::Account)
+            .into_tuple()
+            .all(db)
+            .await?;
+        let sum = |account: Account| {
+            accounts
+                .iter()
+                .filter(|a| a.0 == account)
+                .map(|a| a.1.unwrap_or_default())
+                .sum::<i64>()
+        };
+
+        let (balance, lock_amount): (Option<i64>, Option<i64>) = user::Entity::find()
+            .select_only()
+            .column_as(sum_bigint(db, user::Column::Balance), "balance")
+            .column_as(sum_bigint(db, user::Column::LockAmount), "lock_amount")
+            .into_tuple()
+            .one(db)
+            .await?
+            .unwrap_or_default();
+
+        let node_balance = self.lightning.channel_balance().await? as i64;
+        let liabilities = balance.unwrap_or_default() + lock_amount.unwrap_or_default();
+        let ledger_liabilities = -(sum(Account::User) + sum(Account::UserLocked));
+        let unbalanced = accounts.iter().map(|a| a.1.unwrap_or_default()).sum();
+        let surplus = node_balance - liabilities;
+
+        let report = Reconciliation {
+            liabilities,
+            ledger_liabilities,
+            ledger_node: sum(Account::Node),
+            fee_income: -sum(Account::FeeIncome),
+            routing_fee: sum(Account::RoutingFee),
+            unbalanced,
+            node_balance,
+            surplus,
+            drift: unbalanced != 0 || ledger_liabilities != liabilities || surplus < 0,
+        };
+        if report.drift {
+            tracing::warn!("reconcile drift {:?}", report);
+        }
+        Ok(report)
+    }
+
+    /// Sync invoices created after `from_time`,
+    /// or from the persisted cursor and advance it to the oldest invoice which may still change.
+    pub async fn sync_invoices(&self, from_time: Option<u64>) -> Result<usize> {
+        if let Some(from_time) = from_time {
+            return self.sync_invoices_range(from_time, None, None).await;
+        }
+        let time = now() as i64;
+        let from_time = self.get_sync_state(INVOICE_TIME).await?.unwrap_or_default();
+        let from_index = self.get_sync_state(INVOICE_INDEX).await?.filter(|i| *i > 0);
+        let updated = self
+            .sync_invoices_range(from_time as u64, from_index.map(|i| i as u64), None)
+            .await?;
+
+        // unpaid, or paid internal which may be paid by external again,
+        // accepted hold invoices are pending until settled or canceled even if expired
+        let pending = invoice::Entity::find()
+            .filter(invoice::Column::Type.eq(invoice::Type::Invoice))
+            .filter(invoice::Column::GeneratedAt.gte(from_time))
+            .filter(
+                Condition::any()
+                    .add(invoice::Column::Status.eq(invoice::Status::Accepted))
+                    .add(
+                        Condition::all()
+                            .add(invoice::Column::ExpiredAt.gte(time - EXPIRY_GRACE))
+                            .add(
+                                Condition::any()
+                                    .add(invoice::Column::Status.eq(invoice::Status::Unpaid))
+                                    .add(
+                                        Condition::all()
+                                            .add(invoice::Column::Status.eq(invoice::Status::Paid))
+                                            .add(invoice::Column::Internal.eq(true))
+                                            .add(invoice::Column::Duplicate.eq(false)),
+                                    ),
+                            ),
+                    ),
+            )
+            .order_by_asc(invoice::Column::GeneratedAt)
+            .one(self.db())
+            .await?;
+        let (time, index) = pending
+            .map(|inv| (inv.generated_at, inv.index))
+            .unwrap_or((time - CURSOR_OVERLAP, 0));
+        self.set_sync_state(INVOICE_TIME, time).await?;
+        self.set_sync_state(INVOICE_INDEX, index).await?;
+        Ok(updated)
+    }
+
+    /// sync invoices created in the time range,
+    /// cln list from the invoice index, use the first local invoice index if none,
+    /// or from the start without local invoices.
+    async fn sync_invoices_range(
+        &self,
+        from_time: u64,
+        from_index: Option<u64>,
+        to_time: Option<u64>,
+    ) -> Result<usize> {
         // get invoices unpaid for update status
         // get paid for check duplicate pay by external and internal
-        let invoices = invoice::Entity::find()
+        let mut query = invoice::Entity::find()
             .filter(invoice::Column::Type.eq(invoice::Type::Invoice))
             .filter(invoice::Column::Status.ne(invoice::Status::Canceled))
-            .filter(invoice::Column::GeneratedAt.gte(from_time as i64))
+            .filter(invoice::Column::GeneratedAt.gte(from_time as i64));
+        if let Some(to_time) = to_time {
+            query = query.filter(invoice::Column::GeneratedAt.lte(to_time as i64));
+        }
+        let invoices = query
             .order_by_asc(invoice::Column::GeneratedAt)
             .all(self.db())
             .await?;
         let mut updated = 0;
 
-        if invoices.is_empty() {
-            return Ok(updated);
-        }
-        let first = invoices.first().unwrap();
+        // received keysend payments have no local invoices
+        let from_index = from_index
+            .or_else(|| invoices.first().map(|inv| inv.index as u64))
+            .unwrap_or_default();
 
-        let map = self
+        let mut map = self
             .lightning
-            .list_invoices(Some((from_time, first.index as u64)), None)
+            .list_invoices(Some((from_time, from_index)), to_time)
             .await?
             .into_iter()
             .map(|inv| (inv.payment_hash.clone(), inv))
             .collect::<HashMap<_, _>>();
+
+        // received keysend payments and offer invoices have no local invoices
+        let local = invoices
+            .iter()
+            .map(|inv| &inv.payment_hash)
+            .collect::<HashSet<_>>();
+        for remote in map.values() {
+            if remote.is_spontaneous()
+                && remote.status == lightning::InvoiceStatus::Paid
+                && !local.contains(&remote.payment_hash)
+            {
+                match self.spontaneous_paid(remote).await {
+                    Ok(true) => updated += 1,
+                    Ok(false) => {}
+                    Err(e) => tracing::error!(
+                        "handle spontaneous invoice {} {:?}",
+                        hex::encode(&remote.payment_hash),
+                        e
+                    ),
+                }
+            }
+        }
+
+        // the hold invoices of cln are kept by the plugin, not in the list
+        for invoice in invoices.iter() {
+            if invoice.hold
+                && invoice.status != invoice::Status::Paid
+                && !map.contains_key(&invoice.payment_hash)
+            {
+                if let Ok(remote) = self
+                    .lightning
+                    .lookup_invoice(invoice.payment_hash.clone())
+                    .await
+                {
+                    map.insert(invoice.payment_hash.clone(), remote);
+                }
+            }
+        }
+
         for invoice in invoices.iter() {
             if let Some(remote) = map.get(&invoice.payment_hash) {
                 match invoice.status {
-                    invoice::Status::Unpaid => {
+                    invoice::Status::Unpaid | invoice::Status::Accepted => {
                         match remote.status {
                             lightning::InvoiceStatus::Open => {
                                 // ignore
                             }
+                            lightning::InvoiceStatus::Accepted => {
+                                // held until settled by the user
+                                if invoice.status == invoice::Status::Unpaid {
+                                    updated += 1;
+                                    let _res = invoice::Entity::update_many()
+                                        .set(invoice::ActiveModel {
+                                            status: Set(invoice::Status::Accepted),
+                                            ..Default::default()
+                                        })
+                                        .filter(invoice::Column::Id.eq(invoice.id))
+                                        .filter(invoice::Column::Status.eq(invoice::Status::Unpaid))
+                                        .exec(self.db())
+                                        .await;
+                                }
+                            }
                             lightning::InvoiceStatus::Paid => {
                                 // updated paid
                                 updated += 1;
@@ -509,7 +1811,10 @@ impl Service {
                                         ..Default::default()
                                     })
                                     .filter(invoice::Column::Id.eq(invoice.id))
-                                    .filter(invoice::Column::Status.eq(invoice::Status::Unpaid))
+                                    .filter(invoice::Column::Status.is_in([
+                                        invoice::Status::Unpaid,
+                                        invoice::Status::Accepted,
+                                    ]))
                                     .exec(self.db())
                                     .await;
                                 // if res.rows_affected != 1 {
@@ -527,7 +1832,7 @@ impl Service {
                         {
                             updated += 1;
                             // todo: log error
-                            let _r = invoice_dup_paid(self.db(), invoice, remote).await;
+                            let _r = invoice_dup_paid(self, invoice, remote).await;
                         }
                     }
                     invoice::Status::Canceled => {
@@ -539,21 +1844,50 @@ impl Service {
         Ok(updated)
     }
 
+    /// Sync unpaid payments created after `from_time`,
+    /// or from the persisted cursor and advance it to the oldest unpaid payment.
     pub async fn sync_payments(&self, from_time: Option<u64>) -> Result<usize> {
-        let payments = invoice::Entity::find()
+        if let Some(from_time) = from_time {
+            return self.sync_payments_range(from_time, None).await;
+        }
+        let time = now() as i64;
+        let from_time = self.get_sync_state(PAYMENT_TIME).await?.unwrap_or_default();
+        let updated = self.sync_payments_range(from_time as u64, None).await?;
+
+        let pending = invoice::Entity::find()
             .filter(invoice::Column::Type.eq(invoice::Type::Payment))
             .filter(invoice::Column::Status.eq(invoice::Status::Unpaid))
-            .order_by_asc(invoice::Column::GeneratedAt)
+            .order_by_asc(invoice::Column::CreatedAt)
+            .one(self.db())
+            .await?;
+        let time = pending
+            .map(|p| p.created_at)
+            .unwrap_or(time - CURSOR_OVERLAP);
+        self.set_sync_state(PAYMENT_TIME, time).await?;
+        Ok(updated)
+    }
+
+    /// sync unpaid payments sent in the time range
+    async fn sync_payments_range(&self, from_time: u64, to_time: Option<u64>) -> Result<usize> {
+        let mut query = invoice::Entity::find()
+            .filter(invoice::Column::Type.eq(invoice::Type::Payment))
+            .filter(invoice::Column::Status.eq(invoice::Status::Unpaid))
+            .filter(invoice::Column::CreatedAt.gte(from_time as i64));
+        if let Some(to_time) = to_time {
+            query = query.filter(invoice::Column::CreatedAt.lte(to_time as i64));
+        }
+        let payments = query
+            .order_by_asc(invoice::Column::CreatedAt)
             .all(self.db())
             .await?;
 
         let mut updated = 0;
 
         if !payments.is_empty() {
-            let from_time = from_time.unwrap_or(payments[0].generated_at as u64);
+            let from_time = payments[0].created_at as u64;
             let map = self
                 .lightning
-                .list_payments(Some(from_time), None)
+                .list_payments(Some(from_time), to_time)
                 .await?
                 .into_iter()
                 .map(|inv| (inv.payment_hash.clone(), inv))
@@ -567,12 +1901,12 @@ impl Service {
                             lightning::PaymentStatus::Succeeded => {
                                 updated += 1;
                                 // todo: log error
-                                let _r = pay_success(self.db(), remote, payment).await;
+                                let _r = pay_success(self, remote, payment).await;
                             }
                             lightning::PaymentStatus::Failed => {
                                 updated += 1;
                                 // todo: log error
-                                let _r = pay_failed(self.db(), payment).await;
+                                let _r = pay_failed(self, payment).await;
                             }
                         }
                     }
@@ -580,68 +1914,971 @@ impl Service {
             }
         }
 
-        Ok(updated)
+        Ok(updated)
+    }
+
+    /// events service
+    pub async fn get_event(&self, event_id: Vec<u8>) -> Result<Option<event::Model>> {
+        Ok(event::Entity::find()
+            .filter(event::Column::EventId.eq(event_id))
+            .one(self.db())
+            .await?)
+    }
+
+    pub async fn create_event(&self, event: &Event) -> Result<Option<event::Model>> {
+        let now = now() as i64;
+        let res = event::ActiveModel {
+            id: NotSet,
+            event_id: Set(event.id.as_bytes().to_vec()),
+            json: Set(event.as_json()),
+            created_at: Set(now),
+            updated_at: Set(now),
+            status: Set(event::Status::Created),
+            message: NotSet,
+        }
+        .insert(self.db())
+        .await;
+        match res {
+            Ok(res) => Ok(Some(res)),
+            Err(e) => {
+                if matches!(
+                    e.sql_err(),
+                    Some(sea_orm::SqlErr::UniqueConstraintViolation(_))
+                ) {
+                    // return false unique constraint
+                    return Ok(None);
+                }
+                Err(e.into())
+            }
+        }
+    }
+
+    pub async fn update_event_error(&self, id: i32, err: &Error) -> Result<()> {
+        event::ActiveModel {
+            id: Set(id),
+            status: Set(event::Status::Failed),
+            message: Set(err.to_string()),
+            updated_at: Set(now() as i64),
+            ..Default::default()
+        }
+        .update(self.db())
+        .await?;
+        Ok(())
+    }
+
+    pub async fn update_event_success(&self, id: i32, message: String) -> Result<()> {
+        event::ActiveModel {
+            id: Set(id),
+            status: Set(event::Status::Succeeded),
+            message: Set(message),
+            updated_at: Set(now() as i64),
+            ..Default::default()
+        }
+        .update(self.db())
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_offer(&self, user_id: i32) -> Result<Option<offer::Model>> {
+        Ok(offer::Entity::find()
+            .filter(offer::Column::Service.eq(self.name.clone()))
+            .filter(offer::Column::UserId.eq(user_id))
+            .one(self.db())
+            .await?)
+    }
+
+    /// Get the reusable bolt12 offer of the user, create it by the node if not exists.
+    pub async fn get_or_create_offer(&self, user: &user::Model) -> Result<offer::Model> {
+        if let Some(offer) = self.get_offer(user.id).await? {
+            return Ok(offer);
+        }
+        // cln returns the existing offer for the same description, keep it unique per user
+        let description = format!("Pay to {}", hex::encode(&user.pubkey));
+        let remote = self.lightning.create_offer(description, None).await?;
+        let res = offer::ActiveModel {
+            id: NotSet,
+            user_id: Set(user.id),
+            service: Set(self.name.clone()),
+            offer_id: Set(remote.id),
+            bolt12: Set(remote.bolt12),
+            description: Set(remote.description),
+            amount: Set(remote.amount as i64),
+            created_at: Set(now() as i64),
+        }
+        .insert(self.db())
+        .await;
+        match res {
+            Ok(offer) => Ok(offer),
+            // created at the same time
+            Err(e)
+                if matches!(
+                    e.sql_err(),
+                    Some(sea_orm::SqlErr::UniqueConstraintViolation(_))
+                ) =>
+            {
+                self.get_offer(user.id)
+                    .await?
+                    .ok_or(Error::Str("offer not found"))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// get the offer created by our node
+    async fn get_local_offer(&self, bolt12: &str) -> Result<Option<offer::Model>> {
+        Ok(offer::Entity::find()
+            .filter(offer::Column::Service.eq(self.name.clone()))
+            .filter(offer::Column::Bolt12.eq(bolt12.to_lowercase()))
+            .one(self.db())
+            .await?)
+    }
+
+    /// find the user of the offer by the offer id
+    async fn offer_user(&self, offer_id: &[u8]) -> Result<Option<user::Model>> {
+        let offer = offer::Entity::find()
+            .filter(offer::Column::Service.eq(self.name.clone()))
+            .filter(offer::Column::OfferId.eq(offer_id.to_vec()))
+            .one(self.db())
+            .await?;
+        match offer {
+            Some(offer) => Ok(Some(get_user_by_id(self.db(), offer.user_id).await?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn create_nwc_connection(
+        &self,
+        user: &user::Model,
+        pubkey: Vec<u8>,
+        name: String,
+        policy: NwcPolicy,
+    ) -> Result<nwc_connection::Model> {
+        let now = now() as i64;
+        Ok(nwc_connection::ActiveModel {
+            id: NotSet,
+            user_id: Set(user.id),
+            pubkey: Set(pubkey),
+            name: Set(name),
+            methods: Set(policy.methods.join(" ")),
+            max_amount: Set(policy.max_amount),
+            budget: Set(policy.budget),
+            budget_renewal: Set(policy.budget_renewal),
+            budget_used: Set(0),
+            budget_renewed_at: Set(now),
+            expires_at: Set(policy.expires_at),
+            revoked_at: Set(0),
+            last_used_at: Set(0),
+            created_at: Set(now),
+            updated_at: Set(now),
+        }
+        .insert(self.db())
+        .await?)
+    }
+
+    pub async fn list_nwc_connections(&self, user_id: i32) -> Result<Vec<nwc_connection::Model>> {
+        Ok(nwc_connection::Entity::find()
+            .filter(nwc_connection::Column::UserId.eq(user_id))
+            .order_by_desc(nwc_connection::Column::Id)
+            .all(self.db())
+            .await?)
+    }
+
+    /// get connection by the client pubkey
+    pub async fn get_nwc_connection(
+        &self,
+        pubkey: Vec<u8>,
+    ) -> Result<Option<nwc_connection::Model>> {
+        Ok(nwc_connection::Entity::find()
+            .filter(nwc_connection::Column::Pubkey.eq(pubkey))
+            .one(self.db())
+            .await?)
+    }
+
+    pub async fn revoke_nwc_connection(
+        &self,
+        user_id: i32,
+        id: i32,
+    ) -> Result<nwc_connection::Model> {
+        let now = now() as i64;
+        nwc_connection::Entity::update_many()
+            .col_expr(nwc_connection::Column::RevokedAt, Expr::value(now))
+            .col_expr(nwc_connection::Column::UpdatedAt, Expr::value(now))
+            .filter(nwc_connection::Column::Id.eq(id))
+            .filter(nwc_connection::Column::UserId.eq(user_id))
+            .filter(nwc_connection::Column::RevokedAt.eq(0))
+            .exec(self.db())
+            .await?;
+        nwc_connection::Entity::find_by_id(id)
+            .filter(nwc_connection::Column::UserId.eq(user_id))
+            .one(self.db())
+            .await?
+            .ok_or_else(|| Error::NotFound("Connection not found".to_owned()))
+    }
+
+    /// Spend the budget of the connection before paying, renew the budget when a new period starts.
+    pub async fn spend_nwc_budget(&self, conn: &nwc_connection::Model, amount: i64) -> Result<()> {
+        let now = now() as i64;
+        let start = budget_period_start(conn, now);
+        if conn.budget_renewed_at < start {
+            nwc_connection::Entity::update_many()
+                .col_expr(nwc_connection::Column::BudgetUsed, Expr::value(0))
+                .col_expr(nwc_connection::Column::BudgetRenewedAt, Expr::value(start))
+                .filter(nwc_connection::Column::Id.eq(conn.id))
+                .filter(nwc_connection::Column::BudgetRenewedAt.lt(start))
+                .exec(self.db())
+                .await?;
+        }
+
+        let mut update = nwc_connection::Entity::update_many()
+            .col_expr(
+                nwc_connection::Column::BudgetUsed,
+                Expr::col(nwc_connection::Column::BudgetUsed).add(amount),
+            )
+            .col_expr(nwc_connection::Column::LastUsedAt, Expr::value(now))
+            .filter(nwc_connection::Column::Id.eq(conn.id));
+        if conn.budget > 0 {
+            update = update.filter(nwc_connection::Column::BudgetUsed.lte(conn.budget - amount));
+        }
+        let res = update.exec(self.db()).await?;
+        if res.rows_affected != 1 {
+            return Err(Error::QuotaExceeded(
+                "The budget of the connection is exceeded".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn create_withdraw_link(
+        &self,
+        user: &user::Model,
+        policy: WithdrawPolicy,
+    ) -> Result<withdraw_link::Model> {
+        let now = now() as i64;
+        Ok(withdraw_link::ActiveModel {
+            id: NotSet,
+            user_id: Set(user.id),
+            k1: Set(hex::encode(rand_preimage())),
+            title: Set(policy.title),
+            min_withdrawable: Set(policy.min_withdrawable),
+            max_withdrawable: Set(policy.max_withdrawable),
+            total_cap: Set(policy.total_cap),
+            withdrawn: Set(0),
+            max_uses: Set(policy.max_uses),
+            uses: Set(0),
+            expires_at: Set(policy.expires_at),
+            revoked_at: Set(0),
+            last_used_at: Set(0),
+            created_at: Set(now),
+            updated_at: Set(now),
+        }
+        .insert(self.db())
+        .await?)
+    }
+
+    pub async fn list_withdraw_links(&self, user_id: i32) -> Result<Vec<withdraw_link::Model>> {
+        Ok(withdraw_link::Entity::find()
+            .filter(withdraw_link::Column::UserId.eq(user_id))
+            .order_by_desc(withdraw_link::Column::Id)
+            .all(self.db())
+            .await?)
+    }
+
+    /// Get the usable withdraw link by the secret k1,
+    /// error if it's revoked, expired or used up.
+    pub async fn get_withdraw_link(&self, k1: &str) -> Result<withdraw_link::Model> {
+        let link = withdraw_link::Entity::find()
+            .filter(withdraw_link::Column::K1.eq(k1))
+            .one(self.db())
+            .await?
+            .ok_or_else(|| Error::NotFound("The withdraw link is not found".to_owned()))?;
+        if link.revoked_at > 0 {
+            return Err(Error::Restricted("The withdraw link is revoked".to_owned()));
+        }
+        if link.expires_at > 0 && link.expires_at <= now() as i64 {
+            return Err(Error::Restricted("The withdraw link is expired".to_owned()));
+        }
+        if (link.max_uses > 0 && link.uses >= link.max_uses)
+            || (link.total_cap > 0 && link.withdrawn + link.min_withdrawable > link.total_cap)
+        {
+            return Err(Error::QuotaExceeded(
+                "The withdraw link is used up".to_owned(),
+            ));
+        }
+        Ok(link)
+    }
+
+    pub async fn revoke_withdraw_link(
+        &self,
+        user_id: i32,
+        id: i32,
+    ) -> Result<withdraw_link::Model> {
+        let now = now() as i64;
+        withdraw_link::Entity::update_many()
+            .col_expr(withdraw_link::Column::RevokedAt, Expr::value(now))
+            .col_expr(withdraw_link::Column::UpdatedAt, Expr::value(now))
+            .filter(withdraw_link::Column::Id.eq(id))
+            .filter(withdraw_link::Column::UserId.eq(user_id))
+            .filter(withdraw_link::Column::RevokedAt.eq(0))
+            .exec(self.db())
+            .await?;
+        withdraw_link::Entity::find_by_id(id)
+            .filter(withdraw_link::Column::UserId.eq(user_id))
+            .one(self.db())
+            .await?
+            .ok_or_else(|| Error::NotFound("Withdraw link not found".to_owned()))
+    }
+
+    /// Pay the invoice presented to the withdraw link from the balance of the link owner,
+    /// the use is counted before paying and given back when the payment fails.
+    pub async fn withdraw(&self, k1: &str, bolt11: String, fee: &Fee) -> Result<invoice::Model> {
+        let (link, amount) = self.reserve_withdraw(k1, &bolt11).await?;
+        self.pay_withdraw(&link, bolt11, amount, fee).await
+    }
+
+    /// Validate the invoice presented to the withdraw link and count the use,
+    /// return the link and the amount in msats.
+    pub async fn reserve_withdraw(
+        &self,
+        k1: &str,
+        bolt11: &str,
+    ) -> Result<(withdraw_link::Model, i64)> {
+        let link = self.get_withdraw_link(k1).await?;
+        let inv = lightning::Invoice::from_bolt11(bolt11.to_owned())?;
+        if inv.created_at + inv.expiry <= now() {
+            return Err(Error::Str("The invoice is expired."));
+        }
+        let amount = inv.amount as i64;
+        if amount < link.min_withdrawable || amount > link.max_withdrawable {
+            return Err(Error::InvalidParam(format!(
+                "Amount out of bounds (min: {} sat, max: {} sat).",
+                link.min_withdrawable / 1000,
+                link.max_withdrawable / 1000,
+            )));
+        }
+        if get_user_by_id(self.db(), link.user_id).await?.balance < amount {
+            return Err(Error::Str("The balance is insufficient."));
+        }
+
+        let mut update = withdraw_link::Entity::update_many()
+            .col_expr(
+                withdraw_link::Column::Withdrawn,
+                Expr::col(withdraw_link::Column::Withdrawn).add(amount),
+            )
+            .col_expr(
+                withdraw_link::Column::Uses,
+                Expr::col(withdraw_link::Column::Uses).add(1),
+            )
+            .col_expr(withdraw_link::Column::LastUsedAt, Expr::value(now() as i64))
+            .filter(withdraw_link::Column::Id.eq(link.id))
+            .filter(withdraw_link::Column::RevokedAt.eq(0));
+        if link.max_uses > 0 {
+            update = update.filter(withdraw_link::Column::Uses.lt(link.max_uses));
+        }
+        if link.total_cap > 0 {
+            update = update.filter(withdraw_link::Column::Withdrawn.lte(link.total_cap - amount));
+        }
+        let res = update.exec(self.db()).await?;
+        if res.rows_affected != 1 {
+            return Err(Error::QuotaExceeded(
+                "The withdraw link is used up".to_owned(),
+            ));
+        }
+        Ok((link, amount))
+    }
+
+    /// Pay the invoice of the reserved use, the use is given back when the payment fails.
+    pub async fn pay_withdraw(
+        &self,
+        link: &withdraw_link::Model,
+        bolt11: String,
+        amount: i64,
+        fee: &Fee,
+    ) -> Result<invoice::Model> {
+        let res = match get_user_by_id(self.db(), link.user_id).await {
+            Ok(user) => {
+                self.pay(&user, bolt11, None, fee, invoice::Source::Withdraw, false)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        self.release_budget(Budget::WithdrawLink, link.id, amount, &res)
+            .await?;
+        res
+    }
+
+    /// Create the lnurl-auth challenge, link the signed key to the user if set,
+    /// the expired challenges are cleaned.
+    pub async fn create_auth_challenge(
+        &self,
+        user_id: Option<i32>,
+    ) -> Result<auth_challenge::Model> {
+        let now = now() as i64;
+        auth_challenge::Entity::delete_many()
+            .filter(auth_challenge::Column::CreatedAt.lt(now - AUTH_CHALLENGE_EXPIRY))
+            .exec(self.db())
+            .await?;
+        Ok(auth_challenge::ActiveModel {
+            id: NotSet,
+            k1: Set(rand_preimage()),
+            user_id: Set(user_id.unwrap_or_default()),
+            authed_user_id: Set(0),
+            created_at: Set(now),
+            authed_at: Set(0),
+        }
+        .insert(self.db())
+        .await?)
+    }
+
+    async fn get_auth_challenge(&self, k1: &[u8]) -> Result<auth_challenge::Model> {
+        auth_challenge::Entity::find()
+            .filter(auth_challenge::Column::K1.eq(k1))
+            .filter(auth_challenge::Column::CreatedAt.gte(now() as i64 - AUTH_CHALLENGE_EXPIRY))
+            .one(self.db())
+            .await?
+            .ok_or_else(|| Error::NotFound("The challenge is not found or expired".to_owned()))
+    }
+
+    /// Verify the LUD-04 signature of the challenge by the linking key,
+    /// sign in the user of the key or link the key to the user of the challenge.
+    /// A new key signs in the user of the x-only key.
+    pub async fn lnurl_auth(&self, k1: &[u8], key: &[u8], sig: &[u8]) -> Result<user::Model> {
+        let challenge = self.get_auth_challenge(k1).await?;
+        if challenge.authed_user_id > 0 {
+            return Err(Error::InvalidParam("The challenge is used".to_owned()));
+        }
+        let pubkey = PublicKey::from_slice(key)?;
+        let mut sig = Signature::from_der(sig)?;
+        sig.normalize_s();
+        SECP256K1.verify_ecdsa(&Message::from_slice(k1)?, &sig, &pubkey)?;
+
+        let now = now() as i64;
+        let identity = auth_identity::Entity::find()
+            .filter(auth_identity::Column::Kind.eq(auth_identity::Kind::Lnurl))
+            .filter(auth_identity::Column::Identifier.eq(key))
+            .one(self.db())
+            .await?;
+        let user = match identity {
+            Some(identity) => {
+                if challenge.user_id > 0 && challenge.user_id != identity.user_id {
+                    return Err(Error::InvalidParam(
+                        "The key is linked to another user".to_owned(),
+                    ));
+                }
+                auth_identity::Entity::update_many()
+                    .col_expr(auth_identity::Column::LastUsedAt, Expr::value(now))
+                    .filter(auth_identity::Column::Id.eq(identity.id))
+                    .exec(self.db())
+                    .await?;
+                get_user_by_id(self.db(), identity.user_id).await?
+            }
+            None => {
+                let user = if challenge.user_id > 0 {
+                    get_user_by_id(self.db(), challenge.user_id).await?
+                } else {
+                    let (xonly, _) = pubkey.x_only_public_key();
+                    self.get_or_create_user(xonly.serialize().to_vec()).await?
+                };
+                auth_identity::ActiveModel {
+                    id: NotSet,
+                    user_id: Set(user.id),
+                    kind: Set(auth_identity::Kind::Lnurl),
+                    identifier: Set(key.to_vec()),
+                    last_used_at: Set(now),
+                    created_at: Set(now),
+                }
+                .insert(self.db())
+                .await?;
+                user
+            }
+        };
+
+        let res = auth_challenge::Entity::update_many()
+            .col_expr(auth_challenge::Column::AuthedUserId, Expr::value(user.id))
+            .col_expr(auth_challenge::Column::AuthedAt, Expr::value(now))
+            .filter(auth_challenge::Column::Id.eq(challenge.id))
+            .filter(auth_challenge::Column::AuthedUserId.eq(0))
+            .exec(self.db())
+            .await?;
+        if res.rows_affected != 1 {
+            return Err(Error::InvalidParam("The challenge is used".to_owned()));
+        }
+        Ok(user)
+    }
+
+    /// Take the user signed in by the challenge, the challenge is removed once taken,
+    /// None if the wallet has not signed yet.
+    pub async fn take_auth_challenge(&self, k1: &[u8]) -> Result<Option<user::Model>> {
+        let challenge = self.get_auth_challenge(k1).await?;
+        if challenge.authed_user_id == 0 {
+            return Ok(None);
+        }
+        let res = auth_challenge::Entity::delete_many()
+            .filter(auth_challenge::Column::Id.eq(challenge.id))
+            .exec(self.db())
+            .await?;
+        if res.rows_affected != 1 {
+            return Err(Error::NotFound(
+                "The challenge is not found or expired".to_owned(),
+            ));
+        }
+        Ok(Some(
+            get_user_by_id(self.db(), challenge.authed_user_id).await?,
+        ))
     }
 
-    /// events service
-    pub async fn get_event(&self, event_id: Vec<u8>) -> Result<Option<event::Model>> {
-        Ok(event::Entity::find()
-            .filter(event::Column::EventId.eq(event_id))
-            .one(self.db())
+    pub async fn list_auth_identities(&self, user_id: i32) -> Result<Vec<auth_identity::Model>> {
+        Ok(auth_identity::Entity::find()
+            .filter(auth_identity::Column::UserId.eq(user_id))
+            .order_by_desc(auth_identity::Column::Id)
+            .all(self.db())
             .await?)
     }
 
-    pub async fn create_event(&self, event: &Event) -> Result<Option<event::Model>> {
+    /// create the session of the signed in device, expiry in seconds,
+    /// the lndhub credential id if signed in by it, otherwise 0
+    pub async fn create_session(
+        &self,
+        user_id: i32,
+        credential_id: i32,
+        device: &str,
+        ip: &str,
+        expiry: i64,
+    ) -> Result<session::Model> {
         let now = now() as i64;
-        let res = event::ActiveModel {
+        Ok(session::ActiveModel {
             id: NotSet,
-            event_id: Set(event.id.as_bytes().to_vec()),
-            json: Set(event.as_json()),
+            user_id: Set(user_id),
+            credential_id: Set(credential_id),
+            jti: Set(hex::encode(rand_preimage())),
+            device: Set(device.chars().take(SESSION_DEVICE_MAX_CHARS).collect()),
+            ip: Set(ip.to_owned()),
+            last_used_at: Set(now),
+            expires_at: Set(now + expiry),
+            revoked_at: Set(0),
             created_at: Set(now),
             updated_at: Set(now),
-            status: Set(event::Status::Created),
-            message: NotSet,
         }
         .insert(self.db())
-        .await;
-        match res {
-            Ok(res) => Ok(Some(res)),
-            Err(e) => {
+        .await?)
+    }
+
+    /// get the session which is not revoked or expired
+    pub async fn get_active_session(&self, id: i32) -> Result<session::Model> {
+        session::Entity::find_by_id(id)
+            .filter(session::Column::RevokedAt.eq(0))
+            .filter(session::Column::ExpiresAt.gt(now() as i64))
+            .one(self.db())
+            .await?
+            .ok_or_else(|| AuthError::Invalid("Invalid session").into())
+    }
+
+    /// Rotate the refresh token id of the session.
+    /// A reused refresh token revokes the session, the token may be stolen.
+    /// The session signed in by a revoked lndhub credential is revoked.
+    pub async fn refresh_session(
+        &self,
+        id: i32,
+        jti: &str,
+        ip: &str,
+        expiry: i64,
+    ) -> Result<session::Model> {
+        let session = self.get_active_session(id).await?;
+        if session.credential_id > 0 {
+            let active = lndhub_credential::Entity::find_by_id(session.credential_id)
+                .filter(lndhub_credential::Column::RevokedAt.eq(0))
+                .one(self.db())
+                .await?;
+            if active.is_none() {
+                self.revoke_session(session.user_id, session.id).await?;
+                return Err(AuthError::Invalid("The credential of the session is revoked").into());
+            }
+        }
+        let now = now() as i64;
+        let res = session::Entity::update_many()
+            .col_expr(
+                session::Column::Jti,
+                Expr::value(hex::encode(rand_preimage())),
+            )
+            .col_expr(session::Column::Ip, Expr::value(ip))
+            .col_expr(session::Column::LastUsedAt, Expr::value(now))
+            .col_expr(session::Column::ExpiresAt, Expr::value(now + expiry))
+            .col_expr(session::Column::UpdatedAt, Expr::value(now))
+            .filter(session::Column::Id.eq(session.id))
+            .filter(session::Column::Jti.eq(jti))
+            .filter(session::Column::RevokedAt.eq(0))
+            .exec(self.db())
+            .await?;
+        if res.rows_affected == 0 {
+            self.revoke_session(session.user_id, session.id).await?;
+            return Err(AuthError::Invalid("Refresh token reused, the session is revoked").into());
+        }
+        self.get_active_session(session.id).await
+    }
+
+    /// update the last used time and ip of the session, at most once a minute
+    pub async fn touch_session(&self, session: &session::Model, ip: &str) -> Result<()> {
+        let now = now() as i64;
+        if now - session.last_used_at >= SESSION_TOUCH_INTERVAL || session.ip != ip {
+            session::Entity::update_many()
+                .col_expr(session::Column::Ip, Expr::value(ip))
+                .col_expr(session::Column::LastUsedAt, Expr::value(now))
+                .filter(session::Column::Id.eq(session.id))
+                .exec(self.db())
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn list_sessions(&self, user_id: i32) -> Result<Vec<session::Model>> {
+        Ok(session::Entity::find()
+            .filter(session::Column::UserId.eq(user_id))
+            .filter(session::Column::RevokedAt.eq(0))
+            .filter(session::Column::ExpiresAt.gt(now() as i64))
+            .order_by_desc(session::Column::LastUsedAt)
+            .all(self.db())
+            .await?)
+    }
+
+    pub async fn revoke_session(&self, user_id: i32, id: i32) -> Result<session::Model> {
+        let now = now() as i64;
+        session::Entity::update_many()
+            .col_expr(session::Column::RevokedAt, Expr::value(now))
+            .col_expr(session::Column::UpdatedAt, Expr::value(now))
+            .filter(session::Column::Id.eq(id))
+            .filter(session::Column::UserId.eq(user_id))
+            .filter(session::Column::RevokedAt.eq(0))
+            .exec(self.db())
+            .await?;
+        session::Entity::find_by_id(id)
+            .filter(session::Column::UserId.eq(user_id))
+            .one(self.db())
+            .await?
+            .ok_or_else(|| Error::NotFound("Session not found".to_owned()))
+    }
+
+    /// Record the nip98 auth event until it expires, false if it is a replay.
+    pub async fn record_auth_event(&self, id: &[u8], expires_at: i64) -> Result<bool> {
+        let now = now() as i64;
+        if !self.auth_events.insert(id, expires_at, now)? {
+            return Ok(false);
+        }
+        if self.auth_events_db {
+            auth_event::Entity::delete_many()
+                .filter(auth_event::Column::ExpiresAt.lte(now))
+                .exec(self.db())
+                .await?;
+            let res = auth_event::ActiveModel {
+                event_id: Set(id.to_vec()),
+                expires_at: Set(expires_at),
+                ..Default::default()
+            }
+            .insert(self.db())
+            .await;
+            if let Err(e) = res {
                 if matches!(
                     e.sql_err(),
                     Some(sea_orm::SqlErr::UniqueConstraintViolation(_))
                 ) {
-                    // return false unique constraint
-                    return Ok(None);
+                    // seen by another instance
+                    return Ok(false);
                 }
-                Err(e.into())
+                return Err(e.into());
             }
         }
+        Ok(true)
     }
 
-    pub async fn update_event_error(&self, id: i32, err: &Error) -> Result<()> {
-        event::ActiveModel {
-            id: Set(id),
-            status: Set(event::Status::Failed),
-            message: Set(err.to_string()),
-            updated_at: Set(now() as i64),
-            ..Default::default()
+    /// Issue a new api key, the secret is only returned here.
+    pub async fn create_api_key(
+        &self,
+        user_id: i32,
+        name: String,
+        policy: ApiKeyPolicy,
+    ) -> Result<(api_key::Model, String)> {
+        let now = now() as i64;
+        let count = api_key::Entity::find()
+            .filter(api_key::Column::UserId.eq(user_id))
+            .filter(api_key::Column::RevokedAt.eq(0))
+            .filter(
+                Condition::any()
+                    .add(api_key::Column::ExpiresAt.eq(0))
+                    .add(api_key::Column::ExpiresAt.gt(now)),
+            )
+            .count(self.db())
+            .await?;
+        if count >= API_KEY_MAX_KEYS {
+            return Err(Error::QuotaExceeded(format!(
+                "The number of api keys cannot be greater than {}",
+                API_KEY_MAX_KEYS
+            )));
         }
-        .update(self.db())
+        let secret = format!("{}{}", API_KEY_PREFIX, hex::encode(rand_preimage()));
+        let key = api_key::ActiveModel {
+            id: NotSet,
+            user_id: Set(user_id),
+            name: Set(name),
+            prefix: Set(secret[..API_KEY_DISPLAY_LEN].to_owned()),
+            key_hash: Set(sha256(&secret)),
+            scopes: Set(policy
+                .scopes
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")),
+            spend_limit: Set(policy.spend_limit),
+            spent: Set(0),
+            allowed_ips: Set(policy.allowed_ips.join(" ")),
+            expires_at: Set(policy.expires_at),
+            revoked_at: Set(0),
+            last_used_at: Set(0),
+            created_at: Set(now),
+            updated_at: Set(now),
+        }
+        .insert(self.db())
         .await?;
+        Ok((key, secret))
+    }
+
+    /// get the api key by the secret, error if it's revoked or expired
+    pub async fn get_active_api_key(&self, secret: &str) -> Result<api_key::Model> {
+        let now = now() as i64;
+        api_key::Entity::find()
+            .filter(api_key::Column::KeyHash.eq(sha256(secret)))
+            .filter(api_key::Column::RevokedAt.eq(0))
+            .filter(
+                Condition::any()
+                    .add(api_key::Column::ExpiresAt.eq(0))
+                    .add(api_key::Column::ExpiresAt.gt(now)),
+            )
+            .one(self.db())
+            .await?
+            .ok_or_else(|| AuthError::Invalid("Invalid api key").into())
+    }
+
+    /// update the last used time of the api key, at most once a minute
+    pub async fn touch_api_key(&self, key: &api_key::Model) -> Result<()> {
+        let now = now() as i64;
+        if now - key.last_used_at >= SESSION_TOUCH_INTERVAL {
+            api_key::Entity::update_many()
+                .col_expr(api_key::Column::LastUsedAt, Expr::value(now))
+                .filter(api_key::Column::Id.eq(key.id))
+                .exec(self.db())
+                .await?;
+        }
         Ok(())
     }
 
-    pub async fn update_event_success(&self, id: i32, message: String) -> Result<()> {
-        event::ActiveModel {
-            id: Set(id),
-            status: Set(event::Status::Succeeded),
-            message: Set(message),
-            updated_at: Set(now() as i64),
-            ..Default::default()
+    pub async fn list_api_keys(&self, user_id: i32) -> Result<Vec<api_key::Model>> {
+        Ok(api_key::Entity::find()
+            .filter(api_key::Column::UserId.eq(user_id))
+            .order_by_desc(api_key::Column::Id)
+            .all(self.db())
+            .await?)
+    }
+
+    pub async fn revoke_api_key(&self, user_id: i32, id: i32) -> Result<api_key::Model> {
+        let now = now() as i64;
+        api_key::Entity::update_many()
+            .col_expr(api_key::Column::RevokedAt, Expr::value(now))
+            .col_expr(api_key::Column::UpdatedAt, Expr::value(now))
+            .filter(api_key::Column::Id.eq(id))
+            .filter(api_key::Column::UserId.eq(user_id))
+            .filter(api_key::Column::RevokedAt.eq(0))
+            .exec(self.db())
+            .await?;
+        api_key::Entity::find_by_id(id)
+            .filter(api_key::Column::UserId.eq(user_id))
+            .one(self.db())
+            .await?
+            .ok_or_else(|| Error::NotFound("Api key not found".to_owned()))
+    }
+
+    /// Spend the limit of the api key before paying
+    pub async fn spend_api_key(&self, key: &api_key::Model, amount: i64) -> Result<()> {
+        let mut update = api_key::Entity::update_many()
+            .col_expr(
+                api_key::Column::Spent,
+                Expr::col(api_key::Column::Spent).add(amount),
+            )
+            .filter(api_key::Column::Id.eq(key.id));
+        if key.spend_limit > 0 {
+            update = update.filter(api_key::Column::Spent.lte(key.spend_limit - amount));
         }
-        .update(self.db())
+        let res = update.exec(self.db()).await?;
+        if res.rows_affected != 1 {
+            return Err(Error::QuotaExceeded(
+                "The spend limit of the api key is exceeded".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Settle the budget charged before paying by the payment result,
+    /// give back the budget when the payment failed, hold it while the payment is in flight.
+    pub async fn release_budget(
+        &self,
+        budget: Budget,
+        budget_id: i32,
+        amount: i64,
+        res: &Result<invoice::Model>,
+    ) -> Result<()> {
+        match res {
+            Ok(payment) => {
+                self.settle_budget(budget, budget_id, amount, payment.total)
+                    .await
+            }
+            Err(Error::PaymentInProgress(invoice_id)) => {
+                self.hold_budget(*invoice_id, budget, budget_id, amount)
+                    .await
+            }
+            Err(_) => self.refund_budget(budget, budget_id, amount).await,
+        }
+    }
+
+    /// Hold the budget until the in flight payment is resolved,
+    /// the payment may be resolved before the hold is saved.
+    async fn hold_budget(
+        &self,
+        invoice_id: i32,
+        budget: Budget,
+        budget_id: i32,
+        amount: i64,
+    ) -> Result<()> {
+        budget_hold::ActiveModel {
+            id: NotSet,
+            invoice_id: Set(invoice_id),
+            budget: Set(budget),
+            budget_id: Set(budget_id),
+            amount: Set(amount),
+            created_at: Set(now() as i64),
+        }
+        .insert(self.db())
         .await?;
+        let payment = self.get_invoice(invoice_id).await?;
+        match payment {
+            Some(p) if p.status == invoice::Status::Canceled => {
+                self.release_budget_holds(invoice_id).await
+            }
+            Some(p) if p.status == invoice::Status::Paid => {
+                self.settle_budget_holds(invoice_id, p.total).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Give back the budget charged over the total of the succeeded payment,
+    /// the withdraw link counts the amount without the fees.
+    async fn settle_budget(
+        &self,
+        budget: Budget,
+        budget_id: i32,
+        charged: i64,
+        total: i64,
+    ) -> Result<()> {
+        if budget == Budget::WithdrawLink || charged <= total {
+            return Ok(());
+        }
+        self.refund_budget(budget, budget_id, charged - total).await
+    }
+
+    /// Settle the budgets held by the succeeded payment to the total, each hold is settled once.
+    async fn settle_budget_holds(&self, invoice_id: i32, total: i64) -> Result<()> {
+        let holds = budget_hold::Entity::find()
+            .filter(budget_hold::Column::InvoiceId.eq(invoice_id))
+            .all(self.db())
+            .await?;
+        for hold in holds {
+            let res = budget_hold::Entity::delete_by_id(hold.id)
+                .exec(self.db())
+                .await?;
+            if res.rows_affected == 1 {
+                self.settle_budget(hold.budget, hold.budget_id, hold.amount, total)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Give back the budgets held by the failed payment, each hold is given back once.
+    async fn release_budget_holds(&self, invoice_id: i32) -> Result<()> {
+        let holds = budget_hold::Entity::find()
+            .filter(budget_hold::Column::InvoiceId.eq(invoice_id))
+            .all(self.db())
+            .await?;
+        for hold in holds {
+            let res = budget_hold::Entity::delete_by_id(hold.id)
+                .exec(self.db())
+                .await?;
+            if res.rows_affected == 1 {
+                self.refund_budget(hold.budget, hold.budget_id, hold.amount)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Give back the budget when the payment failed
+    async fn refund_budget(&self, budget: Budget, budget_id: i32, amount: i64) -> Result<()> {
+        match budget {
+            Budget::NwcConnection => {
+                nwc_connection::Entity::update_many()
+                    .col_expr(
+                        nwc_connection::Column::BudgetUsed,
+                        Expr::col(nwc_connection::Column::BudgetUsed).sub(amount),
+                    )
+                    .filter(nwc_connection::Column::Id.eq(budget_id))
+                    .filter(nwc_connection::Column::BudgetUsed.gte(amount))
+                    .exec(self.db())
+                    .await?;
+            }
+            Budget::ApiKey => {
+                api_key::Entity::update_many()
+                    .col_expr(
+                        api_key::Column::Spent,
+                        Expr::col(api_key::Column::Spent).sub(amount),
+                    )
+                    .filter(api_key::Column::Id.eq(budget_id))
+                    .filter(api_key::Column::Spent.gte(amount))
+                    .exec(self.db())
+                    .await?;
+            }
+            Budget::WithdrawLink => {
+                withdraw_link::Entity::update_many()
+                    .col_expr(
+                        withdraw_link::Column::Withdrawn,
+                        Expr::col(withdraw_link::Column::Withdrawn).sub(amount),
+                    )
+                    .col_expr(
+                        withdraw_link::Column::Uses,
+                        Expr::col(withdraw_link::Column::Uses).sub(1),
+                    )
+                    .filter(withdraw_link::Column::Id.eq(budget_id))
+                    .filter(withdraw_link::Column::Uses.gt(0))
+                    .exec(self.db())
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Start time of the current budget period, the periods start from the creation time,
+/// a month is 30 days.
+fn budget_period_start(conn: &nwc_connection::Model, now: i64) -> i64 {
+    let len = match conn.budget_renewal {
+        BudgetRenewal::Never => return conn.created_at,
+        BudgetRenewal::Daily => 3600 * 24,
+        BudgetRenewal::Weekly => 3600 * 24 * 7,
+        BudgetRenewal::Monthly => 3600 * 24 * 30,
+    };
+    conn.created_at + (now - conn.created_at).max(0) / len * len
+}
+
+/// the balance of the frozen user can't be spent
+fn check_frozen(user: &user::Model) -> Result<()> {
+    if user.frozen_at > 0 {
+        Err(Error::Restricted("The account is frozen".to_owned()))
+    } else {
         Ok(())
     }
 }
@@ -660,6 +2897,79 @@ async fn get_user<C: ConnectionTrait>(conn: &C, pubkey: Vec<u8>) -> Result<Optio
         .await?)
 }
 
+/// argon2id hash in the PHC string format
+fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|_| Error::Str("hash password failed"))?
+        .to_string())
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+async fn insert_lndhub_credential<C: ConnectionTrait>(
+    conn: &C,
+    user_id: i32,
+    name: &str,
+    hash: String,
+    last_used_at: i64,
+) -> Result<lndhub_credential::Model> {
+    Ok(lndhub_credential::ActiveModel {
+        id: NotSet,
+        user_id: Set(user_id),
+        name: Set(name.to_owned()),
+        password_hash: Set(hash),
+        last_used_at: Set(last_used_at),
+        revoked_at: Set(0),
+        created_at: Set(now() as i64),
+    }
+    .insert(conn)
+    .await?)
+}
+
+async fn revoke_lndhub_credential<C: ConnectionTrait>(
+    conn: &C,
+    user_id: i32,
+    id: i32,
+) -> Result<()> {
+    lndhub_credential::Entity::update_many()
+        .col_expr(
+            lndhub_credential::Column::RevokedAt,
+            Expr::value(now() as i64),
+        )
+        .filter(lndhub_credential::Column::Id.eq(id))
+        .filter(lndhub_credential::Column::UserId.eq(user_id))
+        .filter(lndhub_credential::Column::RevokedAt.eq(0))
+        .exec(conn)
+        .await?;
+    revoke_credential_sessions(conn, user_id, Some(id)).await
+}
+
+/// revoke the sessions signed in by the lndhub credential, or by any credential if none
+async fn revoke_credential_sessions<C: ConnectionTrait>(
+    conn: &C,
+    user_id: i32,
+    credential_id: Option<i32>,
+) -> Result<()> {
+    let now = now() as i64;
+    let mut update = session::Entity::update_many()
+        .col_expr(session::Column::RevokedAt, Expr::value(now))
+        .col_expr(session::Column::UpdatedAt, Expr::value(now))
+        .filter(session::Column::UserId.eq(user_id))
+        .filter(session::Column::RevokedAt.eq(0));
+    update = match credential_id {
+        Some(id) => update.filter(session::Column::CredentialId.eq(id)),
+        None => update.filter(session::Column::CredentialId.gt(0)),
+    };
+    update.exec(conn).await?;
+    Ok(())
+}
+
 async fn create_user<C: ConnectionTrait>(conn: &C, pubkey: Vec<u8>) -> Result<user::Model> {
     let now = now() as i64;
     // create ConnectionTrait
@@ -671,6 +2981,8 @@ async fn create_user<C: ConnectionTrait>(conn: &C, pubkey: Vec<u8>) -> Result<us
         username: NotSet,
         password: NotSet,
         donate_amount: NotSet,
+        success_action: NotSet,
+        frozen_at: NotSet,
         created_at: Set(now),
         updated_at: Set(now),
     }
@@ -679,10 +2991,11 @@ async fn create_user<C: ConnectionTrait>(conn: &C, pubkey: Vec<u8>) -> Result<us
 }
 
 async fn invoice_dup_paid(
-    conn: &DbConn,
+    service: &Service,
     invoice: &invoice::Model,
     remote: &lightning::Invoice,
 ) -> Result<()> {
+    let conn = service.db();
     let amount = remote.paid_amount as i64;
     let user = get_user_by_id(conn, invoice.user_id).await?;
 
@@ -731,10 +3044,34 @@ async fn invoice_dup_paid(
     .insert(&txn)
     .await?;
 
+    insert_postings(
+        &txn,
+        "duplicate_payment",
+        Some(invoice.id),
+        &[
+            (Account::Node, None, amount),
+            (Account::User, Some(invoice.user_id), -amount),
+        ],
+    )
+    .await?;
+
     txn.commit().await?;
+    notify_invoice_paid(service, invoice, amount).await;
     Ok(())
 }
 
+async fn notify_invoice_paid(service: &Service, invoice: &invoice::Model, amount: i64) {
+    service.bus.publish(
+        invoice.user_id,
+        WalletEventKind::InvoicePaid {
+            id: invoice.id,
+            payment_hash: hex::encode(&invoice.payment_hash),
+            amount,
+        },
+    );
+    service.notify_balance(invoice.user_id).await;
+}
+
 async fn invoice_paid(
     service: &Service,
     invoice: &invoice::Model,
@@ -756,7 +3093,7 @@ async fn invoice_paid(
             ..Default::default()
         })
         .filter(invoice::Column::Id.eq(invoice.id))
-        .filter(invoice::Column::Status.eq(invoice::Status::Unpaid))
+        .filter(invoice::Column::Status.is_in([invoice::Status::Unpaid, invoice::Status::Accepted]))
         .exec(&txn)
         .await?;
 
@@ -790,9 +3127,21 @@ async fn invoice_paid(
     .insert(&txn)
     .await?;
 
+    insert_postings(
+        &txn,
+        "external_payment",
+        Some(invoice.id),
+        &[
+            (Account::Node, None, amount),
+            (Account::User, Some(invoice.user_id), -amount),
+        ],
+    )
+    .await?;
+
     sync_donation(service, None, &txn, invoice).await?;
 
     txn.commit().await?;
+    notify_invoice_paid(service, invoice, amount).await;
     Ok(())
 }
 
@@ -852,7 +3201,8 @@ async fn sync_donation(
     Ok(false)
 }
 
-async fn pay_failed(conn: &DbConn, model: &invoice::Model) -> Result<()> {
+async fn pay_failed(service: &Service, model: &invoice::Model) -> Result<()> {
+    let conn = service.db();
     let lock_amount = model.lock_amount;
 
     let update = invoice::ActiveModel {
@@ -895,16 +3245,37 @@ async fn pay_failed(conn: &DbConn, model: &invoice::Model) -> Result<()> {
         ));
     }
 
-    txn.commit().await?;
+    insert_postings(
+        &txn,
+        "payment_failed",
+        Some(model.id),
+        &[
+            (Account::UserLocked, Some(model.user_id), lock_amount),
+            (Account::User, Some(model.user_id), -lock_amount),
+        ],
+    )
+    .await?;
 
+    txn.commit().await?;
+    service.release_budget_holds(model.id).await?;
+
+    service.bus.publish(
+        model.user_id,
+        WalletEventKind::PaymentFailed {
+            id: model.id,
+            payment_hash: hex::encode(&model.payment_hash),
+        },
+    );
+    service.notify_balance(model.user_id).await;
     Ok(())
 }
 
 async fn pay_success(
-    conn: &DbConn,
+    service: &Service,
     payment: &lightning::Payment,
     model: &invoice::Model,
 ) -> Result<invoice::Model> {
+    let conn = service.db();
     let lock_amount = model.lock_amount;
     let payback = lock_amount - model.service_fee - payment.total as i64;
     let total = lock_amount - payback;
@@ -966,7 +3337,34 @@ async fn pay_success(
     .insert(&txn)
     .await?;
 
+    // the routing fee is charged to the user
+    let routing_fee = payment.fee as i64;
+    insert_postings(
+        &txn,
+        "external_payment",
+        Some(model.id),
+        &[
+            (Account::UserLocked, Some(model.user_id), lock_amount),
+            (Account::User, Some(model.user_id), -payback),
+            (Account::Node, None, -(payment.total as i64)),
+            (Account::RoutingFee, None, routing_fee),
+            (Account::FeeIncome, None, -(model.service_fee + routing_fee)),
+        ],
+    )
+    .await?;
+
     txn.commit().await?;
+    service.settle_budget_holds(model.id, total).await?;
+
+    service.bus.publish(
+        model.user_id,
+        WalletEventKind::PaymentSucceeded {
+            id: model.id,
+            payment_hash: hex::encode(&model.payment_hash),
+            total,
+        },
+    );
+    service.notify_balance(model.user_id).await;
 
     invoice::Entity::find_by_id(model.id)
         .one(conn)
@@ -995,6 +3393,55 @@ fn new_record(
     }
 }
 
+/// ledger entry of (account, user id, amount), positive is debit, negative is credit
+type Entry = (Account, Option<i32>, i64);
+
+/// insert the balanced postings of a movement
+async fn insert_postings<C: ConnectionTrait>(
+    conn: &C,
+    kind: &str,
+    invoice_id: Option<i32>,
+    entries: &[Entry],
+) -> Result<()> {
+    if entries.iter().map(|e| e.2).sum::<i64>() != 0 {
+        return Err(Error::Message(format!(
+            "unbalanced postings: {:?}",
+            entries
+        )));
+    }
+    let mut journal = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut journal);
+    let journal = hex::encode(journal);
+    let now = now() as i64;
+    let models = entries
+        .iter()
+        .filter(|e| e.2 != 0)
+        .map(|(account, user_id, amount)| posting::ActiveModel {
+            id: NotSet,
+            journal: Set(journal.clone()),
+            kind: Set(kind.to_owned()),
+            account: Set(*account),
+            user_id: Set(*user_id),
+            invoice_id: Set(invoice_id),
+            amount: Set(*amount),
+            created_at: Set(now),
+        })
+        .collect::<Vec<_>>();
+    if !models.is_empty() {
+        posting::Entity::insert_many(models).exec(conn).await?;
+    }
+    Ok(())
+}
+
+/// sum as bigint, postgres and mysql return decimal for the sum of bigint
+fn sum_bigint<C: ConnectionTrait>(conn: &C, col: impl sea_orm::IntoSimpleExpr) -> SimpleExpr {
+    let ty = match conn.get_database_backend() {
+        DbBackend::MySql => "SIGNED",
+        _ => "BIGINT",
+    };
+    Func::cast_as(Func::sum(col.into_simple_expr()), Alias::new(ty)).into()
+}
+
 async fn get_user_by_id(conn: &DbConn, id: i32) -> Result<user::Model> {
     user::Entity::find_by_id(id)
         .one(conn)
@@ -1037,6 +3484,7 @@ fn create_invoice_active_model(
         lock_amount: Set(0),
         internal: Set(false),
         duplicate: Set(false),
+        hold: Set(false),
         service_fee: Set(0),
         source: Set(extra.source),
         service: Set(service),
@@ -46,7 +46,12 @@ pub struct Network {
     /// server bind port
     pub port: u16,
 
+    /// header of the client ip set by the trusted proxies, defaults to X-Forwarded-For
     pub real_ip_header: Option<String>,
+
+    /// ips or networks of the reverse proxies trusted to set the client ip header,
+    /// the header is ignored if empty.
+    pub trusted_proxies: Vec<String>,
 }
 
 impl Default for Network {
@@ -55,6 +60,7 @@ impl Default for Network {
             host: "127.0.0.1".to_string(),
             port: 8080,
             real_ip_header: None,
+            trusted_proxies: Vec::new(),
         }
     }
 }
@@ -65,6 +71,8 @@ impl Default for Network {
 pub enum Lightning {
     Lnd,
     Cln,
+    /// in-process mock node, for tests and ui development
+    Mock,
 }
 
 impl Default for Lightning {
@@ -97,6 +105,21 @@ pub struct Cln {
     pub client: PathBuf,
     /// client-key.pem
     pub client_key: PathBuf,
+    /// grpc of the hold plugin, required by hold invoices
+    pub hold: Option<ClnHold>,
+}
+
+/// Cln hold invoice plugin setting
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct ClnHold {
+    /// plugin grpc url
+    pub url: String,
+    /// ca.pem path
+    pub ca: PathBuf,
+    /// client.pem path
+    pub client: PathBuf,
+    /// client-key.pem
+    pub client_key: PathBuf,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -138,6 +161,13 @@ impl Fee {
         };
         (pct(msats, fee_pct), pct(msats, self.service_pct))
     }
+
+    /// The most the payment of the amount costs with the fees, paid internally or externally.
+    pub fn max_total(&self, msats: i64) -> i64 {
+        let (max_fee, service_fee) = self.cal(msats, false);
+        let (internal_fee, _) = self.cal(msats, true);
+        msats + max_fee.max(internal_fee) + service_fee
+    }
 }
 
 /// auth config
@@ -146,6 +176,8 @@ impl Fee {
 pub struct Auth {
     /// only whitelist pubkey can use service.
     pub whitelist: Vec<Pubkey>,
+    /// pubkeys allowed to use the admin api, the admin api is disabled if empty.
+    pub admins: Vec<Pubkey>,
     /// jwt auth secret
     pub secret: String,
 
@@ -154,6 +186,10 @@ pub struct Auth {
 
     /// jwt access token expiry in seconds
     pub access_token_expiry: usize,
+
+    /// Also store the seen nip98 auth events in the database,
+    /// reject the replays across multiple instances sharing the database.
+    pub replay_db: bool,
 }
 
 impl Default for Auth {
@@ -163,6 +199,8 @@ impl Default for Auth {
             refresh_token_expiry: 7 * 24 * 60 * 60,
             access_token_expiry: 2 * 24 * 60 * 60,
             whitelist: Default::default(),
+            admins: Default::default(),
+            replay_db: false,
         }
     }
 }
@@ -176,6 +214,15 @@ impl Auth {
             Err(AuthError::Whitelist.into())
         }
     }
+
+    pub fn check_admin(&self, pubkey: &[u8]) -> Result<()> {
+        let key: Pubkey = XOnlyPublicKey::from_slice(pubkey)?.into();
+        if self.admins.contains(&key) {
+            Ok(())
+        } else {
+            Err(Error::Restricted("Admin required".to_owned()))
+        }
+    }
 }
 
 /// nwc config
@@ -190,6 +237,13 @@ pub struct Nwc {
     pub proxy: Option<String>,
 
     pub rate_limit_per_second: NonZeroU32,
+
+    /// reject the nip04 encrypted requests, only accept nip44 v2.
+    pub require_nip44: bool,
+
+    /// accept the requests signed by the user keys without a connection,
+    /// the user keys spend the whole balance without budget or permissions.
+    pub legacy_user_keys: bool,
 }
 
 impl Nwc {
@@ -205,6 +259,8 @@ impl Default for Nwc {
             privkey: None,
             proxy: None,
             rate_limit_per_second: NonZeroU32::new(10).unwrap(),
+            require_nip44: false,
+            legacy_user_keys: false,
         }
     }
 }
@@ -223,6 +279,10 @@ pub struct Lnurl {
     pub relays: Vec<String>,
     /// relay proxy
     pub proxy: Option<String>,
+
+    /// domains of the lightning addresses served by this service, with the port if not default,
+    /// paying to the addresses of these domains is an internal transfer
+    pub domains: Vec<String>,
 }
 
 impl Default for Lnurl {
@@ -234,6 +294,7 @@ impl Default for Lnurl {
             privkey: None,
             relays: vec![],
             proxy: None,
+            domains: vec![],
         }
     }
 }
@@ -295,6 +356,9 @@ pub struct Setting {
     pub lightning: Lightning,
     /// lightning node address
     pub lightning_node: String,
+    /// custom tlv record type of the keysend payee, the value is the user pubkey hex or username,
+    /// unsupported by cln which doesn't return the tlv records of the received payments
+    pub keysend_record: u64,
 
     pub cln: Option<Cln>,
     pub lnd: Option<Lnd>,
@@ -330,6 +394,7 @@ impl Default for Setting {
             lnd: None,
             site: None,
             lightning_node: "127.0.0.1:9735".to_string(),
+            keysend_record: 696969,
             lightning: Default::default(),
             thread: Default::default(),
             network: Default::default(),
@@ -508,6 +573,7 @@ impl Setting {
             .list_separator(" ")
             .with_list_parse_key("nwc.relays")
             .with_list_parse_key("lnurl.relays")
+            .with_list_parse_key("lnurl.domains")
             .with_list_parse_key("donation.amounts")
     }
 
@@ -640,6 +706,8 @@ mod tests {
         assert_eq!(fee.cal(1000, false), (15, 3));
         assert_eq!(fee.cal(2_000_000, false), (10_000, 6000));
         assert_eq!(fee.cal(1000, true), (25, 3));
+        assert_eq!(fee.max_total(1000), 1028);
+        assert_eq!(fee.max_total(2_000_000), 2_056_000);
         Ok(())
     }
 }
@@ -0,0 +1,196 @@
+use actix_web::{test::init_service, web};
+use anyhow::Result;
+use entity::invoice;
+use lightning_client::{mock::PayBehavior, Lightning};
+use nostr_sdk::{prelude::ToBech32, Keys};
+use satsbox::{create_web_app, Error};
+use serde_json::json;
+use util::{auth_get, create_mock_state, create_peer, fee, nostr_auth_get, nostr_auth_post, post};
+
+mod util;
+
+#[tokio::test]
+async fn admin_api() -> Result<()> {
+    let (mut state, mock) = create_mock_state().await?;
+    let admin = Keys::generate();
+    state.setting.auth.admins = vec![admin.public_key().into()];
+    let state = web::Data::new(state);
+    let service = &state.service;
+    let app = init_service(create_web_app(state.clone())).await;
+    let payee = create_peer(&mock);
+    let fee = fee();
+    let url = |path: &str| format!("http://localhost:8080/admin{}", path);
+
+    let keys = Keys::generate();
+    let user = service
+        .get_or_create_user(keys.public_key().serialize().to_vec())
+        .await?;
+    service
+        .update_username(user.id, Some("alice".to_owned()))
+        .await?;
+
+    // only the admins
+    let (_val, status) = nostr_auth_get(&app, &url("/users"), &keys).await?;
+    assert_eq!(status, 403);
+    let (val, status) = nostr_auth_get(&app, &url("/info"), &admin).await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["node"]["id"], hex::encode(mock.id()));
+
+    // the bearer token of the admin is not accepted
+    let admin_user = service
+        .get_or_create_user(admin.public_key().serialize().to_vec())
+        .await?;
+    service
+        .update_user_password(admin_user.id, Some("password".to_owned()))
+        .await?;
+    let (res, _) = post(
+        &app,
+        "/auth",
+        json!({"login": admin.public_key().to_string(), "password": "password"}),
+    )
+    .await?;
+    let token = res["access_token"].as_str().unwrap().to_owned();
+    let (_val, status) = auth_get(&app, &url("/info"), &token).await?;
+    assert_eq!(status, 403);
+
+    // search
+    let npub = keys.public_key().to_bech32()?;
+    let (val, _status) = nostr_auth_get(&app, &url(&format!("/users?q={}", npub)), &admin).await?;
+    assert_eq!(val["users"][0]["id"], json!(user.id));
+    let (val, _status) = nostr_auth_get(&app, &url("/users?q=lic"), &admin).await?;
+    assert_eq!(val["users"][0]["username"], json!("alice"));
+    let (val, _status) = nostr_auth_get(&app, &url("/users?q=bob"), &admin).await?;
+    assert_eq!(val["users"].as_array().unwrap().len(), 0);
+    let (_val, status) = nostr_auth_get(&app, &url("/users/10000"), &admin).await?;
+    assert_eq!(status, 404);
+
+    // adjust balance
+    let adjust = url(&format!("/users/{}/adjust_balance", user.id));
+    let (_val, status) =
+        nostr_auth_post(&app, &adjust, &admin, json!({ "amount": 10_000_000 })).await?;
+    assert_eq!(status, 400);
+    let (_val, status) = nostr_auth_post(
+        &app,
+        &adjust,
+        &admin,
+        json!({ "amount": -1, "note": "debit" }),
+    )
+    .await?;
+    assert_eq!(status, 400);
+    let (val, status) = nostr_auth_post(
+        &app,
+        &adjust,
+        &admin,
+        json!({ "amount": 10_000_000, "note": "deposit by bank" }),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["user"]["balance"], json!(10_000_000));
+    // the debit is checked against the current balance
+    let res = service
+        .admin_adjust_user_balance(&user, -10_000_001, None)
+        .await;
+    assert!(res.is_err());
+    let user = service.get_user_by_id(user.id).await?;
+    assert_eq!(user.balance, 10_000_000);
+
+    // frozen
+    let (val, status) = nostr_auth_post(
+        &app,
+        &url(&format!("/users/{}/freeze", user.id)),
+        &admin,
+        json!({}),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    assert!(val["user"]["frozen_at"].as_i64().unwrap() > 0);
+    let inv = payee
+        .create_invoice("test".to_owned(), 1_000_000, None, Some(600))
+        .await?;
+    let user = service.get_user_by_id(user.id).await?;
+    let res = service
+        .pay(
+            &user,
+            inv.bolt11.clone(),
+            None,
+            &fee,
+            invoice::Source::Test,
+            false,
+        )
+        .await;
+    assert!(matches!(res, Err(Error::Restricted(_))));
+    let (val, _status) = nostr_auth_post(
+        &app,
+        &url(&format!("/users/{}/unfreeze", user.id)),
+        &admin,
+        json!({}),
+    )
+    .await?;
+    assert_eq!(val["user"]["frozen_at"], json!(0));
+    let user = service.get_user_by_id(user.id).await?;
+    service
+        .pay(&user, inv.bolt11, None, &fee, invoice::Source::Test, false)
+        .await?;
+
+    // resolve the in-flight payments
+    mock.set_default_pay_behavior(PayBehavior::InFlight);
+    let mut hashes = vec![];
+    for _ in 0..2 {
+        let inv = payee
+            .create_invoice("test".to_owned(), 1_000_000, None, Some(600))
+            .await?;
+        let res = service
+            .pay(&user, inv.bolt11, None, &fee, invoice::Source::Test, false)
+            .await;
+        assert!(matches!(res, Err(Error::PaymentInProgress(_))));
+        hashes.push(inv.payment_hash);
+    }
+    let (val, _status) = nostr_auth_get(&app, &url("/payments"), &admin).await?;
+    let payments = val["payments"].as_array().unwrap();
+    assert_eq!(payments.len(), 2);
+    let id = |hash: &[u8]| {
+        payments
+            .iter()
+            .find(|p| p["payment_hash"] == json!(hex::encode(hash)))
+            .unwrap()["id"]
+            .as_i64()
+            .unwrap()
+    };
+    let (settled, failed) = (id(&hashes[0]), id(&hashes[1]));
+    let settle = url(&format!("/payments/{}/settle", settled));
+    let fail = url(&format!("/payments/{}/fail", failed));
+
+    // still in flight on the node
+    let (_val, status) = nostr_auth_post(&app, &settle, &admin, json!({})).await?;
+    assert_eq!(status, 400);
+    let (_val, status) = nostr_auth_post(&app, &fail, &admin, json!({})).await?;
+    assert_eq!(status, 400);
+
+    mock.complete_payment(&hashes[0], 0)?;
+    mock.fail_payment(&hashes[1])?;
+    let (_val, status) = nostr_auth_post(
+        &app,
+        &url(&format!("/payments/{}/fail", settled)),
+        &admin,
+        json!({}),
+    )
+    .await?;
+    assert_eq!(status, 400);
+    let (val, status) = nostr_auth_post(&app, &settle, &admin, json!({})).await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["payment"]["status"], json!("paid"));
+    let (val, status) = nostr_auth_post(&app, &fail, &admin, json!({})).await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["payment"]["status"], json!("canceled"));
+    let (_val, status) = nostr_auth_post(&app, &fail, &admin, json!({})).await?;
+    assert_eq!(status, 400);
+    let user = service.get_user_by_id(user.id).await?;
+    assert_eq!(user.lock_amount, 0);
+
+    let (val, _status) = nostr_auth_get(&app, &url("/payments"), &admin).await?;
+    assert_eq!(val["payments"].as_array().unwrap().len(), 0);
+    let (val, status) = nostr_auth_get(&app, &url("/events"), &admin).await?;
+    assert_eq!(status, 200);
+    assert!(val["events"].is_array());
+    Ok(())
+}
@@ -1,16 +1,27 @@
 use actix_rt::time::sleep;
-use actix_web::{test::init_service, web};
+use actix_web::{
+    body::MessageBody,
+    http::header,
+    test::{call_service, init_service},
+    web,
+};
 use anyhow::Result;
-use entity::user;
+use base64::engine::{general_purpose, Engine};
+use entity::{api_key, auth_event, invoice, user};
+use futures::future::poll_fn;
+use lightning_client::{mock::PayBehavior, Lightning};
 use nostr_sdk::{
     secp256k1::{SecretKey, XOnlyPublicKey},
-    Keys,
+    EventBuilder, Keys, Kind, Tag,
+};
+use satsbox::{create_web_app, now, sha256, InvoiceExtra, Service};
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use serde_json::{json, Value};
+use std::{pin::Pin, str::FromStr, time::Duration};
+use util::{
+    auth_get_req, auth_post_req, call, create_funded_user, create_mock_state, create_peer,
+    create_test_state, fee, nostr_auth_get, nostr_auth_get_req, nostr_auth_post,
 };
-use satsbox::{create_web_app, Service};
-use sea_orm::{ActiveModelTrait, Set};
-use serde_json::json;
-use std::{str::FromStr, time::Duration};
-use util::create_test_state;
 
 mod util;
 
@@ -289,8 +300,564 @@ async fn reset_lndhub() -> Result<()> {
     );
     assert_eq!(val["lndhub"]["url"], json!(lndhub_url));
 
+    // the password is only returned once
     let (val, _status) = util::nostr_auth_get(&app, "http://127.0.0.1:8080/v1/my", &keys).await?;
-    assert!(val["user"]["lndhub"]["url"].is_string());
+    assert!(val["user"]["lndhub"]["url"].is_null());
+    assert_eq!(
+        val["user"]["lndhub"]["credentials"][0]["name"],
+        json!("default")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn transactions() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let service = &state.service;
+    let keys = Keys::generate();
+    let pubkey = keys.public_key().serialize().to_vec();
+    let msats = 2_000_000;
+    let user = service.get_or_create_user(pubkey).await?;
+
+    let mut hashes = vec![];
+    for _ in 0..3 {
+        let inv = service
+            .create_invoice(
+                &user,
+                "test".to_owned(),
+                msats,
+                600,
+                InvoiceExtra::new(invoice::Source::Test),
+            )
+            .await?;
+        hashes.push(inv.payment_hash);
+    }
+    mock.settle_invoice(&hashes[0], None)?;
+    service.sync_invoices(Some(now() - 60)).await?;
+
+    let state = web::Data::new(state);
+    let service = &state.service;
+    let app = init_service(create_web_app(state.clone())).await;
+    let url = "http://localhost:8080/v1/transactions?type=invoice&limit=2";
+    let (val, status) = nostr_auth_get(&app, url, &keys).await?;
+    assert_eq!(status, 200);
+    let list = val["transactions"].as_array().unwrap();
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0]["payment_hash"], json!(hex::encode(&hashes[2])));
+    let cursor = val["next_cursor"].as_str().unwrap().to_owned();
+
+    let url = format!(
+        "http://localhost:8080/v1/transactions?type=invoice&limit=2&cursor={}",
+        cursor
+    );
+    let (val, _status) = nostr_auth_get(&app, &url, &keys).await?;
+    let list = val["transactions"].as_array().unwrap();
+    assert_eq!(list.len(), 1);
+    assert!(val["next_cursor"].is_null());
+    assert_eq!(list[0]["status"], json!("paid"));
+    assert_eq!(list[0]["source"], json!("test"));
+    assert_eq!(list[0]["records"][0]["change"], json!(msats));
+
+    let url = "http://localhost:8080/v1/transactions?status=paid";
+    let (val, _status) = nostr_auth_get(&app, url, &keys).await?;
+    assert_eq!(val["transactions"].as_array().unwrap().len(), 1);
+
+    let url = "http://localhost:8080/v1/transactions?type=payment";
+    let (val, _status) = nostr_auth_get(&app, url, &keys).await?;
+    assert_eq!(val["transactions"].as_array().unwrap().len(), 0);
+
+    // the balance adjustments without invoice
+    let user = service.get_user_by_id(user.id).await?;
+    service
+        .admin_adjust_user_balance(&user, -1000, Some("fee refund".to_owned()))
+        .await?;
+    let url = "http://localhost:8080/v1/transactions?type=adjustment";
+    let (val, _status) = nostr_auth_get(&app, url, &keys).await?;
+    let list = val["transactions"].as_array().unwrap();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0]["change"], json!(-1000));
+    assert_eq!(list[0]["note"], json!("fee refund"));
+
+    let mut ids = vec![];
+    let mut url = "http://localhost:8080/v1/transactions?limit=3".to_owned();
+    loop {
+        let (val, _status) = nostr_auth_get(&app, &url, &keys).await?;
+        for item in val["transactions"].as_array().unwrap() {
+            ids.push((
+                item["type"].as_str().unwrap().to_owned(),
+                item["id"].clone(),
+            ));
+        }
+        match val["next_cursor"].as_str() {
+            Some(cursor) => {
+                url = format!(
+                    "http://localhost:8080/v1/transactions?limit=3&cursor={}",
+                    cursor
+                )
+            }
+            None => break,
+        }
+    }
+    assert_eq!(ids.len(), 4);
+    assert_eq!(ids.iter().filter(|i| i.0 == "adjustment").count(), 1);
+    assert_eq!(ids.iter().filter(|i| i.0 == "invoice").count(), 3);
+
+    // invalid
+    let url = "http://localhost:8080/v1/transactions?status=unknown";
+    let (_val, status) = nostr_auth_get(&app, url, &keys).await?;
+    assert_eq!(status, 400);
+    let url = "http://localhost:8080/v1/transactions?cursor=a_1";
+    let (_val, status) = nostr_auth_get(&app, url, &keys).await?;
+    assert_eq!(status, 400);
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_invoice() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let state = web::Data::new(state);
+    let app = init_service(create_web_app(state.clone())).await;
+    let keys = Keys::generate();
+    let msats = 2_000_000;
+    let url = "http://localhost:8080/v1/invoices";
+
+    let memo = "api invoice";
+    let (val, status) = nostr_auth_post(
+        &app,
+        url,
+        &keys,
+        json!({
+            "amount": msats,
+            "memo": memo,
+            "description_hash": hex::encode(satsbox::sha256(memo)),
+        }),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["invoice"]["status"], json!("unpaid"));
+    assert!(val["invoice"]["id"].is_number());
+    let hash = val["invoice"]["payment_hash"].as_str().unwrap().to_owned();
 
+    let get_url = format!("{}/{}", url, hash);
+    let (val, status) = nostr_auth_get(&app, &get_url, &keys).await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["invoice"]["status"], json!("unpaid"));
+
+    mock.settle_invoice(&hex::decode(&hash)?, None)?;
+    state.service.sync_invoices(Some(now() - 60)).await?;
+    let (val, _status) = nostr_auth_get(&app, &get_url, &keys).await?;
+    assert_eq!(val["invoice"]["status"], json!("paid"));
+    assert_eq!(val["invoice"]["paid_amount"], json!(msats));
+
+    // other user
+    let (_val, status) = nostr_auth_get(&app, &get_url, &Keys::generate()).await?;
+    assert_eq!(status, 404);
+
+    // invalid
+    let (_val, status) = nostr_auth_post(
+        &app,
+        url,
+        &keys,
+        json!({ "amount": msats, "memo": memo, "description_hash": hex::encode([0; 32]) }),
+    )
+    .await?;
+    assert_eq!(status, 400);
+    let (_val, status) =
+        nostr_auth_post(&app, url, &keys, json!({ "amount": 0, "memo": memo })).await?;
+    assert_eq!(status, 400);
+    Ok(())
+}
+
+/// read the next server-sent event
+async fn next_event<B: MessageBody + Unpin>(body: &mut B) -> serde_json::Value {
+    let chunk = poll_fn(|cx| Pin::new(&mut *body).poll_next(cx))
+        .await
+        .unwrap()
+        .map_err(|e| e.into())
+        .unwrap();
+    let data = String::from_utf8(chunk.to_vec()).unwrap();
+    serde_json::from_str(data.trim().trim_start_matches("data: ")).unwrap()
+}
+
+#[tokio::test]
+async fn stream() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let state = web::Data::new(state);
+    let app = init_service(create_web_app(state.clone())).await;
+    let keys = Keys::generate();
+    let msats = 2_000_000;
+
+    let req = nostr_auth_get_req("http://localhost:8080/v1/stream", &keys)?;
+    let res = call_service(&app, req.to_request()).await;
+    assert_eq!(res.status(), 200);
+    assert_eq!(
+        res.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/event-stream"
+    );
+    let mut body = res.into_body();
+
+    let service = &state.service;
+    let user = service
+        .get_or_create_user(keys.public_key().serialize().to_vec())
+        .await?;
+    // other user
+    create_funded_user(service, vec![1; 32], 1000).await?;
+
+    let inv = service
+        .create_invoice(
+            &user,
+            "test".to_owned(),
+            msats,
+            600,
+            InvoiceExtra::new(invoice::Source::Test),
+        )
+        .await?;
+    mock.settle_invoice(&inv.payment_hash, None)?;
+    service.sync_invoices(Some(now() - 60)).await?;
+
+    let event = next_event(&mut body).await;
+    assert_eq!(event["type"], json!("invoice_paid"));
+    assert_eq!(event["payment_hash"], json!(hex::encode(&inv.payment_hash)));
+    assert_eq!(event["amount"], json!(msats));
+    let event = next_event(&mut body).await;
+    assert_eq!(event["type"], json!("balance"));
+    assert_eq!(event["balance"], json!(msats));
+    Ok(())
+}
+
+#[tokio::test]
+async fn nostr_auth_replay() -> Result<()> {
+    let (mut state, _mock) = create_mock_state().await?;
+    state.service.auth_events_db = true;
+    let state = web::Data::new(state);
+    let service = &state.service;
+    let app = init_service(create_web_app(state.clone())).await;
+    let keys = Keys::generate();
+    let token = |url: &str| -> Result<String> {
+        let event = EventBuilder::new(
+            Kind::from(27235),
+            "",
+            &[
+                Tag::try_from(vec!["u", url])?,
+                Tag::try_from(vec!["method", "GET"])?,
+            ],
+        )
+        .to_event(&keys)?;
+        Ok(format!(
+            "Nostr {}",
+            general_purpose::STANDARD.encode(event.as_json())
+        ))
+    };
+
+    let url = "http://localhost:8080/v1/my";
+    let auth = token(url)?;
+    let (_, status) = call(auth_get_req(url, auth.clone()), &app).await?;
+    assert_eq!(status, 200);
+    let (res, status) = call(auth_get_req(url, auth), &app).await?;
+    assert_eq!(status, 401);
+    assert!(res["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("already used"));
+
+    // the u tag must match the query string
+    let auth = token(url)?;
+    let (_, status) = call(auth_get_req("http://localhost:8080/v1/my?a=1", auth), &app).await?;
+    assert_eq!(status, 401);
+    let auth = token("http://localhost:8080/v1/my?a=1")?;
+    let (_, status) = call(auth_get_req("http://localhost:8080/v1/my?a=1", auth), &app).await?;
+    assert_eq!(status, 200);
+
+    // no body for get
+    let auth = token(url)?;
+    let req = auth_get_req(url, auth).set_payload("{}");
+    let (_, status) = call(req, &app).await?;
+    assert_eq!(status, 401);
+
+    // seen by another instance sharing the database
+    let id = sha256("another instance");
+    auth_event::ActiveModel {
+        event_id: Set(id.clone()),
+        expires_at: Set(now() as i64 + 60),
+        ..Default::default()
+    }
+    .insert(service.db())
+    .await?;
+    assert!(!service.record_auth_event(&id, now() as i64 + 60).await?);
+    let id = sha256("new");
+    assert!(service.record_auth_event(&id, now() as i64 + 60).await?);
+    assert!(auth_event::Entity::find()
+        .filter(auth_event::Column::EventId.eq(id))
+        .one(service.db())
+        .await?
+        .is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_keys() -> Result<()> {
+    let (mut state, mock) = create_mock_state().await?;
+    state.setting.fee = fee();
+    state.setting.network.trusted_proxies = vec!["127.0.0.1".to_owned()];
+    let state = web::Data::new(state);
+    let service = &state.service;
+    let app = init_service(create_web_app(state.clone())).await;
+    let keys = Keys::generate();
+    let url = "http://localhost:8080/v1/api_keys";
+    let create = |data: Value| nostr_auth_post(&app, url, &keys, data);
+    let bearer = |secret: &str| format!("Bearer {}", secret);
+
+    let (_val, status) = create(json!({ "name": "test", "scopes": ["admin"] })).await?;
+    assert_eq!(status, 400);
+    let (_val, status) = create(json!({
+        "name": "test",
+        "scopes": ["read"],
+        "allowed_ips": ["localhost"],
+    }))
+    .await?;
+    assert_eq!(status, 400);
+
+    let (val, status) = create(json!({ "name": "reader", "scopes": ["read"] })).await?;
+    assert_eq!(status, 200);
+    let reader = val["secret"].as_str().unwrap().to_owned();
+    assert!(reader.starts_with("sk_"));
+    assert!(reader.starts_with(val["key"]["prefix"].as_str().unwrap()));
+    let reader_id = val["key"]["id"].as_i64().unwrap();
+    let (val, _status) = create(json!({
+        "name": "payer",
+        "scopes": ["receive", "send"],
+        "spend_limit": 305_000,
+    }))
+    .await?;
+    let payer = val["secret"].as_str().unwrap().to_owned();
+    let (val, _status) = create(json!({
+        "name": "office",
+        "scopes": ["read"],
+        "allowed_ips": ["10.0.0.0/8"],
+    }))
+    .await?;
+    let office = val["secret"].as_str().unwrap().to_owned();
+
+    // stored hashed
+    let key = api_key::Entity::find()
+        .filter(api_key::Column::Name.eq("reader"))
+        .one(service.db())
+        .await?
+        .unwrap();
+    assert_eq!(key.key_hash, sha256(&reader));
+    let (val, _status) = nostr_auth_get(&app, url, &keys).await?;
+    assert_eq!(val["keys"].as_array().unwrap().len(), 3);
+    assert!(val["keys"][0]["secret"].is_null());
+
+    // scopes on the v1 and lndhub routes
+    let (val, status) = call(
+        auth_get_req("http://localhost:8080/v1/my", bearer(&reader)),
+        &app,
+    )
+    .await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["user"]["pubkey"], json!(keys.public_key().to_string()));
+    let (val, _status) = call(auth_get_req("/balance", bearer(&reader)), &app).await?;
+    assert_eq!(val["BTC"]["AvailableBalance"], json!(0));
+    let (_val, status) = call(
+        auth_post_req(
+            "http://localhost:8080/v1/invoices",
+            bearer(&reader),
+            json!({ "amount": 1000 }),
+        ),
+        &app,
+    )
+    .await?;
+    assert_eq!(status, 403);
+    let (val, _status) = call(
+        auth_post_req("/addinvoice", bearer(&reader), json!({ "amt": 1 })),
+        &app,
+    )
+    .await?;
+    assert_eq!(val["error"], json!(true));
+    let (val, _status) = call(
+        auth_post_req("/addinvoice", bearer(&payer), json!({ "amt": 1 })),
+        &app,
+    )
+    .await?;
+    assert!(val["payment_request"].is_string());
+    // the api keys can't manage the account
+    let (_val, status) = call(auth_get_req(url, bearer(&payer)), &app).await?;
+    assert_eq!(status, 403);
+    let (_val, status) = call(
+        auth_get_req("http://localhost:8080/v1/my", bearer("sk_invalid")),
+        &app,
+    )
+    .await?;
+    assert_eq!(status, 401);
+
+    // ip allowlist
+    let req = auth_get_req("/balance", bearer(&office)).peer_addr("10.1.2.3:1234".parse()?);
+    let (val, _status) = call(req, &app).await?;
+    assert!(val["BTC"].is_object());
+    let req = auth_get_req("/balance", bearer(&office)).peer_addr("192.168.1.2:1234".parse()?);
+    let (val, _status) = call(req, &app).await?;
+    assert_eq!(val["code"], json!(1));
+    // the forwarded ip is only accepted from the trusted proxies
+    let forwarded = |peer: &str, ips: &str| {
+        auth_get_req("/balance", bearer(&office))
+            .peer_addr(peer.parse().unwrap())
+            .insert_header(("X-Forwarded-For", ips.to_owned()))
+    };
+    let (val, _status) = call(forwarded("192.168.1.2:1234", "10.1.2.3"), &app).await?;
+    assert_eq!(val["code"], json!(1));
+    let (val, _status) = call(forwarded("127.0.0.1:1234", "10.1.2.3"), &app).await?;
+    assert!(val["BTC"].is_object());
+    let req = forwarded("127.0.0.1:1234", "10.1.2.3, 192.168.1.2");
+    let (val, _status) = call(req, &app).await?;
+    assert_eq!(val["code"], json!(1));
+
+    // spend limit over the v1 and lndhub routes
+    let user = service
+        .get_user(keys.public_key().serialize().to_vec())
+        .await?
+        .unwrap();
+    service
+        .admin_adjust_user_balance(&user, 5_000_000, None)
+        .await?;
+    let payee = create_peer(&mock);
+    let invoice = |msats: u64| payee.create_invoice("api".to_owned(), msats, None, Some(600));
+    let inv = invoice(200_000).await?;
+    mock.set_pay_behavior(
+        inv.payment_hash.clone(),
+        PayBehavior::Succeed { fee: 1_000 },
+    );
+    let (_val, status) = call(
+        auth_post_req(
+            "http://localhost:8080/v1/pay_invoice",
+            bearer(&reader),
+            json!({ "invoice": inv.bolt11 }),
+        ),
+        &app,
+    )
+    .await?;
+    assert_eq!(status, 403);
+    let (val, status) = call(
+        auth_post_req(
+            "http://localhost:8080/v1/pay_invoice",
+            bearer(&payer),
+            json!({ "invoice": inv.bolt11 }),
+        ),
+        &app,
+    )
+    .await?;
+    assert_eq!(status, 200);
+    assert!(val["preimage"].is_string());
+    let inv = invoice(150_000).await?;
+    let (val, _status) = call(
+        auth_post_req(
+            "/payinvoice",
+            bearer(&payer),
+            json!({ "invoice": inv.bolt11 }),
+        ),
+        &app,
+    )
+    .await?;
+    assert_eq!(val["error"], json!(true));
+    let inv = invoice(100_000).await?;
+    let (val, _status) = call(
+        auth_post_req(
+            "/payinvoice",
+            bearer(&payer),
+            json!({ "invoice": inv.bolt11 }),
+        ),
+        &app,
+    )
+    .await?;
+    assert!(val["payment_preimage"].is_string());
+    let key = api_key::Entity::find()
+        .filter(api_key::Column::Name.eq("payer"))
+        .one(service.db())
+        .await?
+        .unwrap();
+    // charged with the max fees and settled to the totals
+    assert_eq!(key.spent, 200_000 + 1_000 + 600 + 100_000 + 300);
+    // the amount is within the limit but the fees are not
+    let inv = invoice(3_050).await?;
+    let (_val, status) = call(
+        auth_post_req(
+            "http://localhost:8080/v1/pay_invoice",
+            bearer(&payer),
+            json!({ "invoice": inv.bolt11 }),
+        ),
+        &app,
+    )
+    .await?;
+    assert_ne!(status, 200);
+
+    // the offer is charged by the total of the requested invoice
+    let (val, _status) = create(json!({
+        "name": "offer",
+        "scopes": ["send"],
+        "spend_limit": 1_500,
+    }))
+    .await?;
+    let offer_payer = val["secret"].as_str().unwrap().to_owned();
+    let offer = payee.create_offer("coffee".to_owned(), Some(1_000)).await?;
+    let pay_offer = || {
+        call(
+            auth_post_req(
+                "http://localhost:8080/v1/pay_invoice",
+                bearer(&offer_payer),
+                json!({ "invoice": offer.bolt12 }),
+            ),
+            &app,
+        )
+    };
+    let (val, status) = pay_offer().await?;
+    assert_eq!(status, 200);
+    assert!(val["preimage"].is_string());
+    let key = api_key::Entity::find()
+        .filter(api_key::Column::Name.eq("offer"))
+        .one(service.db())
+        .await?
+        .unwrap();
+    assert_eq!(key.spent, 1_000 + 3);
+    let (_val, status) = pay_offer().await?;
+    assert_ne!(status, 200);
+
+    // expired
+    let mut office_key: api_key::ActiveModel = api_key::Entity::find()
+        .filter(api_key::Column::Name.eq("office"))
+        .one(service.db())
+        .await?
+        .unwrap()
+        .into();
+    office_key.expires_at = Set(now() as i64 - 1);
+    office_key.update(service.db()).await?;
+    let req = auth_get_req("/balance", bearer(&office)).peer_addr("10.1.2.3:1234".parse()?);
+    let (val, _status) = call(req, &app).await?;
+    assert_eq!(val["code"], json!(1));
+
+    // revoke
+    let (val, status) = nostr_auth_post(
+        &app,
+        &format!("{}/{}/revoke", url, reader_id),
+        &keys,
+        json!({}),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    assert!(val["key"]["revoked_at"].as_i64().unwrap() > 0);
+    let (_val, status) = call(
+        auth_get_req("http://localhost:8080/v1/my", bearer(&reader)),
+        &app,
+    )
+    .await?;
+    assert_eq!(status, 401);
+    let (_val, status) = nostr_auth_post(
+        &app,
+        &format!("{}/{}/revoke", url, reader_id),
+        &Keys::generate(),
+        json!({}),
+    )
+    .await?;
+    assert_eq!(status, 404);
     Ok(())
 }
@@ -7,11 +7,16 @@ use actix_web::{
     web,
 };
 use anyhow::Result;
-use nostr_sdk::secp256k1::XOnlyPublicKey;
+use entity::{lndhub_credential, user};
+use nostr_sdk::{secp256k1::XOnlyPublicKey, Keys};
 use satsbox::{create_web_app, AppState};
-use serde_json::json;
+use sea_orm::{ActiveModelTrait, Set};
+use serde_json::{json, Value};
 use std::{str::FromStr, time::Duration};
-use util::create_test_state;
+use util::{
+    auth_get, auth_post, create_mock_state, create_test_state, nostr_auth_get, nostr_auth_post,
+    post,
+};
 
 mod util;
 
@@ -348,3 +353,261 @@ async fn payment() -> Result<()> {
     );
     Ok(())
 }
+
+#[tokio::test]
+async fn lndhub_credentials() -> Result<()> {
+    let (state, _mock) = create_mock_state().await?;
+    let state = web::Data::new(state);
+    let service = &state.service;
+    let app = init_service(create_web_app(state.clone())).await;
+    let keys = Keys::generate();
+    let login = keys.public_key().to_string();
+    let user = service
+        .get_or_create_user(keys.public_key().serialize().to_vec())
+        .await?;
+    let auth = |password: &str| {
+        post(
+            &app,
+            "/auth",
+            json!({"login": login.clone(), "password": password}),
+        )
+    };
+
+    // the legacy plaintext password is hashed on the first login
+    user::ActiveModel {
+        id: Set(user.id),
+        password: Set(Some("legacy password".to_owned())),
+        ..Default::default()
+    }
+    .update(service.db())
+    .await?;
+    let (val, _) = auth("wrong password").await?;
+    assert_eq!(val["error"], json!(true));
+    let (val, _) = auth("legacy password").await?;
+    assert!(val["access_token"].is_string());
+    assert_eq!(service.get_user_by_id(user.id).await?.password, None);
+    let list = service.list_lndhub_credentials(user.id).await?;
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].name, "default");
+    assert!(list[0].password_hash.starts_with("$argon2id$"));
+    assert!(list[0].last_used_at > 0);
+    let (val, _) = auth("legacy password").await?;
+    assert!(val["access_token"].is_string());
+
+    // named credentials
+    let url = "http://localhost:8080/v1/reset_lndhub";
+    let (phone, status) = nostr_auth_post(&app, url, &keys, json!({"name": "phone"})).await?;
+    assert_eq!(status, 200);
+    let phone = phone["lndhub"]["password"].as_str().unwrap().to_owned();
+    let (laptop, _) = nostr_auth_post(&app, url, &keys, json!({"name": "laptop"})).await?;
+    assert_eq!(laptop["lndhub"]["credentials"].as_array().unwrap().len(), 3);
+    let laptop_id = laptop["lndhub"]["credentials"][0]["id"].clone();
+    let laptop = laptop["lndhub"]["password"].as_str().unwrap().to_owned();
+    let (val, _) = auth(&phone).await?;
+    let phone_token = val["access_token"].as_str().unwrap().to_owned();
+    let (val, _) = auth(&laptop).await?;
+    let access_token = val["access_token"].as_str().unwrap().to_owned();
+
+    // rotate by the name, the sessions of the replaced credential are revoked
+    let (val, _) = nostr_auth_post(&app, url, &keys, json!({"name": "phone"})).await?;
+    let (res, _) = auth(&phone).await?;
+    assert_eq!(res["error"], json!(true));
+    let (res, _) = auth_get(&app, "/balance", &phone_token).await?;
+    assert_eq!(res["code"], json!(1));
+    let phone = val["lndhub"]["password"].as_str().unwrap().to_owned();
+    let (res, _) = auth(&phone).await?;
+    assert!(res["access_token"].is_string());
+
+    // revoke
+    let (val, status) = nostr_auth_post(
+        &app,
+        &format!(
+            "http://localhost:8080/v1/lndhub_credentials/{}/revoke",
+            laptop_id
+        ),
+        &keys,
+        json!({}),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    assert!(val["credential"]["revoked_at"].as_i64().unwrap() > 0);
+    let (res, _) = auth(&laptop).await?;
+    assert_eq!(res["error"], json!(true));
+    let (res, _) = auth_get(&app, "/balance", &access_token).await?;
+    assert_eq!(res["code"], json!(1));
+    let (val, _) = nostr_auth_get(&app, "http://localhost:8080/v1/my", &keys).await?;
+    assert_eq!(
+        val["user"]["lndhub"]["credentials"]
+            .as_array()
+            .unwrap()
+            .len(),
+        2
+    );
+    let (val, _) =
+        nostr_auth_get(&app, "http://localhost:8080/v1/lndhub_credentials", &keys).await?;
+    assert_eq!(val["credentials"].as_array().unwrap().len(), 4);
+
+    // the refresh checks the credential is still active
+    let (val, _) = auth(&phone).await?;
+    let credential = service.list_lndhub_credentials(user.id).await?;
+    let credential = credential.iter().find(|c| c.name == "phone").unwrap();
+    lndhub_credential::ActiveModel {
+        id: Set(credential.id),
+        revoked_at: Set(1),
+        ..Default::default()
+    }
+    .update(service.db())
+    .await?;
+    let (res, _) = post(
+        &app,
+        "/auth",
+        json!({ "refresh_token": val["refresh_token"] }),
+    )
+    .await?;
+    assert_eq!(res["error"], json!(true));
+    let (res, _) = auth_get(
+        &app,
+        "/balance",
+        &val["access_token"].as_str().unwrap().to_owned(),
+    )
+    .await?;
+    assert_eq!(res["code"], json!(1));
+
+    // disable, the sessions of the credentials are revoked
+    let (val, _) = auth("legacy password").await?;
+    let access_token = val["access_token"].as_str().unwrap().to_owned();
+    let (res, status) = auth_get(&app, "/balance", &access_token).await?;
+    assert_eq!(status, 200, "{}", res);
+    let (_val, status) = nostr_auth_post(&app, url, &keys, json!({"disable": true})).await?;
+    assert_eq!(status, 200);
+    let (res, _) = auth("legacy password").await?;
+    assert_eq!(res["error"], json!(true));
+    let (res, _) = auth_get(&app, "/balance", &access_token).await?;
+    assert_eq!(res["code"], json!(1));
+    Ok(())
+}
+
+#[tokio::test]
+async fn sessions() -> Result<()> {
+    let (state, _mock) = create_mock_state().await?;
+    let state = web::Data::new(state);
+    let service = &state.service;
+    let app = init_service(create_web_app(state.clone())).await;
+    let keys = Keys::generate();
+    let login = keys.public_key().to_string();
+    let user = service
+        .get_or_create_user(keys.public_key().serialize().to_vec())
+        .await?;
+    service
+        .update_user_password(user.id, Some("password".to_owned()))
+        .await?;
+    let device_login = |device: &str| {
+        post(
+            &app,
+            "/auth",
+            json!({"login": login.clone(), "password": "password", "device": device}),
+        )
+    };
+    let refresh = |token: &Value| post(&app, "/auth", json!({ "refresh_token": token }));
+    let access_token = |res: &Value| res["access_token"].as_str().unwrap().to_owned();
+
+    let (phone, _) = device_login("phone").await?;
+    let (laptop, _) = device_login("laptop").await?;
+    let (_res, status) = auth_get(&app, "/balance", &access_token(&phone)).await?;
+    assert_eq!(status, 200);
+    // the lndhub sessions are limited to the lndhub api
+    let (_res, status) =
+        auth_get(&app, "http://localhost:8080/v1/my", &access_token(&phone)).await?;
+    assert_eq!(status, 403);
+
+    // an access token is not a refresh token
+    let (res, _) = refresh(&phone["access_token"]).await?;
+    assert_eq!(res["error"], json!(true));
+
+    // rotate
+    let (rotated, _) = refresh(&phone["refresh_token"]).await?;
+    assert!(rotated["refresh_token"].is_string());
+    assert_ne!(rotated["refresh_token"], phone["refresh_token"]);
+    let (val, _) = nostr_auth_get(&app, "http://localhost:8080/v1/sessions", &keys).await?;
+    let list = val["sessions"].as_array().unwrap();
+    assert_eq!(list.len(), 2);
+    assert!(list.iter().any(|s| s["device"] == json!("phone")));
+
+    // reuse the old refresh token revokes the session
+    let (res, _) = refresh(&phone["refresh_token"]).await?;
+    assert_eq!(res["error"], json!(true));
+    let (res, _) = refresh(&rotated["refresh_token"]).await?;
+    assert_eq!(res["error"], json!(true));
+    let (res, _) = auth_get(&app, "/balance", &access_token(&rotated)).await?;
+    assert_eq!(res["code"], json!(1));
+
+    let (val, _) = nostr_auth_get(&app, "http://localhost:8080/v1/sessions", &keys).await?;
+    let list = val["sessions"].as_array().unwrap();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0]["device"], json!("laptop"));
+
+    // revoke by the user
+    let (val, status) = nostr_auth_post(
+        &app,
+        &format!("http://localhost:8080/v1/sessions/{}/revoke", list[0]["id"]),
+        &keys,
+        json!({}),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["session"]["device"], json!("laptop"));
+    let (res, _) = auth_get(&app, "/balance", &access_token(&laptop)).await?;
+    assert_eq!(res["code"], json!(1));
+    let (res, _) = refresh(&laptop["refresh_token"]).await?;
+    assert_eq!(res["error"], json!(true));
+    Ok(())
+}
+
+#[tokio::test]
+async fn amounts() -> Result<()> {
+    let (state, _mock) = create_mock_state().await?;
+    let state = web::Data::new(state);
+    let service = &state.service;
+    let app = init_service(create_web_app(state.clone())).await;
+    let keys = Keys::generate();
+    let user = service
+        .get_or_create_user(keys.public_key().serialize().to_vec())
+        .await?;
+    service
+        .update_user_password(user.id, Some("password".to_owned()))
+        .await?;
+    let (val, _) = post(
+        &app,
+        "/auth",
+        json!({"login": keys.public_key().to_string(), "password": "password"}),
+    )
+    .await?;
+    let token = val["access_token"].as_str().unwrap().to_owned();
+
+    // the msats overflow or don't fit in the balance
+    let huge = u64::MAX / 1000;
+    let (val, _) = auth_post(&app, "/addinvoice", &token, json!({ "amt": u64::MAX })).await?;
+    assert_eq!(val["code"], json!(8));
+    let (val, _) = auth_post(&app, "/addinvoice", &token, json!({ "amt": huge })).await?;
+    assert_eq!(val["code"], json!(8));
+    let (val, _) = auth_post(
+        &app,
+        "/payinvoice",
+        &token,
+        json!({ "invoice": "alice@example.com", "amount": huge }),
+    )
+    .await?;
+    assert_eq!(val["code"], json!(8));
+    let destination = hex::encode([2; 33]);
+    for amount in [0, huge] {
+        let (val, _) = auth_post(
+            &app,
+            "/keysend",
+            &token,
+            json!({ "destination": destination, "amount": amount }),
+        )
+        .await?;
+        assert_eq!(val["code"], json!(8));
+    }
+    Ok(())
+}
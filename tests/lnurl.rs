@@ -1,17 +1,32 @@
 use actix_rt::time::sleep;
-use actix_web::{test::init_service, web};
+use actix_web::{test::init_service, web, HttpServer};
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
 use anyhow::Result;
-use lightning_client::{lightning::Invoice, sha256};
+use base64::engine::{general_purpose, Engine};
+use entity::invoice;
+use lightning_client::{lightning::Invoice, mock::PayBehavior, sha256, Lightning};
 use nostr_sdk::{
-    secp256k1::XOnlyPublicKey, Client, Event, EventBuilder, EventId, Filter, Keys, Kind, Options,
-    RelayPoolNotification, Tag,
+    prelude::ToBech32,
+    secp256k1::{Message, PublicKey, SecretKey, XOnlyPublicKey},
+    Client, Event, EventBuilder, EventId, Filter, Keys, Kind, Options, RelayPoolNotification, Tag,
+    SECP256K1,
 };
-use satsbox::{create_web_app, lnurl::handle_receipts, now};
-use serde_json::json;
+use satsbox::{
+    create_web_app,
+    lnurl::handle_receipts,
+    lnurl_client, now,
+    nwc::{Nwc, Request},
+    Error, WithdrawPolicy,
+};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use serde_json::{json, Value};
 use std::time::Duration;
 use tokio::time::timeout;
 use url::form_urlencoded::byte_serialize;
-use util::{create_test_state, create_test_state2};
+use util::{
+    auth_get, auth_post, create_funded_user, create_mock_state, create_peer, create_test_state,
+    create_test_state2, fee, get, nostr_auth_get, nostr_auth_post,
+};
 
 mod util;
 
@@ -239,6 +254,7 @@ async fn zaps() -> Result<()> {
         .pay(
             &user,
             pr.to_owned(),
+            None,
             &state.setting.fee,
             entity::invoice::Source::Test,
             false,
@@ -430,6 +446,7 @@ async fn donate_by_lud18() -> Result<()> {
         .pay(
             &payer,
             pr.to_owned(),
+            None,
             &state.setting.fee,
             entity::invoice::Source::Test,
             false,
@@ -472,13 +489,14 @@ async fn donate_by_lud18() -> Result<()> {
         .pay(
             &payer_user,
             pr.to_owned(),
+            None,
             &state.setting.fee,
             entity::invoice::Source::Test,
             false,
         )
         .await?;
     sleep(Duration::from_secs(1)).await;
-    let count = state.service.sync_invoices(now() - 60).await?;
+    let count = state.service.sync_invoices(Some(now() - 60)).await?;
     assert_eq!(count, 1);
 
     let donor = state
@@ -489,3 +507,595 @@ async fn donate_by_lud18() -> Result<()> {
     assert_eq!(donor.donate_amount, amount * 2);
     Ok(())
 }
+
+#[tokio::test]
+async fn lnurl_pay() -> Result<()> {
+    // the remote lnurl service
+    let (remote, remote_mock) = create_mock_state().await?;
+    let remote = web::Data::new(remote);
+    let data = remote.clone();
+    let server = HttpServer::new(move || create_web_app(data.clone()))
+        .workers(1)
+        .bind(("127.0.0.1", 0))?;
+    let port = server.addrs()[0].port();
+    tokio::spawn(server.run());
+
+    let (mut state, mock) = create_mock_state().await?;
+    mock.connect_peer(&remote_mock);
+    state.setting.fee = fee();
+    state.service.lnurl_domains = vec!["example.com".to_owned()];
+    // the remote service is served by http on the loopback address
+    state.service.lnurl.insecure = true;
+    let state = web::Data::new(state);
+    let service = &state.service;
+    let msats = 2_000_000;
+    let keys = Keys::generate();
+    let user = create_funded_user(
+        service,
+        keys.public_key().serialize().to_vec(),
+        10 * msats as i64,
+    )
+    .await?;
+
+    // lud-06 lnurl of the remote user
+    let payee_keys = Keys::generate();
+    let url = format!(
+        "http://127.0.0.1:{}/.well-known/lnurlp/{}",
+        port,
+        payee_keys.public_key().to_bech32()?
+    );
+    let lnurl = lnurl_client::encode(&url);
+    let res = service
+        .pay_lnurl(
+            &user,
+            lnurl.clone(),
+            500,
+            None,
+            &fee(),
+            invoice::Source::Test,
+        )
+        .await;
+    assert!(matches!(res, Err(Error::InvalidParam(_))));
+    let res = service
+        .pay_lnurl(
+            &user,
+            lnurl.clone(),
+            msats,
+            Some("a".repeat(256)),
+            &fee(),
+            invoice::Source::Test,
+        )
+        .await;
+    assert!(matches!(res, Err(Error::InvalidParam(_))));
+    let payment = service
+        .pay_lnurl(
+            &user,
+            lnurl.to_uppercase(),
+            msats,
+            Some("hello".to_owned()),
+            &fee(),
+            invoice::Source::Test,
+        )
+        .await?;
+    assert_eq!(payment.status, invoice::Status::Paid);
+    assert!(!payment.internal);
+    assert_eq!(remote.service.sync_invoices(Some(now() - 60)).await?, 1);
+    let payee = remote
+        .service
+        .get_user(payee_keys.public_key().serialize().to_vec())
+        .await?
+        .unwrap();
+    assert_eq!(payee.balance, msats as i64);
+    let inv = remote.service.get_invoice(1).await?.unwrap();
+    assert_eq!(inv.comment, Some("hello".to_owned()));
+    assert_eq!(
+        inv.payer_pubkey,
+        Some(keys.public_key().serialize().to_vec())
+    );
+
+    // the local lightning address by the api
+    let local = service.get_or_create_user(vec![2; 32]).await?;
+    service
+        .update_username(local.id, Some("bob".to_owned()))
+        .await?;
+    let app = init_service(create_web_app(state.clone())).await;
+    let (val, status) = nostr_auth_post(
+        &app,
+        "http://localhost:8080/v1/pay_lnurl",
+        &keys,
+        json!({ "lnurl": "Bob@example.com", "amount": msats, "comment": "hi" }),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    assert!(val["preimage"].is_string());
+    let local = service.get_user_by_id(local.id).await?;
+    assert_eq!(local.balance, msats as i64);
+    let inv = invoice::Entity::find()
+        .filter(invoice::Column::UserId.eq(local.id))
+        .one(service.db())
+        .await?
+        .unwrap();
+    assert_eq!(inv.status, invoice::Status::Paid);
+    assert_eq!(inv.source, invoice::Source::Lnurlp);
+    assert_eq!(inv.comment, Some("hi".to_owned()));
+    let (_val, status) = nostr_auth_post(
+        &app,
+        "http://localhost:8080/v1/pay_lnurl",
+        &keys,
+        json!({ "lnurl": "nobody@example.com", "amount": msats }),
+    )
+    .await?;
+    assert_eq!(status, 404);
+
+    // nwc pay_lnurl
+    let nwc = Nwc::new(state.clone().into_inner());
+    let request: Request = serde_json::from_value(json!({
+        "method": "pay_lnurl",
+        "params": { "lnurl": "bob@example.com", "amount": msats },
+    }))?;
+    let res = nwc
+        .handle(keys.public_key().serialize().to_vec(), None, request)
+        .await;
+    assert!(res[0].1.as_ref().unwrap()["preimage"].is_string());
+    let local = service.get_user_by_id(local.id).await?;
+    assert_eq!(local.balance, 2 * msats as i64);
+    Ok(())
+}
+
+#[tokio::test]
+async fn lnurl_withdraw() -> Result<()> {
+    let (mut state, mock) = create_mock_state().await?;
+    state.setting.fee = fee();
+    let state = web::Data::new(state);
+    let service = &state.service;
+    let app = init_service(create_web_app(state.clone())).await;
+    let wallet = create_peer(&mock);
+    let keys = Keys::generate();
+    let msats = 100_000;
+    let user = create_funded_user(
+        service,
+        keys.public_key().serialize().to_vec(),
+        10 * msats as i64,
+    )
+    .await?;
+
+    let url = "http://localhost:8080/v1/withdraw_links";
+    let (_val, status) = nostr_auth_post(
+        &app,
+        url,
+        &keys,
+        json!({ "min_withdrawable": msats, "max_withdrawable": 1_000 }),
+    )
+    .await?;
+    assert_eq!(status, 400);
+    let (val, status) = nostr_auth_post(
+        &app,
+        url,
+        &keys,
+        json!({
+            "title": "voucher",
+            "min_withdrawable": 1_000,
+            "max_withdrawable": msats,
+            "max_uses": 2,
+        }),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    let link = val["link"].clone();
+    let url = lnurl_client::decode(link["lnurl"].as_str().unwrap())?;
+
+    // lud-03 withdraw request
+    let (info, status) = get(&app, url.as_str()).await?;
+    assert_eq!(status, 200);
+    assert_eq!(info["tag"], json!("withdrawRequest"));
+    assert_eq!(info["defaultDescription"], json!("voucher"));
+    assert_eq!(info["maxWithdrawable"], json!(msats));
+    let k1 = info["k1"].as_str().unwrap().to_owned();
+    let callback = info["callback"].as_str().unwrap().to_owned();
+    let withdraw = |pr: String, k1: String| {
+        let mut url = url::Url::parse(&callback).unwrap();
+        url.query_pairs_mut()
+            .append_pair("k1", &k1)
+            .append_pair("pr", &pr);
+        url.to_string()
+    };
+
+    let inv = wallet
+        .create_invoice("too much".to_owned(), 2 * msats, None, Some(600))
+        .await?;
+    let (val, _) = get(&app, &withdraw(inv.bolt11, k1.clone())).await?;
+    assert_eq!(val["status"], json!("ERROR"));
+    let inv = wallet
+        .create_invoice("withdraw".to_owned(), msats, None, Some(600))
+        .await?;
+    let (val, _) = get(&app, &withdraw(inv.bolt11.clone(), "00".repeat(32))).await?;
+    assert_eq!(val["status"], json!("ERROR"));
+    let (val, _) = get(&app, &withdraw(inv.bolt11, k1.clone())).await?;
+    assert_eq!(val["status"], json!("OK"));
+    // paid in the background
+    sleep(Duration::from_millis(100)).await;
+    let remote = wallet.lookup_invoice(inv.payment_hash).await?;
+    assert_eq!(
+        remote.status,
+        lightning_client::lightning::InvoiceStatus::Paid
+    );
+    let payment = invoice::Entity::find()
+        .filter(invoice::Column::UserId.eq(user.id))
+        .filter(invoice::Column::Type.eq(invoice::Type::Payment))
+        .one(service.db())
+        .await?
+        .unwrap();
+    assert_eq!(payment.source, invoice::Source::Withdraw);
+    assert!(service.get_user_by_id(user.id).await?.balance < 9 * msats as i64);
+
+    // the failed payment gives back the use
+    mock.set_default_pay_behavior(PayBehavior::Fail("no route".to_owned()));
+    let inv = wallet
+        .create_invoice("withdraw".to_owned(), msats, None, Some(600))
+        .await?;
+    let (val, _) = get(&app, &withdraw(inv.bolt11, k1.clone())).await?;
+    assert_eq!(val["status"], json!("OK"));
+    sleep(Duration::from_millis(100)).await;
+    let link = service.get_withdraw_link(&k1).await?;
+    assert_eq!(link.uses, 1);
+    assert_eq!(link.withdrawn, msats as i64);
+    mock.set_default_pay_behavior(PayBehavior::Succeed { fee: 0 });
+
+    // the second and the last use
+    let inv = wallet
+        .create_invoice("withdraw".to_owned(), msats, None, Some(600))
+        .await?;
+    let (val, _) = get(&app, &withdraw(inv.bolt11, k1.clone())).await?;
+    assert_eq!(val["status"], json!("OK"));
+    sleep(Duration::from_millis(100)).await;
+    let inv = wallet
+        .create_invoice("withdraw".to_owned(), msats, None, Some(600))
+        .await?;
+    let (val, _) = get(&app, &withdraw(inv.bolt11, k1.clone())).await?;
+    assert_eq!(val["status"], json!("ERROR"));
+    let (val, _) = get(&app, url.as_str()).await?;
+    assert_eq!(val["status"], json!("ERROR"));
+
+    let (val, status) =
+        nostr_auth_get(&app, "http://localhost:8080/v1/withdraw_links", &keys).await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["links"][0]["uses"], json!(2));
+    assert_eq!(val["links"][0]["withdrawn"], json!(2 * msats));
+
+    // revoked reusable link
+    let link = service
+        .create_withdraw_link(
+            &user,
+            WithdrawPolicy {
+                min_withdrawable: 1_000,
+                max_withdrawable: msats as i64,
+                ..Default::default()
+            },
+        )
+        .await?;
+    let url = format!("http://localhost:8080/v1/withdraw_links/{}/revoke", link.id);
+    let (val, status) = nostr_auth_post(&app, &url, &keys, json!({})).await?;
+    assert_eq!(status, 200);
+    assert!(val["link"]["revoked_at"].as_i64().unwrap() > 0);
+    let inv = wallet
+        .create_invoice("withdraw".to_owned(), msats, None, Some(600))
+        .await?;
+    let res = service.withdraw(&link.k1, inv.bolt11, &fee()).await;
+    assert!(matches!(res, Err(Error::Restricted(_))));
+    Ok(())
+}
+
+#[tokio::test]
+async fn lnurl_success_action() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let state = web::Data::new(state);
+    let service = &state.service;
+    let app = init_service(create_web_app(state.clone())).await;
+    let keys = Keys::generate();
+    let user = service
+        .get_or_create_user(keys.public_key().serialize().to_vec())
+        .await?;
+    service
+        .update_username(user.id, Some("alice".to_owned()))
+        .await?;
+    let callback = format!(
+        "http://localhost:8080/.well-known/lnurlp/alice/callback?amount={}",
+        state.setting.lnurl.min_sendable
+    );
+
+    // default message and lud-21 verify
+    let (val, _) = get(&app, &callback).await?;
+    assert_eq!(val["status"], json!("OK"));
+    assert_eq!(val["successAction"]["tag"], json!("message"));
+    let verify = val["verify"].as_str().unwrap().to_owned();
+    let hash = verify.rsplit('/').next().unwrap().to_owned();
+    assert_eq!(
+        verify,
+        format!(
+            "http://localhost:8080/.well-known/lnurlp/alice/verify/{}",
+            hash
+        )
+    );
+    let (res, _) = get(&app, &verify).await?;
+    assert_eq!(res["status"], json!("OK"));
+    assert_eq!(res["settled"], json!(false));
+    assert_eq!(res["preimage"], json!(null));
+    assert_eq!(res["pr"], val["pr"]);
+
+    mock.settle_invoice(&hex::decode(&hash)?, None)?;
+    assert_eq!(service.sync_invoices(Some(now() - 60)).await?, 1);
+    let (res, _) = get(&app, &verify).await?;
+    assert_eq!(res["settled"], json!(true));
+    let preimage = hex::decode(res["preimage"].as_str().unwrap())?;
+    assert_eq!(hex::encode(sha256(&preimage)), hash);
+
+    // the invoice of other addresses
+    let (res, _) = get(&app, &verify.replace("/alice/", "/bob/")).await?;
+    assert_eq!(res["status"], json!("ERROR"));
+    let (res, _) = get(&app, &verify.replace(&hash, &hex::encode([0; 32]))).await?;
+    assert_eq!(res["status"], json!("ERROR"));
+
+    // custom actions
+    let url = "http://localhost:8080/v1/update_success_action";
+    let (_res, status) = nostr_auth_post(
+        &app,
+        url,
+        &keys,
+        json!({"success_action": {"tag": "url", "description": "Open", "url": "ftp://a"}}),
+    )
+    .await?;
+    assert_eq!(status, 400);
+    let (_res, status) = nostr_auth_post(
+        &app,
+        url,
+        &keys,
+        json!({"success_action": {"tag": "message", "message": "a".repeat(145)}}),
+    )
+    .await?;
+    assert_eq!(status, 400);
+    let action = json!({"tag": "url", "description": "Open", "url": "https://example.com/a"});
+    let (res, status) =
+        nostr_auth_post(&app, url, &keys, json!({ "success_action": action })).await?;
+    assert_eq!(status, 200);
+    assert_eq!(res["success"], json!(true));
+    let (val, _) = get(&app, &callback).await?;
+    assert_eq!(val["successAction"], action);
+    let (res, _) = nostr_auth_get(&app, "http://localhost:8080/v1/my", &keys).await?;
+    assert_eq!(res["user"]["success_action"], action);
+
+    // lud-10 aes encrypted by the preimage
+    let (_res, status) = nostr_auth_post(
+        &app,
+        url,
+        &keys,
+        json!({"success_action": {"tag": "aes", "description": "Code", "plaintext": "secret code"}}),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    let (val, _) = get(&app, &callback).await?;
+    let action = &val["successAction"];
+    assert_eq!(action["tag"], json!("aes"));
+    assert_eq!(action["description"], json!("Code"));
+    let hash = val["verify"].as_str().unwrap().rsplit('/').next().unwrap();
+    let inv = service
+        .get_lnurl_invoice(user.id, hex::decode(hash)?)
+        .await?
+        .unwrap();
+    let b64 = |v: &Value| general_purpose::STANDARD.decode(v.as_str().unwrap());
+    let plaintext = cbc::Decryptor::<aes::Aes256>::new_from_slices(
+        &inv.payment_preimage,
+        &b64(&action["iv"])?,
+    )?
+    .decrypt_padded_vec_mut::<Pkcs7>(&b64(&action["ciphertext"])?)
+    .unwrap();
+    assert_eq!(plaintext, b"secret code");
+
+    // reset
+    let (_res, status) =
+        nostr_auth_post(&app, url, &keys, json!({ "success_action": null })).await?;
+    assert_eq!(status, 200);
+    let (val, _) = get(&app, &callback).await?;
+    assert_eq!(val["successAction"]["tag"], json!("message"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn lnurl_profile() -> Result<()> {
+    let (state, _mock) = create_mock_state().await?;
+    let state = web::Data::new(state);
+    let service = &state.service;
+    let app = init_service(create_web_app(state.clone())).await;
+    let setting = &state.setting.lnurl;
+    let keys = Keys::generate();
+    let user = service
+        .get_or_create_user(keys.public_key().serialize().to_vec())
+        .await?;
+    service
+        .update_username(user.id, Some("bob".to_owned()))
+        .await?;
+    let info = "http://localhost:8080/.well-known/lnurlp/bob";
+
+    let (val, _) = get(&app, info).await?;
+    assert_eq!(val["minSendable"], json!(setting.min_sendable));
+    assert_eq!(val["maxSendable"], json!(setting.max_sendable));
+    assert_eq!(val["commentAllowed"], json!(setting.comment_allowed));
+    let (val, status) = nostr_auth_get(&app, "http://localhost:8080/v1/profile", &keys).await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["profile"]["min_sendable"], json!(null));
+
+    let url = "http://localhost:8080/v1/update_profile";
+    for invalid in [
+        json!({"min_sendable": setting.min_sendable - 1}),
+        json!({"max_sendable": setting.max_sendable + 1}),
+        json!({"min_sendable": 20_000, "max_sendable": 10_000}),
+        json!({"comment_allowed": setting.comment_allowed + 1}),
+        json!({"avatar": general_purpose::STANDARD.encode(b"not a png")}),
+    ] {
+        let (_res, status) = nostr_auth_post(&app, url, &keys, invalid).await?;
+        assert_eq!(status, 400);
+    }
+    let avatar = general_purpose::STANDARD.encode(b"\x89PNG\r\n\x1a\nimage");
+    let (val, status) = nostr_auth_post(
+        &app,
+        url,
+        &keys,
+        json!({
+            "min_sendable": 5_000,
+            "max_sendable": 100_000,
+            "comment_allowed": 5,
+            "description": "Tips for bob",
+            "long_description": "Bob writes about bitcoin",
+            "avatar": avatar,
+        }),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["profile"]["min_sendable"], json!(5_000));
+    let (val, _) = nostr_auth_get(&app, "http://localhost:8080/v1/profile", &keys).await?;
+    assert_eq!(val["profile"]["avatar"], json!(avatar));
+
+    let (val, _) = get(&app, info).await?;
+    assert_eq!(val["minSendable"], json!(5_000));
+    assert_eq!(val["maxSendable"], json!(100_000));
+    assert_eq!(val["commentAllowed"], json!(5));
+    let metadata = val["metadata"].as_str().unwrap().to_owned();
+    assert_eq!(
+        serde_json::from_str::<Value>(&metadata)?,
+        json!([
+            ["text/plain", "Tips for bob"],
+            ["text/identifier", "bob@localhost:8080"],
+            ["text/long-desc", "Bob writes about bitcoin"],
+            ["image/png;base64", avatar],
+        ])
+    );
+
+    let callback = format!("{}/callback", info);
+    let (res, _) = get(&app, &format!("{}?amount=4000", callback)).await?;
+    assert_eq!(res["status"], json!("ERROR"));
+    let (res, _) = get(&app, &format!("{}?amount=5000&comment=toolong", callback)).await?;
+    assert_eq!(res["status"], json!("ERROR"));
+    let (res, _) = get(&app, &format!("{}?amount=5000&comment=hi", callback)).await?;
+    assert_eq!(res["status"], json!("OK"));
+    let inv = Invoice::from_bolt11(res["pr"].as_str().unwrap().to_owned())?;
+    assert_eq!(inv.description_hash, Some(sha256(&metadata)));
+
+    // reset to the default setting
+    let (_val, status) = nostr_auth_post(&app, url, &keys, json!({})).await?;
+    assert_eq!(status, 200);
+    let (val, _) = get(&app, info).await?;
+    assert_eq!(val["minSendable"], json!(setting.min_sendable));
+    assert_eq!(
+        serde_json::from_str::<Value>(val["metadata"].as_str().unwrap())?,
+        json!([
+            ["text/plain", "Sats for "],
+            ["text/identifier", "bob@localhost:8080"]
+        ])
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn lnurl_auth() -> Result<()> {
+    let (state, _mock) = create_mock_state().await?;
+    let state = web::Data::new(state);
+    let app = init_service(create_web_app(state.clone())).await;
+    let linking_key = SecretKey::from_slice(&[3; 32])?;
+    let key = hex::encode(PublicKey::from_secret_key(&SECP256K1, &linking_key).serialize());
+    // the wallet signs k1 of the lnurl
+    let sign = |lnurl: &Value, secret: &SecretKey| -> Result<String> {
+        let url = lnurl_client::decode(lnurl.as_str().unwrap())?;
+        let k1 = url
+            .query_pairs()
+            .find(|(k, _)| k == "k1")
+            .map(|(_, v)| hex::decode(v.as_ref()))
+            .unwrap()?;
+        let sig = SECP256K1.sign_ecdsa(&Message::from_slice(&k1)?, secret);
+        let key = PublicKey::from_secret_key(&SECP256K1, secret);
+        Ok(format!(
+            "{}&sig={}&key={}",
+            url,
+            hex::encode(sig.serialize_der()),
+            hex::encode(key.serialize())
+        ))
+    };
+
+    let (val, status) = get(&app, "http://localhost:8080/v1/lnurl_auth").await?;
+    assert_eq!(status, 200);
+    let check = format!(
+        "http://localhost:8080/v1/lnurl_auth/{}",
+        val["k1"].as_str().unwrap()
+    );
+    let (res, _) = get(&app, &check).await?;
+    assert_eq!(res["status"], json!("pending"));
+
+    // invalid signature
+    let url = sign(&val["lnurl"], &linking_key)?;
+    let (res, _) = get(&app, &url.replace(&key, &hex::encode([2; 33]))).await?;
+    assert_eq!(res["status"], json!("ERROR"));
+    let (res, _) = get(&app, &url).await?;
+    assert_eq!(res["status"], json!("OK"));
+    let (res, _) = get(&app, &url).await?;
+    assert_eq!(res["status"], json!("ERROR"));
+
+    // the tokens are taken once
+    let (res, status) = get(&app, &check).await?;
+    assert_eq!(status, 200);
+    assert_eq!(res["status"], json!("OK"));
+    let (xonly, _) = PublicKey::from_secret_key(&SECP256K1, &linking_key).x_only_public_key();
+    assert_eq!(res["pubkey"], json!(hex::encode(xonly.serialize())));
+    let token = res["access_token"].as_str().unwrap().to_owned();
+    let (_res, status) = get(&app, &check).await?;
+    assert_eq!(status, 404);
+
+    let (res, status) = auth_get(&app, "http://localhost:8080/v1/my", &token).await?;
+    assert_eq!(status, 200);
+    assert_eq!(res["user"]["pubkey"], json!(hex::encode(xonly.serialize())));
+    let (_res, status) = auth_get(&app, "/balance", &token).await?;
+    assert_eq!(status, 200);
+    // the account management requires the nostr event
+    let (_res, status) = auth_get(&app, "http://localhost:8080/v1/api_keys", &token).await?;
+    assert_eq!(status, 403);
+    let (_res, status) = auth_post(
+        &app,
+        "http://localhost:8080/v1/nwc_connections",
+        &token,
+        json!({ "name": "test" }),
+    )
+    .await?;
+    assert_eq!(status, 403);
+
+    // link the wallet key to the nostr user
+    let keys = Keys::generate();
+    let url = "http://localhost:8080/v1/lnurl_auth/link";
+    let (val, status) = nostr_auth_post(&app, url, &keys, json!({})).await?;
+    assert_eq!(status, 200);
+    let (res, _) = get(&app, &sign(&val["lnurl"], &linking_key)?).await?;
+    assert_eq!(res["status"], json!("ERROR"));
+    let (val, _) = nostr_auth_post(&app, url, &keys, json!({})).await?;
+    let (res, _) = get(
+        &app,
+        &sign(&val["lnurl"], &SecretKey::from_slice(&[4; 32])?)?,
+    )
+    .await?;
+    assert_eq!(res["status"], json!("OK"));
+    let (val, _) = get(&app, "http://localhost:8080/v1/lnurl_auth").await?;
+    let (res, _) = get(
+        &app,
+        &sign(&val["lnurl"], &SecretKey::from_slice(&[4; 32])?)?,
+    )
+    .await?;
+    assert_eq!(res["status"], json!("OK"));
+    let (res, _) = get(
+        &app,
+        &format!(
+            "http://localhost:8080/v1/lnurl_auth/{}",
+            val["k1"].as_str().unwrap()
+        ),
+    )
+    .await?;
+    assert_eq!(
+        res["pubkey"],
+        json!(hex::encode(keys.public_key().serialize()))
+    );
+    Ok(())
+}
@@ -0,0 +1,208 @@
+// hermetic service tests backed by the in-process mock node
+// cargo test --test mock
+
+use actix_web::{test::init_service, web};
+use anyhow::Result;
+use entity::invoice;
+use lightning_client::{mock::PayBehavior, Lightning};
+use satsbox::{create_web_app, now, InvoiceExtra};
+use util::{create_funded_user, create_mock_state, create_peer, fee, get};
+
+mod util;
+
+#[tokio::test]
+async fn info() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let info = state.service.info().await?;
+    assert_eq!(&info.id, mock.id());
+
+    let app = init_service(create_web_app(web::Data::new(state))).await;
+    let (val, status) = get(&app, "/v1/info").await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["node"]["id"], hex::encode(mock.id()));
+    Ok(())
+}
+
+#[tokio::test]
+async fn receive() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let service = &state.service;
+    let pubkey = vec![1; 32];
+    let msats = 2_000_000;
+    let user = service.get_or_create_user(pubkey.clone()).await?;
+
+    // overpay
+    let inv1 = service
+        .create_invoice(
+            &user,
+            "test".to_owned(),
+            msats,
+            600,
+            InvoiceExtra::new(invoice::Source::Test),
+        )
+        .await?;
+    // cancel
+    let inv2 = service
+        .create_invoice(
+            &user,
+            "test".to_owned(),
+            msats,
+            600,
+            InvoiceExtra::new(invoice::Source::Test),
+        )
+        .await?;
+
+    mock.settle_invoice(&inv1.payment_hash, Some(msats + 1000))?;
+    mock.cancel_invoice(&inv2.payment_hash)?;
+
+    let count = service.sync_invoices(Some(now() - 60)).await?;
+    assert_eq!(count, 2);
+
+    let inv1 = service.get_invoice(inv1.id).await?.unwrap();
+    assert_eq!(inv1.status, invoice::Status::Paid);
+    assert_eq!(inv1.paid_amount, msats as i64 + 1000);
+    let inv2 = service.get_invoice(inv2.id).await?.unwrap();
+    assert_eq!(inv2.status, invoice::Status::Canceled);
+
+    let user = service.get_user(pubkey).await?.unwrap();
+    assert_eq!(user.balance, msats as i64 + 1000);
+
+    // nothing changed
+    let count = service.sync_invoices(Some(now() - 60)).await?;
+    assert_eq!(count, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn external_payment() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let payee = create_peer(&mock);
+
+    let service = &state.service;
+    let fee = fee();
+    let msats: i64 = 2_000_000;
+    let balance: i64 = 5_000_000;
+    let pubkey = vec![1; 32];
+    let user = create_funded_user(service, pubkey.clone(), balance).await?;
+    let (_max_fee, service_fee) = fee.cal(msats, false);
+
+    // succeed
+    let inv = payee
+        .create_invoice("test".to_owned(), msats as u64, None, Some(600))
+        .await?;
+    mock.set_pay_behavior(inv.payment_hash.clone(), PayBehavior::Succeed { fee: 10 });
+    let payment = service
+        .pay(
+            &user,
+            inv.bolt11.clone(),
+            None,
+            &fee,
+            invoice::Source::Test,
+            false,
+        )
+        .await?;
+    assert!(!payment.internal);
+    assert_eq!(payment.status, invoice::Status::Paid);
+    assert_eq!(payment.fee, 10);
+    assert_eq!(payment.total, msats + 10 + service_fee);
+    let remote = mock.lookup_payment(inv.payment_hash.clone()).await?;
+    assert_eq!(payment.payment_preimage, remote.payment_preimage);
+    let remote = payee.lookup_invoice(inv.payment_hash.clone()).await?;
+    assert_eq!(remote.paid_amount, msats as u64);
+    let balance = balance - msats - 10 - service_fee;
+    let user = service.get_user(pubkey.clone()).await?.unwrap();
+    assert_eq!(user.balance, balance);
+    assert_eq!(user.lock_amount, 0);
+
+    // failed
+    let inv = payee
+        .create_invoice("test".to_owned(), msats as u64, None, Some(600))
+        .await?;
+    mock.set_pay_behavior(
+        inv.payment_hash.clone(),
+        PayBehavior::Fail("no route".to_owned()),
+    );
+    let res = service
+        .pay(
+            &user,
+            inv.bolt11.clone(),
+            None,
+            &fee,
+            invoice::Source::Test,
+            false,
+        )
+        .await;
+    assert!(res.err().unwrap().to_string().contains("no route"));
+    let user = service.get_user(pubkey.clone()).await?.unwrap();
+    assert_eq!(user.balance, balance);
+    assert_eq!(user.lock_amount, 0);
+
+    // in flight, handled by sync
+    let inv = payee
+        .create_invoice("test".to_owned(), msats as u64, None, Some(600))
+        .await?;
+    mock.set_pay_behavior(inv.payment_hash.clone(), PayBehavior::InFlight);
+    let res = service
+        .pay(
+            &user,
+            inv.bolt11.clone(),
+            None,
+            &fee,
+            invoice::Source::Test,
+            false,
+        )
+        .await;
+    assert!(matches!(res, Err(satsbox::Error::PaymentInProgress(_))));
+    let user = service.get_user(pubkey.clone()).await?.unwrap();
+    assert!(user.lock_amount > 0);
+    assert_eq!(service.sync_payments(None).await?, 0);
+
+    mock.complete_payment(&inv.payment_hash, 0)?;
+    assert_eq!(service.sync_payments(None).await?, 1);
+    let balance = balance - msats - service_fee;
+    let user = service.get_user(pubkey.clone()).await?.unwrap();
+    assert_eq!(user.balance, balance);
+    assert_eq!(user.lock_amount, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn internal_payment() -> Result<()> {
+    let (state, _mock) = create_mock_state().await?;
+    let service = &state.service;
+    let fee = fee();
+    let msats: i64 = 2_000_000;
+    let balance: i64 = 5_000_000;
+
+    let payee = service.get_or_create_user(vec![1; 32]).await?;
+    let payer = create_funded_user(service, vec![2; 32], balance).await?;
+    let inv = service
+        .create_invoice(
+            &payee,
+            "test".to_owned(),
+            msats as u64,
+            600,
+            InvoiceExtra::new(invoice::Source::Test),
+        )
+        .await?;
+    let payment = service
+        .pay(
+            &payer,
+            inv.bolt11.clone(),
+            None,
+            &fee,
+            invoice::Source::Test,
+            false,
+        )
+        .await?;
+    let (internal_fee, service_fee) = fee.cal(msats, true);
+    assert!(payment.internal);
+    assert_eq!(payment.status, invoice::Status::Paid);
+    assert_eq!(payment.total, msats + internal_fee + service_fee);
+
+    let payee = service.get_user_by_id(payee.id).await?;
+    let payer = service.get_user_by_id(payer.id).await?;
+    assert_eq!(payee.balance, msats);
+    assert_eq!(payer.balance, balance - msats - internal_fee - service_fee);
+    Ok(())
+}
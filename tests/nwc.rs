@@ -1,15 +1,25 @@
 use actix_rt::time::sleep;
+use actix_web::{test::init_service, web};
 use anyhow::Result;
+use entity::nwc_connection::BudgetRenewal;
+use lightning_client::{mock::PayBehavior, Lightning};
 use nostr_sdk::{
-    nips,
     secp256k1::{SecretKey, XOnlyPublicKey},
     Client, Event, EventBuilder, EventId, Filter, Keys, Kind, Options, RelayPoolNotification, Tag,
+    TagKind,
+};
+use satsbox::{
+    bus::{WalletEvent, WalletEventKind},
+    create_web_app, now,
+    nwc::{self, Encryption, Nwc, Request},
+    AppState, Error, InvoiceExtra, NwcPolicy,
 };
-use satsbox::{now, nwc, AppState};
 use serde_json::{json, Value};
 use std::{str::FromStr, sync::Arc, time::Duration};
 use tokio::time::timeout;
-use util::create_test_state;
+use util::{
+    create_mock_state, create_peer, create_test_state, fee, nostr_auth_get, nostr_auth_post,
+};
 
 mod util;
 
@@ -24,6 +34,7 @@ async fn get_balance() -> Result<()> {
 
     let server_keys = Keys::generate();
     state.setting.nwc.privkey = Some(server_keys.secret_key()?.into());
+    state.setting.nwc.legacy_user_keys = true;
     let client_keys = Keys::generate();
 
     let state = Arc::new(state);
@@ -62,7 +73,15 @@ async fn get_balance() -> Result<()> {
         Ok(None)
     })
     .await?;
-    assert_eq!(res.content, nwc::METHODS);
+    assert_eq!(res.content, format!("{} notifications", nwc::METHODS));
+    assert!(res
+        .tags
+        .iter()
+        .any(|t| t.as_vec() == ["encryption", "nip44_v2 nip04"]));
+    assert!(res
+        .tags
+        .iter()
+        .any(|t| t.as_vec() == ["notifications", nwc::NOTIFICATIONS]));
 
     let (res, _) = request(
         &client,
@@ -106,6 +125,63 @@ async fn get_balance() -> Result<()> {
     Ok(())
 }
 
+#[actix_rt::test]
+async fn nip44() -> Result<()> {
+    let mut state = create_test_state().await?;
+    state.setting.nwc.require_nip44 = true;
+
+    let server_keys = Keys::generate();
+    state.setting.nwc.privkey = Some(server_keys.secret_key()?.into());
+    state.setting.nwc.legacy_user_keys = true;
+    let client_keys = Keys::generate();
+
+    let state = Arc::new(state);
+
+    let nwc = nwc::Nwc::new(state.clone());
+    nwc.connect().await?;
+    let handle = tokio::spawn(async move { nwc.handle_notifications().await });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let client = connect(client_keys.secret_key()?, &state).await?;
+
+    sleep(Duration::from_millis(100)).await;
+
+    let response = Filter::new()
+        .kind(Kind::WalletConnectResponse)
+        .pubkey(client_keys.public_key())
+        .since((now() - 60 * 5).into());
+
+    client.subscribe(vec![response]).await;
+
+    let (res, _) = request_with(
+        &client,
+        &server_keys,
+        &client_keys,
+        "get_balance",
+        json!({}),
+        5,
+        Encryption::Nip44V2,
+    )
+    .await?;
+    assert_eq!(res["balance"], json!(0));
+
+    // nip04 is rejected
+    let res = request(
+        &client,
+        &server_keys,
+        &client_keys,
+        "get_balance",
+        json!({}),
+        5,
+    )
+    .await;
+    assert!(res.unwrap_err().to_string().contains("encryption"));
+
+    handle.abort();
+    Ok(())
+}
+
 #[actix_rt::test]
 async fn whitelist() -> Result<()> {
     let mut state = create_test_state().await?;
@@ -118,6 +194,7 @@ async fn whitelist() -> Result<()> {
 
     let server_keys = Keys::generate();
     state.setting.nwc.privkey = Some(server_keys.secret_key()?.into());
+    state.setting.nwc.legacy_user_keys = true;
     let client_keys = Keys::generate();
 
     let state = Arc::new(state);
@@ -164,14 +241,41 @@ async fn request(
     params: Value,
     timeout_seconds: u64,
 ) -> anyhow::Result<(Value, Event)> {
-    let event = create_request_event(server_keys.public_key(), client_keys, method, params)?;
+    request_with(
+        client,
+        server_keys,
+        client_keys,
+        method,
+        params,
+        timeout_seconds,
+        Encryption::Nip04,
+    )
+    .await
+}
+
+async fn request_with(
+    client: &Client,
+    server_keys: &Keys,
+    client_keys: &Keys,
+    method: &str,
+    params: Value,
+    timeout_seconds: u64,
+    encryption: Encryption,
+) -> anyhow::Result<(Value, Event)> {
+    let event = create_request_event(
+        server_keys.public_key(),
+        client_keys,
+        method,
+        params,
+        encryption,
+    )?;
     let event_id = event.id.clone();
     client.send_event(event.clone()).await?;
     let res = wait(&client, timeout_seconds, |notification| async {
         match notification {
             RelayPoolNotification::Event(_url, event) => {
                 if event.kind == Kind::WalletConnectResponse {
-                    let res = parse_response(&event, &client_keys, &event_id)?;
+                    let res = parse_response(&event, &client_keys, &event_id, encryption)?;
                     return Ok(Some(res));
                 }
             }
@@ -230,30 +334,34 @@ fn create_request_event(
     client_keys: &Keys,
     method: &str,
     params: Value,
+    encryption: Encryption,
 ) -> Result<Event> {
     let content = json!({
         "method": method,
         "params": params,
     });
 
-    let content = nips::nip04::encrypt(
-        &client_keys.secret_key().unwrap(),
+    let content = encryption.encrypt(
+        client_keys,
         &server_pubkey,
         serde_json::to_string(&content)?,
     )?;
 
-    Ok(EventBuilder::new(
-        23194.into(),
-        content,
-        &vec![Tag::PubKey(server_pubkey, None)],
-    )
-    .to_event(client_keys)?)
+    let mut tags = vec![Tag::PubKey(server_pubkey, None)];
+    if encryption == Encryption::Nip44V2 {
+        tags.push(Tag::Generic(
+            TagKind::Custom("encryption".to_owned()),
+            vec![encryption.as_str().to_owned()],
+        ));
+    }
+    Ok(EventBuilder::new(23194.into(), content, &tags).to_event(client_keys)?)
 }
 
 fn parse_response(
     event: &Event,
     keys: &Keys,
     request_event_id: &EventId,
+    encryption: Encryption,
 ) -> satsbox::Result<(String, Value)> {
     if event.kind != Kind::WalletConnectResponse
         || !event.tags.iter().any(|t| match t {
@@ -268,11 +376,7 @@ fn parse_response(
         return Err(satsbox::Error::Str("Invalid event kind or tags"));
     }
 
-    let content = nips::nip04::decrypt(
-        &keys.secret_key().unwrap(),
-        &event.pubkey,
-        event.content.clone(),
-    )?;
+    let content = encryption.decrypt(keys, &event.pubkey, event.content.clone())?;
     let val: Value = serde_json::from_str(&content)?;
     if !val["result_type"].is_string() {
         return Err(satsbox::Error::Str("Invalid method"));
@@ -289,3 +393,403 @@ fn parse_response(
         val["result"].clone(),
     ))
 }
+
+#[tokio::test]
+async fn nwc_methods() -> Result<()> {
+    let (mut state, mock) = create_mock_state().await?;
+    state.setting.nwc.privkey = Some(Keys::generate().secret_key()?.into());
+    state.setting.fee = fee();
+    let state = Arc::new(state);
+    let nwc = Nwc::new(state.clone());
+    let pubkey = Keys::generate().public_key().serialize().to_vec();
+    let request = |method: &str, params: serde_json::Value| -> Request {
+        serde_json::from_value(json!({ "method": method, "params": params })).unwrap()
+    };
+    let msats = 2_000_000;
+
+    let res = nwc
+        .handle(pubkey.clone(), None, request("get_info", json!({})))
+        .await;
+    let info = res[0].1.as_ref().unwrap();
+    assert_eq!(info["pubkey"], json!(hex::encode(mock.id())));
+    assert!(info["methods"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("make_invoice")));
+
+    let res = nwc
+        .handle(
+            pubkey.clone(),
+            None,
+            request(
+                "make_invoice",
+                json!({ "amount": msats, "description": "nwc" }),
+            ),
+        )
+        .await;
+    let inv = res[0].1.as_ref().unwrap().clone();
+    assert_eq!(inv["type"], json!("incoming"));
+    assert_eq!(inv["amount"], json!(msats));
+    let hash = inv["payment_hash"].as_str().unwrap().to_owned();
+    for expiry in [0, u64::MAX] {
+        let res = nwc
+            .handle(
+                pubkey.clone(),
+                None,
+                request(
+                    "make_invoice",
+                    json!({ "amount": msats, "description": "nwc", "expiry": expiry }),
+                ),
+            )
+            .await;
+        assert!(matches!(res[0].1, Err(Error::InvalidParam(_))));
+    }
+
+    mock.settle_invoice(&hex::decode(&hash)?, None)?;
+    state.service.sync_invoices(Some(now() - 60)).await?;
+
+    let res = nwc
+        .handle(
+            pubkey.clone(),
+            None,
+            request("lookup_invoice", json!({ "invoice": inv["invoice"] })),
+        )
+        .await;
+    let tx = res[0].1.as_ref().unwrap();
+    assert_eq!(tx["payment_hash"], json!(hash));
+    assert!(tx["settled_at"].is_number());
+    assert!(tx["preimage"].is_string());
+
+    let res = nwc
+        .handle(
+            pubkey.clone(),
+            None,
+            request(
+                "lookup_invoice",
+                json!({ "payment_hash": hex::encode([0; 32]) }),
+            ),
+        )
+        .await;
+    assert!(matches!(res[0].1, Err(Error::NotFound(_))));
+
+    // pay two external invoices
+    let payee = create_peer(&mock);
+    let inv1 = payee
+        .create_invoice("1".to_owned(), 100_000, None, Some(600))
+        .await?;
+    let inv2 = payee
+        .create_invoice("2".to_owned(), 100_000, None, Some(600))
+        .await?;
+    let res = nwc
+        .handle(
+            pubkey.clone(),
+            None,
+            request(
+                "multi_pay_invoice",
+                json!({ "invoices": [
+                    { "id": "first", "invoice": inv1.bolt11 },
+                    { "invoice": inv2.bolt11 },
+                ] }),
+            ),
+        )
+        .await;
+    assert_eq!(res.len(), 2);
+    assert_eq!(res[0].0, Some("first".to_owned()));
+    assert_eq!(res[1].0, Some(hex::encode(&inv2.payment_hash)));
+    assert!(res
+        .iter()
+        .all(|(_, r)| r.as_ref().unwrap()["preimage"].is_string()));
+
+    let res = nwc
+        .handle(
+            pubkey.clone(),
+            None,
+            request("list_transactions", json!({})),
+        )
+        .await;
+    let list = res[0].1.as_ref().unwrap()["transactions"].clone();
+    assert_eq!(list.as_array().unwrap().len(), 3);
+    let res = nwc
+        .handle(
+            pubkey.clone(),
+            None,
+            request(
+                "list_transactions",
+                json!({ "type": "outgoing", "limit": 1 }),
+            ),
+        )
+        .await;
+    let list = res[0].1.as_ref().unwrap()["transactions"].clone();
+    assert_eq!(list.as_array().unwrap().len(), 1);
+    assert_eq!(list[0]["type"], json!("outgoing"));
+
+    let res = nwc
+        .handle(
+            pubkey.clone(),
+            None,
+            request(
+                "pay_keysend",
+                json!({
+                    "amount": 100_000,
+                    "pubkey": hex::encode(payee.id()),
+                    "tlv_records": [{ "type": 696969, "value": hex::encode("satsbox") }],
+                }),
+            ),
+        )
+        .await;
+    assert!(res[0].1.as_ref().unwrap()["preimage"].is_string());
+
+    let res = nwc
+        .handle(pubkey.clone(), None, request("unknown_method", json!({})))
+        .await;
+    assert!(matches!(res[0].1, Err(Error::NotImplemented)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn nwc_connection() -> Result<()> {
+    let (mut state, mock) = create_mock_state().await?;
+    state.setting.nwc.privkey = Some(Keys::generate().secret_key()?.into());
+    state.setting.nwc.relays = vec!["ws://localhost:7000".to_owned()];
+    state.setting.fee = fee();
+    let state = web::Data::new(state);
+    let app = init_service(create_web_app(state.clone())).await;
+    let nwc = Nwc::new(state.clone().into_inner());
+    let keys = Keys::generate();
+    let url = "http://localhost:8080/v1/nwc_connections";
+
+    let (val, status) = nostr_auth_post(
+        &app,
+        url,
+        &keys,
+        json!({
+            "name": "test",
+            "methods": ["pay_invoice", "get_info"],
+            "max_amount": 205_000,
+            "budget": 310_000,
+            "budget_renewal": "daily",
+        }),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    let uri = url::Url::parse(val["uri"].as_str().unwrap())?;
+    assert_eq!(uri.scheme(), "nostr+walletconnect");
+    let secret = uri
+        .query_pairs()
+        .find(|(k, _)| k == "secret")
+        .unwrap()
+        .1
+        .to_string();
+    let client = Keys::new(SecretKey::from_str(&secret)?);
+    let id = val["connection"]["id"].as_i64().unwrap();
+
+    let (val, _status) = nostr_auth_get(&app, url, &keys).await?;
+    assert_eq!(val["connections"].as_array().unwrap().len(), 1);
+
+    // fund the owner
+    let service = &state.service;
+    let user = service
+        .get_user(keys.public_key().serialize().to_vec())
+        .await?
+        .unwrap();
+    service
+        .admin_adjust_user_balance(&user, 5_000_000, None)
+        .await?;
+
+    let conn = service
+        .get_nwc_connection(client.public_key().serialize().to_vec())
+        .await?
+        .unwrap();
+    let payee = create_peer(&mock);
+    let pay = |msats: u64| {
+        let payee = payee.clone();
+        let nwc = nwc.clone();
+        let conn = conn.clone();
+        let pubkey = user.pubkey.clone();
+        async move {
+            let inv = payee
+                .create_invoice("nwc".to_owned(), msats, None, Some(600))
+                .await
+                .unwrap();
+            let req: Request = serde_json::from_value(
+                json!({ "method": "pay_invoice", "params": { "invoice": inv.bolt11 } }),
+            )
+            .unwrap();
+            nwc.handle(pubkey, Some(&conn), req).await.remove(0).1
+        }
+    };
+
+    // method
+    let req: Request = serde_json::from_value(json!({ "method": "get_balance" }))?;
+    let res = nwc.handle(user.pubkey.clone(), Some(&conn), req).await;
+    assert!(matches!(res[0].1, Err(Error::Restricted(_))));
+
+    // max amount with the fees
+    assert!(matches!(pay(250_000).await, Err(Error::QuotaExceeded(_))));
+    assert!(matches!(pay(201_000).await, Err(Error::QuotaExceeded(_))));
+    pay(200_000).await?;
+
+    // the budget of the failed in flight payment is given back
+    let budget_used = || async {
+        service
+            .get_nwc_connection(client.public_key().serialize().to_vec())
+            .await
+            .unwrap()
+            .unwrap()
+            .budget_used
+    };
+    mock.set_default_pay_behavior(PayBehavior::InFlight);
+    let payment_id = match pay(100_000).await {
+        Err(Error::PaymentInProgress(id)) => id,
+        res => panic!("unexpected {:?}", res),
+    };
+    mock.set_default_pay_behavior(PayBehavior::Succeed { fee: 0 });
+    // the amount with the max fees is held
+    assert_eq!(budget_used().await, 200_600 + 102_300);
+    let payment = service.get_invoice(payment_id).await?.unwrap();
+    mock.fail_payment(&payment.payment_hash)?;
+    service.sync_payments(None).await?;
+    assert_eq!(budget_used().await, 200_600);
+
+    // budget
+    assert!(matches!(pay(150_000).await, Err(Error::QuotaExceeded(_))));
+    pay(100_000).await?;
+    let conn = service
+        .get_nwc_connection(client.public_key().serialize().to_vec())
+        .await?
+        .unwrap();
+    // settled to the totals
+    assert_eq!(conn.budget_used, 200_600 + 100_300);
+
+    // revoke
+    let (val, status) =
+        nostr_auth_post(&app, &format!("{}/{}/revoke", url, id), &keys, json!({})).await?;
+    assert_eq!(status, 200);
+    assert!(val["connection"]["revoked_at"].as_i64().unwrap() > 0);
+    let conn = service
+        .get_nwc_connection(client.public_key().serialize().to_vec())
+        .await?
+        .unwrap();
+    let req: Request = serde_json::from_value(json!({ "method": "get_info" }))?;
+    let res = nwc.handle(user.pubkey.clone(), Some(&conn), req).await;
+    assert!(matches!(res[0].1, Err(Error::Restricted(_))));
+
+    // other user
+    let (_val, status) = nostr_auth_post(
+        &app,
+        &format!("{}/{}/revoke", url, id),
+        &Keys::generate(),
+        json!({}),
+    )
+    .await?;
+    assert_eq!(status, 404);
+    Ok(())
+}
+
+#[test]
+fn nwc_encryption() -> Result<()> {
+    let keys = Keys::generate();
+    let event = |tags: &[Tag], content: &str| {
+        EventBuilder::new(Kind::WalletConnectRequest, content, tags).to_event(&keys)
+    };
+    let tag = |value: &str| {
+        Tag::Generic(
+            TagKind::Custom("encryption".to_owned()),
+            vec![value.to_owned()],
+        )
+    };
+    assert_eq!(
+        Encryption::detect(&event(&[tag("nip44_v2")], "")?)?,
+        Encryption::Nip44V2
+    );
+    assert_eq!(
+        Encryption::detect(&event(&[tag("nip04")], "")?)?,
+        Encryption::Nip04
+    );
+    assert_eq!(
+        Encryption::detect(&event(&[], "abc?iv=def")?)?,
+        Encryption::Nip04
+    );
+    assert_eq!(
+        Encryption::detect(&event(&[], "abc")?)?,
+        Encryption::Nip44V2
+    );
+    assert!(matches!(
+        Encryption::detect(&event(&[tag("nip44_v3")], "")?),
+        Err(Error::UnsupportedEncryption)
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn nwc_notification() -> Result<()> {
+    let (mut state, mock) = create_mock_state().await?;
+    let server = Keys::generate();
+    state.setting.nwc.privkey = Some(server.secret_key()?.into());
+    let state = Arc::new(state);
+    let nwc = Nwc::new(state.clone());
+    let service = &state.service;
+    let client = Keys::generate();
+    let user = service.get_or_create_user(vec![3; 32]).await?;
+    service
+        .create_nwc_connection(
+            &user,
+            client.public_key().serialize().to_vec(),
+            "test".to_owned(),
+            NwcPolicy {
+                methods: vec![],
+                max_amount: 0,
+                budget: 0,
+                budget_renewal: BudgetRenewal::Never,
+                expires_at: 0,
+            },
+        )
+        .await?;
+
+    let invoice = service
+        .create_invoice(
+            &user,
+            "notify".to_owned(),
+            1_000_000,
+            3600,
+            InvoiceExtra::default(),
+        )
+        .await?;
+    mock.settle_invoice(&invoice.payment_hash, None)?;
+    state.service.sync_invoices(Some(now() - 60)).await?;
+
+    let events = nwc
+        .notification_events(&WalletEvent {
+            user_id: user.id,
+            kind: WalletEventKind::InvoicePaid {
+                id: invoice.id,
+                payment_hash: hex::encode(&invoice.payment_hash),
+                amount: 1_000_000,
+            },
+        })
+        .await?;
+    let kinds = events.iter().map(|e| e.kind.as_u64()).collect::<Vec<_>>();
+    assert_eq!(kinds, vec![23197, 23196]);
+    let content =
+        Encryption::Nip44V2.decrypt(&client, &server.public_key(), events[0].content.clone())?;
+    let val: serde_json::Value = serde_json::from_str(&content)?;
+    assert_eq!(val["notification_type"], json!("payment_received"));
+    assert_eq!(
+        val["notification"]["payment_hash"],
+        json!(hex::encode(&invoice.payment_hash))
+    );
+    assert!(val["notification"]["settled_at"].is_number());
+
+    // no notification for other events
+    let events = nwc
+        .notification_events(&WalletEvent {
+            user_id: user.id,
+            kind: WalletEventKind::Balance {
+                balance: 0,
+                lock_amount: 0,
+            },
+        })
+        .await?;
+    assert!(events.is_empty());
+    Ok(())
+}
@@ -1,15 +1,24 @@
 // RUST_TEST_THREADS=1 cargo test --test service -- --nocapture
 
+use actix_web::{test::init_service, web};
 use anyhow::Result;
-use entity::invoice;
+use entity::{invoice, user};
+use lightning_client::{mock::PayBehavior, Lightning as _};
+use migration::{Migrator, MigratorTrait};
+use nostr_sdk::Keys;
 use satsbox::{
-    now,
+    create_web_app, now,
     setting::{Fee, Lightning},
-    InvoiceExtra,
+    sha256, Error, InvoiceExtra,
 };
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use serde_json::json;
 use std::time::Duration;
 use tokio::time::sleep;
-use util::create_test_state2;
+use util::{
+    create_funded_user, create_mock_state, create_peer, create_test_state2, fee, nostr_auth_get,
+    nostr_auth_post,
+};
 
 mod util;
 
@@ -99,6 +108,7 @@ async fn internal_payment() -> Result<()> {
         .pay(
             &payer_user,
             payee_invoice.bolt11.clone(),
+            None,
             &fee,
             entity::invoice::Source::Test,
             false,
@@ -119,6 +129,7 @@ async fn internal_payment() -> Result<()> {
         service.pay(
             &payer_user,
             payee_invoice.bolt11.clone(),
+            None,
             &fee,
             entity::invoice::Source::Test,
             false
@@ -126,6 +137,7 @@ async fn internal_payment() -> Result<()> {
         service.pay(
             &payer_user,
             payee_invoice.bolt11.clone(),
+            None,
             &fee,
             entity::invoice::Source::Test,
             false
@@ -170,6 +182,7 @@ async fn internal_payment() -> Result<()> {
         .pay(
             &payer_user,
             payee_invoice.bolt11.clone(),
+            None,
             &fee,
             entity::invoice::Source::Test,
             false,
@@ -222,6 +235,7 @@ async fn self_payment() -> Result<()> {
         service.pay(
             &user,
             invoice.bolt11.clone(),
+            None,
             &fee,
             entity::invoice::Source::Test,
             false
@@ -229,6 +243,7 @@ async fn self_payment() -> Result<()> {
         service.pay(
             &user,
             invoice.bolt11.clone(),
+            None,
             &fee,
             entity::invoice::Source::Test,
             false
@@ -333,6 +348,7 @@ async fn pay(payer: Lightning, payee: Lightning, test_sync: bool) -> Result<()>
         .pay(
             &payer_user,
             payee_invoice.bolt11.clone(),
+            None,
             &fee,
             entity::invoice::Source::Test,
             false,
@@ -353,6 +369,7 @@ async fn pay(payer: Lightning, payee: Lightning, test_sync: bool) -> Result<()>
         payer_service.pay(
             &payer_user,
             payee_invoice.bolt11.clone(),
+            None,
             &fee,
             entity::invoice::Source::Test,
             test_sync
@@ -360,6 +377,7 @@ async fn pay(payer: Lightning, payee: Lightning, test_sync: bool) -> Result<()>
         payer_service.pay(
             &payer_user,
             payee_invoice.bolt11.clone(),
+            None,
             &fee,
             entity::invoice::Source::Test,
             test_sync
@@ -382,7 +400,7 @@ async fn pay(payer: Lightning, payee: Lightning, test_sync: bool) -> Result<()>
 
     // println!("payment {:?} {:?}", _err, payment);
     sleep(Duration::from_secs(1)).await;
-    let count = payee_service.sync_invoices(now() - 60).await?;
+    let count = payee_service.sync_invoices(Some(now() - 60)).await?;
     assert_eq!(count, 1);
 
     let (_max_fee, service_fee) = fee.cal(msats, false);
@@ -414,6 +432,7 @@ async fn pay(payer: Lightning, payee: Lightning, test_sync: bool) -> Result<()>
         .pay(
             &payer_user,
             payee_invoice.bolt11.clone(),
+            None,
             &fee,
             entity::invoice::Source::Test,
             false,
@@ -473,6 +492,7 @@ async fn duplicate_payment() -> Result<()> {
         service.pay(
             &payer_user,
             payee_invoice.bolt11.clone(),
+            None,
             &fee,
             entity::invoice::Source::Test,
             false
@@ -480,6 +500,7 @@ async fn duplicate_payment() -> Result<()> {
         service.pay(
             &payer_user,
             payee_invoice.bolt11.clone(),
+            None,
             &fee,
             entity::invoice::Source::Test,
             false
@@ -501,7 +522,7 @@ async fn duplicate_payment() -> Result<()> {
         .await?;
 
     sleep(Duration::from_secs(1)).await;
-    let count = service.sync_invoices(now() - 60).await?;
+    let count = service.sync_invoices(Some(now() - 60)).await?;
     assert_eq!(count, 1);
 
     let (internal_fee, service_fee) = fee.cal(msats, true);
@@ -535,6 +556,7 @@ async fn duplicate_payment() -> Result<()> {
         .pay(
             &payer_user,
             payee_invoice.bolt11.clone(),
+            None,
             &fee,
             entity::invoice::Source::Test,
             false,
@@ -545,3 +567,655 @@ async fn duplicate_payment() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn keysend() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let payee = create_peer(&mock);
+
+    let service = &state.service;
+    let fee = fee();
+    let msats: i64 = 2_000_000;
+    let balance: i64 = 5_000_000;
+    let user = create_funded_user(service, vec![1; 32], balance).await?;
+    let (_max_fee, service_fee) = fee.cal(msats, false);
+
+    // external
+    mock.set_default_pay_behavior(PayBehavior::Succeed { fee: 10 });
+    let payment = service
+        .keysend(
+            &user,
+            payee.id().clone(),
+            msats as u64,
+            vec![(7629169, b"podcast".to_vec())],
+            &fee,
+            invoice::Source::Test,
+        )
+        .await?;
+    assert!(!payment.internal);
+    assert_eq!(payment.status, invoice::Status::Paid);
+    assert_eq!(payment.total, msats + 10 + service_fee);
+    assert_eq!(
+        satsbox::sha256(&payment.payment_preimage),
+        payment.payment_hash
+    );
+    let remote = payee.lookup_invoice(payment.payment_hash.clone()).await?;
+    assert_eq!(remote.paid_amount, msats as u64);
+    let balance = balance - msats - 10 - service_fee;
+    let user = service.get_user_by_id(user.id).await?;
+    assert_eq!(user.balance, balance);
+    assert_eq!(user.lock_amount, 0);
+
+    // failed
+    mock.set_default_pay_behavior(PayBehavior::Fail("no route".to_owned()));
+    let res = service
+        .keysend(
+            &user,
+            payee.id().clone(),
+            msats as u64,
+            vec![],
+            &fee,
+            invoice::Source::Test,
+        )
+        .await;
+    assert!(res.err().unwrap().to_string().contains("no route"));
+    let user = service.get_user_by_id(user.id).await?;
+    assert_eq!(user.balance, balance);
+    assert_eq!(user.lock_amount, 0);
+
+    // in flight, the payment hash is stored before sending
+    mock.set_default_pay_behavior(PayBehavior::InFlight);
+    let res = service
+        .keysend(
+            &user,
+            payee.id().clone(),
+            msats as u64,
+            vec![],
+            &fee,
+            invoice::Source::Test,
+        )
+        .await;
+    let payment_id = match res {
+        Err(Error::PaymentInProgress(id)) => id,
+        _ => panic!("the payment should be in flight"),
+    };
+    let payment = service.get_invoice(payment_id).await?.unwrap();
+    assert_eq!(payment.status, invoice::Status::Unpaid);
+    assert!(mock
+        .lookup_payment(payment.payment_hash.clone())
+        .await
+        .is_ok());
+    let user = service.get_user_by_id(user.id).await?;
+    assert_eq!(user.balance, balance - payment.total);
+    mock.fail_payment(&payment.payment_hash)?;
+    service.sync_payments(None).await?;
+    let user = service.get_user_by_id(user.id).await?;
+    assert_eq!(user.balance, balance);
+    assert_eq!(user.lock_amount, 0);
+
+    // reserved record
+    let res = service
+        .keysend(
+            &user,
+            payee.id().clone(),
+            msats as u64,
+            vec![(5482373484, vec![0; 32])],
+            &fee,
+            invoice::Source::Test,
+        )
+        .await;
+    assert!(matches!(res, Err(Error::InvalidParam(_))));
+
+    // internal, routed by the user pubkey record
+    let receiver = service.get_or_create_user(vec![2; 32]).await?;
+    let res = service
+        .keysend(
+            &user,
+            mock.id().clone(),
+            msats as u64,
+            vec![],
+            &fee,
+            invoice::Source::Test,
+        )
+        .await;
+    assert!(matches!(res, Err(Error::InvalidPayment(_))));
+    let payment = service
+        .keysend(
+            &user,
+            mock.id().clone(),
+            msats as u64,
+            vec![(696969, hex::encode(&receiver.pubkey).into_bytes())],
+            &fee,
+            invoice::Source::Test,
+        )
+        .await?;
+    assert!(payment.internal);
+    assert_eq!(payment.status, invoice::Status::Paid);
+    let receiver = service.get_user_by_id(receiver.id).await?;
+    assert_eq!(receiver.balance, msats);
+    Ok(())
+}
+
+#[tokio::test]
+async fn receive_keysend() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let service = state.service.clone();
+    let msats = 2_000_000;
+    let user = service.get_or_create_user(vec![1; 32]).await?;
+    let user = service
+        .update_username(user.id, Some("alice".to_owned()))
+        .await?;
+
+    // by polling without local invoices, routed by pubkey
+    mock.receive_keysend(
+        msats,
+        vec![(696969, hex::encode(&user.pubkey).into_bytes())],
+    )?;
+    assert_eq!(service.sync_invoices(None).await?, 1);
+
+    // by subscription, routed by username
+    let task = tokio::spawn(async move { service.subscribe_invoices().await });
+    sleep(Duration::from_millis(100)).await;
+    let hash = mock.receive_keysend(msats, vec![(696969, b"alice".to_vec())])?;
+    // unknown payee is ignored
+    mock.receive_keysend(msats, vec![(696969, b"bob".to_vec())])?;
+    mock.receive_keysend(msats, vec![])?;
+    sleep(Duration::from_millis(100)).await;
+    task.abort();
+
+    let service = &state.service;
+    let user = service.get_user_by_id(user.id).await?;
+    assert_eq!(user.balance, 2 * msats as i64);
+    let inv = invoice::Entity::find()
+        .filter(invoice::Column::PaymentHash.eq(hash))
+        .one(service.db())
+        .await?
+        .unwrap();
+    assert_eq!(inv.status, invoice::Status::Paid);
+    assert_eq!(inv.source, invoice::Source::Keysend);
+    assert_eq!(inv.paid_amount, msats as i64);
+
+    // by polling, routed by pubkey
+    let pending = service
+        .create_invoice(
+            &user,
+            "test".to_owned(),
+            msats,
+            600,
+            InvoiceExtra::new(invoice::Source::Test),
+        )
+        .await?;
+    mock.receive_keysend(
+        msats + 1,
+        vec![(696969, hex::encode(&user.pubkey).into_bytes())],
+    )?;
+    assert_eq!(service.sync_invoices(Some(now() - 60)).await?, 1);
+    // credited once
+    assert_eq!(service.sync_invoices(Some(now() - 60)).await?, 0);
+    let user = service.get_user_by_id(user.id).await?;
+    assert_eq!(user.balance, 3 * msats as i64 + 1);
+    let pending = service.get_invoice(pending.id).await?.unwrap();
+    assert_eq!(pending.status, invoice::Status::Unpaid);
+    Ok(())
+}
+
+#[tokio::test]
+async fn offer() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let payer = create_peer(&mock);
+    let service = state.service.clone();
+    let fee = fee();
+    let msats = 2_000_000;
+    let keys = Keys::generate();
+    let user = service
+        .get_or_create_user(keys.public_key().serialize().to_vec())
+        .await?;
+    let offer = service.get_or_create_offer(&user).await?;
+    assert_eq!(service.get_or_create_offer(&user).await?.id, offer.id);
+
+    // invoice requested by the offer
+    let task = tokio::spawn(async move { service.subscribe_invoices().await });
+    sleep(Duration::from_millis(100)).await;
+    let inv = payer
+        .fetch_invoice(offer.bolt12.clone(), Some(msats))
+        .await?;
+    payer.pay(inv.bolt11, None).await?;
+    sleep(Duration::from_millis(100)).await;
+    task.abort();
+
+    let service = &state.service;
+    let user = service.get_user_by_id(user.id).await?;
+    assert_eq!(user.balance, msats as i64);
+    let local = invoice::Entity::find()
+        .filter(invoice::Column::PaymentHash.eq(inv.payment_hash))
+        .one(service.db())
+        .await?
+        .unwrap();
+    assert_eq!(local.status, invoice::Status::Paid);
+    assert_eq!(local.source, invoice::Source::Offer);
+
+    // pay the offer of the external node
+    let external = payer.create_offer("coffee".to_owned(), Some(1_000)).await?;
+    let payment = service
+        .pay(
+            &user,
+            external.bolt12,
+            None,
+            &fee,
+            invoice::Source::Test,
+            false,
+        )
+        .await?;
+    assert_eq!(payment.status, invoice::Status::Paid);
+    assert_eq!(payment.amount, 1_000);
+    let remote = payer.lookup_invoice(payment.payment_hash).await?;
+    assert_eq!(remote.offer_id, Some(external.id));
+
+    // the amount is required for the local offer
+    let other_keys = Keys::generate();
+    let other = create_funded_user(
+        service,
+        other_keys.public_key().serialize().to_vec(),
+        msats as i64,
+    )
+    .await?;
+    let res = service
+        .pay(
+            &other,
+            offer.bolt12.clone(),
+            None,
+            &fee,
+            invoice::Source::Test,
+            false,
+        )
+        .await;
+    assert!(matches!(res, Err(Error::InvalidParam(_))));
+
+    let balance = service.get_user_by_id(user.id).await?.balance;
+    let state = web::Data::new(state);
+    let app = init_service(create_web_app(state.clone())).await;
+    let (val, status) = nostr_auth_get(&app, "http://localhost:8080/v1/offer", &keys).await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["bolt12"], json!(offer.bolt12));
+
+    // pay the local offer with the amount
+    let (_, status) = nostr_auth_post(
+        &app,
+        "http://localhost:8080/v1/pay_invoice",
+        &other_keys,
+        json!({ "invoice": offer.bolt12, "amount": 1_000 }),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    let service = &state.service;
+    let user = service.get_user_by_id(user.id).await?;
+    assert_eq!(user.balance, balance + 1_000);
+    let other = service.get_user_by_id(other.id).await?;
+    let (_, service_fee) = fee.cal(1_000, true);
+    assert_eq!(other.balance, msats as i64 - 1_000 - service_fee);
+    Ok(())
+}
+
+#[tokio::test]
+async fn hold_invoice() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let payer = create_peer(&mock);
+    let keys = Keys::generate();
+    let msats = 2_000_000;
+    let preimage = vec![1; 32];
+    let hash = hex::encode(sha256(&preimage));
+
+    let state = web::Data::new(state);
+    let service = &state.service;
+    let app = init_service(create_web_app(state.clone())).await;
+    let url = "http://localhost:8080/v1/hold_invoices";
+    let (val, status) = nostr_auth_post(
+        &app,
+        url,
+        &keys,
+        json!({ "amount": msats, "memo": "escrow", "payment_hash": hash }),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["invoice"]["status"], json!("unpaid"));
+    let bolt11 = val["invoice"]["bolt11"].as_str().unwrap().to_owned();
+    let user = service
+        .get_user(keys.public_key().serialize().to_vec())
+        .await?
+        .unwrap();
+
+    // held, not credited
+    assert!(payer.pay(bolt11.clone(), None).await.is_err());
+    assert_eq!(service.sync_invoices(Some(now() - 60)).await?, 1);
+    let inv = service.get_invoice(1).await?.unwrap();
+    assert_eq!(inv.status, invoice::Status::Accepted);
+    assert_eq!(service.get_user_by_id(user.id).await?.balance, 0);
+
+    // internal payments can't be held
+    let other = create_funded_user(service, vec![2; 32], 2 * msats as i64).await?;
+    let res = service
+        .pay(&other, bolt11, None, &fee(), invoice::Source::Test, false)
+        .await;
+    assert!(matches!(res, Err(Error::InvalidPayment(_))));
+
+    // settle by the preimage
+    let url = format!("http://localhost:8080/v1/hold_invoices/{}/settle", hash);
+    let (_val, status) = nostr_auth_post(
+        &app,
+        &url,
+        &keys,
+        json!({ "preimage": hex::encode([2; 32]) }),
+    )
+    .await?;
+    assert_eq!(status, 400);
+    let (val, status) = nostr_auth_post(
+        &app,
+        &url,
+        &keys,
+        json!({ "preimage": hex::encode(&preimage) }),
+    )
+    .await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["invoice"]["status"], json!("paid"));
+    assert_eq!(service.get_user_by_id(user.id).await?.balance, msats as i64);
+    let payment = payer.lookup_payment(sha256(&preimage)).await?;
+    assert_eq!(payment.payment_preimage, preimage);
+    // settled once
+    let (_val, status) = nostr_auth_post(
+        &app,
+        &url,
+        &keys,
+        json!({ "preimage": hex::encode(&preimage) }),
+    )
+    .await?;
+    assert_eq!(status, 400);
+
+    // cancel the held payment
+    let hash = sha256([3; 32]);
+    let inv = service
+        .create_hold_invoice(
+            &user,
+            "escrow".to_owned(),
+            msats,
+            hash.clone(),
+            600,
+            InvoiceExtra::new(invoice::Source::Test),
+        )
+        .await?;
+    assert!(payer.pay(inv.bolt11, None).await.is_err());
+    let url = format!(
+        "http://localhost:8080/v1/hold_invoices/{}/cancel",
+        hex::encode(&hash)
+    );
+    let (val, status) = nostr_auth_post(&app, &url, &keys, json!({})).await?;
+    assert_eq!(status, 200);
+    assert_eq!(val["invoice"]["status"], json!("canceled"));
+    let payment = payer.lookup_payment(hash).await?;
+    assert_eq!(
+        payment.status,
+        lightning_client::lightning::PaymentStatus::Failed
+    );
+    assert_eq!(service.get_user_by_id(user.id).await?.balance, msats as i64);
+    Ok(())
+}
+
+#[tokio::test]
+async fn subscribe() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let service = state.service.clone();
+    let pubkey = vec![1; 32];
+    let msats = 2_000_000;
+    let user = service.get_or_create_user(pubkey.clone()).await?;
+
+    let inv1 = service
+        .create_invoice(
+            &user,
+            "test".to_owned(),
+            msats,
+            600,
+            InvoiceExtra::new(invoice::Source::Test),
+        )
+        .await?;
+    let inv2 = service
+        .create_invoice(
+            &user,
+            "test".to_owned(),
+            msats,
+            600,
+            InvoiceExtra::new(invoice::Source::Test),
+        )
+        .await?;
+    // paid before subscribe
+    mock.settle_invoice(&inv1.payment_hash, None)?;
+
+    let task = tokio::spawn(async move { service.subscribe_invoices().await });
+    sleep(Duration::from_millis(100)).await;
+    mock.settle_invoice(&inv2.payment_hash, None)?;
+    sleep(Duration::from_millis(100)).await;
+    task.abort();
+
+    let service = &state.service;
+    let inv1 = service.get_invoice(inv1.id).await?.unwrap();
+    let inv2 = service.get_invoice(inv2.id).await?.unwrap();
+    assert_eq!(inv1.status, invoice::Status::Paid);
+    assert_eq!(inv2.status, invoice::Status::Paid);
+    let user = service.get_user(pubkey).await?.unwrap();
+    assert_eq!(user.balance, 2 * msats as i64);
+    assert_eq!(service.get_sync_state("pay_index").await?, Some(2));
+
+    // resume from the persisted index
+    let inv3 = service
+        .create_invoice(
+            &user,
+            "test".to_owned(),
+            msats,
+            600,
+            InvoiceExtra::new(invoice::Source::Test),
+        )
+        .await?;
+    mock.settle_invoice(&inv3.payment_hash, Some(msats + 1))?;
+    let service = state.service.clone();
+    let task = tokio::spawn(async move { service.subscribe_invoices().await });
+    sleep(Duration::from_millis(100)).await;
+    task.abort();
+
+    let service = &state.service;
+    let user = service.get_user_by_id(user.id).await?;
+    assert_eq!(user.balance, 3 * msats as i64 + 1);
+    assert_eq!(service.get_sync_state("pay_index").await?, Some(3));
+    Ok(())
+}
+
+#[tokio::test]
+async fn sync_cursor() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let service = &state.service;
+    let msats = 2_000_000;
+    let user = service.get_or_create_user(vec![1; 32]).await?;
+
+    let inv1 = service
+        .create_invoice(
+            &user,
+            "test".to_owned(),
+            msats,
+            600,
+            InvoiceExtra::new(invoice::Source::Test),
+        )
+        .await?;
+    assert_eq!(service.sync_invoices(None).await?, 0);
+    // cursor stays at the unpaid invoice
+    assert_eq!(
+        service.get_sync_state("invoice_time").await?,
+        Some(inv1.generated_at)
+    );
+    assert_eq!(
+        service.get_sync_state("invoice_index").await?,
+        Some(inv1.index)
+    );
+
+    mock.settle_invoice(&inv1.payment_hash, None)?;
+    assert_eq!(service.sync_invoices(None).await?, 1);
+    // nothing pending
+    assert_eq!(service.get_sync_state("invoice_index").await?, Some(0));
+    assert!(service.get_sync_state("invoice_time").await?.unwrap() >= inv1.generated_at - 60);
+    assert_eq!(service.sync_invoices(None).await?, 0);
+
+    // invoice before the cursor is only updated by resync
+    let inv2 = service
+        .create_invoice(
+            &user,
+            "test".to_owned(),
+            msats,
+            600,
+            InvoiceExtra::new(invoice::Source::Test),
+        )
+        .await?;
+    service
+        .set_sync_state("invoice_time", now() as i64 + 10)
+        .await?;
+    mock.settle_invoice(&inv2.payment_hash, None)?;
+    assert_eq!(service.sync_invoices(None).await?, 0);
+    assert_eq!(service.resync(now() - 60, None).await?, (1, 0));
+    let inv2 = service.get_invoice(inv2.id).await?.unwrap();
+    assert_eq!(inv2.status, invoice::Status::Paid);
+    assert_eq!(service.resync(now() - 60, Some(now() - 30)).await?, (0, 0));
+
+    let user = service.get_user_by_id(user.id).await?;
+    assert_eq!(user.balance, 2 * msats as i64);
+    Ok(())
+}
+
+#[tokio::test]
+async fn ledger() -> Result<()> {
+    let (state, mock) = create_mock_state().await?;
+    let payee = create_peer(&mock);
+    let service = &state.service;
+    let fee = fee();
+    let msats: i64 = 2_000_000;
+
+    let u1 = create_funded_user(service, vec![1; 32], 10_000_000).await?;
+    let u2 = service.get_or_create_user(vec![2; 32]).await?;
+
+    // receive
+    let inv = service
+        .create_invoice(
+            &u2,
+            "test".to_owned(),
+            msats as u64,
+            600,
+            InvoiceExtra::new(invoice::Source::Test),
+        )
+        .await?;
+    mock.settle_invoice(&inv.payment_hash, None)?;
+    service.sync_invoices(Some(now() - 60)).await?;
+
+    // internal
+    let inv = service
+        .create_invoice(
+            &u2,
+            "test".to_owned(),
+            msats as u64,
+            600,
+            InvoiceExtra::new(invoice::Source::Test),
+        )
+        .await?;
+    service
+        .pay(&u1, inv.bolt11, None, &fee, invoice::Source::Test, false)
+        .await?;
+
+    // external succeed, failed, in flight
+    let u1 = service.get_user_by_id(u1.id).await?;
+    let inv = payee
+        .create_invoice("test".to_owned(), msats as u64, None, Some(600))
+        .await?;
+    mock.set_pay_behavior(inv.payment_hash.clone(), PayBehavior::Succeed { fee: 10 });
+    service
+        .pay(&u1, inv.bolt11, None, &fee, invoice::Source::Test, false)
+        .await?;
+    let u1 = service.get_user_by_id(u1.id).await?;
+    let inv = payee
+        .create_invoice("test".to_owned(), msats as u64, None, Some(600))
+        .await?;
+    mock.set_pay_behavior(
+        inv.payment_hash.clone(),
+        PayBehavior::Fail("fail".to_owned()),
+    );
+    assert!(service
+        .pay(&u1, inv.bolt11, None, &fee, invoice::Source::Test, false)
+        .await
+        .is_err());
+    let u1 = service.get_user_by_id(u1.id).await?;
+    let inv = payee
+        .create_invoice("test".to_owned(), msats as u64, None, Some(600))
+        .await?;
+    mock.set_pay_behavior(inv.payment_hash.clone(), PayBehavior::InFlight);
+    assert!(service
+        .pay(&u1, inv.bolt11, None, &fee, invoice::Source::Test, false)
+        .await
+        .is_err());
+
+    // locked
+    let report = service.reconcile().await?;
+    assert_eq!(report.unbalanced, 0);
+    assert_eq!(report.ledger_liabilities, report.liabilities);
+    assert_eq!(report.surplus, report.node_balance - report.liabilities);
+    assert!(!report.drift);
+
+    mock.complete_payment(&inv.payment_hash, 5)?;
+    service.sync_payments(None).await?;
+
+    let u1 = service.get_user_by_id(u1.id).await?;
+    let u2 = service.get_user_by_id(u2.id).await?;
+    let report = service.reconcile().await?;
+    assert_eq!(report.unbalanced, 0);
+    assert_eq!(report.liabilities, u1.balance + u2.balance);
+    assert_eq!(report.ledger_liabilities, report.liabilities);
+    assert_eq!(report.routing_fee, 15);
+    assert_eq!(report.ledger_node, msats - 2 * msats - 15);
+    assert_eq!(report.node_balance, mock.balance() as i64);
+    assert!(!report.drift);
+
+    // the users are credited without the node liquidity
+    mock.set_balance(0);
+    let report = service.reconcile().await?;
+    assert!(report.surplus < 0);
+    assert!(report.drift);
+    Ok(())
+}
+
+#[tokio::test]
+async fn opening_balances() -> Result<()> {
+    let (state, _mock) = create_mock_state().await?;
+    let service = &state.service;
+    let u1 = create_funded_user(service, vec![1; 32], 1_000_000).await?;
+    let u2 = service.get_or_create_user(vec![2; 32]).await?;
+
+    // balances before the ledger was introduced
+    for (user, balance, lock_amount) in [(&u1, 3_000_000, 0), (&u2, 2_000_000, 500_000)] {
+        user::ActiveModel {
+            id: Set(user.id),
+            balance: Set(balance),
+            lock_amount: Set(lock_amount),
+            ..Default::default()
+        }
+        .update(service.db())
+        .await?;
+    }
+    let report = service.reconcile().await?;
+    assert_ne!(report.ledger_liabilities, report.liabilities);
+
+    // rerun the opening balances migration
+    let steps = Migrator::migrations()
+        .iter()
+        .rev()
+        .position(|m| m.name() == "m20231123_024815_post_opening_balances")
+        .unwrap()
+        + 1;
+    Migrator::down(service.db(), Some(steps as u32)).await?;
+    Migrator::up(service.db(), None).await?;
+    let report = service.reconcile().await?;
+    assert_eq!(report.unbalanced, 0);
+    assert_eq!(report.liabilities, 5_500_000);
+    assert_eq!(report.ledger_liabilities, report.liabilities);
+    Ok(())
+}
@@ -9,10 +9,12 @@ use actix_web::{
 };
 use anyhow::Result;
 use base64::engine::{general_purpose, Engine};
+use entity::user;
+use lightning_client::Mock;
 use migration::{Migrator, MigratorTrait};
 use nostr_sdk::{EventBuilder, Keys, Kind, Tag};
 use satsbox::{
-    setting::{Lightning, Setting},
+    setting::{Fee, Lightning, Setting},
     sha256, AppState,
 };
 use serde_json::Value;
@@ -35,6 +37,47 @@ pub async fn create_test_state2(lightning: Option<Lightning>) -> Result<AppState
     Ok(state)
 }
 
+/// app state backed by an in-process mock node, the mock handle shares state with the service
+pub async fn create_mock_state() -> Result<(AppState, Mock)> {
+    dotenvy::from_filename(".test.env")?;
+    let mut setting = Setting::from_env("SATSBOX".to_owned())?;
+    setting.lightning = Lightning::Mock;
+    let mock = Mock::new();
+    let state =
+        AppState::with_lightning(setting, "mock".to_owned(), Box::new(mock.clone())).await?;
+    Migrator::fresh(state.service.db()).await?;
+    Ok((state, mock))
+}
+
+/// fee setting of the mock tests
+pub fn fee() -> Fee {
+    Fee {
+        pay_limit_pct: 1.0,
+        small_pay_limit_pct: 2.0,
+        internal_pct: 0.5,
+        service_pct: 0.3,
+    }
+}
+
+/// another mock node with a channel to the mock node
+pub fn create_peer(mock: &Mock) -> Mock {
+    let peer = Mock::new();
+    mock.connect_peer(&peer);
+    peer
+}
+
+/// get or create the user and credit the balance in msats
+pub async fn create_funded_user(
+    service: &satsbox::Service,
+    pubkey: Vec<u8>,
+    balance: i64,
+) -> Result<user::Model> {
+    let user = service.get_or_create_user(pubkey).await?;
+    Ok(service
+        .admin_adjust_user_balance(&user, balance, None)
+        .await?)
+}
+
 pub async fn get(
     app: impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>,
     path: &str,
@@ -48,18 +91,28 @@ pub async fn nostr_auth_get(
     url: &str,
     keys: &Keys,
 ) -> Result<(Value, u16)> {
+    let req = nostr_auth_get_req(url, keys)?;
+    call(req, app).await
+}
+
+pub fn nostr_auth_get_req(url: &str, keys: &Keys) -> Result<TestRequest> {
     let event = EventBuilder::new(
         Kind::from(27235),
         "",
         &vec![
             Tag::try_from(vec!["u", url])?,
             Tag::try_from(vec!["method", "GET"])?,
+            Tag::try_from(vec!["nonce", &nonce()])?,
         ],
     )
     .to_event(keys)?;
     let token = general_purpose::STANDARD.encode(event.as_json());
-    let req = auth_get_req(url, format!("Nostr {}", token));
-    call(req, app).await
+    Ok(auth_get_req(url, format!("Nostr {}", token)))
+}
+
+/// random tag to keep the event id unique, the same request may be signed in the same second
+fn nonce() -> String {
+    hex::encode(rand::random::<[u8; 8]>())
 }
 
 pub async fn nostr_auth_post(
@@ -76,6 +129,7 @@ pub async fn nostr_auth_post(
             Tag::try_from(vec!["u", url])?,
             Tag::try_from(vec!["method", "POST"])?,
             Tag::try_from(vec!["payload", &hex::encode(&hash)])?,
+            Tag::try_from(vec!["nonce", &nonce()])?,
         ],
     )
     .to_event(keys)?;
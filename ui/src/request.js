@@ -30,11 +30,18 @@ export const auth = {
   },
 }
 
+function nonce() {
+  let bytes = crypto.getRandomValues(new Uint8Array(8))
+  return Array.from(bytes, (b) => b.toString(16).padStart(2, '0')).join('')
+}
+
 function createEvent(privkey, url, data) {
   let isPost = data !== undefined
   let tags = [
     ['method', isPost ? 'POST' : 'GET'],
     ['u', url],
+    // keep the event id unique, the auth events can't be reused
+    ['nonce', nonce()],
   ]
   if (isPost) {
     let hash = sha256.create()